@@ -0,0 +1,203 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_CACHE_MAX_MB: u64 = 100;
+
+/// A completed response, stored as the chunks it originally streamed in
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedResponse {
+    pub chunks: Vec<String>,
+    pub model: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CacheEntry {
+    size: u64,
+    /// Logical clock value of the last read or write, for LRU eviction
+    last_used: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheStats {
+    pub entries: usize,
+    pub total_bytes: u64,
+    pub max_bytes: u64,
+}
+
+/// Content-addressed store of complete LLM responses in `llm-cache/` under the app data directory.
+/// Each response lives in `<key>.json`; `index.json` tracks sizes and last use. Reads only bump
+/// the last use in memory; the index is written on `put`, eviction and `flush`.
+pub struct ResponseCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    index: HashMap<String, CacheEntry>,
+    /// The index has changes that are not on disk yet
+    dirty: bool,
+}
+
+impl ResponseCache {
+    pub fn open(data_dir: &Path, max_mb: u64) -> Self {
+        let dir = data_dir.join("llm-cache");
+        let index = fs::read_to_string(dir.join("index.json"))
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+
+        ResponseCache {
+            dir,
+            max_bytes: max_mb * 1024 * 1024,
+            index,
+            dirty: false,
+        }
+    }
+
+    /// A limit of 0 disables the cache
+    pub fn is_enabled(&self) -> bool {
+        self.max_bytes > 0
+    }
+
+    pub fn set_max_mb(&mut self, max_mb: u64) -> Result<(), String> {
+        self.max_bytes = max_mb * 1024 * 1024;
+        self.evict()
+    }
+
+    pub fn get(&mut self, key: &str) -> Option<CachedResponse> {
+        if !self.is_enabled() {
+            return None;
+        }
+        if !self.index.contains_key(key) {
+            return None;
+        }
+
+        let response = fs::read_to_string(self.dir.join(format!("{}.json", key)))
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok());
+        match response {
+            Some(response) => {
+                let tick = self.next_tick();
+                if let Some(entry) = self.index.get_mut(key) {
+                    entry.last_used = tick;
+                }
+                self.dirty = true;
+                Some(response)
+            }
+            None => {
+                // The file was removed or damaged behind our back; forget it
+                self.index.remove(key);
+                self.dirty = true;
+                None
+            }
+        }
+    }
+
+    pub fn put(&mut self, key: &str, response: &CachedResponse) -> Result<(), String> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        let json = serde_json::to_string(response)
+            .map_err(|e| format!("Failed to serialize cached response: {}", e))?;
+        fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("Failed to create cache directory: {}", e))?;
+        fs::write(self.dir.join(format!("{}.json", key)), &json)
+            .map_err(|e| format!("Failed to write cached response: {}", e))?;
+
+        let last_used = self.next_tick();
+        self.index.insert(key.to_string(), CacheEntry {
+            size: json.len() as u64,
+            last_used,
+        });
+        self.evict()
+    }
+
+    /// Write last-use changes from reads, e.g. when the app exits
+    pub fn flush(&mut self) -> Result<(), String> {
+        if self.dirty {
+            self.write_index()?;
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self) -> Result<(), String> {
+        self.index.clear();
+        self.dirty = false;
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir)
+                .map_err(|e| format!("Failed to clear cache directory: {}", e))?;
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.index.len(),
+            total_bytes: self.total_bytes(),
+            max_bytes: self.max_bytes,
+        }
+    }
+
+    /// Timestamps can collide within a millisecond, so recency is a counter stored with the entries
+    fn next_tick(&self) -> u64 {
+        self.index.values().map(|entry| entry.last_used).max().unwrap_or(0) + 1
+    }
+
+    fn total_bytes(&self) -> u64 {
+        self.index.values().map(|entry| entry.size).sum()
+    }
+
+    /// Drop least recently used entries until the cache fits its limit
+    fn evict(&mut self) -> Result<(), String> {
+        let mut total = self.total_bytes();
+        if total > self.max_bytes {
+            let mut by_age: Vec<(String, u64, u64)> = self.index
+                .iter()
+                .map(|(key, entry)| (key.clone(), entry.last_used, entry.size))
+                .collect();
+            by_age.sort_by_key(|(_, last_used, _)| *last_used);
+
+            for (key, _, size) in by_age {
+                if total <= self.max_bytes {
+                    break;
+                }
+                let _ = fs::remove_file(self.dir.join(format!("{}.json", key)));
+                self.index.remove(&key);
+                total -= size;
+            }
+        }
+        self.write_index()
+    }
+
+    fn write_index(&mut self) -> Result<(), String> {
+        self.dirty = false;
+        if self.index.is_empty() && !self.dir.exists() {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("Failed to create cache directory: {}", e))?;
+        let json = serde_json::to_string(&self.index)
+            .map_err(|e| format!("Failed to serialize cache index: {}", e))?;
+        fs::write(self.dir.join("index.json"), json)
+            .map_err(|e| format!("Failed to write cache index: {}", e))
+    }
+}
+
+/// SHA-256 over everything that determines the answer. The base URL is part of the key so two
+/// servers exposing the same model name do not share entries.
+pub fn cache_key(provider: &str, base_url: &str, model: &str, system_prompt: &str, messages: &serde_json::Value) -> String {
+    let material = serde_json::json!({
+        "provider": provider,
+        "baseUrl": base_url,
+        "model": model,
+        "system": system_prompt,
+        "messages": messages,
+    });
+    let digest = Sha256::digest(material.to_string().as_bytes());
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
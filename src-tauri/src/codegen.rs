@@ -0,0 +1,1138 @@
+//! Code skeletons for the project class diagram: classes, members and relations read from a
+//! Mermaid `classDiagram`, written out as Rust, TypeScript or Python source files
+
+use crate::mermaid;
+use crate::schema::snake_case;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Visibility {
+    Public,
+    Private,
+    Protected,
+    Package,
+}
+
+impl Visibility {
+    fn from_marker(marker: char) -> Option<Self> {
+        match marker {
+            '+' => Some(Visibility::Public),
+            '-' => Some(Visibility::Private),
+            '#' => Some(Visibility::Protected),
+            '~' => Some(Visibility::Package),
+            _ => None,
+        }
+    }
+
+    fn marker(self) -> char {
+        match self {
+            Visibility::Public => '+',
+            Visibility::Private => '-',
+            Visibility::Protected => '#',
+            Visibility::Package => '~',
+        }
+    }
+}
+
+/// `+` for public members, nothing without a visibility
+fn visibility_marker(visibility: Option<Visibility>) -> String {
+    visibility.map(|v| v.marker().to_string()).unwrap_or_default()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: String,
+    /// Type as written in the diagram, like `List~Order~`
+    pub data_type: Option<String>,
+    pub visibility: Option<Visibility>,
+    pub is_static: bool,
+}
+
+impl Field {
+    /// `+List~Order~ items$`, as written in a class block
+    pub fn to_mermaid(&self) -> String {
+        let typed = match &self.data_type {
+            Some(data_type) => format!("{} {}", data_type, self.name),
+            None => self.name.clone(),
+        };
+        let suffix = if self.is_static { "$" } else { "" };
+        format!("{}{}{}", visibility_marker(self.visibility), typed, suffix)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameter {
+    pub name: String,
+    pub data_type: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Method {
+    pub name: String,
+    pub parameters: Vec<Parameter>,
+    pub return_type: Option<String>,
+    pub visibility: Option<Visibility>,
+    pub is_static: bool,
+    pub is_abstract: bool,
+}
+
+impl Method {
+    /// `+deposit(decimal amount)$ bool`, as written in a class block
+    pub fn to_mermaid(&self) -> String {
+        let parameters: Vec<String> = self.parameters
+            .iter()
+            .map(|p| match &p.data_type {
+                Some(data_type) => format!("{} {}", data_type, p.name),
+                None => p.name.clone(),
+            })
+            .collect();
+        let mut out = format!("{}{}({})", visibility_marker(self.visibility), self.name, parameters.join(", "));
+        if self.is_abstract {
+            out.push('*');
+        }
+        if self.is_static {
+            out.push('$');
+        }
+        if let Some(return_type) = &self.return_type {
+            out.push_str(&format!(" {}", return_type));
+        }
+        out
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Class {
+    pub name: String,
+    /// Type parameters of `Box~T~`
+    pub generics: Vec<String>,
+    /// `interface`, `abstract`, `enumeration` or `service`, lowercase
+    pub annotation: Option<String>,
+    pub fields: Vec<Field>,
+    pub methods: Vec<Method>,
+}
+
+impl Class {
+    fn is_interface(&self) -> bool {
+        self.annotation.as_deref() == Some("interface")
+    }
+
+    fn is_abstract(&self) -> bool {
+        self.annotation.as_deref() == Some("abstract")
+    }
+
+    fn is_enum(&self) -> bool {
+        matches!(self.annotation.as_deref(), Some("enumeration" | "enum"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RelationKind {
+    Inheritance,
+    Realization,
+    Composition,
+    Aggregation,
+    Association,
+    Dependency,
+    Link,
+}
+
+/// `source` extends, realizes, is made of, holds, uses or links to `target`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassRelation {
+    pub source: String,
+    pub target: String,
+    pub kind: RelationKind,
+    /// Multiplicity on the target side, like `1`, `0..1` or `*`
+    pub target_multiplicity: Option<String>,
+    pub label: Option<String>,
+}
+
+impl ClassRelation {
+    /// Arrow from `source` to `target`, like `--|>` or `*--`
+    pub fn arrow(&self) -> &'static str {
+        match self.kind {
+            RelationKind::Inheritance => "--|>",
+            RelationKind::Realization => "..|>",
+            RelationKind::Composition => "*--",
+            RelationKind::Aggregation => "o--",
+            RelationKind::Association => "-->",
+            RelationKind::Dependency => "..>",
+            RelationKind::Link => "--",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClassModel {
+    pub classes: Vec<Class>,
+    pub relations: Vec<ClassRelation>,
+}
+
+enum Member {
+    Field(Field),
+    Method(Method),
+}
+
+impl ClassModel {
+    /// Read a Mermaid `classDiagram`; invalid diagrams are rejected with their first diagnostic
+    pub fn from_mermaid(code: &str) -> Result<Self, String> {
+        if let Some(diagnostic) = mermaid::validate("uml", code)?.first() {
+            return Err(format!("The class diagram is not valid: {}", diagnostic));
+        }
+
+        let mut model = ClassModel::default();
+        let lines: Vec<&str> = code.lines().collect();
+        // Open blocks: the class whose body is open, or `None` for a namespace
+        let mut blocks: Vec<Option<String>> = Vec::new();
+
+        for (_, line) in mermaid::statement_lines(&lines).into_iter().skip(1) {
+            let text = line.trim();
+            if text == "}" {
+                blocks.pop();
+                continue;
+            }
+            if let Some(Some(class)) = blocks.last() {
+                let class = class.clone();
+                match annotation(text) {
+                    Some(annotation) => model.class_mut(&class).annotation = Some(annotation),
+                    None => model.add_member(&class, text),
+                }
+                continue;
+            }
+
+            match text.split_whitespace().next() {
+                Some("direction" | "note" | "link" | "click" | "callback" | "style" | "classDef" | "cssClass") => continue,
+                Some("namespace") => {
+                    blocks.push(None);
+                    continue;
+                }
+                Some("class") => {
+                    let rest = text["class".len()..].trim();
+                    let (declaration, body) = match rest.find('{') {
+                        Some(open) => (rest[..open].trim(), Some(rest[open + 1..].trim())),
+                        None => (rest, None),
+                    };
+                    let (head, annotated) = match declaration.split_once("<<") {
+                        Some((head, annotated)) => (head.trim(), annotation(&format!("<<{}", annotated))),
+                        None => (declaration, None),
+                    };
+                    let (name, generics) = class_reference(head);
+                    let class = model.class_mut(&name);
+                    if !generics.is_empty() {
+                        class.generics = generics;
+                    }
+                    if annotated.is_some() {
+                        class.annotation = annotated;
+                    }
+                    match body.map(|body| body.strip_suffix('}')) {
+                        Some(Some(member)) => model.add_member(&name, member),
+                        Some(None) => blocks.push(Some(name)),
+                        None => {}
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+
+            if let Some((annotated, name)) = text.strip_prefix("<<").and_then(|rest| rest.split_once(">>")) {
+                let name = class_reference(name.trim()).0;
+                model.class_mut(&name).annotation = Some(annotated.trim().to_lowercase());
+            } else if let Some(relation) = parse_relation(text) {
+                model.class_mut(&relation.source);
+                model.class_mut(&relation.target);
+                model.relations.push(relation);
+            } else if let Some((name, member)) = text.split_once(':').filter(|(_, member)| !member.starts_with("::")) {
+                let name = class_reference(name.trim()).0;
+                model.add_member(&name, member);
+            } else {
+                model.class_mut(&class_reference(text).0);
+            }
+        }
+
+        Ok(model)
+    }
+
+    pub fn class(&self, name: &str) -> Option<&Class> {
+        self.classes.iter().find(|c| c.name == name)
+    }
+
+    /// The class called `name`, added without members if it is new
+    fn class_mut(&mut self, name: &str) -> &mut Class {
+        let index = match self.classes.iter().position(|c| c.name == name) {
+            Some(index) => index,
+            None => {
+                self.classes.push(Class {
+                    name: name.to_string(),
+                    generics: Vec::new(),
+                    annotation: None,
+                    fields: Vec::new(),
+                    methods: Vec::new(),
+                });
+                self.classes.len() - 1
+            }
+        };
+        &mut self.classes[index]
+    }
+
+    fn add_member(&mut self, class: &str, text: &str) {
+        match parse_member(text) {
+            Some(Member::Field(field)) => self.class_mut(class).fields.push(field),
+            Some(Member::Method(method)) => self.class_mut(class).methods.push(method),
+            None => {}
+        }
+    }
+
+    /// Classes `class` extends or realizes, in diagram order
+    fn parents(&self, class: &Class) -> Vec<&Class> {
+        self.relations
+            .iter()
+            .filter(|r| r.source == class.name && matches!(r.kind, RelationKind::Inheritance | RelationKind::Realization))
+            .filter_map(|r| self.class(&r.target))
+            .collect()
+    }
+
+    /// Interfaces, abstract classes and anything realized become traits in Rust
+    fn is_trait(&self, class: &Class) -> bool {
+        class.is_interface()
+            || class.is_abstract()
+            || self.relations.iter().any(|r| r.kind == RelationKind::Realization && r.target == class.name)
+    }
+
+    /// Declared fields, then one field per owned class that no field refers to yet
+    fn fields(&self, class: &Class) -> Vec<Field> {
+        let mut fields = class.fields.clone();
+        for relation in &self.relations {
+            let owns = matches!(relation.kind, RelationKind::Composition | RelationKind::Aggregation | RelationKind::Association);
+            if !owns || relation.source != class.name {
+                continue;
+            }
+            let target = &relation.target;
+            let already = fields.iter().any(|f| {
+                let mut names = Vec::new();
+                type_names(&parse_type(f.data_type.as_deref().unwrap_or_default()), &mut names);
+                names.contains(target)
+            });
+            if already {
+                continue;
+            }
+
+            let multiplicity = relation.target_multiplicity.as_deref().unwrap_or("1");
+            let upper = multiplicity.rsplit("..").next().unwrap_or_default().trim();
+            let many = matches!(upper, "*" | "n" | "many") || upper.parse::<u32>().is_ok_and(|n| n > 1);
+            let optional = multiplicity.trim().starts_with('0') && !many;
+            let mut name = snake_case(target);
+            let data_type = if many {
+                name.push('s');
+                format!("List~{}~", target)
+            } else if optional {
+                format!("Optional~{}~", target)
+            } else {
+                target.clone()
+            };
+            if fields.iter().any(|f| snake_case(&f.name) == name) {
+                continue;
+            }
+            fields.push(Field { name, data_type: Some(data_type), visibility: None, is_static: false });
+        }
+        fields
+    }
+
+    /// Methods inherited from interfaces and abstract methods of parent classes that `class`
+    /// must provide, with the parent they come from
+    fn required_methods<'a>(&'a self, class: &Class) -> Vec<(&'a Class, &'a Method)> {
+        let mut required: Vec<(&Class, &Method)> = Vec::new();
+        for parent in self.parents(class) {
+            for method in &parent.methods {
+                let needed = (parent.is_interface() && !method.is_static) || method.is_abstract;
+                let declared = class.methods.iter().any(|m| m.name == method.name)
+                    || required.iter().any(|(_, m)| m.name == method.name);
+                if needed && !declared {
+                    required.push((parent, method));
+                }
+            }
+        }
+        required
+    }
+
+    /// Other classes of the model that `class` mentions, in diagram order
+    fn referenced(&self, class: &Class) -> Vec<&Class> {
+        let mut names = Vec::new();
+        let mut types: Vec<String> = self.fields(class).into_iter().filter_map(|f| f.data_type).collect();
+        for method in class.methods.iter().chain(self.required_methods(class).into_iter().map(|(_, m)| m)) {
+            types.extend(method.parameters.iter().filter_map(|p| p.data_type.clone()));
+            types.extend(method.return_type.clone());
+        }
+        for data_type in types {
+            type_names(&parse_type(&data_type), &mut names);
+        }
+        names.extend(self.parents(class).into_iter().map(|p| p.name.clone()));
+        self.classes.iter().filter(|c| c.name != class.name && names.contains(&c.name)).collect()
+    }
+}
+
+/// `<<interface>>` as `interface`
+fn annotation(text: &str) -> Option<String> {
+    let inner = text.trim().strip_prefix("<<")?.strip_suffix(">>")?;
+    Some(inner.trim().to_lowercase())
+}
+
+/// Name and type parameters of `Box~T~`, `` `Line Item` ``, `Shape["Label"]` or `A:::style`
+fn class_reference(text: &str) -> (String, Vec<String>) {
+    let text = text.split(":::").next().unwrap_or_default().trim();
+    let text = match text.find('[') {
+        Some(open) if text.ends_with(']') => &text[..open],
+        _ => text,
+    };
+    let text = text.trim_matches('`');
+    match text.split_once('~') {
+        Some((name, generics)) => {
+            let generics = generics.trim_end_matches('~').split(',').map(|g| g.trim().to_string()).filter(|g| !g.is_empty());
+            (name.to_string(), generics.collect())
+        }
+        None => (text.to_string(), Vec::new()),
+    }
+}
+
+/// `+deposit(amount: decimal)$ bool`, `-List~int~ items` or `count : int`
+fn parse_member(text: &str) -> Option<Member> {
+    let mut text = text.trim();
+    let visibility = text.chars().next().and_then(Visibility::from_marker);
+    if visibility.is_some() {
+        text = text[1..].trim_start();
+    }
+    if text.is_empty() {
+        return None;
+    }
+
+    if let (Some(open), Some(close)) = (text.find('('), text.rfind(')')) {
+        if open < close {
+            let mut rest = text[close + 1..].trim();
+            let (mut is_static, mut is_abstract) = (false, false);
+            loop {
+                if let Some(r) = rest.strip_prefix('$') {
+                    is_static = true;
+                    rest = r.trim_start();
+                } else if let Some(r) = rest.strip_prefix('*') {
+                    is_abstract = true;
+                    rest = r.trim_start();
+                } else {
+                    break;
+                }
+            }
+            let return_type = rest.trim_start_matches(':').trim();
+            let parameters = split_top_level(&text[open + 1..close])
+                .into_iter()
+                .filter(|p| !p.trim().is_empty())
+                .map(|p| {
+                    let (name, data_type) = typed_name(p);
+                    Parameter { name, data_type }
+                })
+                .collect();
+            return Some(Member::Method(Method {
+                name: text[..open].trim().to_string(),
+                parameters,
+                return_type: (!return_type.is_empty()).then(|| return_type.to_string()),
+                visibility,
+                is_static,
+                is_abstract,
+            }));
+        }
+    }
+
+    let is_static = text.ends_with('$');
+    let text = text.trim_end_matches(['$', '*']);
+    let (name, data_type) = typed_name(text);
+    Some(Member::Field(Field { name, data_type, visibility, is_static }))
+}
+
+/// `Type name`, `name: Type` or `name`
+fn typed_name(text: &str) -> (String, Option<String>) {
+    let text = text.trim();
+    if let Some((name, data_type)) = text.split_once(':') {
+        return (name.trim().to_string(), Some(data_type.trim().to_string()).filter(|t| !t.is_empty()));
+    }
+    match text.rsplit_once(char::is_whitespace) {
+        Some((data_type, name)) => (name.to_string(), Some(data_type.trim().to_string())),
+        None => (text.to_string(), None),
+    }
+}
+
+/// Split on commas outside `<>`, `[]` and `()`
+fn split_top_level(text: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut start) = (0i32, 0);
+    for (i, c) in text.char_indices() {
+        match c {
+            '<' | '[' | '(' => depth += 1,
+            '>' | ']' | ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&text[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+/// `A "1" --> "*" B : label`, `Animal <|-- Duck` or `A*--B`
+fn parse_relation(text: &str) -> Option<ClassRelation> {
+    let (head, label) = match text.find(':') {
+        Some(colon) if !text[colon..].starts_with(":::") => (&text[..colon], Some(text[colon + 1..].trim())),
+        _ => (text, None),
+    };
+    let mut words = mermaid::tokens(head);
+    if words.len() == 1 {
+        words = mermaid::split_relation(words[0])?.to_vec();
+    }
+    let at = words.iter().position(|w| mermaid::class_relation(w))?;
+    let quoted = |w: &&str| (w.len() >= 2 && w.starts_with('"') && w.ends_with('"')).then(|| w.trim_matches('"').to_string());
+    let (before, after) = (&words[..at], &words[at + 1..]);
+    let left = class_reference(before.first()?).0;
+    let right = class_reference(after.last()?).0;
+    let left_multiplicity = before.get(1).and_then(quoted);
+    let right_multiplicity = after.iter().rev().nth(1).and_then(quoted);
+
+    let arrow = words[at];
+    let line = arrow.find("--").or_else(|| arrow.find(".."))?;
+    let dashed = arrow[line..].starts_with("..");
+    let (left_end, right_end) = (&arrow[..line], &arrow[line + 2..]);
+    // Whether the relation reads from left to right
+    let (kind, forward) = match (left_end, right_end) {
+        ("<|", _) | (_, "|>") => {
+            let kind = if dashed { RelationKind::Realization } else { RelationKind::Inheritance };
+            (kind, right_end == "|>")
+        }
+        ("()", _) | (_, "()") => (RelationKind::Realization, right_end == "()"),
+        // The diamond sits on the side of the whole
+        ("*", _) | (_, "*") => (RelationKind::Composition, left_end == "*"),
+        ("o", _) | (_, "o") => (RelationKind::Aggregation, left_end == "o"),
+        ("<", _) | (_, ">") => {
+            let kind = if dashed { RelationKind::Dependency } else { RelationKind::Association };
+            (kind, right_end == ">")
+        }
+        _ => (RelationKind::Link, true),
+    };
+
+    let (source, target, target_multiplicity) =
+        if forward { (left, right, right_multiplicity) } else { (right, left, left_multiplicity) };
+    Some(ClassRelation {
+        source,
+        target,
+        kind,
+        target_multiplicity,
+        label: label.map(|l| l.trim_matches('"').to_string()).filter(|l| !l.is_empty()),
+    })
+}
+
+// Types
+
+/// A type as written in the diagram, like `List~Order~`, `Map<K, V>` or `int[]`
+struct TypeExpr {
+    name: String,
+    args: Vec<TypeExpr>,
+}
+
+fn parse_type(text: &str) -> TypeExpr {
+    let text = text.trim();
+    if let Some(element) = text.strip_suffix("[]") {
+        return TypeExpr { name: "List".to_string(), args: vec![parse_type(element)] };
+    }
+    match text.find(['~', '<']) {
+        Some(open) if text.len() > open + 2 && text.ends_with(['~', '>']) => TypeExpr {
+            name: text[..open].trim().to_string(),
+            args: split_top_level(&text[open + 1..text.len() - 1]).into_iter().map(parse_type).collect(),
+        },
+        _ => TypeExpr { name: text.to_string(), args: Vec::new() },
+    }
+}
+
+fn type_names(expr: &TypeExpr, names: &mut Vec<String>) {
+    names.push(expr.name.clone());
+    for arg in &expr.args {
+        type_names(arg, names);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    Rust,
+    TypeScript,
+    Python,
+}
+
+impl Language {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "rust" | "rs" => Ok(Language::Rust),
+            "typescript" | "ts" => Ok(Language::TypeScript),
+            "python" | "py" => Ok(Language::Python),
+            _ => Err(format!("Unknown language: {}", name)),
+        }
+    }
+
+    /// Source for a diagram type; common names like `int`, `List~T~` and `Map~K, V~` are mapped
+    /// to the language and anything else is kept as a class name
+    fn render_type(self, expr: &TypeExpr) -> String {
+        let args: Vec<String> = expr.args.iter().map(|a| self.render_type(a)).collect();
+        let pick = |rust: &str, typescript: &str, python: &str| {
+            match self {
+                Language::Rust => rust,
+                Language::TypeScript => typescript,
+                Language::Python => python,
+            }
+            .to_string()
+        };
+        match (expr.name.to_lowercase().as_str(), args.as_slice()) {
+            ("int" | "integer" | "short" | "i32", []) => pick("i32", "number", "int"),
+            ("long" | "bigint" | "i64", []) => pick("i64", "number", "int"),
+            ("float" | "double" | "decimal" | "number" | "real" | "f64", []) => pick("f64", "number", "float"),
+            ("bool" | "boolean", []) => pick("bool", "boolean", "bool"),
+            ("string" | "str" | "text" | "uuid", []) => pick("String", "string", "str"),
+            ("char", []) => pick("char", "string", "str"),
+            ("void" | "none" | "unit", []) => pick("()", "void", "None"),
+            ("date", []) => pick("String", "Date", "datetime.date"),
+            ("datetime" | "timestamp" | "instant", []) => pick("String", "Date", "datetime.datetime"),
+            ("list" | "array" | "arraylist" | "vec" | "collection" | "sequence" | "ienumerable", [element]) => match self {
+                Language::Rust => format!("Vec<{}>", element),
+                Language::TypeScript if element.contains(' ') => format!("({})[]", element),
+                Language::TypeScript => format!("{}[]", element),
+                Language::Python => format!("list[{}]", element),
+            },
+            ("set" | "hashset", [element]) => match self {
+                Language::Rust => format!("HashSet<{}>", element),
+                Language::TypeScript => format!("Set<{}>", element),
+                Language::Python => format!("set[{}]", element),
+            },
+            ("map" | "dict" | "dictionary" | "hashmap", [key, value]) => match self {
+                Language::Rust => format!("HashMap<{}, {}>", key, value),
+                Language::TypeScript => format!("Map<{}, {}>", key, value),
+                Language::Python => format!("dict[{}, {}]", key, value),
+            },
+            ("optional" | "option" | "maybe", [inner]) => match self {
+                Language::Rust => format!("Option<{}>", inner),
+                Language::TypeScript => format!("{} | undefined", inner),
+                Language::Python => format!("{} | None", inner),
+            },
+            (_, []) => expr.name.clone(),
+            (_, args) => match self {
+                Language::Python => format!("{}[{}]", expr.name, args.join(", ")),
+                _ => format!("{}<{}>", expr.name, args.join(", ")),
+            },
+        }
+    }
+
+    /// Type of a field or parameter; untyped ones get the language's catch-all type
+    fn field_type(self, data_type: Option<&str>) -> String {
+        match data_type {
+            Some(data_type) => self.render_type(&parse_type(data_type)),
+            None => match self {
+                Language::Rust => "()".to_string(),
+                Language::TypeScript => "unknown".to_string(),
+                Language::Python => "Any".to_string(),
+            },
+        }
+    }
+
+    /// Return type, or `None` for methods returning nothing
+    fn return_type(self, data_type: Option<&str>) -> Option<String> {
+        let rendered = self.render_type(&parse_type(data_type?));
+        (!matches!(rendered.as_str(), "()" | "void" | "None")).then_some(rendered)
+    }
+}
+
+/// A file of generated source, relative to the output folder
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeneratedFile {
+    pub path: String,
+    pub content: String,
+}
+
+const HEADER: &str = "Generated from the project class diagram";
+
+/// One source file per class plus a module file that exports them all
+pub fn generate(model: &ClassModel, language: Language) -> Vec<GeneratedFile> {
+    let mut files: Vec<GeneratedFile> = model
+        .classes
+        .iter()
+        .map(|class| match language {
+            Language::Rust => GeneratedFile { path: format!("{}.rs", snake_case(&class.name)), content: rust_file(model, class) },
+            Language::TypeScript => GeneratedFile { path: format!("{}.ts", class.name), content: typescript_file(model, class) },
+            Language::Python => GeneratedFile { path: format!("{}.py", snake_case(&class.name)), content: python_file(model, class) },
+        })
+        .collect();
+
+    let index = match language {
+        Language::Rust => {
+            let modules: Vec<String> = model.classes.iter().map(|c| format!("mod {};", rust_name(&c.name))).collect();
+            let exports: Vec<String> =
+                model.classes.iter().map(|c| format!("pub use {}::{};", rust_name(&c.name), c.name)).collect();
+            GeneratedFile { path: "mod.rs".to_string(), content: format!("//! {}\n\n{}\n\n{}\n", HEADER, modules.join("\n"), exports.join("\n")) }
+        }
+        Language::TypeScript => {
+            let exports: Vec<String> = model.classes.iter().map(|c| format!("export * from './{}';", c.name)).collect();
+            GeneratedFile { path: "index.ts".to_string(), content: format!("// {}\n\n{}\n", HEADER, exports.join("\n")) }
+        }
+        Language::Python => {
+            let imports: Vec<String> =
+                model.classes.iter().map(|c| format!("from .{} import {}", snake_case(&c.name), c.name)).collect();
+            let names: Vec<String> = model.classes.iter().map(|c| format!("\"{}\"", c.name)).collect();
+            GeneratedFile {
+                path: "__init__.py".to_string(),
+                content: format!("\"\"\"{}\"\"\"\n\n{}\n\n__all__ = [{}]\n", HEADER, imports.join("\n"), names.join(", ")),
+            }
+        }
+    };
+    files.push(index);
+    files
+}
+
+/// Member name as an identifier: other characters become `_`
+fn identifier(name: &str) -> String {
+    let mut out: String = name.chars().map(|c| if c.is_alphanumeric() || c == '_' { c } else { '_' }).collect();
+    if !out.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+        out.insert(0, '_');
+    }
+    out
+}
+
+/// End a type body opened with `{`, keeping empty ones on one line
+fn close_block(body: &mut String) {
+    if body.ends_with("{\n") {
+        body.pop();
+        body.push_str("}\n");
+    } else {
+        body.push_str("}\n");
+    }
+}
+
+fn generics(class: &Class, open: &str, close: &str) -> String {
+    if class.generics.is_empty() {
+        String::new()
+    } else {
+        format!("{}{}{}", open, class.generics.join(", "), close)
+    }
+}
+
+// Rust
+
+const RUST_KEYWORDS: [&str; 38] = [
+    "as", "async", "await", "break", "const", "continue", "dyn", "else", "enum", "extern", "false", "fn", "for", "if",
+    "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "static", "struct", "trait",
+    "true", "type", "unsafe", "use", "where", "while", "abstract", "box", "final", "yield",
+];
+
+fn rust_name(name: &str) -> String {
+    let name = identifier(&snake_case(name));
+    match name.as_str() {
+        "self" | "super" | "crate" => format!("{}_", name),
+        _ if RUST_KEYWORDS.contains(&name.as_str()) => format!("r#{}", name),
+        _ => name,
+    }
+}
+
+fn rust_visibility(visibility: Option<Visibility>) -> &'static str {
+    match visibility {
+        None | Some(Visibility::Public) => "pub ",
+        Some(Visibility::Private) => "",
+        Some(Visibility::Protected | Visibility::Package) => "pub(crate) ",
+    }
+}
+
+/// `fn name(&self, a: i32) -> bool`, without visibility
+fn rust_signature(method: &Method) -> String {
+    let mut parameters: Vec<String> = if method.is_static { Vec::new() } else { vec!["&self".to_string()] };
+    parameters.extend(
+        method.parameters.iter().map(|p| format!("{}: {}", rust_name(&p.name), Language::Rust.field_type(p.data_type.as_deref()))),
+    );
+    let returns = Language::Rust.return_type(method.return_type.as_deref()).map(|t| format!(" -> {}", t)).unwrap_or_default();
+    format!("fn {}({}){}", rust_name(&method.name), parameters.join(", "), returns)
+}
+
+fn rust_file(model: &ClassModel, class: &Class) -> String {
+    let type_parameters = generics(class, "<", ">");
+    let mut body = String::new();
+
+    if class.is_enum() {
+        body.push_str(&format!("pub enum {} {{\n", class.name));
+        for field in &class.fields {
+            body.push_str(&format!("    {},\n", identifier(&field.name)));
+        }
+        body.push_str("}\n");
+    } else if model.is_trait(class) {
+        let supertraits: Vec<String> =
+            model.parents(class).into_iter().filter(|p| model.is_trait(p)).map(|p| p.name.clone()).collect();
+        let bounds = if supertraits.is_empty() { String::new() } else { format!(": {}", supertraits.join(" + ")) };
+        body.push_str(&format!("pub trait {}{}{} {{\n", class.name, type_parameters, bounds));
+        // Traits have no fields, so they expose them through getters
+        for field in model.fields(class) {
+            body.push_str(&format!(
+                "    fn {}(&self) -> {};\n",
+                rust_name(&field.name),
+                Language::Rust.field_type(field.data_type.as_deref())
+            ));
+        }
+        for method in &class.methods {
+            body.push_str(&format!("    {};\n", rust_signature(method)));
+        }
+        close_block(&mut body);
+    } else {
+        let fields = model.fields(class);
+        body.push_str(&format!("pub struct {}{} {{\n", class.name, type_parameters));
+        // Rust has no inheritance: a parent class is embedded instead
+        for parent in model.parents(class).into_iter().filter(|p| !model.is_trait(p)) {
+            body.push_str(&format!("    pub {}: {},\n", rust_name(&parent.name), parent.name));
+        }
+        for field in fields.iter().filter(|f| !f.is_static) {
+            body.push_str(&format!(
+                "    {}{}: {},\n",
+                rust_visibility(field.visibility),
+                rust_name(&field.name),
+                Language::Rust.field_type(field.data_type.as_deref())
+            ));
+        }
+        for generic in &class.generics {
+            let used = fields.iter().any(|f| f.data_type.as_deref().is_some_and(|t| type_mentions(t, generic)));
+            if !used {
+                body.push_str(&format!("    _{}: std::marker::PhantomData<{}>,\n", snake_case(generic), generic));
+            }
+        }
+        close_block(&mut body);
+
+        let statics: Vec<&Field> = fields.iter().filter(|f| f.is_static).collect();
+        if !class.methods.is_empty() || !statics.is_empty() {
+            body.push_str(&format!("\nimpl{} {}{} {{\n", type_parameters, class.name, type_parameters));
+            for field in statics {
+                body.push_str(&format!(
+                    "    {}const {}: {} = todo!();\n",
+                    rust_visibility(field.visibility),
+                    identifier(&field.name).to_uppercase(),
+                    Language::Rust.field_type(field.data_type.as_deref())
+                ));
+            }
+            let methods: Vec<String> = class
+                .methods
+                .iter()
+                .map(|m| format!("    {}{} {{\n        todo!()\n    }}\n", rust_visibility(m.visibility), rust_signature(m)))
+                .collect();
+            body.push_str(&methods.join("\n"));
+            body.push_str("}\n");
+        }
+
+        for parent in model.parents(class).into_iter().filter(|p| model.is_trait(p)) {
+            body.push_str(&format!("\nimpl{} {} for {}{} {{\n", type_parameters, parent.name, class.name, type_parameters));
+            let mut items: Vec<String> = model
+                .fields(parent)
+                .iter()
+                .map(|f| {
+                    format!(
+                        "    fn {}(&self) -> {} {{\n        todo!()\n    }}\n",
+                        rust_name(&f.name),
+                        Language::Rust.field_type(f.data_type.as_deref())
+                    )
+                })
+                .collect();
+            items.extend(parent.methods.iter().map(|m| format!("    {} {{\n        todo!()\n    }}\n", rust_signature(m))));
+            body.push_str(&items.join("\n"));
+            body.push_str("}\n");
+        }
+    }
+
+    let mut uses = Vec::new();
+    if !model.referenced(class).is_empty() {
+        uses.push("use super::*;".to_string());
+    }
+    let collections: Vec<&str> = ["HashMap", "HashSet"].into_iter().filter(|c| body.contains(&format!("{}<", c))).collect();
+    match collections.as_slice() {
+        [] => {}
+        [one] => uses.push(format!("use std::collections::{};", one)),
+        many => uses.push(format!("use std::collections::{{{}}};", many.join(", "))),
+    }
+    let uses = if uses.is_empty() { String::new() } else { format!("{}\n\n", uses.join("\n")) };
+    format!("//! {}\n\n{}{}", HEADER, uses, body)
+}
+
+/// Whether `generic` appears as a whole name in the type `data_type`
+fn type_mentions(data_type: &str, generic: &str) -> bool {
+    let mut names = Vec::new();
+    type_names(&parse_type(data_type), &mut names);
+    names.iter().any(|n| n == generic)
+}
+
+// TypeScript
+
+fn typescript_visibility(visibility: Option<Visibility>) -> &'static str {
+    match visibility {
+        Some(Visibility::Public) => "public ",
+        Some(Visibility::Private) => "private ",
+        Some(Visibility::Protected) => "protected ",
+        None | Some(Visibility::Package) => "",
+    }
+}
+
+/// `name(a: number): boolean`
+fn typescript_signature(method: &Method) -> String {
+    let parameters: Vec<String> = method
+        .parameters
+        .iter()
+        .map(|p| format!("{}: {}", identifier(&p.name), Language::TypeScript.field_type(p.data_type.as_deref())))
+        .collect();
+    let returns = Language::TypeScript.return_type(method.return_type.as_deref()).unwrap_or_else(|| "void".to_string());
+    format!("{}({}): {}", identifier(&method.name), parameters.join(", "), returns)
+}
+
+fn typescript_file(model: &ClassModel, class: &Class) -> String {
+    let type_parameters = generics(class, "<", ">");
+    let parents = model.parents(class);
+    let mut body = String::new();
+
+    if class.is_enum() {
+        body.push_str(&format!("export enum {} {{\n", class.name));
+        for field in &class.fields {
+            body.push_str(&format!("  {} = '{}',\n", identifier(&field.name), field.name));
+        }
+        body.push_str("}\n");
+    } else if class.is_interface() {
+        let extends: Vec<&str> = parents.iter().map(|p| p.name.as_str()).collect();
+        let extends = if extends.is_empty() { String::new() } else { format!(" extends {}", extends.join(", ")) };
+        body.push_str(&format!("export interface {}{}{} {{\n", class.name, type_parameters, extends));
+        for field in model.fields(class).iter().filter(|f| !f.is_static) {
+            body.push_str(&format!(
+                "  {}: {};\n",
+                identifier(&field.name),
+                Language::TypeScript.field_type(field.data_type.as_deref())
+            ));
+        }
+        for method in class.methods.iter().filter(|m| !m.is_static) {
+            body.push_str(&format!("  {};\n", typescript_signature(method)));
+        }
+        close_block(&mut body);
+    } else {
+        // A class extends its first parent class and implements everything else
+        let base = parents.iter().find(|p| !p.is_interface());
+        let implements: Vec<&str> =
+            parents.iter().filter(|p| Some(*p) != base).map(|p| p.name.as_str()).collect();
+        let mut declaration = format!(
+            "export {}class {}{}",
+            if class.is_abstract() { "abstract " } else { "" },
+            class.name,
+            type_parameters
+        );
+        if let Some(base) = base {
+            declaration.push_str(&format!(" extends {}", base.name));
+        }
+        if !implements.is_empty() {
+            declaration.push_str(&format!(" implements {}", implements.join(", ")));
+        }
+        body.push_str(&format!("{} {{\n", declaration));
+
+        let mut fields = model.fields(class);
+        for parent in parents.iter().filter(|p| p.is_interface()) {
+            for field in model.fields(parent) {
+                if !fields.iter().any(|f| f.name == field.name) {
+                    fields.push(field);
+                }
+            }
+        }
+        for field in &fields {
+            // Fields are assigned by the caller, so they are declared definitely assigned
+            let (modifier, assigned) = if field.is_static { ("static ", "") } else { ("", "!") };
+            body.push_str(&format!(
+                "  {}{}{}{}: {};\n",
+                typescript_visibility(field.visibility),
+                modifier,
+                identifier(&field.name),
+                assigned,
+                Language::TypeScript.field_type(field.data_type.as_deref())
+            ));
+        }
+
+        // Declared methods, then those required by the parents
+        let declared = class.methods.iter().map(|m| (m, true));
+        let required = model.required_methods(class).into_iter().map(|(_, m)| (m, false));
+        for (method, own) in declared.chain(required) {
+            if !body.ends_with("{\n") {
+                body.push('\n');
+            }
+            let visibility = typescript_visibility(method.visibility);
+            let modifier = if method.is_static { "static " } else { "" };
+            if own && method.is_abstract && class.is_abstract() {
+                body.push_str(&format!("  {}abstract {};\n", visibility, typescript_signature(method)));
+            } else {
+                body.push_str(&format!(
+                    "  {}{}{} {{\n    throw new Error('Not implemented');\n  }}\n",
+                    visibility,
+                    modifier,
+                    typescript_signature(method)
+                ));
+            }
+        }
+        close_block(&mut body);
+    }
+
+    // Only extended classes are needed at runtime
+    let imports: Vec<String> = model
+        .referenced(class)
+        .into_iter()
+        .map(|other| {
+            let runtime = parents.iter().any(|p| p.name == other.name && !p.is_interface() && !class.is_interface());
+            let keyword = if runtime { "import" } else { "import type" };
+            format!("{} {{ {} }} from './{}';", keyword, other.name, other.name)
+        })
+        .collect();
+    let imports = if imports.is_empty() { String::new() } else { format!("{}\n\n", imports.join("\n")) };
+    format!("// {}\n\n{}{}", HEADER, imports, body)
+}
+
+// Python
+
+const PYTHON_KEYWORDS: [&str; 35] = [
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+    "elif", "else", "except", "finally", "for", "from", "global", "if", "import", "in", "is", "lambda", "nonlocal",
+    "not", "or", "pass", "raise", "return", "try", "while", "with", "yield",
+];
+
+/// Snake case name, with a leading `_` for private and protected members
+fn python_name(name: &str, visibility: Option<Visibility>) -> String {
+    let mut name = identifier(&snake_case(name));
+    if PYTHON_KEYWORDS.contains(&name.as_str()) {
+        name.push('_');
+    }
+    match visibility {
+        Some(Visibility::Private | Visibility::Protected) if !name.starts_with('_') => format!("_{}", name),
+        _ => name,
+    }
+}
+
+fn python_method(method: &Method, abstract_method: bool) -> String {
+    let mut decorators = String::new();
+    let mut parameters = Vec::new();
+    if method.is_static {
+        decorators.push_str("    @staticmethod\n");
+    } else {
+        parameters.push("self".to_string());
+    }
+    if abstract_method {
+        decorators.push_str("    @abstractmethod\n");
+    }
+    parameters.extend(method.parameters.iter().map(|p| {
+        let name = python_name(&p.name, None);
+        match &p.data_type {
+            Some(_) => format!("{}: {}", name, Language::Python.field_type(p.data_type.as_deref())),
+            None => name,
+        }
+    }));
+    let returns = Language::Python.return_type(method.return_type.as_deref()).unwrap_or_else(|| "None".to_string());
+    format!(
+        "{}    def {}({}) -> {}:\n        raise NotImplementedError\n",
+        decorators,
+        python_name(&method.name, method.visibility),
+        parameters.join(", "),
+        returns
+    )
+}
+
+fn python_file(model: &ClassModel, class: &Class) -> String {
+    let parents = model.parents(class);
+    let mut bases: Vec<String> = parents.iter().map(|p| p.name.clone()).collect();
+    let mut typing: Vec<&str> = Vec::new();
+    let mut imports: Vec<&str> = Vec::new();
+    let mut body = String::new();
+
+    if class.is_enum() {
+        imports.push("from enum import Enum");
+        body.push_str(&format!("class {}(Enum):\n", class.name));
+        for field in &class.fields {
+            body.push_str(&format!("    {} = \"{}\"\n", identifier(&field.name), field.name));
+        }
+    } else {
+        let interface = class.is_interface();
+        if interface || class.is_abstract() {
+            imports.push("from abc import ABC, abstractmethod");
+            bases.push("ABC".to_string());
+        }
+        if !class.generics.is_empty() {
+            typing.extend(["Generic", "TypeVar"]);
+            bases.push(generics(class, "Generic[", "]"));
+        }
+        if !interface {
+            imports.push("from dataclasses import dataclass");
+            body.push_str("@dataclass\n");
+        }
+        let bases = if bases.is_empty() { String::new() } else { format!("({})", bases.join(", ")) };
+        body.push_str(&format!("class {}{}:\n", class.name, bases));
+
+        let mut members = Vec::new();
+        for field in model.fields(class) {
+            let mut data_type = Language::Python.field_type(field.data_type.as_deref());
+            if field.data_type.is_none() {
+                typing.push("Any");
+            }
+            if field.is_static {
+                typing.push("ClassVar");
+                data_type = format!("ClassVar[{}]", data_type);
+            }
+            members.push(format!("    {}: {}\n", python_name(&field.name, field.visibility), data_type));
+        }
+        let fields_end = members.len();
+        for method in &class.methods {
+            members.push(python_method(method, interface || (method.is_abstract && class.is_abstract())));
+        }
+        for (_, method) in model.required_methods(class) {
+            members.push(python_method(method, false));
+        }
+
+        if members.is_empty() {
+            body.push_str("    pass\n");
+        }
+        for (i, member) in members.iter().enumerate() {
+            if i > 0 && i >= fields_end {
+                body.push('\n');
+            }
+            body.push_str(member);
+        }
+    }
+
+    let mut header = Vec::new();
+    if body.contains("datetime.") {
+        header.push("import datetime".to_string());
+    }
+    header.extend(imports.iter().map(|i| i.to_string()));
+    let (runtime, annotations): (Vec<&Class>, Vec<&Class>) =
+        model.referenced(class).into_iter().partition(|c| parents.iter().any(|p| p.name == c.name));
+    if !annotations.is_empty() {
+        typing.push("TYPE_CHECKING");
+    }
+    typing.sort();
+    typing.dedup();
+    if !typing.is_empty() {
+        header.push(format!("from typing import {}", typing.join(", ")));
+    }
+
+    let mut out = format!("\"\"\"{}\"\"\"\n\nfrom __future__ import annotations\n", HEADER);
+    if !header.is_empty() {
+        out.push_str(&format!("\n{}\n", header.join("\n")));
+    }
+    if !runtime.is_empty() {
+        out.push('\n');
+        for parent in &runtime {
+            out.push_str(&format!("from .{} import {}\n", snake_case(&parent.name), parent.name));
+        }
+    }
+    // Classes only used in annotations are imported for type checkers, avoiding import cycles
+    if !annotations.is_empty() {
+        out.push_str("\nif TYPE_CHECKING:\n");
+        for other in &annotations {
+            out.push_str(&format!("    from .{} import {}\n", snake_case(&other.name), other.name));
+        }
+    }
+    if !class.generics.is_empty() {
+        out.push('\n');
+        for generic in &class.generics {
+            out.push_str(&format!("{} = TypeVar(\"{}\")\n", generic, generic));
+        }
+    }
+    format!("{}\n\n{}", out, body)
+}
@@ -1,9 +1,27 @@
-use crate::models::{Workspace, Project, Section, Topic, Refinement};
+use crate::cache::{self, CacheStats, CachedResponse};
+use crate::codegen::{self, ClassModel, GeneratedFile, Language};
+use crate::diagnostics::{self, ConnectionDiagnosis, DiagnosisKind};
+use crate::diagram_diff::{self, DiagramChange};
+use crate::diff::{self, DiffLine, MergeResult, MergeStatus};
+use crate::echo;
+use crate::llm::{self, build_conversation, relay_stream, start_stream, LocalModel, Protocol, StreamEvent};
+use crate::mermaid::{self, MermaidReport};
+use crate::models::{self, Workspace, Project, Section, Topic, Refinement, PromptTemplate, ConversationThread, Diagram, DiagramVersion};
+use crate::prompts::{self, PromptTemplateEntry};
+use crate::restructure::{self, SplitChange, SplitPlan, StructuredPrompt};
+use crate::schema::{self, Dialect, ErModel};
+use crate::settings::{build_http_client, load_settings, write_settings, LlmProfile, LlmSettings};
 use crate::state::AppState;
+use crate::stories;
+use crate::usage::{find_price, TokenUsage, UsageRecord, UsageSummary};
+use crate::vault::{self, VaultStatus};
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
+use std::time::Duration;
 use tauri::State;
 use tauri::AppHandle;
+use tauri::Manager;
 /// Get the current platform/OS
 #[tauri::command]
 pub fn get_platform() -> String {
@@ -375,304 +393,158 @@ pub fn delete_project_refinement(
     Ok(())
 }
 
+/// Add a diagram of `kind` to the active project, named after its kind unless `name` is given
 #[tauri::command]
-pub fn save_project_er_diagram(
+pub fn create_diagram(
     state: State<AppState>,
-    er_diagram: String,
-) -> Result<(), String> {
+    kind: String,
+    name: Option<String>,
+    code: Option<String>,
+) -> Result<Diagram, String> {
+    known_kind(&kind)?;
+    let name = name
+        .map(|n| n.trim().to_string())
+        .filter(|n| !n.is_empty())
+        .unwrap_or_else(|| models::default_diagram_name(&kind));
+
     let mut workspace = state.workspace.lock().unwrap();
     let project = workspace.get_active_project_mut()
         .ok_or("No active project found".to_string())?;
 
-    project.er_diagram = Some(er_diagram);
-    project.updated_at = chrono::Utc::now().to_rfc3339();
+    let diagram = Diagram::new(kind, name, code.unwrap_or_default());
+    project.add_diagram(diagram.clone());
 
     if let Err(e) = save_workspace(&workspace, &state.data_dir) {
         return Err(format!("Failed to save workspace: {}", e));
     }
 
-    Ok(())
+    Ok(diagram)
 }
 
-use tauri::Emitter;
-use futures_util::StreamExt;
-
 #[tauri::command]
-pub async fn refine_with_llm_stream(app: tauri::AppHandle, content: String) -> Result<(), String> {
-    let settings = load_settings()?;
-    let client = reqwest::Client::new();
-    
-    // Determine protocol
-    let is_anthropic = settings.protocol.as_deref().unwrap_or("openai") == "anthropic" 
-        || (settings.protocol.is_none() && settings.provider == "anthropic");
-
-    let request_body = if !is_anthropic {
-        // OpenAI Format (Works for OpenAI, GLM, Gemini, etc.)
-        serde_json::json!({
-            "model": settings.model.unwrap_or("gpt-4".to_string()),
-            "stream": true,
-            "messages": [
-                {
-                    "role": "system",
-                    "content": "You are an expert at refining and improving prompts for software development projects. Your task is to take the user's prompt and make it clearer, more specific, and more effective while maintaining the original intent."
-                },
-                {
-                    "role": "user",
-                    "content": format!("Refine and improve the following prompt for a software development project:\n\n{}", content)
-                }
-            ]
-        })
-    } else {
-        // Anthropic Format
-        serde_json::json!({
-            "model": settings.model.unwrap_or("claude-3-sonnet-20240229".to_string()),
-            "max_tokens": 4096,
-            "stream": true,
-            "messages": [
-                {
-                    "role": "user",
-                    "content": format!("You are an expert at refining and improving prompts for software development projects. Your task is to take the user's prompt and make it clearer, more specific, and more effective while maintaining the original intent.\n\nRefine and improve the following prompt for a software development project:\n\n{}", content)
-                }
-            ]
-        })
-    };
-    
-    let url = if !is_anthropic {
-        format!("{}/chat/completions", settings.base_url.trim_end_matches('/'))
-    } else {
-        format!("{}/messages", settings.base_url.trim_end_matches('/'))
-    };
-    
-    // Construct request
-    let mut request_builder = client.post(&url)
-        .header("Content-Type", "application/json")
-        .json(&request_body);
-        
-    if is_anthropic {
-         request_builder = request_builder
-            .header("x-api-key", &settings.api_key)
-            .header("anthropic-version", "2023-06-01");
-    } else {
-         request_builder = request_builder.header("Authorization", format!("Bearer {}", settings.api_key));
+pub fn rename_diagram(state: State<AppState>, diagram_id: String, name: String) -> Result<(), String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Diagram name cannot be empty".to_string());
     }
 
-    // Send request
-    let response = request_builder
-        .send()
-        .await
-        .map_err(|e| format!("Failed to send request: {}", e))?;
-    
-    if !response.status().is_success() {
-        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(format!("LLM API error: {}", error_text));
-    }
+    let mut workspace = state.workspace.lock().unwrap();
+    let project = workspace.get_active_project_mut()
+        .ok_or("No active project found".to_string())?;
+    let diagram = project.get_diagram_mut(&diagram_id)
+        .ok_or(format!("Diagram with id {} not found", diagram_id))?;
 
-    // Spawn a task to handle streaming so we don't block
-    tauri::async_runtime::spawn(async move {
-        let mut stream = response.bytes_stream();
-        while let Some(item) = stream.next().await {
-            match item {
-                Ok(bytes) => {
-                    let chunk_str = String::from_utf8_lossy(&bytes);
-                    for line in chunk_str.lines() {
-                        let line = line.trim();
-                        if line.is_empty() { continue; }
-                        
-                        // Parse SSE
-                        if line.starts_with("data: ") {
-                            let data = &line[6..];
-                            if data == "[DONE]" {
-                                let _ = app.emit("refine:done", ());
-                                return;
-                            }
-                            
-                            if let Ok(json) = serde_json::from_str::<serde_json::Value>(data) {
-                                if !is_anthropic {
-                                    // OpenAI format
-                                    if let Some(content) = json["choices"][0]["delta"]["content"].as_str() {
-                                        let _ = app.emit("refine:chunk", content);
-                                    }
-                                } else {
-                                    // Anthropic format (data: { type: "content_block_delta", delta: { type: "text_delta", text: "..." } })
-                                    if json["type"] == "content_block_delta" {
-                                        if let Some(text) = json["delta"]["text"].as_str() {
-                                            let _ = app.emit("refine:chunk", text);
-                                        }
-                                    }
-                                    // Check for message_stop
-                                    if json["type"] == "message_stop" {
-                                        let _ = app.emit("refine:done", ());
-                                        return;
-                                    }
-                                }
-                            }
-                        } 
-                        // Anthropic sometimes sends event: ... lines, we mostly care about data:
-                    }
-                }
-                Err(e) => {
-                    let _ = app.emit("refine:error", format!("Stream error: {}", e));
-                    return;
-                }
-            }
-        }
-        let _ = app.emit("refine:done", ());
-    });
+    diagram.name = name.to_string();
+    diagram.updated_at = chrono::Utc::now().to_rfc3339();
+    project.updated_at = chrono::Utc::now().to_rfc3339();
+
+    if let Err(e) = save_workspace(&workspace, &state.data_dir) {
+        return Err(format!("Failed to save workspace: {}", e));
+    }
 
     Ok(())
 }
 
 #[tauri::command]
-pub async fn refine_er_diagram_with_llm_stream(app: tauri::AppHandle, content: String) -> Result<(), String> {
-    let settings = load_settings()?;
-    let client = reqwest::Client::new();
-    
-    // Determine protocol
-    let is_anthropic = settings.protocol.as_deref().unwrap_or("openai") == "anthropic" 
-        || (settings.protocol.is_none() && settings.provider == "anthropic");
+pub fn delete_diagram(state: State<AppState>, diagram_id: String) -> Result<(), String> {
+    let mut workspace = state.workspace.lock().unwrap();
+    let project = workspace.get_active_project_mut()
+        .ok_or("No active project found".to_string())?;
 
-    let system_prompt = "You are an expert software architect. Your task is to analyze the provided software project description and generate a Mermaid ER Diagram representing the data model.
-    
-Output ONLY the mermaid code block. Do not include markdown code fences (```mermaid). Do not include any explanation. Just the code.
-Start with `erDiagram`.
-    
-Example output:
-erDiagram
-    USER ||--o{ ORDER : places
-    USER {
-        string username
-        string email
+    project.remove_diagram(&diagram_id)?;
+
+    if let Err(e) = save_workspace(&workspace, &state.data_dir) {
+        return Err(format!("Failed to save workspace: {}", e));
     }
-    ORDER ||--|{ ORDER_ITEM : contains
-    ORDER {
-        int id
-        string deliveryAddress
-    }";
-
-    let request_body = if !is_anthropic {
-        // OpenAI Format
-        serde_json::json!({
-            "model": settings.model.unwrap_or("gpt-4".to_string()),
-            "stream": true,
-            "messages": [
-                {
-                    "role": "system",
-                    "content": system_prompt
-                },
-                {
-                    "role": "user",
-                    "content": format!("Generate a Mermaid ER Diagram for the following project description:\n\n{}", content)
-                }
-            ]
-        })
-    } else {
-        // Anthropic Format
-        serde_json::json!({
-            "model": settings.model.unwrap_or("claude-3-sonnet-20240229".to_string()),
-            "max_tokens": 4096,
-            "stream": true,
-            "messages": [
-                {
-                    "role": "user",
-                    "content": format!("{}\n\nGenerate a Mermaid ER Diagram for the following project description:\n\n{}", system_prompt, content)
-                }
-            ]
-        })
-    };
-    
-    let url = if !is_anthropic {
-        format!("{}/chat/completions", settings.base_url.trim_end_matches('/'))
-    } else {
-        format!("{}/messages", settings.base_url.trim_end_matches('/'))
+
+    Ok(())
+}
+
+/// Store the Mermaid code of one of the active project's diagrams
+#[tauri::command]
+pub fn save_project_diagram(
+    state: State<AppState>,
+    diagram_id: String,
+    code: String,
+    allow_invalid: Option<bool>,
+) -> Result<String, String> {
+    let kind = stored_diagram(&state, &diagram_id)?.kind;
+    let code = checked_diagram(&kind, code, allow_invalid)?;
+    store_diagram(&state, &kind, Some(&diagram_id), code, "save")
+}
+
+/// Store `code` in the diagram `diagram_id`, or without one in the first diagram of `kind`,
+/// which is created if the project has none. `source` is recorded with the new version.
+fn store_diagram(state: &AppState, kind: &str, diagram_id: Option<&str>, code: String, source: &str) -> Result<String, String> {
+    let mut workspace = state.workspace.lock().unwrap();
+    let project = workspace.get_active_project_mut()
+        .ok_or("No active project found".to_string())?;
+
+    let existing = match diagram_id {
+        Some(id) => Some(project.get_diagram_mut(id).ok_or(format!("Diagram with id {} not found", id))?),
+        None => project.diagrams.iter_mut().find(|d| d.kind == kind),
     };
-    
-    // Construct request
-    let mut request_builder = client.post(&url)
-        .header("Content-Type", "application/json")
-        .json(&request_body);
-        
-    if is_anthropic {
-         request_builder = request_builder
-            .header("x-api-key", &settings.api_key)
-            .header("anthropic-version", "2023-06-01");
-    } else {
-         request_builder = request_builder.header("Authorization", format!("Bearer {}", settings.api_key));
+    match existing {
+        Some(diagram) => diagram.save(code.clone(), source),
+        None => {
+            let mut diagram = Diagram::new(kind.to_string(), models::default_diagram_name(kind), String::new());
+            diagram.save(code.clone(), source);
+            project.diagrams.push(diagram);
+        }
     }
+    project.updated_at = chrono::Utc::now().to_rfc3339();
 
-    // Send request
-    let response = request_builder
-        .send()
-        .await
-        .map_err(|e| format!("Failed to send request: {}", e))?;
-    
-    if !response.status().is_success() {
-        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(format!("LLM API error: {}", error_text));
+    if let Err(e) = save_workspace(&workspace, &state.data_dir) {
+        return Err(format!("Failed to save workspace: {}", e));
     }
 
-    // Spawn a task to handle streaming so we don't block
-    tauri::async_runtime::spawn(async move {
-        let mut stream = response.bytes_stream();
-        while let Some(item) = stream.next().await {
-            match item {
-                Ok(bytes) => {
-                    let chunk_str = String::from_utf8_lossy(&bytes);
-                    for line in chunk_str.lines() {
-                        let line = line.trim();
-                        if line.is_empty() { continue; }
-                        
-                        // Parse SSE
-                        if line.starts_with("data: ") {
-                            let data = &line[6..];
-                            if data == "[DONE]" {
-                                let _ = app.emit("er:done", ());
-                                return;
-                            }
-                            
-                            if let Ok(json) = serde_json::from_str::<serde_json::Value>(data) {
-                                if !is_anthropic {
-                                    // OpenAI format
-                                    if let Some(content) = json["choices"][0]["delta"]["content"].as_str() {
-                                        let _ = app.emit("er:chunk", content);
-                                    }
-                                } else {
-                                    // Anthropic format
-                                    if json["type"] == "content_block_delta" {
-                                        if let Some(text) = json["delta"]["text"].as_str() {
-                                            let _ = app.emit("er:chunk", text);
-                                        }
-                                    }
-                                    if json["type"] == "message_stop" {
-                                        let _ = app.emit("er:done", ());
-                                        return;
-                                    }
-                                }
-                            }
-                        } 
-                    }
-                }
-                Err(e) => {
-                    let _ = app.emit("er:error", format!("Stream error: {}", e));
-                    return;
-                }
-            }
-        }
-        let _ = app.emit("er:done", ());
-    });
+    Ok(code)
+}
 
-    Ok(())
+/// Versions of one of the active project's diagrams, newest first
+#[tauri::command]
+pub fn list_diagram_versions(state: State<AppState>, diagram_id: String) -> Result<Vec<DiagramVersion>, String> {
+    let mut versions = stored_diagram(&state, &diagram_id)?.versions;
+    versions.reverse();
+    Ok(versions)
+}
+
+/// Line diff from one version of a diagram to another
+#[tauri::command]
+pub fn diff_diagram_versions(
+    state: State<AppState>,
+    diagram_id: String,
+    from_version_id: String,
+    to_version_id: String,
+) -> Result<Vec<DiffLine>, String> {
+    let diagram = stored_diagram(&state, &diagram_id)?;
+    let version = |id: &str| {
+        diagram.get_version(id)
+            .map(|v| v.code.as_str())
+            .ok_or(format!("Version with id {} not found", id))
+    };
+    Ok(diff::line_diff(version(&from_version_id)?, version(&to_version_id)?))
 }
 
+/// Name a version, which also keeps it from being dropped; an empty label removes the name
 #[tauri::command]
-pub fn save_project_uml_diagram(
+pub fn label_diagram_version(
     state: State<AppState>,
-    uml_diagram: String,
+    diagram_id: String,
+    version_id: String,
+    label: Option<String>,
 ) -> Result<(), String> {
     let mut workspace = state.workspace.lock().unwrap();
     let project = workspace.get_active_project_mut()
         .ok_or("No active project found".to_string())?;
-
-    project.uml_diagram = Some(uml_diagram);
+    let diagram = project.get_diagram_mut(&diagram_id)
+        .ok_or(format!("Diagram with id {} not found", diagram_id))?;
+    let version = diagram.versions
+        .iter_mut()
+        .find(|v| v.id == version_id)
+        .ok_or(format!("Version with id {} not found", version_id))?;
+
+    version.label = label.map(|l| l.trim().to_string()).filter(|l| !l.is_empty());
     project.updated_at = chrono::Utc::now().to_rfc3339();
 
     if let Err(e) = save_workspace(&workspace, &state.data_dir) {
@@ -682,1016 +554,1272 @@ pub fn save_project_uml_diagram(
     Ok(())
 }
 
+/// Restore the code of an earlier version; the restored code is recorded as a new version, so
+/// reverting can be undone
 #[tauri::command]
-pub async fn refine_uml_diagram_with_llm_stream(app: tauri::AppHandle, content: String) -> Result<(), String> {
-    let settings = load_settings()?;
-    let client = reqwest::Client::new();
-    
-    // Determine protocol
-    let is_anthropic = settings.protocol.as_deref().unwrap_or("openai") == "anthropic" 
-        || (settings.protocol.is_none() && settings.provider == "anthropic");
+pub fn revert_diagram(state: State<AppState>, diagram_id: String, version_id: String) -> Result<String, String> {
+    let diagram = stored_diagram(&state, &diagram_id)?;
+    let code = diagram.get_version(&version_id)
+        .map(|v| v.code.clone())
+        .ok_or(format!("Version with id {} not found", version_id))?;
+    store_diagram(&state, &diagram.kind, Some(&diagram_id), code, "revert")
+}
 
-    let system_prompt = "You are an expert software architect. Your task is to analyze the provided software project description and generate a Mermaid Class Diagram representing the architecture using the Repository Pattern.
-    
-Include the following layers where appropriate:
-- Interface
-- Repository
-- Service
-- Resource/Controller
-- DTO
-
-Output ONLY the mermaid code block. Do not include markdown code fences (```mermaid). Do not include any explanation. Just the code.
-Start with `classDiagram`.
-
-Example output:
-classDiagram
-    class UserRepository {
-        <<interface>>
-        +findById(id: Long) User
-        +save(user: User) User
-    }
-    class UserRepositoryImpl {
-        +findById(id: Long) User
-        +save(user: User) User
+/// A copy of one of the active project's diagrams
+fn stored_diagram(state: &AppState, diagram_id: &str) -> Result<Diagram, String> {
+    let workspace = state.workspace.lock().unwrap();
+    let project = workspace.get_active_project()
+        .ok_or("No active project found".to_string())?;
+    project.get_diagram(diagram_id)
+        .cloned()
+        .ok_or(format!("Diagram with id {} not found", diagram_id))
+}
+
+/// Code of the diagram `diagram_id`, which must be of `kind`, or of the project's first diagram
+/// of `kind`
+fn diagram_code(state: &AppState, kind: &str, diagram_id: Option<&str>) -> Result<Option<String>, String> {
+    match diagram_id {
+        Some(id) => {
+            let diagram = stored_diagram(state, id)?;
+            if diagram.kind != kind {
+                return Err(format!("Diagram {} is not a {} diagram", diagram.name, kind));
+            }
+            Ok(Some(diagram.code).filter(|code| !code.trim().is_empty()))
+        }
+        None => {
+            let workspace = state.workspace.lock().unwrap();
+            let project = workspace.get_active_project()
+                .ok_or("No active project found".to_string())?;
+            Ok(project.diagram(kind).map(str::to_string))
+        }
     }
-    class UserService {
-        -userRepository: UserRepository
-        +createUser(userDto: UserDTO) UserDTO
+}
+
+/// Kind and stored diagram targeted by an LLM command, given either a diagram kind or the ID
+/// of one of the project's diagrams
+fn diagram_target(state: &AppState, kind: Option<String>, diagram_id: Option<String>) -> Result<(String, Option<Diagram>), String> {
+    match (diagram_id, kind) {
+        (Some(diagram_id), _) => {
+            let diagram = stored_diagram(state, &diagram_id)?;
+            Ok((diagram.kind.clone(), Some(diagram)))
+        }
+        (None, Some(kind)) => Ok((known_kind(&kind)?.to_string(), None)),
+        (None, None) => Err("A diagram kind or diagram ID is required".to_string()),
     }
-    UserRepository <|.. UserRepositoryImpl
-    UserService --> UserRepository";
-
-    let request_body = if !is_anthropic {
-        // OpenAI Format
-        serde_json::json!({
-            "model": settings.model.unwrap_or("gpt-4".to_string()),
-            "stream": true,
-            "messages": [
-                {
-                    "role": "system",
-                    "content": system_prompt
-                },
-                {
-                    "role": "user",
-                    "content": format!("Generate a Mermaid Class Diagram (Repository Pattern) for the following project description:\n\n{}", content)
-                }
-            ]
-        })
-    } else {
-        // Anthropic Format
-        serde_json::json!({
-            "model": settings.model.unwrap_or("claude-3-sonnet-20240229".to_string()),
-            "max_tokens": 4096,
-            "stream": true,
-            "messages": [
-                {
-                    "role": "user",
-                    "content": format!("{}\n\nGenerate a Mermaid Class Diagram (Repository Pattern) for the following project description:\n\n{}", system_prompt, content)
-                }
-            ]
-        })
-    };
-    
-    let url = if !is_anthropic {
-        format!("{}/chat/completions", settings.base_url.trim_end_matches('/'))
-    } else {
-        format!("{}/messages", settings.base_url.trim_end_matches('/'))
-    };
-    
-    // Construct request
-    let mut request_builder = client.post(&url)
-        .header("Content-Type", "application/json")
-        .json(&request_body);
-        
-    if is_anthropic {
-         request_builder = request_builder
-            .header("x-api-key", &settings.api_key)
-            .header("anthropic-version", "2023-06-01");
+}
+
+fn known_kind(kind: &str) -> Result<&str, String> {
+    if mermaid::DIAGRAM_KINDS.contains(&kind) {
+        Ok(kind)
     } else {
-         request_builder = request_builder.header("Authorization", format!("Bearer {}", settings.api_key));
+        Err(format!("Unknown diagram kind: {}", kind))
     }
+}
 
-    // Send request
-    let response = request_builder
-        .send()
-        .await
-        .map_err(|e| format!("Failed to send request: {}", e))?;
-    
-    if !response.status().is_success() {
-        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(format!("LLM API error: {}", error_text));
+/// Clean and validate a diagram before it is stored. Drafts saved while typing
+/// (`allow_invalid`) are stored exactly as given.
+fn checked_diagram(kind: &str, code: String, allow_invalid: Option<bool>) -> Result<String, String> {
+    if allow_invalid.unwrap_or(false) || code.trim().is_empty() {
+        return Ok(code);
+    }
+    let report = mermaid::check(kind, &code)?;
+    if report.diagnostics.is_empty() {
+        return Ok(report.cleaned);
     }
+    let lines: Vec<String> = report.diagnostics.iter().map(|d| d.to_string()).collect();
+    Err(format!("Invalid diagram:\n{}", lines.join("\n")))
+}
 
-    // Spawn a task to handle streaming so we don't block
-    tauri::async_runtime::spawn(async move {
-        let mut stream = response.bytes_stream();
-        while let Some(item) = stream.next().await {
-            match item {
-                Ok(bytes) => {
-                    let chunk_str = String::from_utf8_lossy(&bytes);
-                    for line in chunk_str.lines() {
-                        let line = line.trim();
-                        if line.is_empty() { continue; }
-                        
-                        // Parse SSE
-                        if line.starts_with("data: ") {
-                            let data = &line[6..];
-                            if data == "[DONE]" {
-                                let _ = app.emit("uml:done", ());
-                                return;
-                            }
-                            
-                            if let Ok(json) = serde_json::from_str::<serde_json::Value>(data) {
-                                if !is_anthropic {
-                                    // OpenAI format
-                                    if let Some(content) = json["choices"][0]["delta"]["content"].as_str() {
-                                        let _ = app.emit("uml:chunk", content);
-                                    }
-                                } else {
-                                    // Anthropic format
-                                    if json["type"] == "content_block_delta" {
-                                        if let Some(text) = json["delta"]["text"].as_str() {
-                                            let _ = app.emit("uml:chunk", text);
-                                        }
-                                    }
-                                    if json["type"] == "message_stop" {
-                                        let _ = app.emit("uml:done", ());
-                                        return;
-                                    }
-                                }
-                            }
-                        } 
-                    }
-                }
-                Err(e) => {
-                    let _ = app.emit("uml:error", format!("Stream error: {}", e));
-                    return;
-                }
-            }
-        }
-        let _ = app.emit("uml:done", ());
-    });
+/// SQL DDL for one of the active project's ER diagrams (the first one without `diagram_id`) in
+/// `dialect` (`postgresql`, `mysql` or `sqlite`)
+#[tauri::command]
+pub fn generate_sql_from_er_diagram(
+    state: State<AppState>,
+    dialect: String,
+    diagram_id: Option<String>,
+) -> Result<String, String> {
+    let dialect = Dialect::parse(&dialect)?;
+    let er_diagram = diagram_code(&state, "er", diagram_id.as_deref())?
+        .ok_or("The project has no ER diagram yet".to_string())?;
+
+    let model = ErModel::from_mermaid(&er_diagram)?;
+    Ok(schema::to_ddl(&model, dialect))
+}
 
-    Ok(())
+/// Replace one of the active project's ER diagrams with the tables of a SQL DDL script
+#[tauri::command]
+pub fn import_er_diagram_from_sql(state: State<AppState>, sql: String, diagram_id: Option<String>) -> Result<String, String> {
+    // Only ER diagrams can take a schema
+    diagram_code(&state, "er", diagram_id.as_deref())?;
+    let model = schema::parse_ddl(&sql)?;
+    store_diagram(&state, "er", diagram_id.as_deref(), model.to_mermaid(), "import")
 }
 
+/// Replace one of the active project's ER diagrams with the schema of a local SQLite database
 #[tauri::command]
-pub async fn refine_with_llm(_content: String) -> Result<String, String> {
-    // Legacy implementation kept for fallback if needed, or redirect to stream?
-    // For now, let's keep it simple and just return error saying to use stream,
-    // or keep the old implementation as is. 
-    // Actually, let's keep the old implementation as a fallback for now.
-    let _settings = load_settings()?;
-    // ... (rest of the old implementation would go here, but for brevity/cleanliness, 
-    // I am effectively replacing the old function with the streaming one in the frontend usage.
-    // The user might still call this, so I will keep a minimal version or error out).
-    
-    Err("Please use refine_with_llm_stream".to_string())
+pub fn import_er_diagram_from_sqlite(state: State<AppState>, path: String, diagram_id: Option<String>) -> Result<String, String> {
+    // Only ER diagrams can take a schema
+    diagram_code(&state, "er", diagram_id.as_deref())?;
+    let model = schema::read_sqlite(std::path::Path::new(path.trim()))?;
+    store_diagram(&state, "er", diagram_id.as_deref(), model.to_mermaid(), "import")
 }
 
-fn save_workspace(workspace: &Workspace, data_dir: &PathBuf) -> Result<(), String> {
-    let workspace_path = data_dir.join("workspace.json");
-    
-    let workspace_json = serde_json::to_string_pretty(workspace)
-        .map_err(|e| format!("Failed to serialize workspace: {}", e))?;
-    
-    fs::write(workspace_path, workspace_json)
-        .map_err(|e| format!("Failed to write workspace file: {}", e))?;
-    
-    Ok(())
+/// Code skeleton files for one of the active project's class diagrams (the first one without
+/// `diagram_id`) in `language` (`rust`, `typescript` or `python`)
+#[tauri::command]
+pub fn generate_code_from_uml_diagram(
+    state: State<AppState>,
+    language: String,
+    diagram_id: Option<String>,
+) -> Result<Vec<GeneratedFile>, String> {
+    let language = Language::parse(&language)?;
+    let uml_diagram = diagram_code(&state, "uml", diagram_id.as_deref())?
+        .ok_or("The project has no class diagram yet".to_string())?;
+
+    let model = ClassModel::from_mermaid(&uml_diagram)?;
+    Ok(codegen::generate(&model, language))
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct LlmSettings {
-    pub provider: String,
-    pub api_key: String,
-    pub base_url: String,
-    pub model: Option<String>,
-    pub protocol: Option<String>,
+/// Strip fences and surrounding prose from `code` and report syntax problems
+#[tauri::command]
+pub fn validate_mermaid(kind: String, code: String) -> Result<MermaidReport, String> {
+    mermaid::check(&kind, &code)
 }
 
+/// Entities, attributes, edges and labels an edit of an ER, class or flowchart diagram would
+/// add, remove, rename or change
 #[tauri::command]
-pub fn get_llm_settings() -> Result<LlmSettings, String> {
-    load_settings()
+pub fn diff_diagram_edit(kind: String, current: String, proposed: String) -> Result<Vec<DiagramChange>, String> {
+    diagram_diff::diff(&kind, &current, &proposed)
 }
 
+/// `current` with the reviewed `changes` from `diff_diagram_edit` taken over from `proposed`
 #[tauri::command]
-pub fn save_llm_settings(settings: LlmSettings) -> Result<(), String> {
-    let home_dir = dirs::home_dir()
-        .ok_or("Failed to get home directory")?;
-    let settings_dir = home_dir.join(".promptmux");
-    let settings_path = settings_dir.join("settings.json");
-    
-    if !settings_dir.exists() {
-        fs::create_dir_all(&settings_dir)
-            .map_err(|e| format!("Failed to create settings directory: {}", e))?;
-    }
-    
-    let settings_json = serde_json::to_string_pretty(&settings)
-        .map_err(|e| format!("Failed to serialize settings: {}", e))?;
-        
-    fs::write(settings_path, settings_json)
-        .map_err(|e| format!("Failed to write settings file: {}", e))?;
-        
-    Ok(())
+pub fn apply_diagram_edit(
+    kind: String,
+    current: String,
+    proposed: String,
+    changes: Vec<DiagramChange>,
+) -> Result<String, String> {
+    diagram_diff::apply(&kind, &current, &proposed, &changes)
 }
 
-fn load_settings() -> Result<LlmSettings, String> {
-    let settings_path = dirs::home_dir()
-        .ok_or("Failed to get home directory")?
-        .join(".promptmux")
-        .join("settings.json");
-    
-    if !settings_path.exists() {
-        // Return default settings if not found
-        return Ok(LlmSettings {
-            provider: "openai".to_string(),
-            api_key: "".to_string(),
-            base_url: "https://api.openai.com/v1".to_string(),
-            model: Some("gpt-4".to_string()),
-            protocol: Some("openai".to_string()),
-        });
+use tauri::Emitter;
+
+#[tauri::command]
+pub async fn refine_with_llm_stream(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    content: String,
+    bypass_cache: Option<bool>,
+    thread_id: Option<String>,
+) -> Result<(), String> {
+    perform_llm_stream(app, &state, "refine", &[("content", &content)], bypass_cache.unwrap_or(false), thread_id).await
+}
+
+/// Refine one topic with the project outline and its neighboring topics as context, streaming
+/// as `refine_topic:*` events. Call `accept_refinement` to record the result.
+#[tauri::command]
+pub async fn refine_topic_with_llm_stream(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    topic_id: String,
+    bypass_cache: Option<bool>,
+    thread_id: Option<String>,
+) -> Result<(), String> {
+    let (project_name, section_name, topic_name, context, content) = {
+        let workspace = state.workspace.lock().unwrap();
+        let project = workspace.get_active_project()
+            .ok_or("No active project found".to_string())?;
+        let topic = project.get_topic(&topic_id)
+            .ok_or(format!("Topic with id {} not found", topic_id))?;
+        let section_name = project.get_section(&topic.section_id)
+            .map(|s| s.name.clone())
+            .unwrap_or_default();
+        let context = project.topic_context(&topic_id).unwrap_or_default();
+        (project.name.clone(), section_name, topic.name.clone(), context, topic.content.clone())
+    };
+    if content.trim().is_empty() {
+        return Err("No content to refine".to_string());
     }
-    
-    let settings_content = fs::read_to_string(settings_path)
-        .map_err(|e| format!("Failed to read settings file: {}", e))?;
-    
-    let mut settings: LlmSettings = serde_json::from_str(&settings_content)
-        .map_err(|e| format!("Failed to parse settings file: {}", e))?;
 
-    // Backfill protocol if missing
-    if settings.protocol.is_none() {
-        settings.protocol = Some(if settings.provider == "anthropic" {
-            "anthropic".to_string()
-        } else {
-            "openai".to_string()
-        });
+    state.refinement_drafts.lock().unwrap().insert(topic_id, content.clone());
+    perform_llm_stream(
+        app,
+        &state,
+        "refine_topic",
+        &[
+            ("project_name", &project_name),
+            ("section_name", &section_name),
+            ("topic_name", &topic_name),
+            ("context", &context),
+            ("content", &content),
+        ],
+        bypass_cache.unwrap_or(false),
+        thread_id,
+    )
+    .await
+}
+
+/// Refine the topics of one section together, with the project outline and its neighboring
+/// sections as context, streaming as `refine_section:*` events
+#[tauri::command]
+pub async fn refine_section_with_llm_stream(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    section_id: String,
+    bypass_cache: Option<bool>,
+    thread_id: Option<String>,
+) -> Result<(), String> {
+    let (project_name, section_name, context, content) = {
+        let workspace = state.workspace.lock().unwrap();
+        let project = workspace.get_active_project()
+            .ok_or("No active project found".to_string())?;
+        let section = project.get_section(&section_id)
+            .ok_or(format!("Section with id {} not found", section_id))?;
+        let context = project.section_context(&section_id).unwrap_or_default();
+        (project.name.clone(), section.name.clone(), context, section.get_content())
+    };
+    if content.trim().is_empty() {
+        return Err("No content to refine".to_string());
     }
-    
-    Ok(settings)
+
+    state.refinement_drafts.lock().unwrap().insert(section_id, content.clone());
+    perform_llm_stream(
+        app,
+        &state,
+        "refine_section",
+        &[
+            ("project_name", &project_name),
+            ("section_name", &section_name),
+            ("context", &context),
+            ("content", &content),
+        ],
+        bypass_cache.unwrap_or(false),
+        thread_id,
+    )
+    .await
 }
 
+/// Record the accepted result of the last topic or section refinement in that entity's history,
+/// with the content that was sent as the original. `refined_content` is taken from the caller
+/// because the streamed answer may have been edited before accepting.
 #[tauri::command]
-pub fn save_project_flowchart(
+pub fn accept_refinement(
     state: State<AppState>,
-    flowchart: String,
-) -> Result<(), String> {
+    target_type: String,
+    target_id: String,
+    refined_content: String,
+) -> Result<Refinement, String> {
+    let original_content = state.refinement_drafts.lock().unwrap()
+        .get(&target_id)
+        .cloned()
+        .ok_or(format!("No refinement in progress for {}", target_id))?;
+
+    let refinement = Refinement {
+        id: uuid::Uuid::new_v4().to_string(),
+        original_content,
+        refined_content,
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        kind: Some("text".to_string()),
+        mode: None,
+        diagram_id: None,
+    };
+
     let mut workspace = state.workspace.lock().unwrap();
     let project = workspace.get_active_project_mut()
         .ok_or("No active project found".to_string())?;
 
-    project.flowchart = Some(flowchart);
+    match target_type.as_str() {
+        "topic" => project.get_topic_mut(&target_id)
+            .ok_or(format!("Topic with id {} not found", target_id))?
+            .add_refinement(refinement.clone()),
+        "section" => project.get_section_mut(&target_id)
+            .ok_or(format!("Section with id {} not found", target_id))?
+            .add_refinement(refinement.clone()),
+        _ => return Err(format!("Invalid refinement target: {}", target_type)),
+    }
     project.updated_at = chrono::Utc::now().to_rfc3339();
 
     if let Err(e) = save_workspace(&workspace, &state.data_dir) {
         return Err(format!("Failed to save workspace: {}", e));
     }
 
-    Ok(())
+    state.refinement_drafts.lock().unwrap().remove(&target_id);
+    Ok(refinement)
 }
 
+/// Apply a refinement from a topic's history to its content. If the topic was edited after the
+/// refinement's original was captured, nothing is written; the result holds a three-way merge of
+/// the edits and the refinement for the user to review and save.
 #[tauri::command]
-pub async fn refine_flowchart_with_llm_stream(app: tauri::AppHandle, content: String) -> Result<(), String> {
-    let settings = load_settings()?;
-    let client = reqwest::Client::new();
-    
-    // Determine protocol
-    let is_anthropic = settings.protocol.as_deref().unwrap_or("openai") == "anthropic" 
-        || (settings.protocol.is_none() && settings.provider == "anthropic");
-
-    let system_prompt = "You are an expert software architect. Your task is to analyze the provided software project description and generate a detailed Mermaid Flowchart representing the system logic, data flow, and key processes.
-
-Guidelines:
-1. Syntax: Start with `graph TD` (Top-Down) or `graph LR` (Left-Right) as appropriate for the flow.
-2. Shapes: Use standard flowchart shapes to represent different elements clearly:
-   - `[\"Action/Process\"]` for standard steps (Square).
-   - `{\"Decision?\"}` for logic checks and branching (Diamond).
-   - `([\"Start/End\"])` for entry and exit points (Rounded).
-   - `[(\"Database\")]` for data storage interactions (Cylinder).
-   - `[[\"Subroutine/Module\"]]` for complex components (Double border).
-3. Text Labels: **CRITICAL** - ALWAYS enclose the text inside shapes in double quotes.
-   - CORRECT: `A[\"User Request (HTTP)\"]`, `B{\"Is Valid?\"}`
-   - INCORRECT: `A[User Request (HTTP)]`, `B{Is Valid?}`
-   - This prevents syntax errors from special characters like `(`, `)`, `,`, etc.
-4. Structure: 
-   - Use `subgraph` to group related components (e.g., `subgraph Frontend`, `subgraph Backend`).
-   - Ensure arrows `-->` are clearly labeled with logic or data passed (e.g., `-->|\"Valid\"|`, `-->|\"Data\"|`).
-5. Clarity: Keep the chart legible. Avoid excessive crossing lines.
-
-Output Requirement:
-- Output ONLY the raw Mermaid code.
-- Do NOT include markdown code fences (```mermaid).
-- Do NOT include any explanations.
-- The output must start directly with `graph` or `flowchart`.";
-
-    let request_body = if !is_anthropic {
-        // OpenAI Format
-        serde_json::json!({
-            "model": settings.model.unwrap_or("gpt-4".to_string()),
-            "stream": true,
-            "messages": [
-                {
-                    "role": "system",
-                    "content": system_prompt
-                },
-                {
-                    "role": "user",
-                    "content": format!("Generate a Mermaid Flowchart for the following project description:\n\n{}", content)
-                }
-            ]
-        })
-    } else {
-        // Anthropic Format
-        serde_json::json!({
-            "model": settings.model.unwrap_or("claude-3-sonnet-20240229".to_string()),
-            "max_tokens": 4096,
-            "stream": true,
-            "messages": [
-                {
-                    "role": "user",
-                    "content": format!("{}\n\nGenerate a Mermaid Flowchart for the following project description:\n\n{}", system_prompt, content)
-                }
-            ]
-        })
-    };
-    
-    let url = if !is_anthropic {
-        format!("{}/chat/completions", settings.base_url.trim_end_matches('/'))
-    } else {
-        format!("{}/messages", settings.base_url.trim_end_matches('/'))
-    };
-    
-    // Construct request
-    let mut request_builder = client.post(&url)
-        .header("Content-Type", "application/json")
-        .json(&request_body);
-        
-    if is_anthropic {
-         request_builder = request_builder
-            .header("x-api-key", &settings.api_key)
-            .header("anthropic-version", "2023-06-01");
-    } else {
-         request_builder = request_builder.header("Authorization", format!("Bearer {}", settings.api_key));
-    }
-
-    // Send request
-    let response = request_builder
-        .send()
-        .await
-        .map_err(|e| format!("Failed to send request: {}", e))?;
-    
-    if !response.status().is_success() {
-        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(format!("LLM API error: {}", error_text));
-    }
+pub fn apply_refinement(
+    state: State<AppState>,
+    topic_id: String,
+    refinement_id: String,
+) -> Result<MergeResult, String> {
+    let mut workspace = state.workspace.lock().unwrap();
+    let project = workspace.get_active_project_mut()
+        .ok_or("No active project found".to_string())?;
+    let topic = project.get_topic_mut(&topic_id)
+        .ok_or(format!("Topic with id {} not found", topic_id))?;
+    let refinement = topic.history
+        .iter()
+        .find(|r| r.id == refinement_id)
+        .ok_or(format!("Refinement with id {} not found", refinement_id))?;
+
+    let result = diff::merge_refinement(&refinement.original_content, &topic.content, &refinement.refined_content);
+    if result.status == MergeStatus::Applied {
+        topic.content = result.content.clone();
+        project.updated_at = chrono::Utc::now().to_rfc3339();
 
-    // Spawn a task to handle streaming so we don't block
-    tauri::async_runtime::spawn(async move {
-        let mut stream = response.bytes_stream();
-        while let Some(item) = stream.next().await {
-            match item {
-                Ok(bytes) => {
-                    let chunk_str = String::from_utf8_lossy(&bytes);
-                    for line in chunk_str.lines() {
-                        let line = line.trim();
-                        if line.is_empty() { continue; }
-                        
-                        // Parse SSE
-                        if line.starts_with("data: ") {
-                            let data = &line[6..];
-                            if data == "[DONE]" {
-                                let _ = app.emit("flowchart:done", ());
-                                return;
-                            }
-                            
-                            if let Ok(json) = serde_json::from_str::<serde_json::Value>(data) {
-                                if !is_anthropic {
-                                    if let Some(content) = json["choices"][0]["delta"]["content"].as_str() {
-                                        let _ = app.emit("flowchart:chunk", content);
-                                    }
-                                } else {
-                                    if json["type"] == "content_block_delta" {
-                                        if let Some(text) = json["delta"]["text"].as_str() {
-                                            let _ = app.emit("flowchart:chunk", text);
-                                        }
-                                    }
-                                    if json["type"] == "message_stop" {
-                                        let _ = app.emit("flowchart:done", ());
-                                        return;
-                                    }
-                                }
-                            }
-                        } 
-                    }
-                }
-                Err(e) => {
-                    let _ = app.emit("flowchart:error", format!("Stream error: {}", e));
-                    return;
-                }
-            }
+        if let Err(e) = save_workspace(&workspace, &state.data_dir) {
+            return Err(format!("Failed to save workspace: {}", e));
         }
-        let _ = app.emit("flowchart:done", ());
-    });
+    }
 
-    Ok(())
+    Ok(result)
 }
 
+/// Refine the whole project as JSON sections and topics, streaming as `refine_structured:*`
+/// events, so `split_merged_output` can map the answer back without guessing topic boundaries
 #[tauri::command]
-pub async fn save_project_user_journey(
+pub async fn refine_structured_with_llm_stream(
+    app: AppHandle,
     state: State<'_, AppState>,
-    project_id: String,
-    content: String,
+    bypass_cache: Option<bool>,
+    thread_id: Option<String>,
 ) -> Result<(), String> {
-    let mut workspace = state.workspace.lock().map_err(|e| e.to_string())?;
-    
-    if let Some(project) = workspace.get_project_mut(&project_id) {
-        project.user_journey = Some(content);
-        project.updated_at = chrono::Utc::now().to_rfc3339();
-    } else {
-        return Err("Project not found".to_string());
-    }
-    
-    // Save to file
+    let structure = {
+        let workspace = state.workspace.lock().unwrap();
+        let project = workspace.get_active_project()
+            .ok_or("No active project found".to_string())?;
+        StructuredPrompt::from_project(project)
+    };
+    let content = serde_json::to_string_pretty(&structure)
+        .map_err(|e| format!("Failed to serialize project structure: {}", e))?;
+
+    perform_llm_stream(
+        app,
+        &state,
+        "refine_structured",
+        &[("content", &content)],
+        bypass_cache.unwrap_or(false),
+        thread_id,
+    )
+    .await
+}
+
+/// Parse a refined merged output (`// Section:` markers or structured JSON) and report how it
+/// differs from the active project's sections and topics. Nothing is written.
+#[tauri::command]
+pub fn split_merged_output(state: State<AppState>, content: String) -> Result<SplitPlan, String> {
+    let refined = restructure::parse_refined(&content)?;
+    let workspace = state.workspace.lock().unwrap();
+    let project = workspace.get_active_project()
+        .ok_or("No active project found".to_string())?;
+    Ok(restructure::plan_split(project, &refined))
+}
+
+/// Write the reviewed `changes` from `split_merged_output` into the active project
+#[tauri::command]
+pub fn apply_merged_split(state: State<AppState>, changes: Vec<SplitChange>) -> Result<Project, String> {
+    let mut workspace = state.workspace.lock().unwrap();
+    let project = workspace.get_active_project_mut()
+        .ok_or("No active project found".to_string())?;
+
+    // Applied to a copy, so a change that fails leaves the project as it was
+    *project = restructure::apply_split(project, &changes)?;
+    let project = project.clone();
+
     if let Err(e) = save_workspace(&workspace, &state.data_dir) {
         return Err(format!("Failed to save workspace: {}", e));
     }
-    
-    Ok(())
-}
 
+    Ok(project)
+}
+
+/// Generate a diagram from `content`, streaming as `<kind>:*` events. The kind is given
+/// directly or taken from the project diagram `diagram_id`.
 #[tauri::command]
-pub async fn save_project_user_stories(
+#[allow(clippy::too_many_arguments)]
+pub async fn generate_diagram_with_llm_stream(
+    app: AppHandle,
     state: State<'_, AppState>,
-    project_id: String,
+    kind: Option<String>,
+    diagram_id: Option<String>,
     content: String,
+    bypass_cache: Option<bool>,
+    thread_id: Option<String>,
+    auto_repair: Option<bool>,
 ) -> Result<(), String> {
-    let mut workspace = state.workspace.lock().map_err(|e| e.to_string())?;
+    let (kind, _) = diagram_target(&state, kind, diagram_id)?;
+    perform_diagram_stream(
+        app,
+        &state,
+        &kind,
+        &[("content", &content)],
+        bypass_cache.unwrap_or(false),
+        thread_id,
+        auto_repair.unwrap_or(false),
+    )
+    .await
+}
+
+#[tauri::command]
+pub async fn refine_with_llm(_content: String) -> Result<String, String> {
+    // Legacy implementation kept for fallback if needed, or redirect to stream?
+    // For now, let's keep it simple and just return error saying to use stream,
+    // or keep the old implementation as is. 
+    // Actually, let's keep the old implementation as a fallback for now.
+    let _settings = load_settings()?;
+    // ... (rest of the old implementation would go here, but for brevity/cleanliness, 
+    // I am effectively replacing the old function with the streaming one in the frontend usage.
+    // The user might still call this, so I will keep a minimal version or error out).
     
-    if let Some(project) = workspace.get_project_mut(&project_id) {
-        project.user_stories = Some(content);
-        project.updated_at = chrono::Utc::now().to_rfc3339();
-    } else {
-        return Err("Project not found".to_string());
-    }
+    Err("Please use refine_with_llm_stream".to_string())
+}
+
+fn save_workspace(workspace: &Workspace, data_dir: &PathBuf) -> Result<(), String> {
+    let workspace_path = data_dir.join("workspace.json");
     
-    // Save to file
+    let workspace_json = serde_json::to_string_pretty(workspace)
+        .map_err(|e| format!("Failed to serialize workspace: {}", e))?;
+    
+    fs::write(workspace_path, workspace_json)
+        .map_err(|e| format!("Failed to write workspace file: {}", e))?;
+    
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_llm_settings(state: State<'_, AppState>) -> Result<LlmSettings, String> {
+    let mut settings = load_settings()?;
+    let client = state.http_client.lock().unwrap().clone();
+
+    for profile in settings.profiles.iter_mut() {
+        if Protocol::for_profile(profile) == Protocol::Ollama {
+            // An unreachable server only means there is nothing to suggest in the settings modal
+            profile.available_models = llm::list_ollama_models(&client, &profile.base_url)
+                .await
+                .unwrap_or_default();
+        }
+    }
+
+    Ok(settings)
+}
+
+/// Check unsaved settings against the provider with a one-token request. Keys that were not
+/// retyped are taken from the vault, so a saved profile can be re-tested as is.
+#[tauri::command]
+pub async fn test_llm_settings(
+    state: State<'_, AppState>,
+    settings: LlmSettings,
+    profile_name: Option<String>,
+) -> Result<ConnectionDiagnosis, String> {
+    let name = profile_name.unwrap_or_else(|| settings.active_profile.clone());
+    let mut profile = settings.get_profile(&name)
+        .cloned()
+        .ok_or(format!("Profile '{}' does not exist", name))?;
+
+    if profile.api_key.is_empty() {
+        if let Err(e) = vault::resolve_api_key(&state.vault.lock().unwrap(), &mut profile) {
+            return Ok(ConnectionDiagnosis::new(DiagnosisKind::VaultLocked, e));
+        }
+    }
+
+    let client = match build_http_client(&settings) {
+        Ok(client) => client,
+        Err(e) => return Ok(ConnectionDiagnosis::new(DiagnosisKind::InvalidSettings, e)),
+    };
+
+    Ok(diagnostics::test_connection(&client, &profile).await)
+}
+
+/// Models installed in the Ollama server at `base_url`, with their context length
+#[tauri::command]
+pub async fn list_ollama_models(
+    state: State<'_, AppState>,
+    base_url: String,
+) -> Result<Vec<LocalModel>, String> {
+    let client = state.http_client.lock().unwrap().clone();
+    llm::list_ollama_models(&client, &base_url).await
+}
+
+#[tauri::command]
+pub fn save_llm_settings(state: State<AppState>, mut settings: LlmSettings) -> Result<(), String> {
+    settings.validate()?;
+    // Build the client first so an invalid proxy, CA bundle or header is rejected before saving
+    let http_client = build_http_client(&settings)?;
+
+    // Profiles whose key was not re-entered keep their apiKeyRef and therefore their stored key
+    vault::secure_api_keys(&mut state.vault.lock().unwrap(), &mut settings)?;
+    write_settings(&settings)?;
+
+    *state.http_client.lock().unwrap() = http_client;
+    state.cache.lock().unwrap().set_max_mb(
+        settings.response_cache_mb.unwrap_or(cache::DEFAULT_CACHE_MAX_MB),
+    )?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_response_cache_stats(state: State<AppState>) -> Result<CacheStats, String> {
+    Ok(state.cache.lock().unwrap().stats())
+}
+
+#[tauri::command]
+pub fn clear_response_cache(state: State<AppState>) -> Result<CacheStats, String> {
+    let mut cache = state.cache.lock().unwrap();
+    cache.clear()?;
+    Ok(cache.stats())
+}
+
+#[tauri::command]
+pub fn get_vault_status(state: State<AppState>) -> Result<VaultStatus, String> {
+    Ok(state.vault.lock().unwrap().status())
+}
+
+/// Create the API key vault, keyed by the OS keyring when no passphrase is given
+#[tauri::command]
+pub fn create_vault(state: State<AppState>, passphrase: Option<String>) -> Result<VaultStatus, String> {
+    let mut vault = state.vault.lock().unwrap();
+    vault.create(passphrase.as_deref())?;
+    migrate_plaintext_api_keys(&mut vault)?;
+    Ok(vault.status())
+}
+
+#[tauri::command]
+pub fn unlock_vault(state: State<AppState>, passphrase: String) -> Result<VaultStatus, String> {
+    let mut vault = state.vault.lock().unwrap();
+    vault.unlock(&passphrase)?;
+    migrate_plaintext_api_keys(&mut vault)?;
+    Ok(vault.status())
+}
+
+#[tauri::command]
+pub fn lock_vault(state: State<AppState>) -> Result<VaultStatus, String> {
+    let mut vault = state.vault.lock().unwrap();
+    vault.lock();
+    Ok(vault.status())
+}
+
+/// Recorded LLM calls, optionally limited to one project
+#[tauri::command]
+pub fn get_usage_records(
+    state: State<AppState>,
+    project_id: Option<String>,
+) -> Result<Vec<UsageRecord>, String> {
+    Ok(state.usage.lock().unwrap().records(project_id.as_deref()))
+}
+
+/// Token and cost totals grouped by "day", "project" or "task"
+#[tauri::command]
+pub fn get_usage_summary(
+    state: State<AppState>,
+    group_by: String,
+    project_id: Option<String>,
+) -> Result<Vec<UsageSummary>, String> {
+    state.usage.lock().unwrap().summarize(&group_by, project_id.as_deref())
+}
+
+/// All prompt tasks with their default, overrides and effective template.
+/// `project_id` defaults to the active project.
+#[tauri::command]
+pub fn get_prompt_templates(
+    state: State<AppState>,
+    project_id: Option<String>,
+) -> Result<Vec<PromptTemplateEntry>, String> {
+    let workspace = state.workspace.lock().unwrap();
+    let project_id = project_id.unwrap_or_else(|| workspace.active_project_id.clone());
+    Ok(prompts::list(&workspace, Some(&project_id)))
+}
+
+/// Override the template for `task` in the "workspace" or "project" scope
+#[tauri::command]
+pub fn save_prompt_template(
+    state: State<AppState>,
+    scope: String,
+    task: String,
+    template: PromptTemplate,
+    project_id: Option<String>,
+) -> Result<(), String> {
+    if prompts::default_template(&task).is_none() {
+        return Err(format!("Unknown prompt task: {}", task));
+    }
+
+    let mut workspace = state.workspace.lock().unwrap();
+    prompt_templates_for_scope(&mut workspace, &scope, project_id)?.insert(task, template);
+    workspace.updated_at = chrono::Utc::now().to_rfc3339();
+
     if let Err(e) = save_workspace(&workspace, &state.data_dir) {
         return Err(format!("Failed to save workspace: {}", e));
     }
-    
+
     Ok(())
 }
 
+/// Remove the override for `task` in the given scope, falling back to the next level
 #[tauri::command]
-pub async fn refine_user_journey_with_llm_stream(
-    app: AppHandle,
-    _state: State<'_, AppState>, // State not needed if we load settings from file via helper, but kept for signature consistency if needed
-    content: String,
+pub fn reset_prompt_template(
+    state: State<AppState>,
+    scope: String,
+    task: String,
+    project_id: Option<String>,
 ) -> Result<(), String> {
-    let settings = load_settings()?;
-    
-    let is_anthropic = settings.provider == "anthropic";
-    
-    // Create client
-    let client = reqwest::Client::new();
-    
-    let system_prompt = "You are an expert UX designer and Product Manager. Your task is to analyze the provided software project description and generate a Mermaid User Journey Map (`journey`) that visualizes the user's experience.
-
-Guidelines:
-1. Syntax: Start with `journey`.
-2. Structure:
-   - title: A clear title for the journey.
-   - section: Group actions into logical phases (e.g., `section Onboarding`, `section Usage`, `section Support`).
-   - Task format: `Task name: Score: Actor1, Actor2`
-     - Score: 1-5 (1=bad, 5=delight)
-     - Actors: Who is performing the action (e.g., `User`, `System`, `Admin`).
-3. Content:
-   - Focus on the key interactions derived from the project description.
-   - Ensure the flow is logical and covers the main value proposition.
-
-Output Requirement:
-- Output ONLY the raw Mermaid code.
-- Do NOT include markdown code fences (```mermaid).
-- Do NOT include any explanations.
-- The output must start directly with `journey`.";
-
-    let request_body = if !is_anthropic {
-        // OpenAI Format
-        serde_json::json!({
-            "model": settings.model.unwrap_or("gpt-4".to_string()),
-            "stream": true,
-            "messages": [
-                {
-                    "role": "system",
-                    "content": system_prompt
-                },
-                {
-                    "role": "user",
-                    "content": format!("Generate a Mermaid User Journey for the following project description:\n\n{}", content)
-                }
-            ]
-        })
-    } else {
-        // Anthropic Format
-        serde_json::json!({
-            "model": settings.model.unwrap_or("claude-3-sonnet-20240229".to_string()),
-            "max_tokens": 4096,
-            "stream": true,
-            "messages": [
-                {
-                    "role": "user",
-                    "content": format!("{}\n\nGenerate a Mermaid User Journey for the following project description:\n\n{}", system_prompt, content)
-                }
-            ]
-        })
-    };
-    
-    let url = if !is_anthropic {
-        format!("{}/chat/completions", settings.base_url.trim_end_matches('/'))
-    } else {
-        format!("{}/messages", settings.base_url.trim_end_matches('/'))
-    };
-    
-    // Construct request
-    let mut request_builder = client.post(&url)
-        .header("Content-Type", "application/json")
-        .json(&request_body);
-        
-    if is_anthropic {
-         request_builder = request_builder
-            .header("x-api-key", &settings.api_key)
-            .header("anthropic-version", "2023-06-01");
-    } else {
-         request_builder = request_builder.header("Authorization", format!("Bearer {}", settings.api_key));
-    }
+    let mut workspace = state.workspace.lock().unwrap();
+    prompt_templates_for_scope(&mut workspace, &scope, project_id)?.remove(&task);
+    workspace.updated_at = chrono::Utc::now().to_rfc3339();
 
-    // Send request
-    let response = request_builder
-        .send()
-        .await
-        .map_err(|e| format!("Failed to send request: {}", e))?;
-    
-    if !response.status().is_success() {
-        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(format!("LLM API error: {}", error_text));
+    if let Err(e) = save_workspace(&workspace, &state.data_dir) {
+        return Err(format!("Failed to save workspace: {}", e));
     }
 
-    // Spawn a task to handle streaming so we don't block
-    tauri::async_runtime::spawn(async move {
-        let mut stream = response.bytes_stream();
-        while let Some(item) = stream.next().await {
-            match item {
-                Ok(bytes) => {
-                    let chunk_str = String::from_utf8_lossy(&bytes);
-                    for line in chunk_str.lines() {
-                        let line = line.trim();
-                        if line.is_empty() { continue; }
-                        
-                        // Parse SSE
-                        if line.starts_with("data: ") {
-                            let data = &line[6..];
-                            if data == "[DONE]" {
-                                let _ = app.emit("journey:done", ());
-                                return;
-                            }
-                            
-                            if let Ok(json) = serde_json::from_str::<serde_json::Value>(data) {
-                                if !is_anthropic {
-                                    if let Some(content) = json["choices"][0]["delta"]["content"].as_str() {
-                                        let _ = app.emit("journey:chunk", content);
-                                    }
-                                } else {
-                                    if json["type"] == "content_block_delta" {
-                                        if let Some(text) = json["delta"]["text"].as_str() {
-                                            let _ = app.emit("journey:chunk", text);
-                                        }
-                                    }
-                                    if json["type"] == "message_stop" {
-                                        let _ = app.emit("journey:done", ());
-                                        return;
-                                    }
-                                }
-                            }
-                        } 
-                    }
-                }
-                Err(e) => {
-                    let _ = app.emit("journey:error", format!("Stream error: {}", e));
-                    return;
-                }
-            }
+    Ok(())
+}
+
+fn prompt_templates_for_scope<'a>(
+    workspace: &'a mut Workspace,
+    scope: &str,
+    project_id: Option<String>,
+) -> Result<&'a mut HashMap<String, PromptTemplate>, String> {
+    match scope {
+        "workspace" => Ok(&mut workspace.prompt_templates),
+        "project" => {
+            let project_id = project_id.unwrap_or_else(|| workspace.active_project_id.clone());
+            let project = workspace.get_project_mut(&project_id)
+                .ok_or(format!("Project with id {} not found", project_id))?;
+            project.updated_at = chrono::Utc::now().to_rfc3339();
+            Ok(&mut project.prompt_templates)
         }
-        let _ = app.emit("journey:done", ());
-    });
+        _ => Err(format!("Invalid prompt template scope: {}", scope)),
+    }
+}
 
+fn migrate_plaintext_api_keys(vault: &mut vault::Vault) -> Result<(), String> {
+    let mut settings = load_settings()?;
+    if vault::secure_api_keys(vault, &mut settings)? {
+        write_settings(&settings)?;
+    }
     Ok(())
 }
 
+/// Resolve the profile assigned to `task`, with its API key filled in from the environment or the vault
+fn load_profile(state: &AppState, task: &str) -> Result<LlmProfile, String> {
+    let mut profile = load_settings()?.profile_for_task(task)?;
+    vault::resolve_api_key(&state.vault.lock().unwrap(), &mut profile)?;
+    Ok(profile)
+}
+
 #[tauri::command]
-pub async fn refine_user_stories_with_llm_stream(
-    app: AppHandle,
-    _state: State<'_, AppState>,
+pub async fn save_project_user_stories(
+    state: State<'_, AppState>,
+    project_id: String,
     content: String,
 ) -> Result<(), String> {
-    let settings = load_settings()?;
-    
-    let is_anthropic = settings.provider == "anthropic";
-    
-    // Create client
-    let client = reqwest::Client::new();
-    
-    let system_prompt = "You are an expert Product Manager. Your task is to analyze the provided software project description and generate a comprehensive list of User Stories grouped by Feature.
-
-Guidelines:
-1. Format:
-   - Use Markdown.
-   - Group stories by `## Feature Name`.
-   - Each story should follow the standard format: `- **As a** <role>, **I want to** <action> **so that** <benefit>.`
-   - Optionally add acceptance criteria if relevant as a sub-list.
-2. Content:
-   - Ensure coverage of all key features mentioned or implied in the description.
-   - Include both functional and non-functional requirements (e.g., Install, Report).
-3. Tone: Professional and clear.
-
-Output Requirement:
-- Output only the markdown text.
-- Do NOT wrap the entire output in a code block.";
-
-    let request_body = if !is_anthropic {
-        // OpenAI Format
-        serde_json::json!({
-            "model": settings.model.unwrap_or("gpt-4".to_string()),
-            "stream": true,
-            "messages": [
-                {
-                    "role": "system",
-                    "content": system_prompt
-                },
-                {
-                    "role": "user",
-                    "content": format!("Generate User Stories for the following project description:\n\n{}", content)
-                }
-            ]
-        })
-    } else {
-        // Anthropic Format
-        serde_json::json!({
-            "model": settings.model.unwrap_or("claude-3-sonnet-20240229".to_string()),
-            "max_tokens": 4096,
-            "stream": true,
-            "messages": [
-                {
-                    "role": "user",
-                    "content": format!("{}\n\nGenerate User Stories for the following project description:\n\n{}", system_prompt, content)
-                }
-            ]
-        })
-    };
+    let mut workspace = state.workspace.lock().map_err(|e| e.to_string())?;
     
-    let url = if !is_anthropic {
-        format!("{}/chat/completions", settings.base_url.trim_end_matches('/'))
+    if let Some(project) = workspace.get_project_mut(&project_id) {
+        project.user_stories = Some(content);
+        project.updated_at = chrono::Utc::now().to_rfc3339();
     } else {
-        format!("{}/messages", settings.base_url.trim_end_matches('/'))
-    };
+        return Err("Project not found".to_string());
+    }
     
-    // Construct request
-    let mut request_builder = client.post(&url)
-        .header("Content-Type", "application/json")
-        .json(&request_body);
-        
-    if is_anthropic {
-         request_builder = request_builder
-            .header("x-api-key", &settings.api_key)
-            .header("anthropic-version", "2023-06-01");
-    } else {
-         request_builder = request_builder.header("Authorization", format!("Bearer {}", settings.api_key));
+    // Save to file
+    if let Err(e) = save_workspace(&workspace, &state.data_dir) {
+        return Err(format!("Failed to save workspace: {}", e));
     }
-
-    // Send request
-    let response = request_builder
-        .send()
-        .await
-        .map_err(|e| format!("Failed to send request: {}", e))?;
     
-    if !response.status().is_success() {
-        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(format!("LLM API error: {}", error_text));
+    Ok(())
+}
+
+/// Features, stories and acceptance criteria read from a project's user stories
+#[tauri::command]
+pub fn parse_user_stories(state: State<AppState>, project_id: String) -> Result<Vec<stories::Feature>, String> {
+    let user_stories = {
+        let workspace = state.workspace.lock().unwrap();
+        let project = workspace.get_project(&project_id)
+            .ok_or("Project not found".to_string())?;
+        project.user_stories.clone().filter(|s| !s.trim().is_empty())
     }
+    .ok_or("The project has no user stories yet".to_string())?;
 
-    // Spawn a task to handle streaming so we don't block
-    tauri::async_runtime::spawn(async move {
-        let mut stream = response.bytes_stream();
-        while let Some(item) = stream.next().await {
-            match item {
-                Ok(bytes) => {
-                    let chunk_str = String::from_utf8_lossy(&bytes);
-                    for line in chunk_str.lines() {
-                        let line = line.trim();
-                        if line.is_empty() { continue; }
-                        
-                        // Parse SSE
-                        if line.starts_with("data: ") {
-                            let data = &line[6..];
-                            if data == "[DONE]" {
-                                let _ = app.emit("stories:done", ());
-                                return;
-                            }
-                            
-                            if let Ok(json) = serde_json::from_str::<serde_json::Value>(data) {
-                                if !is_anthropic {
-                                    if let Some(content) = json["choices"][0]["delta"]["content"].as_str() {
-                                        let _ = app.emit("stories:chunk", content);
-                                    }
-                                } else {
-                                    if json["type"] == "content_block_delta" {
-                                        if let Some(text) = json["delta"]["text"].as_str() {
-                                            let _ = app.emit("stories:chunk", text);
-                                        }
-                                    }
-                                    if json["type"] == "message_stop" {
-                                        let _ = app.emit("stories:done", ());
-                                        return;
-                                    }
-                                }
-                            }
-                        } 
-                    }
-                }
-                Err(e) => {
-                    let _ = app.emit("stories:error", format!("Stream error: {}", e));
-                    return;
-                }
-            }
-        }
-        let _ = app.emit("stories:done", ());
-    });
+    Ok(stories::parse(&user_stories))
+}
 
-    Ok(())
+/// Gherkin `.feature` files for a project's user stories, one per feature
+#[tauri::command]
+pub fn export_user_stories_to_gherkin(state: State<AppState>, project_id: String) -> Result<Vec<GeneratedFile>, String> {
+    let features = parse_user_stories(state, project_id)?;
+    if features.is_empty() {
+        return Err("No stories in the form \"As a <role>, I want <goal>\" were found".to_string());
+    }
+    Ok(stories::to_gherkin(&features))
 }
+
 #[tauri::command]
-pub async fn edit_er_diagram_with_llm_stream(
+pub async fn refine_user_stories_with_llm_stream(
     app: AppHandle,
-    current_diagram: String,
-    instruction: String,
+    state: State<'_, AppState>,
+    content: String,
+    bypass_cache: Option<bool>,
+    thread_id: Option<String>,
 ) -> Result<(), String> {
-    let settings = load_settings()?;
-    let is_anthropic = settings.provider == "anthropic";
-    let client = reqwest::Client::new();
-    
-    let system_prompt = "You are an expert software architect. Modify the provided Mermaid ER Diagram based on the user's instruction.
-Output ONLY the mermaid code block. Do not include markdown code fences (```mermaid). Do not include any explanation. Just the code.
-Start with `erDiagram`.";
-
-    let content = format!("Current Diagram:\n{}\n\nInstruction: {}", current_diagram, instruction);
-    
-    // Reuse the streaming request logic (would be better to extract this into a helper function, but for now copying is safer to avoid large refactors)
-    // ... constructing request ...
-    // Using a helper macro or function would be better but I'll write it out for clarity/correctness within this tool constraints.
-    
-    let messages = if !is_anthropic {
-        serde_json::json!([
-            { "role": "system", "content": system_prompt },
-            { "role": "user", "content": content }
-        ])
-    } else {
-        serde_json::json!([
-            { "role": "user", "content": format!("{}\n\n{}", system_prompt, content) }
-        ])
-    };
-    
-    perform_llm_stream(app, "er", settings, client, messages, is_anthropic).await
+    perform_llm_stream(app, &state, "stories", &[("content", &content)], bypass_cache.unwrap_or(false), thread_id).await
 }
 
+/// Change a diagram as `instruction` says, streaming as `<kind>:*` events. The diagram is
+/// `current_diagram` of `kind`, or the project diagram `diagram_id`; `current_diagram` then
+/// replaces its stored code, for edits not saved yet.
 #[tauri::command]
-pub async fn edit_uml_diagram_with_llm_stream(
+#[allow(clippy::too_many_arguments)]
+pub async fn edit_diagram_with_llm_stream(
     app: AppHandle,
-    current_diagram: String,
+    state: State<'_, AppState>,
+    kind: Option<String>,
+    diagram_id: Option<String>,
+    current_diagram: Option<String>,
     instruction: String,
+    bypass_cache: Option<bool>,
+    thread_id: Option<String>,
+    auto_repair: Option<bool>,
 ) -> Result<(), String> {
-    let settings = load_settings()?;
-    let is_anthropic = settings.provider == "anthropic";
-    let client = reqwest::Client::new();
-    
-    let system_prompt = "You are an expert software architect. Modify the provided Mermaid Class Diagram based on the user's instruction.
-Output ONLY the mermaid code block. Do not include markdown code fences (```mermaid). Do not include any explanation. Just the code.
-Start with `classDiagram`.";
-
-    let content = format!("Current Diagram:\n{}\n\nInstruction: {}", current_diagram, instruction);
-    
-    let messages = if !is_anthropic {
-        serde_json::json!([
-            { "role": "system", "content": system_prompt },
-            { "role": "user", "content": content }
-        ])
-    } else {
-        serde_json::json!([
-            { "role": "user", "content": format!("{}\n\n{}", system_prompt, content) }
-        ])
-    };
-    
-    perform_llm_stream(app, "uml", settings, client, messages, is_anthropic).await
+    let (kind, diagram) = diagram_target(&state, kind, diagram_id)?;
+    let current_diagram = current_diagram
+        .or(diagram.map(|d| d.code))
+        .ok_or("No diagram to edit".to_string())?;
+    let task = format!("edit_{}", kind);
+    perform_diagram_stream(
+        app,
+        &state,
+        &task,
+        &[("current_diagram", &current_diagram), ("instruction", &instruction)],
+        bypass_cache.unwrap_or(false),
+        thread_id,
+        auto_repair.unwrap_or(false),
+    )
+    .await
 }
 
+/// Answer a question about a diagram, given like for `edit_diagram_with_llm_stream`
 #[tauri::command]
-pub async fn edit_flowchart_with_llm_stream(
+#[allow(clippy::too_many_arguments)]
+pub async fn ask_llm_about_diagram_stream(
     app: AppHandle,
-    current_diagram: String,
-    instruction: String,
+    state: State<'_, AppState>,
+    diagram_type: Option<String>,
+    diagram_id: Option<String>,
+    current_diagram: Option<String>,
+    question: String,
+    bypass_cache: Option<bool>,
+    thread_id: Option<String>,
 ) -> Result<(), String> {
-    let settings = load_settings()?;
-    let is_anthropic = settings.provider == "anthropic";
-    let client = reqwest::Client::new();
-    
-    let system_prompt = "You are an expert software architect. Modify the provided Mermaid Flowchart based on the user's instruction.
-Output ONLY the mermaid code block. Do not include markdown code fences (```mermaid). Do not include any explanation. Just the code.
-Start with `graph` or `flowchart`.
-Remember to keep text in quotes inside nodes.";
+    let (diagram_type, diagram) = diagram_target(&state, diagram_type, diagram_id)?;
+    let current_diagram = current_diagram
+        .or(diagram.map(|d| d.code))
+        .ok_or("No diagram to ask about".to_string())?;
+    // "ask" has its own event prefix so answers never land in a diagram editor
+    perform_llm_stream(
+        app,
+        &state,
+        "ask",
+        &[
+            ("diagram_type", &diagram_type),
+            ("current_diagram", &current_diagram),
+            ("question", &question),
+        ],
+        bypass_cache.unwrap_or(false),
+        thread_id,
+    )
+    .await
+}
 
-    let content = format!("Current Diagram:\n{}\n\nInstruction: {}", current_diagram, instruction);
-    
-    let messages = if !is_anthropic {
-        serde_json::json!([
-            { "role": "system", "content": system_prompt },
-            { "role": "user", "content": content }
-        ])
-    } else {
-        serde_json::json!([
-            { "role": "user", "content": format!("{}\n\n{}", system_prompt, content) }
-        ])
-    };
-    
-    perform_llm_stream(app, "flowchart", settings, client, messages, is_anthropic).await
+/// Start an empty thread; its first turn is any streaming command called with the thread's ID
+#[tauri::command]
+pub fn create_thread(
+    state: State<AppState>,
+    title: String,
+    task: String,
+    target_type: String,
+    target_id: Option<String>,
+) -> Result<ConversationThread, String> {
+    if !prompts::PROMPT_TASKS.iter().any(|(name, _)| *name == task) {
+        return Err(format!("Unknown prompt task: {}", task));
+    }
+    if !["project", "topic", "diagram"].contains(&target_type.as_str()) {
+        return Err(format!("Invalid thread target: {}", target_type));
+    }
+
+    let mut workspace = state.workspace.lock().unwrap();
+    let project = workspace.get_active_project_mut()
+        .ok_or("No active project found".to_string())?;
+
+    let thread = ConversationThread::new(title, task, target_type, target_id);
+    project.threads.push(thread.clone());
+    project.updated_at = chrono::Utc::now().to_rfc3339();
+
+    if let Err(e) = save_workspace(&workspace, &state.data_dir) {
+        return Err(format!("Failed to save workspace: {}", e));
+    }
+
+    Ok(thread)
 }
 
+/// Threads of the active project, most recently used first, optionally only those attached to
+/// one target
 #[tauri::command]
-pub async fn edit_user_journey_with_llm_stream(
-    app: AppHandle,
-    current_diagram: String,
-    instruction: String,
-) -> Result<(), String> {
-    let settings = load_settings()?;
-    let is_anthropic = settings.provider == "anthropic";
-    let client = reqwest::Client::new();
-    
-    let system_prompt = "You are an expert UX designer. Modify the provided Mermaid User Journey based on the user's instruction.
-Output ONLY the mermaid code block. Do not include markdown code fences (```mermaid). Do not include any explanation. Just the code.
-Start with `journey`.";
+pub fn list_threads(
+    state: State<AppState>,
+    target_type: Option<String>,
+    target_id: Option<String>,
+) -> Result<Vec<ConversationThread>, String> {
+    let workspace = state.workspace.lock().unwrap();
+    let project = workspace.get_active_project()
+        .ok_or("No active project found".to_string())?;
 
-    let content = format!("Current Diagram:\n{}\n\nInstruction: {}", current_diagram, instruction);
-    
-    let messages = if !is_anthropic {
-        serde_json::json!([
-            { "role": "system", "content": system_prompt },
-            { "role": "user", "content": content }
-        ])
-    } else {
-        serde_json::json!([
-            { "role": "user", "content": format!("{}\n\n{}", system_prompt, content) }
-        ])
-    };
-    
-    perform_llm_stream(app, "journey", settings, client, messages, is_anthropic).await
+    let mut threads: Vec<ConversationThread> = project.threads
+        .iter()
+        .filter(|t| target_type.is_none() || target_type.as_ref() == Some(&t.target_type))
+        .filter(|t| target_id.is_none() || t.target_id == target_id)
+        .cloned()
+        .collect();
+    threads.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+    Ok(threads)
 }
 
+/// Send a follow-up `message` in a thread, with every earlier turn as context. The answer
+/// streams on the event prefix of the thread's task.
 #[tauri::command]
-pub async fn ask_llm_about_diagram_stream(
+pub async fn continue_thread(
     app: AppHandle,
-    current_diagram: String,
-    question: String,
-    diagram_type: String,
+    state: State<'_, AppState>,
+    thread_id: String,
+    message: String,
+    bypass_cache: Option<bool>,
 ) -> Result<(), String> {
-    let settings = load_settings()?;
-    let is_anthropic = settings.provider == "anthropic";
-    let client = reqwest::Client::new();
-    
-    let system_prompt = format!(
-        "You are an expert software architect. You are provided with a Mermaid {} diagram.
-Your task is to answer the user's question about this diagram.
-Provide clear, concise explanations. Do not generate code unless asked for an example.",
-        diagram_type
-    );
+    let thread = state.workspace.lock().unwrap()
+        .get_active_project()
+        .and_then(|project| project.get_thread(&thread_id))
+        .cloned()
+        .ok_or(format!("Thread with id {} not found", thread_id))?;
+
+    let previous = thread.messages
+        .iter()
+        .rev()
+        .find(|m| m.role == "assistant")
+        .map(|m| m.content.clone())
+        .ok_or("The thread has no answer to follow up on yet".to_string())?;
+
+    let echo_answer = || echo::follow_up(&thread.task, &previous, &message);
+    stream_turn(
+        app,
+        &state,
+        &thread.task,
+        thread.system_prompt.clone(),
+        message.clone(),
+        echo_answer,
+        bypass_cache.unwrap_or(false),
+        Some(thread_id),
+    )
+    .await
+}
 
-    let content = format!("Current Diagram:\n{}\n\nQuestion: {}", current_diagram, question);
-    
-    let messages = if !is_anthropic {
-        serde_json::json!([
-            { "role": "system", "content": system_prompt },
-            { "role": "user", "content": content }
-        ])
-    } else {
-        serde_json::json!([
-            { "role": "user", "content": format!("{}\n\n{}", system_prompt, content) }
-        ])
-    };
-    
-    // We can reuse perform_llm_stream but we need a distinct event prefix.
-    // Let's use "ask" as prefix.
-    perform_llm_stream(app, "ask", settings, client, messages, is_anthropic).await
+/// Copy a thread, keeping its first `message_count` messages, to try a different follow-up
+#[tauri::command]
+pub fn fork_thread(
+    state: State<AppState>,
+    thread_id: String,
+    message_count: Option<usize>,
+) -> Result<ConversationThread, String> {
+    let mut workspace = state.workspace.lock().unwrap();
+    let project = workspace.get_active_project_mut()
+        .ok_or("No active project found".to_string())?;
+
+    let fork = project.get_thread(&thread_id)
+        .ok_or(format!("Thread with id {} not found", thread_id))?
+        .fork(message_count)?;
+    project.threads.push(fork.clone());
+    project.updated_at = chrono::Utc::now().to_rfc3339();
+
+    if let Err(e) = save_workspace(&workspace, &state.data_dir) {
+        return Err(format!("Failed to save workspace: {}", e));
+    }
+
+    Ok(fork)
+}
+
+#[tauri::command]
+pub fn delete_thread(state: State<AppState>, thread_id: String) -> Result<(), String> {
+    let mut workspace = state.workspace.lock().unwrap();
+    let project = workspace.get_active_project_mut()
+        .ok_or("No active project found".to_string())?;
+
+    project.remove_thread(&thread_id)?;
+
+    if let Err(e) = save_workspace(&workspace, &state.data_dir) {
+        return Err(format!("Failed to save workspace: {}", e));
+    }
+
+    Ok(())
+}
+
+/// Render the prompt template for `task` as configured for the active project
+fn render_prompt(state: &AppState, task: &str, variables: &[(&str, &str)]) -> Result<(String, String), String> {
+    let workspace = state.workspace.lock().unwrap();
+    let template = prompts::resolve(&workspace, Some(&workspace.active_project_id), task)?;
+    Ok(template.render(variables))
 }
 
 // Helper function to avoid code duplication
+/// Run the prompt `task` (see `prompts::PROMPT_TASKS`) with `variables` and stream the answer
+/// as `<prefix>:chunk|done|error` events. Edit tasks share the event prefix and profile of the
+/// diagram they edit, so `edit_er` streams as `er`.
+///
+/// With a `thread_id`, the earlier turns of that thread are sent first and the new exchange is
+/// appended to it once the answer completes.
+///
+/// Identical requests are answered from the response cache unless `bypass_cache` is set; a
+/// bypassed request still refreshes the cached answer.
 async fn perform_llm_stream(
     app: AppHandle,
-    event_prefix: &str,
-    settings: LlmSettings,
-    client: reqwest::Client,
-    messages: serde_json::Value,
-    is_anthropic: bool,
+    state: &AppState,
+    task: &str,
+    variables: &[(&str, &str)],
+    bypass_cache: bool,
+    thread_id: Option<String>,
 ) -> Result<(), String> {
-    let request_body = if !is_anthropic {
-        serde_json::json!({
-            "model": settings.model.unwrap_or("gpt-4".to_string()),
-            "stream": true,
-            "messages": messages
-        })
-    } else {
-        serde_json::json!({
-            "model": settings.model.unwrap_or("claude-3-sonnet-20240229".to_string()),
-            "max_tokens": 4096,
-            "stream": true,
-            "messages": messages
-        })
+    let (system_prompt, user_message) = render_prompt(state, task, variables)?;
+    let echo_answer = || echo::respond(task, variables);
+    stream_turn(app, state, task, system_prompt, user_message, echo_answer, bypass_cache, thread_id).await
+}
+
+/// Like `perform_llm_stream` for diagram tasks. With `auto_repair`, the answer is validated
+/// once complete and parse errors are sent back to the model, up to the configured number of
+/// attempts; `<prefix>:repair` reports each attempt and only the final diagram is streamed.
+#[allow(clippy::too_many_arguments)]
+async fn perform_diagram_stream(
+    app: AppHandle,
+    state: &AppState,
+    task: &str,
+    variables: &[(&str, &str)],
+    bypass_cache: bool,
+    thread_id: Option<String>,
+    auto_repair: bool,
+) -> Result<(), String> {
+    if !auto_repair {
+        return perform_llm_stream(app, state, task, variables, bypass_cache, thread_id).await;
+    }
+
+    let prefix = task.strip_prefix("edit_").unwrap_or(task).to_string();
+    let kind = prefix.clone();
+    let (system_prompt, user_message) = render_prompt(state, task, variables)?;
+    let profile = load_profile(state, profile_task(&prefix))?;
+
+    let mut turns = match &thread_id {
+        Some(thread_id) => thread_turns(state, thread_id)?,
+        None => Vec::new(),
     };
+    turns.push(("user", user_message.clone()));
+    let pending = thread_id.map(|thread_id| PendingTurn {
+        thread_id,
+        system_prompt: system_prompt.clone(),
+        user_message,
+    });
 
-    let url = if !is_anthropic {
-        format!("{}/chat/completions", settings.base_url.trim_end_matches('/'))
-    } else {
-        format!("{}/messages", settings.base_url.trim_end_matches('/'))
+    // The echo provider has nobody to ask for a fix, so its answer is only cleaned
+    if echo::is_echo(&profile.provider) {
+        let outcome = mermaid::check(&kind, &echo::respond(task, variables)).map(|report| mermaid::Repaired {
+            code: report.cleaned,
+            diagnostics: report.diagnostics,
+            attempts: 0,
+        });
+        tauri::async_runtime::spawn(async move {
+            emit_repaired(&app, &prefix, pending, outcome);
+        });
+        return Ok(());
+    }
+
+    let protocol = Protocol::for_profile(&profile);
+    let client = state.http_client.lock().unwrap().clone();
+    let project_id = state.workspace.lock().unwrap().active_project_id.clone();
+    let max_attempts = load_settings()
+        .ok()
+        .and_then(|settings| settings.diagram_repair_attempts)
+        .unwrap_or(mermaid::DEFAULT_REPAIR_ATTEMPTS);
+
+    let messages = build_conversation(&system_prompt, &turns, protocol);
+    let model = llm::model_name(&profile, protocol);
+    let cache_key = cache::cache_key(&profile.provider, &profile.base_url, &model, &system_prompt, &messages);
+    let cached = if bypass_cache { None } else { state.cache.lock().unwrap().get(&cache_key) };
+
+    tauri::async_runtime::spawn(async move {
+        let ask = |turns: Vec<(&'static str, String)>| {
+            let (app, client, profile, project_id, prefix) =
+                (app.clone(), client.clone(), profile.clone(), project_id.clone(), prefix.clone());
+            let messages = build_conversation(&system_prompt, &turns, protocol);
+            async move {
+                let (answer, model, usage) = collect_answer(&client, &profile, protocol, messages).await;
+                if !usage.is_empty() {
+                    record_usage(&app, project_id, &prefix, &profile.name, &model, usage);
+                }
+                answer
+            }
+        };
+
+        let fresh = cached.is_none();
+        let first = match cached {
+            Some(cached) => Ok(cached.chunks.concat()),
+            None => ask(turns.clone()).await,
+        };
+
+        let outcome = match first {
+            Ok(answer) => {
+                // The cache keeps the model's own first answer; repairs depend on validation
+                if fresh {
+                    let response = CachedResponse {
+                        chunks: vec![answer.clone()],
+                        model,
+                        created_at: chrono::Utc::now().to_rfc3339(),
+                    };
+                    if let Err(e) = app.state::<AppState>().cache.lock().unwrap().put(&cache_key, &response) {
+                        eprintln!("Failed to cache LLM response: {}", e);
+                    }
+                }
+                let on_attempt = |attempt: &mermaid::RepairAttempt| {
+                    let _ = app.emit(&format!("{}:repair", prefix), attempt);
+                };
+                mermaid::repair(&kind, turns, answer, max_attempts, ask, on_attempt).await
+            }
+            Err(e) => Err(e),
+        };
+        emit_repaired(&app, &prefix, pending, outcome);
+    });
+
+    Ok(())
+}
+
+/// Stream the outcome of a repair loop as a single chunk, then finish the turn
+fn emit_repaired(app: &AppHandle, prefix: &str, pending: Option<PendingTurn>, outcome: Result<mermaid::Repaired, String>) {
+    match outcome {
+        Ok(repaired) => {
+            emit_stream_event(app, prefix, StreamEvent::Chunk(repaired.code.clone()));
+            emit_stream_event(app, prefix, StreamEvent::Done);
+            finish_turn(app, pending, Some(vec![repaired.code]));
+        }
+        Err(e) => emit_stream_event(app, prefix, StreamEvent::Error(e)),
+    }
+}
+
+/// Run one request to completion without streaming it to the frontend
+async fn collect_answer(
+    client: &reqwest::Client,
+    profile: &LlmProfile,
+    protocol: Protocol,
+    messages: serde_json::Value,
+) -> (Result<String, String>, String, TokenUsage) {
+    let (response, model) = match start_stream(client, profile, protocol, messages).await {
+        Ok(started) => started,
+        Err(e) => return (Err(e), llm::model_name(profile, protocol), TokenUsage::default()),
     };
 
-    let mut request_builder = client.post(&url)
-        .header("Content-Type", "application/json")
-        .json(&request_body);
-        
-    if is_anthropic {
-         request_builder = request_builder
-            .header("x-api-key", &settings.api_key)
-            .header("anthropic-version", "2023-06-01");
-    } else {
-         request_builder = request_builder.header("Authorization", format!("Bearer {}", settings.api_key));
+    let mut answer = String::new();
+    let mut outcome = Err("The response ended before the answer was complete".to_string());
+    let usage = relay_stream(response.bytes_stream(), protocol, |event| match event {
+        StreamEvent::Chunk(text) => answer.push_str(&text),
+        StreamEvent::Done => outcome = Ok(()),
+        StreamEvent::Error(e) => outcome = Err(e),
+    })
+    .await;
+
+    (outcome.map(|_| answer), model, usage)
+}
+
+/// A user message whose exchange is recorded in a thread once the answer completes
+struct PendingTurn {
+    thread_id: String,
+    system_prompt: String,
+    user_message: String,
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn stream_turn(
+    app: AppHandle,
+    state: &AppState,
+    task: &str,
+    system_prompt: String,
+    user_message: String,
+    echo_answer: impl FnOnce() -> String,
+    bypass_cache: bool,
+    thread_id: Option<String>,
+) -> Result<(), String> {
+    let prefix = task.strip_prefix("edit_").unwrap_or(task).to_string();
+    let profile = load_profile(state, profile_task(&prefix))?;
+
+    let mut turns: Vec<(&str, String)> = Vec::new();
+    let pending = match thread_id {
+        Some(thread_id) => {
+            turns = thread_turns(state, &thread_id)?;
+            Some(PendingTurn {
+                thread_id,
+                system_prompt: system_prompt.clone(),
+                user_message: user_message.clone(),
+            })
+        }
+        None => None,
+    };
+
+    if echo::is_echo(&profile.provider) {
+        let answer = echo_answer();
+        tauri::async_runtime::spawn(async move {
+            let (chunks, _) = relay_to_frontend(&app, &prefix, echo::sse_stream(&answer), Protocol::OpenAi).await;
+            finish_turn(&app, pending, chunks);
+        });
+        return Ok(());
     }
 
-    let response = request_builder
-        .send()
-        .await
-        .map_err(|e| format!("Failed to send request: {}", e))?;
-    
-    if !response.status().is_success() {
-        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(format!("LLM API error: {}", error_text));
+    let protocol = Protocol::for_profile(&profile);
+    turns.push(("user", user_message));
+    let messages = build_conversation(&system_prompt, &turns, protocol);
+    let model = llm::model_name(&profile, protocol);
+    let cache_key = cache::cache_key(&profile.provider, &profile.base_url, &model, &system_prompt, &messages);
+
+    if !bypass_cache {
+        let cached = state.cache.lock().unwrap().get(&cache_key);
+        if let Some(cached) = cached {
+            tauri::async_runtime::spawn(async move {
+                let replay = llm::replay_stream(cached.chunks, Duration::ZERO);
+                let (chunks, _) = relay_to_frontend(&app, &prefix, replay, Protocol::OpenAi).await;
+                finish_turn(&app, pending, chunks);
+            });
+            return Ok(());
+        }
     }
 
-    // Clone inputs for the async task if needed, or just move them
-    // Strings are cloned implicitly by move if needed? No, string is not Copy.
-    // We need to move `app` and `event_prefix` (as String) into the closure.
-    let prefix = event_prefix.to_string();
+    let client = state.http_client.lock().unwrap().clone();
+
+    // Attribute the call to the project that is active when it starts
+    let project_id = state.workspace.lock().unwrap().active_project_id.clone();
+
+    let (response, model) = start_stream(&client, &profile, protocol, messages).await?;
     
     tauri::async_runtime::spawn(async move {
-        let mut stream = response.bytes_stream();
-        while let Some(item) = stream.next().await {
-            match item {
-                Ok(bytes) => {
-                    let chunk_str = String::from_utf8_lossy(&bytes);
-                    for line in chunk_str.lines() {
-                        let line = line.trim();
-                        if line.is_empty() { continue; }
-                        
-                        if line.starts_with("data: ") {
-                            let data = &line[6..];
-                            if data == "[DONE]" {
-                                let _ = app.emit(&format!("{}:done", prefix), ());
-                                return;
-                            }
-                            
-                            if let Ok(json) = serde_json::from_str::<serde_json::Value>(data) {
-                                if !is_anthropic {
-                                    if let Some(content) = json["choices"][0]["delta"]["content"].as_str() {
-                                        let _ = app.emit(&format!("{}:chunk", prefix), content);
-                                    }
-                                } else {
-                                    if json["type"] == "content_block_delta" {
-                                        if let Some(text) = json["delta"]["text"].as_str() {
-                                            let _ = app.emit(&format!("{}:chunk", prefix), text);
-                                        }
-                                    }
-                                    if json["type"] == "message_stop" {
-                                        let _ = app.emit(&format!("{}:done", prefix), ());
-                                        return;
-                                    }
-                                }
-                            }
-                        } 
-                    }
-                }
-                Err(e) => {
-                    let _ = app.emit(&format!("{}:error", prefix), format!("Stream error: {}", e));
-                    return;
-                }
+        let (chunks, usage) = relay_to_frontend(&app, &prefix, response.bytes_stream(), protocol).await;
+
+        // Only complete answers are worth replaying
+        if let Some(chunks) = chunks.as_ref().filter(|chunks| !chunks.is_empty()) {
+            let response = CachedResponse {
+                chunks: chunks.clone(),
+                model: model.clone(),
+                created_at: chrono::Utc::now().to_rfc3339(),
+            };
+            if let Err(e) = app.state::<AppState>().cache.lock().unwrap().put(&cache_key, &response) {
+                eprintln!("Failed to cache LLM response: {}", e);
             }
         }
-        let _ = app.emit(&format!("{}:done", prefix), ());
+        finish_turn(&app, pending, chunks);
+
+        // Tokens consumed before a stream error are billed too, so record them either way
+        if !usage.is_empty() {
+            record_usage(&app, project_id, &prefix, &profile.name, &model, usage);
+        }
     });
 
     Ok(())
 }
+
+/// Earlier messages of a thread in the active project, as conversation turns
+fn thread_turns(state: &AppState, thread_id: &str) -> Result<Vec<(&'static str, String)>, String> {
+    let workspace = state.workspace.lock().unwrap();
+    let thread = workspace.get_active_project()
+        .and_then(|project| project.get_thread(thread_id))
+        .ok_or(format!("Thread with id {} not found", thread_id))?;
+    Ok(thread.messages
+        .iter()
+        .map(|message| (if message.role == "assistant" { "assistant" } else { "user" }, message.content.clone()))
+        .collect())
+}
+
+/// Settings key of the profile that runs `prefix`: scoped refinements use the refine profile
+fn profile_task(prefix: &str) -> &str {
+    match prefix {
+        "refine_topic" | "refine_section" | "refine_structured" => "refine",
+        _ => prefix,
+    }
+}
+
+/// Relay `stream` to the frontend, returning the chunks of the answer if it completed
+async fn relay_to_frontend<S, B, E>(
+    app: &AppHandle,
+    prefix: &str,
+    stream: S,
+    protocol: Protocol,
+) -> (Option<Vec<String>>, TokenUsage)
+where
+    S: futures_util::Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: std::fmt::Display,
+{
+    llm::relay_answer(stream, protocol, |event| emit_stream_event(app, prefix, event)).await
+}
+
+/// Append a completed exchange to its thread. Failed turns are dropped so a retry does not
+/// leave an unanswered message in the history.
+fn finish_turn(app: &AppHandle, pending: Option<PendingTurn>, chunks: Option<Vec<String>>) {
+    let (Some(pending), Some(chunks)) = (pending, chunks) else {
+        return;
+    };
+
+    let state = app.state::<AppState>();
+    let mut workspace = state.workspace.lock().unwrap();
+    // The thread may belong to a project that is no longer active, or have been deleted meanwhile
+    let Some(project) = workspace.projects
+        .iter_mut()
+        .find(|project| project.get_thread(&pending.thread_id).is_some())
+    else {
+        return;
+    };
+    if let Some(thread) = project.get_thread_mut(&pending.thread_id) {
+        if thread.system_prompt.is_empty() {
+            thread.system_prompt = pending.system_prompt;
+        }
+        thread.add_turn(pending.user_message, chunks.concat());
+    }
+    project.updated_at = chrono::Utc::now().to_rfc3339();
+
+    if let Err(e) = save_workspace(&workspace, &state.data_dir) {
+        eprintln!("Failed to save conversation thread: {}", e);
+    }
+}
+
+fn emit_stream_event(app: &AppHandle, prefix: &str, event: StreamEvent) {
+    let _ = match event {
+        StreamEvent::Chunk(text) => app.emit(&format!("{}:chunk", prefix), text),
+        StreamEvent::Done => app.emit(&format!("{}:done", prefix), ()),
+        StreamEvent::Error(error) => app.emit(&format!("{}:error", prefix), error),
+    };
+}
+
+fn record_usage(
+    app: &AppHandle,
+    project_id: String,
+    task: &str,
+    profile_name: &str,
+    model: &str,
+    usage: TokenUsage,
+) {
+    let prices = load_settings().map(|s| s.model_prices).unwrap_or_default();
+    let record = UsageRecord::new(
+        Some(project_id),
+        task,
+        profile_name,
+        model,
+        usage,
+        find_price(&prices, model),
+    );
+
+    if let Err(e) = app.state::<AppState>().usage.lock().unwrap().add(record) {
+        eprintln!("Failed to record LLM usage: {}", e);
+    }
+}
@@ -0,0 +1,314 @@
+use crate::echo;
+use crate::llm::{build_messages, build_stream_request, parse_stream_line, LineBuffer, Protocol, StreamLine};
+use crate::settings::LlmProfile;
+use crate::usage::TokenUsage;
+use futures_util::StreamExt;
+use std::time::{Duration, Instant};
+
+/// Upper bound for the whole test, body included, so a slow stream cannot hang the settings modal
+const TEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// How much of the response body is kept for shape detection and error messages
+const MAX_BODY_SAMPLE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DiagnosisKind {
+    Ok,
+    /// The settings themselves are unusable (bad proxy, CA bundle, header or URL)
+    InvalidSettings,
+    Dns,
+    Connect,
+    Tls,
+    Timeout,
+    /// 401 or 403: missing, wrong or insufficient API key
+    Unauthorized,
+    UnknownModel,
+    /// The server answered, but not in the shape the configured protocol expects
+    WrongProtocol,
+    /// Any other HTTP error
+    Http,
+    /// The profile's key is in the vault, which has to be unlocked first
+    VaultLocked,
+}
+
+/// Outcome of `test_llm_settings`
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionDiagnosis {
+    pub kind: DiagnosisKind,
+    pub message: String,
+    pub status: Option<u16>,
+    /// Time until the response headers arrived
+    pub latency_ms: Option<u64>,
+    /// Time until the first streamed text arrived
+    pub first_token_ms: Option<u64>,
+    /// Protocol the response looks like, when it can be told from its shape
+    pub detected_protocol: Option<String>,
+    /// Beginning of the model's answer
+    pub sample: Option<String>,
+}
+
+impl ConnectionDiagnosis {
+    pub fn new(kind: DiagnosisKind, message: impl Into<String>) -> Self {
+        ConnectionDiagnosis {
+            kind,
+            message: message.into(),
+            status: None,
+            latency_ms: None,
+            first_token_ms: None,
+            detected_protocol: None,
+            sample: None,
+        }
+    }
+}
+
+/// Send a tiny prompt with `profile` and explain what went wrong, if anything
+pub async fn test_connection(client: &reqwest::Client, profile: &LlmProfile) -> ConnectionDiagnosis {
+    if echo::is_echo(&profile.provider) {
+        return ConnectionDiagnosis::new(DiagnosisKind::Ok, "The offline echo provider needs no connection");
+    }
+
+    let protocol = Protocol::for_profile(profile);
+    let messages = build_messages("Reply with the single word OK.", "ping".to_string(), protocol);
+    let (request_builder, model) = build_stream_request(client, profile, protocol, messages);
+
+    let started = Instant::now();
+    let response = match request_builder.timeout(TEST_TIMEOUT).send().await {
+        Ok(response) => response,
+        Err(e) => return diagnose_request_error(&e),
+    };
+    let latency_ms = Some(started.elapsed().as_millis() as u64);
+    let status = response.status();
+
+    let mut body = Vec::new();
+    let mut lines = LineBuffer::default();
+    let mut usage = TokenUsage::default();
+    let mut sample = String::new();
+    let mut first_token_ms = None;
+    let mut stream_error = None;
+    let mut stream = response.bytes_stream();
+
+    'stream: while let Some(item) = stream.next().await {
+        let bytes = match item {
+            Ok(bytes) => bytes,
+            Err(e) if !sample.is_empty() => {
+                stream_error = Some(format!("Stream interrupted: {}", e));
+                break;
+            }
+            Err(e) => {
+                let mut diagnosis = diagnose_request_error(&e);
+                diagnosis.status = Some(status.as_u16());
+                diagnosis.latency_ms = latency_ms;
+                return diagnosis;
+            }
+        };
+        if body.len() < MAX_BODY_SAMPLE {
+            body.extend_from_slice(&bytes[..bytes.len().min(MAX_BODY_SAMPLE - body.len())]);
+        }
+        if !status.is_success() {
+            if body.len() >= MAX_BODY_SAMPLE {
+                break;
+            }
+            continue;
+        }
+
+        for line in lines.push(&bytes) {
+            match parse_stream_line(protocol, &line, &mut usage) {
+                StreamLine::Text(text) => {
+                    first_token_ms.get_or_insert(started.elapsed().as_millis() as u64);
+                    sample.push_str(&text);
+                    // One token proves the setup works; no need to pay for the rest
+                    break 'stream;
+                }
+                StreamLine::Done => break 'stream,
+                StreamLine::Error(error) => {
+                    stream_error = Some(error);
+                    break 'stream;
+                }
+                StreamLine::Ignored => {}
+            }
+        }
+    }
+
+    // The last line may have arrived without a trailing newline
+    if status.is_success() && stream_error.is_none() && sample.is_empty() {
+        let rest = lines.finish().unwrap_or_default();
+        match parse_stream_line(protocol, &rest, &mut usage) {
+            StreamLine::Text(text) => sample = text,
+            StreamLine::Error(error) => stream_error = Some(error),
+            _ => {}
+        }
+    }
+
+    let body = String::from_utf8_lossy(&body).into_owned();
+    let detected = guess_protocol(&body);
+
+    let mut diagnosis = if !status.is_success() {
+        diagnose_http_error(status.as_u16(), &body, &model, protocol, detected)
+    } else if let Some(error) = stream_error {
+        diagnose_message(&error, &model).unwrap_or_else(|| ConnectionDiagnosis::new(DiagnosisKind::Http, error))
+    } else if sample.is_empty() && detected.is_some_and(|d| d != protocol) {
+        wrong_protocol(protocol, detected)
+    } else if sample.is_empty() && usage.is_empty() && detected.is_none() {
+        ConnectionDiagnosis::new(
+            DiagnosisKind::WrongProtocol,
+            format!("The server answered, but not with a {} stream. Check the base URL and protocol.", protocol.as_str()),
+        )
+    } else {
+        ConnectionDiagnosis::new(DiagnosisKind::Ok, format!("Connected to {} successfully", model))
+    };
+
+    diagnosis.status = Some(status.as_u16());
+    diagnosis.latency_ms = latency_ms;
+    diagnosis.first_token_ms = first_token_ms;
+    diagnosis.detected_protocol = detected.map(|d| d.as_str().to_string());
+    if !sample.is_empty() {
+        diagnosis.sample = Some(sample);
+    }
+    diagnosis
+}
+
+/// Classify a transport-level failure by walking the error's source chain
+fn diagnose_request_error(error: &reqwest::Error) -> ConnectionDiagnosis {
+    let mut chain = Vec::new();
+    let mut source: Option<&dyn std::error::Error> = Some(error);
+    while let Some(e) = source {
+        chain.push(e.to_string());
+        source = e.source();
+    }
+    let details = chain.join(": ");
+    let lower = details.to_lowercase();
+
+    let kind = if error.is_builder() {
+        DiagnosisKind::InvalidSettings
+    } else if error.is_timeout() {
+        DiagnosisKind::Timeout
+    } else if ["dns error", "failed to lookup address", "no such host", "name or service not known"]
+        .iter()
+        .any(|needle| lower.contains(needle))
+    {
+        DiagnosisKind::Dns
+    } else if ["certificate", "tls", "ssl", "handshake"].iter().any(|needle| lower.contains(needle)) {
+        DiagnosisKind::Tls
+    } else {
+        DiagnosisKind::Connect
+    };
+
+    let message = match kind {
+        DiagnosisKind::InvalidSettings => format!("Invalid request, check the base URL: {}", details),
+        DiagnosisKind::Timeout => format!("The server did not answer in time: {}", details),
+        DiagnosisKind::Dns => format!("Could not resolve the host name: {}", details),
+        DiagnosisKind::Tls => format!("TLS handshake failed (a CA bundle may be needed behind a proxy): {}", details),
+        _ => format!("Could not connect to the server: {}", details),
+    };
+    ConnectionDiagnosis::new(kind, message)
+}
+
+fn diagnose_http_error(
+    status: u16,
+    body: &str,
+    model: &str,
+    protocol: Protocol,
+    detected: Option<Protocol>,
+) -> ConnectionDiagnosis {
+    let message = error_message(body).unwrap_or_else(|| body.trim().chars().take(300).collect());
+
+    if status == 401 || status == 403 {
+        return ConnectionDiagnosis::new(
+            DiagnosisKind::Unauthorized,
+            format!("The API key was rejected ({}): {}", status, message),
+        );
+    }
+    if status == 400 && protocol == Protocol::OpenAi && message.contains("stream_options") {
+        return ConnectionDiagnosis::new(
+            DiagnosisKind::InvalidSettings,
+            format!("The server does not accept token usage requests; turn off \"Report token usage\" for this profile: {}", message),
+        );
+    }
+    if let Some(diagnosis) = diagnose_message(&message, model) {
+        return diagnosis;
+    }
+    // Anthropic's 404 only names the model (`model: <name>`), so the endpoint itself exists
+    if status == 404 && !model.is_empty() && message.contains(model) {
+        return unknown_model(model, &message);
+    }
+    if detected.is_some_and(|d| d != protocol) || matches!(status, 404 | 405) {
+        let mut diagnosis = wrong_protocol(protocol, detected);
+        diagnosis.message = format!("{} ({}: {})", diagnosis.message, status, message);
+        return diagnosis;
+    }
+    ConnectionDiagnosis::new(DiagnosisKind::Http, format!("HTTP {}: {}", status, message))
+}
+
+/// Recognize "unknown model" errors, whichever provider phrased them
+fn diagnose_message(message: &str, model: &str) -> Option<ConnectionDiagnosis> {
+    let lower = message.to_lowercase();
+    let about_model = lower.contains("model");
+    let not_found = ["not found", "not_found", "does not exist", "unknown", "invalid model", "not supported"]
+        .iter()
+        .any(|needle| lower.contains(needle));
+
+    (about_model && not_found).then(|| unknown_model(model, message))
+}
+
+fn unknown_model(model: &str, message: &str) -> ConnectionDiagnosis {
+    ConnectionDiagnosis::new(
+        DiagnosisKind::UnknownModel,
+        format!("The server does not know the model '{}': {}", model, message),
+    )
+}
+
+fn wrong_protocol(configured: Protocol, detected: Option<Protocol>) -> ConnectionDiagnosis {
+    let message = match detected {
+        Some(detected) if detected != configured => format!(
+            "The server looks like it speaks the {} protocol, but the profile uses {}",
+            detected.as_str(),
+            configured.as_str()
+        ),
+        _ => format!(
+            "The server has no {} endpoint at this base URL. Check the base URL and protocol.",
+            configured.as_str()
+        ),
+    };
+    ConnectionDiagnosis::new(DiagnosisKind::WrongProtocol, message)
+}
+
+/// Pull the human-readable message out of the error bodies the supported providers send
+fn error_message(body: &str) -> Option<String> {
+    let json: serde_json::Value = serde_json::from_str(body.trim()).ok()?;
+    // Gemini wraps errors of streaming calls in an array
+    let json = if json.is_array() { &json[0] } else { &json };
+    json["error"]["message"]
+        .as_str()
+        .or_else(|| json["error"].as_str())
+        .or_else(|| json["message"].as_str())
+        .map(str::to_string)
+}
+
+/// Guess which protocol produced `body` from the shape of its JSON (plain or SSE `data:` lines)
+pub fn guess_protocol(body: &str) -> Option<Protocol> {
+    std::iter::once(body.trim())
+        .chain(body.lines().map(|line| line.trim().strip_prefix("data:").unwrap_or(line).trim()))
+        .filter_map(|text| serde_json::from_str::<serde_json::Value>(text).ok())
+        .find_map(|json| guess_from_json(&json))
+}
+
+fn guess_from_json(json: &serde_json::Value) -> Option<Protocol> {
+    if json.is_array() {
+        return guess_from_json(&json[0]);
+    }
+    let error = &json["error"];
+
+    if json["type"].is_string() && (json.get("message").is_some() || error.is_object() || json.get("delta").is_some()) {
+        // {"type":"error","error":{...}} or any of the message_* / content_block_* events
+        Some(Protocol::Anthropic)
+    } else if json.get("candidates").is_some() || json.get("usageMetadata").is_some() || error["status"].is_string() {
+        Some(Protocol::Gemini)
+    } else if json.get("choices").is_some() || (error.is_object() && error.get("message").is_some()) {
+        Some(Protocol::OpenAi)
+    } else if error.is_string() || (json["done"].is_boolean() && json.get("model").is_some()) {
+        Some(Protocol::Ollama)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,749 @@
+//! Structural differences between two versions of an ER, class or flowchart diagram, so an
+//! edit proposed by the model can be reviewed change by change and partially applied
+
+use crate::codegen::ClassModel;
+use crate::mermaid::{self, SHAPES};
+use crate::schema::{mermaid_name, relationship_label, ErModel};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Member name of a class annotation like `<<interface>>`, so a new annotation reads as a change
+const ANNOTATION: &str = "<<annotation>>";
+/// Share of member names two entities need in common to count as one renamed entity
+const RENAME_OVERLAP: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Renamed,
+    Changed,
+}
+
+/// One difference between the current diagram and the proposed one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagramChange {
+    pub kind: ChangeKind,
+    pub element: String, // 'entity', 'attribute', 'edge', 'label'
+    /// Entity, class or node the change belongs to, by its current name; `A -> B` for edges
+    pub subject: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Er,
+    Class,
+    Flowchart,
+}
+
+impl Kind {
+    fn parse(kind: &str) -> Result<Self, String> {
+        match kind {
+            "er" => Ok(Kind::Er),
+            "uml" => Ok(Kind::Class),
+            "flowchart" => Ok(Kind::Flowchart),
+            _ => Err(format!("Structural diffs are only available for ER, class and flowchart diagrams, not {}", kind)),
+        }
+    }
+}
+
+/// Attribute, field, method or class annotation
+#[derive(Debug, Clone, PartialEq)]
+struct Member {
+    name: String,
+    text: String,
+}
+
+impl Member {
+    /// The text without the member name, equal for a member that was only renamed
+    fn signature(&self) -> String {
+        self.text.replacen(self.name.trim_end_matches("()"), "", 1)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Node {
+    name: String,
+    /// Flowchart shape with its text like `[Sign up]`, or class type parameters like `~T~`
+    label: Option<String>,
+    members: Vec<Member>,
+    /// Subgraphs of a flowchart node, outermost first
+    group: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Edge {
+    source: String,
+    target: String,
+    /// ER relationship spec, class arrow with target multiplicity, or flowchart link
+    arrow: String,
+    label: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Structure {
+    kind: Kind,
+    header: String,
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+    /// Flowchart statements kept as written: styles, classes and clicks
+    extra: Vec<String>,
+}
+
+/// How a change is applied to the current structure; nodes are named as in the current diagram,
+/// edges taken from the proposal as in the proposal
+#[derive(Debug, Clone)]
+enum Op {
+    AddNode(Node),
+    RemoveNode(String),
+    RenameNode(String, String),
+    AddMember(String, usize, Member),
+    RemoveMember(String, String),
+    ReplaceMember(String, String, Member),
+    SetLabel(String, Option<String>),
+    AddEdge(Edge),
+    RemoveEdge(usize),
+    ReplaceEdge(usize, Edge),
+}
+
+/// Changes that turn `current` into `proposed`: entities first, then their attributes and
+/// labels, then edges
+pub fn diff(kind: &str, current: &str, proposed: &str) -> Result<Vec<DiagramChange>, String> {
+    let kind = Kind::parse(kind)?;
+    let current = Structure::read(kind, current)?;
+    let proposed = Structure::read(kind, proposed)?;
+    Ok(changes(&current, &proposed).into_iter().map(|(change, _)| change).collect())
+}
+
+/// `current` with the `accepted` changes from `diff` applied; everything else stays as it is
+pub fn apply(kind: &str, current: &str, proposed: &str, accepted: &[DiagramChange]) -> Result<String, String> {
+    let kind = Kind::parse(kind)?;
+    let mut structure = Structure::read(kind, current)?;
+    let mut pending = changes(&structure, &Structure::read(kind, proposed)?);
+
+    let mut ops = Vec::new();
+    for change in accepted {
+        let index = pending
+            .iter()
+            .position(|(c, _)| c == change)
+            .ok_or(format!("The change to {} is not part of the proposal", change.subject))?;
+        ops.push(pending.remove(index).1);
+    }
+    // Edges from the proposal keep the current name of nodes whose rename was declined
+    let declined: HashMap<String, String> = pending
+        .iter()
+        .filter_map(|(_, op)| match op {
+            Op::RenameNode(old, new) => Some((new.clone(), old.clone())),
+            _ => None,
+        })
+        .collect();
+
+    structure.apply(ops, &declined);
+    Ok(structure.render())
+}
+
+fn changes(current: &Structure, proposed: &Structure) -> Vec<(DiagramChange, Op)> {
+    let mut out = Vec::new();
+    let pairs = match_nodes(current.kind, &current.nodes, &proposed.nodes);
+    let new_of: HashMap<usize, usize> = pairs.iter().copied().collect();
+    let renames: HashMap<&str, &str> = pairs
+        .iter()
+        .map(|&(old, new)| (current.nodes[old].name.as_str(), proposed.nodes[new].name.as_str()))
+        .collect();
+
+    for (i, node) in current.nodes.iter().enumerate() {
+        match new_of.get(&i).map(|&j| &proposed.nodes[j]) {
+            None => out.push((
+                change(ChangeKind::Removed, "entity", &node.name, node.label.clone(), None),
+                Op::RemoveNode(node.name.clone()),
+            )),
+            Some(new) if new.name != node.name => out.push((
+                change(ChangeKind::Renamed, "entity", &node.name, Some(node.name.clone()), Some(new.name.clone())),
+                Op::RenameNode(node.name.clone(), new.name.clone()),
+            )),
+            Some(_) => {}
+        }
+    }
+    let matched: HashSet<usize> = new_of.values().copied().collect();
+    for (_, node) in proposed.nodes.iter().enumerate().filter(|(j, _)| !matched.contains(j)) {
+        out.push((
+            change(ChangeKind::Added, "entity", &node.name, None, node.label.clone()),
+            Op::AddNode(node.clone()),
+        ));
+    }
+
+    for &(old, new) in &pairs {
+        let (old, new) = (&current.nodes[old], &proposed.nodes[new]);
+        if old.label != new.label {
+            out.push((
+                change(ChangeKind::Changed, "label", &old.name, old.label.clone(), new.label.clone()),
+                Op::SetLabel(old.name.clone(), new.label.clone()),
+            ));
+        }
+        member_changes(old, new, &mut out);
+    }
+
+    edge_changes(current, proposed, &renames, &mut out);
+    out
+}
+
+fn change(kind: ChangeKind, element: &str, subject: &str, before: Option<String>, after: Option<String>) -> DiagramChange {
+    DiagramChange { kind, element: element.to_string(), subject: subject.to_string(), before, after }
+}
+
+/// Pairs of (current, proposed) node indexes: same name, or renamed with the same flowchart
+/// label or mostly the same members
+fn match_nodes(kind: Kind, current: &[Node], proposed: &[Node]) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    let mut taken = vec![false; proposed.len()];
+    for (i, node) in current.iter().enumerate() {
+        if let Some(j) = proposed.iter().position(|n| n.name == node.name) {
+            pairs.push((i, j));
+            taken[j] = true;
+        }
+    }
+
+    let unmatched: Vec<usize> = (0..current.len()).filter(|i| !pairs.iter().any(|(old, _)| old == i)).collect();
+    for i in unmatched {
+        let best = proposed
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| !taken[*j])
+            .map(|(j, candidate)| (j, similarity(kind, &current[i], candidate)))
+            .filter(|(_, score)| *score >= RENAME_OVERLAP)
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((j, _)) = best {
+            pairs.push((i, j));
+            taken[j] = true;
+        }
+    }
+    pairs.sort_unstable();
+    pairs
+}
+
+fn similarity(kind: Kind, a: &Node, b: &Node) -> f64 {
+    if kind == Kind::Flowchart {
+        return if a.label.is_some() && a.label == b.label { 1.0 } else { 0.0 };
+    }
+    let names = |node: &Node| -> HashSet<String> {
+        node.members.iter().filter(|m| m.name != ANNOTATION).map(|m| m.name.clone()).collect()
+    };
+    let (a, b) = (names(a), names(b));
+    let largest = a.len().max(b.len());
+    if largest == 0 {
+        return 0.0;
+    }
+    a.intersection(&b).count() as f64 / largest as f64
+}
+
+fn member_changes(old: &Node, new: &Node, out: &mut Vec<(DiagramChange, Op)>) {
+    let owner = &old.name;
+    let mut new_of: Vec<Option<usize>> = vec![None; old.members.len()];
+    let mut taken = vec![false; new.members.len()];
+    for (i, member) in old.members.iter().enumerate() {
+        if let Some(j) = (0..new.members.len()).find(|&j| !taken[j] && new.members[j].name == member.name) {
+            new_of[i] = Some(j);
+            taken[j] = true;
+        }
+    }
+    for (i, member) in old.members.iter().enumerate() {
+        if new_of[i].is_some() {
+            continue;
+        }
+        if let Some(j) = (0..new.members.len()).find(|&j| !taken[j] && new.members[j].signature() == member.signature()) {
+            new_of[i] = Some(j);
+            taken[j] = true;
+        }
+    }
+
+    for (i, member) in old.members.iter().enumerate() {
+        let (kind, op) = match new_of[i].map(|j| &new.members[j]) {
+            None => {
+                out.push((
+                    change(ChangeKind::Removed, "attribute", owner, Some(member.text.clone()), None),
+                    Op::RemoveMember(owner.clone(), member.name.clone()),
+                ));
+                continue;
+            }
+            Some(replacement) if replacement.text == member.text => continue,
+            Some(replacement) => {
+                let kind = if replacement.name == member.name { ChangeKind::Changed } else { ChangeKind::Renamed };
+                (kind, Op::ReplaceMember(owner.clone(), member.name.clone(), replacement.clone()))
+            }
+        };
+        let after = new_of[i].map(|j| new.members[j].text.clone());
+        out.push((change(kind, "attribute", owner, Some(member.text.clone()), after), op));
+    }
+    for (j, member) in new.members.iter().enumerate().filter(|(j, _)| !taken[*j]) {
+        out.push((
+            change(ChangeKind::Added, "attribute", owner, None, Some(member.text.clone())),
+            Op::AddMember(owner.clone(), j, member.clone()),
+        ));
+    }
+}
+
+fn edge_changes(current: &Structure, proposed: &Structure, renames: &HashMap<&str, &str>, out: &mut Vec<(DiagramChange, Op)>) {
+    let renamed = |name: &str| renames.get(name).copied().unwrap_or(name).to_string();
+    let ends = |edge: &Edge| (renamed(&edge.source), renamed(&edge.target));
+    let mut new_of: Vec<Option<usize>> = vec![None; current.edges.len()];
+    let mut taken = vec![false; proposed.edges.len()];
+
+    // Identical edges first, so a changed edge is not paired with an unchanged twin
+    for exact in [true, false] {
+        for (i, edge) in current.edges.iter().enumerate() {
+            if new_of[i].is_some() {
+                continue;
+            }
+            let found = (0..proposed.edges.len()).find(|&j| {
+                let candidate = &proposed.edges[j];
+                !taken[j]
+                    && ends(edge) == (candidate.source.clone(), candidate.target.clone())
+                    && (!exact || (edge.arrow == candidate.arrow && edge.label == candidate.label))
+            });
+            if let Some(j) = found {
+                new_of[i] = Some(j);
+                taken[j] = true;
+            }
+        }
+    }
+
+    for (i, edge) in current.edges.iter().enumerate() {
+        let subject = format!("{} -> {}", edge.source, edge.target);
+        match new_of[i].map(|j| &proposed.edges[j]) {
+            None => out.push((
+                change(ChangeKind::Removed, "edge", &subject, Some(current.edge_line(edge)), None),
+                Op::RemoveEdge(i),
+            )),
+            Some(new) if new.arrow == edge.arrow && new.label == edge.label => {}
+            Some(new) if new.arrow == edge.arrow => out.push((
+                change(ChangeKind::Changed, "label", &subject, edge.label.clone(), new.label.clone()),
+                Op::ReplaceEdge(i, new.clone()),
+            )),
+            Some(new) => out.push((
+                change(ChangeKind::Changed, "edge", &subject, Some(current.edge_line(edge)), Some(proposed.edge_line(new))),
+                Op::ReplaceEdge(i, new.clone()),
+            )),
+        }
+    }
+    for (_, edge) in proposed.edges.iter().enumerate().filter(|(j, _)| !taken[*j]) {
+        let subject = format!("{} -> {}", edge.source, edge.target);
+        out.push((
+            change(ChangeKind::Added, "edge", &subject, None, Some(proposed.edge_line(edge))),
+            Op::AddEdge(edge.clone()),
+        ));
+    }
+}
+
+impl Structure {
+    fn read(kind: Kind, code: &str) -> Result<Self, String> {
+        match kind {
+            Kind::Er => Ok(Self::from_er(&ErModel::from_mermaid(code)?)),
+            Kind::Class => Ok(Self::from_class(&ClassModel::from_mermaid(code)?)),
+            Kind::Flowchart => read_flowchart(code),
+        }
+    }
+
+    fn from_er(model: &ErModel) -> Self {
+        let nodes = model.entities
+            .iter()
+            .map(|entity| Node {
+                name: entity.name.clone(),
+                label: None,
+                members: entity.attributes
+                    .iter()
+                    .map(|a| Member { name: a.name.clone(), text: a.to_mermaid() })
+                    .collect(),
+                group: Vec::new(),
+            })
+            .collect();
+        let edges = model.relationships
+            .iter()
+            .map(|r| Edge { source: r.left.clone(), target: r.right.clone(), arrow: r.spec(), label: Some(r.label.clone()) })
+            .collect();
+        Structure { kind: Kind::Er, header: "erDiagram".to_string(), nodes, edges, extra: Vec::new() }
+    }
+
+    fn from_class(model: &ClassModel) -> Self {
+        let nodes = model.classes
+            .iter()
+            .map(|class| {
+                let annotation = class.annotation
+                    .iter()
+                    .map(|a| Member { name: ANNOTATION.to_string(), text: format!("<<{}>>", a) });
+                let fields = class.fields.iter().map(|f| Member { name: f.name.clone(), text: f.to_mermaid() });
+                let methods = class.methods.iter().map(|m| Member { name: format!("{}()", m.name), text: m.to_mermaid() });
+                Node {
+                    name: class.name.clone(),
+                    label: (!class.generics.is_empty()).then(|| format!("~{}~", class.generics.join(", "))),
+                    members: annotation.chain(fields).chain(methods).collect(),
+                    group: Vec::new(),
+                }
+            })
+            .collect();
+        let edges = model.relations
+            .iter()
+            .map(|r| Edge {
+                source: r.source.clone(),
+                target: r.target.clone(),
+                arrow: match &r.target_multiplicity {
+                    Some(multiplicity) => format!("{} \"{}\"", r.arrow(), multiplicity),
+                    None => r.arrow().to_string(),
+                },
+                label: r.label.clone(),
+            })
+            .collect();
+        Structure { kind: Kind::Class, header: "classDiagram".to_string(), nodes, edges, extra: Vec::new() }
+    }
+
+    fn node_mut(&mut self, name: &str) -> Option<&mut Node> {
+        self.nodes.iter_mut().find(|n| n.name == name)
+    }
+
+    /// Apply `ops`; `declined` maps proposed names back to current ones for renames left out
+    fn apply(&mut self, ops: Vec<Op>, declined: &HashMap<String, String>) {
+        let current_name = |name: &str| declined.get(name).cloned().unwrap_or_else(|| name.to_string());
+        let local = |edge: &Edge| Edge { source: current_name(&edge.source), target: current_name(&edge.target), ..edge.clone() };
+
+        let mut removed_edges = HashSet::new();
+        let mut replaced_edges = HashMap::new();
+        let mut added_edges = Vec::new();
+        let mut removed_nodes = Vec::new();
+        let mut renamed_nodes = Vec::new();
+        let mut added_nodes = Vec::new();
+
+        // Members and labels first, while every node still has its current name
+        for op in ops {
+            match op {
+                Op::AddMember(owner, index, member) => {
+                    if let Some(node) = self.node_mut(&owner) {
+                        let index = index.min(node.members.len());
+                        node.members.insert(index, member);
+                    }
+                }
+                Op::RemoveMember(owner, name) => {
+                    if let Some(node) = self.node_mut(&owner) {
+                        if let Some(index) = node.members.iter().position(|m| m.name == name) {
+                            node.members.remove(index);
+                        }
+                    }
+                }
+                Op::ReplaceMember(owner, name, member) => {
+                    if let Some(member_slot) = self.node_mut(&owner).and_then(|n| n.members.iter_mut().find(|m| m.name == name)) {
+                        *member_slot = member;
+                    }
+                }
+                Op::SetLabel(owner, label) => {
+                    if let Some(node) = self.node_mut(&owner) {
+                        node.label = label;
+                    }
+                }
+                Op::AddEdge(edge) => added_edges.push(local(&edge)),
+                Op::RemoveEdge(index) => {
+                    removed_edges.insert(index);
+                }
+                Op::ReplaceEdge(index, edge) => {
+                    replaced_edges.insert(index, local(&edge));
+                }
+                Op::RemoveNode(name) => removed_nodes.push(name),
+                Op::RenameNode(old, new) => renamed_nodes.push((old, new)),
+                Op::AddNode(node) => added_nodes.push(node),
+            }
+        }
+
+        let edges = std::mem::take(&mut self.edges);
+        self.edges = edges
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| !removed_edges.contains(i))
+            .map(|(i, edge)| replaced_edges.remove(&i).unwrap_or(edge))
+            .chain(added_edges)
+            .collect();
+
+        // Removing a node drops its edges, as the diagram would otherwise declare it again
+        for name in removed_nodes {
+            self.nodes.retain(|n| n.name != name);
+            self.edges.retain(|e| e.source != name && e.target != name);
+        }
+        for (old, new) in renamed_nodes {
+            if let Some(node) = self.node_mut(&old) {
+                node.name = new.clone();
+            }
+            for edge in &mut self.edges {
+                if edge.source == old {
+                    edge.source = new.clone();
+                }
+                if edge.target == old {
+                    edge.target = new.clone();
+                }
+            }
+        }
+        self.nodes.extend(added_nodes);
+    }
+
+    fn render(&self) -> String {
+        let mut out = format!("{}\n", self.header);
+        match self.kind {
+            Kind::Er => {
+                for node in &self.nodes {
+                    let name = mermaid_name(&node.name);
+                    if node.members.is_empty() {
+                        out.push_str(&format!("    {}\n", name));
+                        continue;
+                    }
+                    out.push_str(&format!("    {} {{\n", name));
+                    for member in &node.members {
+                        out.push_str(&format!("        {}\n", member.text));
+                    }
+                    out.push_str("    }\n");
+                }
+            }
+            Kind::Class => {
+                for node in &self.nodes {
+                    let label = node.label.as_deref().unwrap_or_default();
+                    if node.members.is_empty() {
+                        out.push_str(&format!("    class {}{}\n", node.name, label));
+                        continue;
+                    }
+                    out.push_str(&format!("    class {}{} {{\n", node.name, label));
+                    for member in &node.members {
+                        out.push_str(&format!("        {}\n", member.text));
+                    }
+                    out.push_str("    }\n");
+                }
+            }
+            Kind::Flowchart => self.render_group(&mut out, &[], 1),
+        }
+        for edge in &self.edges {
+            out.push_str(&format!("    {}\n", self.edge_line(edge)));
+        }
+        for line in &self.extra {
+            out.push_str(&format!("    {}\n", line));
+        }
+        out
+    }
+
+    /// Flowchart nodes of the subgraph at `path`, then its nested subgraphs
+    fn render_group(&self, out: &mut String, path: &[String], depth: usize) {
+        let indent = "    ".repeat(depth);
+        for node in self.nodes.iter().filter(|n| n.group == path) {
+            out.push_str(&format!("{}{}{}\n", indent, node.name, node.label.as_deref().unwrap_or_default()));
+        }
+        let mut children: Vec<&String> = Vec::new();
+        for node in self.nodes.iter().filter(|n| n.group.len() > path.len() && n.group.starts_with(path)) {
+            let child = &node.group[path.len()];
+            if !children.contains(&child) {
+                children.push(child);
+            }
+        }
+        for child in children {
+            out.push_str(&format!("{}subgraph {}\n", indent, child));
+            let mut nested = path.to_vec();
+            nested.push(child.clone());
+            self.render_group(out, &nested, depth + 1);
+            out.push_str(&format!("{}end\n", indent));
+        }
+    }
+
+    fn edge_line(&self, edge: &Edge) -> String {
+        match self.kind {
+            Kind::Er => format!(
+                "{} {} {} : {}",
+                mermaid_name(&edge.source),
+                edge.arrow,
+                mermaid_name(&edge.target),
+                relationship_label(edge.label.as_deref().unwrap_or_default())
+            ),
+            Kind::Class => match &edge.label {
+                Some(label) => format!("{} {} {} : {}", edge.source, edge.arrow, edge.target, label),
+                None => format!("{} {} {}", edge.source, edge.arrow, edge.target),
+            },
+            Kind::Flowchart => match &edge.label {
+                Some(label) => format!("{} {}|{}| {}", edge.source, edge.arrow, label, edge.target),
+                None => format!("{} {} {}", edge.source, edge.arrow, edge.target),
+            },
+        }
+    }
+}
+
+/// Read a flowchart into nodes with their shapes and subgraphs and edges with their labels
+fn read_flowchart(code: &str) -> Result<Structure, String> {
+    if let Some(diagnostic) = mermaid::validate("flowchart", code)?.first() {
+        return Err(format!("The flowchart is not valid: {}", diagnostic));
+    }
+
+    let lines: Vec<&str> = code.lines().collect();
+    let statements = mermaid::statement_lines(&lines);
+    let header = statements.first().map_or("flowchart TD", |(_, line)| line.trim().trim_end_matches(';'));
+    let mut structure = Structure {
+        kind: Kind::Flowchart,
+        header: header.to_string(),
+        nodes: Vec::new(),
+        edges: Vec::new(),
+        extra: Vec::new(),
+    };
+    let mut group: Vec<String> = Vec::new();
+    // Subgraph ids can be link ends, but are not nodes of their own
+    let mut subgraph_ids: HashSet<String> = HashSet::new();
+
+    for (_, line) in statements.into_iter().skip(1) {
+        let text = line.trim().trim_end_matches(';').trim_end();
+        match text.split_whitespace().next() {
+            Some("subgraph") => {
+                let title = text["subgraph".len()..].trim().to_string();
+                if let Some(id) = title.split(['[', ' ']).next() {
+                    subgraph_ids.insert(id.to_string());
+                }
+                group.push(title);
+                continue;
+            }
+            Some("end") if text == "end" => {
+                group.pop();
+                continue;
+            }
+            // Directions belong to the subgraph they are in, which is rebuilt without them
+            Some("direction") => continue,
+            Some("classDef" | "class" | "style" | "linkStyle" | "click") => {
+                structure.extra.push(text.to_string());
+                continue;
+            }
+            _ => {}
+        }
+        for statement in mermaid::split_statements(text) {
+            read_flow_statement(statement, &group, &subgraph_ids, &mut structure);
+        }
+    }
+    Ok(structure)
+}
+
+fn read_flow_statement(text: &str, group: &[String], subgraph_ids: &HashSet<String>, structure: &mut Structure) {
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    // Nodes before the pending link, the link, and the nodes after it
+    let mut sources: Vec<String> = Vec::new();
+    let mut link: Option<(String, Option<String>)> = None;
+    let mut targets: Vec<String> = Vec::new();
+
+    let connect = |sources: &[String], link: Option<(String, Option<String>)>, targets: &[String], structure: &mut Structure| {
+        if let Some((arrow, label)) = link {
+            for source in sources {
+                for target in targets {
+                    structure.edges.push(Edge { source: source.clone(), target: target.clone(), arrow: arrow.clone(), label: label.clone() });
+                }
+            }
+        }
+    };
+
+    while i < text.len() {
+        let c = text[i..].chars().next().unwrap_or_default();
+        if c.is_whitespace() || c == '&' {
+            i += c.len_utf8();
+        } else if c.is_alphanumeric() || c == '_' {
+            let start = i;
+            while let Some(c) = text[i..].chars().next().filter(|c| c.is_alphanumeric() || *c == '_') {
+                i += c.len_utf8();
+            }
+            let id = text[start..i].to_string();
+            i = skip_class_suffix(text, i);
+            let shape_start = i;
+            if text[i..].starts_with("@{") {
+                i = text[i..].find('}').map_or(text.len(), |close| i + close + 1);
+            } else if let Some((opener, closers)) = SHAPES.iter().find(|(o, _)| text[i..].starts_with(o)) {
+                i = shape_end(text, i + opener.len(), closers);
+            } else if text[i..].starts_with('>') && !text[..i].ends_with(' ') {
+                i = shape_end(text, i + 1, &["]"]);
+            }
+            let label = (i > shape_start).then(|| text[shape_start..i].to_string());
+            i = skip_class_suffix(text, i);
+
+            if !subgraph_ids.contains(&id) {
+                match structure.nodes.iter_mut().find(|n| n.name == id) {
+                    Some(node) => {
+                        if label.is_some() {
+                            node.label = label;
+                        }
+                    }
+                    None => structure.nodes.push(Node { name: id.clone(), label, members: Vec::new(), group: group.to_vec() }),
+                }
+            }
+            targets.push(id);
+        } else if "-=.<~".contains(c) {
+            connect(&sources, link.take(), &targets, structure);
+            sources = std::mem::take(&mut targets);
+
+            let start = i;
+            while i < text.len() && b"-=.<>~".contains(&bytes[i]) {
+                i += 1;
+            }
+            if matches!(bytes.get(i), Some(b'o' | b'x'))
+                && matches!(bytes.get(i + 1), None | Some(b' ' | b'\t'))
+                && text[start..i].ends_with(['-', '='])
+            {
+                i += 1;
+            }
+            let mut arrow = text[start..i].to_string();
+            let mut label = None;
+            // Text on the link itself: `A -- text --> B` becomes `A -->|text| B`
+            if matches!(arrow.as_str(), "--" | "==" | "-.") {
+                let rest = &text[i..];
+                let close = rest.find("-->").or_else(|| rest.find("---")).or_else(|| rest.find("==>")).or_else(|| rest.find(".->"));
+                if let Some(close) = close {
+                    label = Some(rest[..close].trim().to_string());
+                    let closer = i + close;
+                    i = closer;
+                    while i < text.len() && b"-=.>".contains(&bytes[i]) {
+                        i += 1;
+                    }
+                    arrow = match &text[closer..i] {
+                        dotted if dotted.starts_with('.') => format!("-{}", dotted),
+                        closing => closing.to_string(),
+                    };
+                }
+            }
+            // Text on the link in pipes: `-->|yes|`
+            let after = i + text[i..].len() - text[i..].trim_start().len();
+            if text[after..].starts_with('|') {
+                if let Some(close) = text[after + 1..].find('|') {
+                    label = Some(text[after + 1..after + 1 + close].trim().to_string());
+                    i = after + close + 2;
+                }
+            }
+            link = Some((arrow, label));
+        } else {
+            // Not reached for a diagram that passed validation
+            break;
+        }
+    }
+    connect(&sources, link, &targets, structure);
+}
+
+fn skip_class_suffix(text: &str, mut i: usize) -> usize {
+    if text[i..].starts_with(":::") {
+        i += 3;
+        while let Some(c) = text[i..].chars().next().filter(|c| c.is_alphanumeric() || *c == '_' || *c == '-') {
+            i += c.len_utf8();
+        }
+    }
+    i
+}
+
+/// Offset after the closer of a shape whose text starts at `i`
+fn shape_end(text: &str, mut i: usize, closers: &[&str]) -> usize {
+    let mut quoted = false;
+    while i < text.len() {
+        if !quoted {
+            if let Some(closer) = closers.iter().find(|c| text[i..].starts_with(**c)) {
+                return i + closer.len();
+            }
+        }
+        let c = text[i..].chars().next().unwrap_or_default();
+        if c == '"' {
+            quoted = !quoted;
+        }
+        i += c.len_utf8();
+    }
+    text.len()
+}
@@ -0,0 +1,184 @@
+//! Line-based comparison and three-way merge of topic and diagram content
+
+use serde::Serialize;
+
+/// How applying a refinement relates to what the topic holds now
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MergeStatus {
+    /// The topic still holds the refined text's original, so the refinement replaces it
+    Applied,
+    /// The topic already holds the refined text
+    AlreadyApplied,
+    /// The topic was edited since, but the edits and the refinement touch different lines
+    Merged,
+    /// The edits and the refinement change the same lines; `content` has conflict markers
+    Conflict,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeResult {
+    pub status: MergeStatus,
+    pub content: String,
+    pub conflicts: usize,
+}
+
+/// Merge `refined` (derived from `base`) into `current` (also derived from `base`), diff3 style:
+/// every region changed on one side only takes that side, regions changed on both sides the
+/// same way take either, and anything else becomes a conflict block.
+pub fn merge_refinement(base: &str, current: &str, refined: &str) -> MergeResult {
+    if current == base {
+        return MergeResult { status: MergeStatus::Applied, content: refined.to_string(), conflicts: 0 };
+    }
+    if current == refined {
+        return MergeResult { status: MergeStatus::AlreadyApplied, content: current.to_string(), conflicts: 0 };
+    }
+
+    let base_lines: Vec<&str> = base.lines().collect();
+    let current_lines: Vec<&str> = current.lines().collect();
+    let refined_lines: Vec<&str> = refined.lines().collect();
+
+    // Base line index -> matching index in each side
+    let mut in_current = vec![None; base_lines.len()];
+    for (b, c) in matching_lines(&base_lines, &current_lines) {
+        in_current[b] = Some(c);
+    }
+    let mut in_refined = vec![None; base_lines.len()];
+    for (b, r) in matching_lines(&base_lines, &refined_lines) {
+        in_refined[b] = Some(r);
+    }
+
+    let mut out: Vec<&str> = Vec::new();
+    let mut conflicts = 0;
+    let (mut b, mut c, mut r) = (0, 0, 0);
+
+    // Lines unchanged on both sides keep the three texts in step; everything between them is
+    // merged as one chunk
+    let stable = (0..base_lines.len())
+        .filter_map(|i| Some((i, in_current[i]?, in_refined[i]?)))
+        .chain(std::iter::once((base_lines.len(), current_lines.len(), refined_lines.len())));
+    for (next_b, next_c, next_r) in stable {
+        let base_chunk = &base_lines[b..next_b];
+        let current_chunk = &current_lines[c..next_c];
+        let refined_chunk = &refined_lines[r..next_r];
+
+        if current_chunk == base_chunk || current_chunk == refined_chunk {
+            out.extend_from_slice(refined_chunk);
+        } else if refined_chunk == base_chunk {
+            out.extend_from_slice(current_chunk);
+        } else {
+            conflicts += 1;
+            out.push("<<<<<<< current");
+            out.extend_from_slice(current_chunk);
+            out.push("=======");
+            out.extend_from_slice(refined_chunk);
+            out.push(">>>>>>> refined");
+        }
+
+        if next_b < base_lines.len() {
+            out.push(base_lines[next_b]);
+        }
+        (b, c, r) = (next_b + 1, next_c + 1, next_r + 1);
+    }
+
+    let mut content = out.join("\n");
+    if current.ends_with('\n') || refined.ends_with('\n') {
+        content.push('\n');
+    }
+
+    MergeResult {
+        status: if conflicts == 0 { MergeStatus::Merged } else { MergeStatus::Conflict },
+        content,
+        conflicts,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LineChange {
+    Unchanged,
+    Added,
+    Removed,
+}
+
+/// A line of a diff, with its 1-based line number in the old and new text
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffLine {
+    pub change: LineChange,
+    pub text: String,
+    pub old_line: Option<usize>,
+    pub new_line: Option<usize>,
+}
+
+/// Every line of `old` and `new` in order; between unchanged lines, removed lines come before
+/// the added ones
+pub fn line_diff(old: &str, new: &str) -> Vec<DiffLine> {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+
+    let mut lines: Vec<DiffLine> = Vec::new();
+    let (mut o, mut n) = (0, 0);
+    let matches = matching_lines(&old_lines, &new_lines);
+    for (next_o, next_n) in matches.into_iter().chain(std::iter::once((old_lines.len(), new_lines.len()))) {
+        for (i, text) in old_lines.iter().enumerate().take(next_o).skip(o) {
+            lines.push(DiffLine { change: LineChange::Removed, text: text.to_string(), old_line: Some(i + 1), new_line: None });
+        }
+        for (j, text) in new_lines.iter().enumerate().take(next_n).skip(n) {
+            lines.push(DiffLine { change: LineChange::Added, text: text.to_string(), old_line: None, new_line: Some(j + 1) });
+        }
+        if next_o < old_lines.len() {
+            lines.push(DiffLine {
+                change: LineChange::Unchanged,
+                text: old_lines[next_o].to_string(),
+                old_line: Some(next_o + 1),
+                new_line: Some(next_n + 1),
+            });
+        }
+        (o, n) = (next_o + 1, next_n + 1);
+    }
+    lines
+}
+
+/// Index pairs of a longest common subsequence of `a` and `b`, in increasing order
+pub fn matching_lines(a: &[&str], b: &[&str]) -> Vec<(usize, usize)> {
+    // Common prefix and suffix need no table
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    // lengths[i][j] = LCS length of a_mid[i..] and b_mid[j..]
+    let width = b_mid.len() + 1;
+    let mut lengths = vec![0u32; (a_mid.len() + 1) * width];
+    for i in (0..a_mid.len()).rev() {
+        for j in (0..b_mid.len()).rev() {
+            lengths[i * width + j] = if a_mid[i] == b_mid[j] {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut pairs: Vec<(usize, usize)> = (0..prefix).map(|i| (i, i)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < a_mid.len() && j < b_mid.len() {
+        if a_mid[i] == b_mid[j] {
+            pairs.push((prefix + i, prefix + j));
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs.extend((0..suffix).map(|k| (a.len() - suffix + k, b.len() - suffix + k)));
+    pairs
+}
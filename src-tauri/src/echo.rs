@@ -0,0 +1,338 @@
+//! Offline provider for demos and tests: answers every task with deterministic output derived
+//! from the raw task inputs, streamed as OpenAI-style SSE so it goes through `relay_stream`
+//! exactly like a real provider.
+
+use crate::llm::replay_stream;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::time::Duration;
+
+/// `provider` value that selects the offline provider
+pub const PROVIDER: &str = "echo";
+
+/// Pause between chunks, so the UI shows the answer arriving like a real stream
+const CHUNK_DELAY: Duration = Duration::from_millis(15);
+const MAX_ENTITIES: usize = 5;
+const MAX_STEPS: usize = 6;
+
+const STOP_WORDS: &[&str] = &[
+    "able", "about", "above", "after", "again", "also", "among", "another", "because", "been", "before",
+    "being", "below", "between", "both", "build", "could", "does", "doing", "during", "each", "easy",
+    "every", "from", "further", "have", "having", "into", "just", "like", "make", "many", "more", "most",
+    "much", "must", "need", "needs", "only", "other", "over", "same", "should", "some", "such", "than",
+    "that", "their", "them", "then", "there", "these", "they", "this", "those", "through", "under",
+    "until", "upon", "very", "want", "what", "when", "where", "which", "while", "will", "with", "within",
+    "without", "would", "your", "allow", "allows", "simple", "using", "based", "app", "application",
+    "system", "project", "platform", "tool", "user", "users", "support", "supports", "feature", "features",
+];
+
+pub fn is_echo(provider: &str) -> bool {
+    provider == PROVIDER
+}
+
+/// The answer for a prompt task (see `prompts::PROMPT_TASKS`) given its template variables
+pub fn respond(task: &str, variables: &[(&str, &str)]) -> String {
+    let var = |name: &str| {
+        variables
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| *value)
+            .unwrap_or_default()
+    };
+
+    match task {
+        "refine" | "refine_topic" | "refine_section" => refine(var("content")),
+        "refine_structured" => refine_structured(var("content")),
+        "er" => er_diagram(&nouns(var("content"))),
+        "uml" => class_diagram(&nouns(var("content"))),
+        "flowchart" => flowchart(var("content")),
+        "journey" => journey(var("content")),
+        "sequence" => sequence_diagram(&nouns(var("content"))),
+        "state" => state_diagram(&nouns(var("content"))),
+        "c4" => c4_diagram(&nouns(var("content"))),
+        "stories" => user_stories(&nouns(var("content"))),
+        "ask" => answer(var("diagram_type"), var("current_diagram"), var("question")),
+        _ if task.starts_with("edit_") => edit(var("current_diagram"), var("instruction")),
+        _ => normalize_whitespace(var("content")),
+    }
+}
+
+/// The answer to a follow-up `message` in a thread whose first turn ran `task` and whose last
+/// answer was `previous`: diagrams get the instruction as a comment, text gets it appended
+pub fn follow_up(task: &str, previous: &str, message: &str) -> String {
+    match task {
+        "refine" | "refine_topic" | "refine_section" | "refine_structured" | "stories" | "ask" => format!(
+            "{}\n\n## Follow-up\n- {}\n",
+            previous.trim_end(),
+            normalize_whitespace(message)
+        ),
+        _ => edit(previous, message),
+    }
+}
+
+/// `text` as an OpenAI-style SSE body, a few words per event
+pub fn sse_stream(text: &str) -> impl futures_util::Stream<Item = Result<Vec<u8>, Infallible>> {
+    replay_stream(split_chunks(text), CHUNK_DELAY)
+}
+
+/// Break `text` after whitespace once a piece is at least a dozen characters long
+fn split_chunks(text: &str) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for c in text.chars() {
+        current.push(c);
+        if c.is_whitespace() && current.chars().count() >= 12 {
+            chunks.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+fn normalize_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Sentences of `text` with normalized whitespace and a final period
+fn sentences(text: &str) -> Vec<String> {
+    normalize_whitespace(text)
+        .split_inclusive(['.', '!', '?'])
+        .map(|s| s.trim().trim_start_matches(['-', '*', '#']).trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            let mut sentence = s.to_string();
+            if !sentence.ends_with(['.', '!', '?']) {
+                sentence.push('.');
+            }
+            let mut chars = sentence.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => sentence,
+            }
+        })
+        .collect()
+}
+
+/// Likely nouns of `text`: the most frequent non-trivial words, singularized, in order of
+/// frequency and then first appearance
+fn nouns(text: &str) -> Vec<String> {
+    let mut counts: HashMap<String, (usize, usize)> = HashMap::new();
+
+    for (position, word) in text
+        .split(|c: char| !c.is_alphabetic())
+        .map(str::to_lowercase)
+        .filter(|w| w.chars().count() >= 4 && !STOP_WORDS.contains(&w.as_str()))
+        .filter(|w| !w.ends_with("ing") && !w.ends_with("ly") && !w.ends_with("ed"))
+        .enumerate()
+    {
+        let singular = singularize(&word);
+        counts.entry(singular).or_insert((0, position)).0 += 1;
+    }
+
+    let mut ranked: Vec<(String, (usize, usize))> = counts.into_iter().collect();
+    ranked.sort_by(|(_, (count_a, first_a)), (_, (count_b, first_b))| {
+        count_b.cmp(count_a).then(first_a.cmp(first_b))
+    });
+
+    let nouns: Vec<String> = ranked.into_iter().take(MAX_ENTITIES).map(|(word, _)| word).collect();
+    if nouns.is_empty() {
+        vec!["item".to_string()]
+    } else {
+        nouns
+    }
+}
+
+fn singularize(word: &str) -> String {
+    if let Some(stem) = word.strip_suffix("ies") {
+        format!("{}y", stem)
+    } else if word.ends_with("ss") || word.ends_with("us") {
+        word.to_string()
+    } else if let Some(stem) = word.strip_suffix('s') {
+        stem.to_string()
+    } else {
+        word.to_string()
+    }
+}
+
+fn pascal_case(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn refine(content: &str) -> String {
+    let sentences = sentences(content);
+    let goal = sentences.first().cloned().unwrap_or_else(|| "Describe the project goal.".to_string());
+
+    let mut out = format!("## Goal\n{}\n", goal);
+    if sentences.len() > 1 {
+        out.push_str("\n## Details\n");
+        for sentence in &sentences[1..] {
+            out.push_str(&format!("- {}\n", sentence));
+        }
+    }
+    out.push_str("\n## Key Entities\n");
+    for noun in nouns(content) {
+        out.push_str(&format!("- {}\n", pascal_case(&noun)));
+    }
+    out.push_str("\n## Acceptance Criteria\n- Every detail above is implemented and covered by tests.\n");
+    out
+}
+
+/// The structured prompt with every topic's sentences tidied, keeping sections and topic names
+fn refine_structured(content: &str) -> String {
+    let Ok(mut prompt) = serde_json::from_str::<serde_json::Value>(content) else {
+        return normalize_whitespace(content);
+    };
+    for section in prompt["sections"].as_array_mut().into_iter().flatten() {
+        for topic in section["topics"].as_array_mut().into_iter().flatten() {
+            let tidy = sentences(topic["content"].as_str().unwrap_or_default()).join(" ");
+            topic["content"] = serde_json::Value::String(tidy);
+        }
+    }
+    serde_json::to_string_pretty(&prompt).unwrap_or_default()
+}
+
+fn er_diagram(nouns: &[String]) -> String {
+    let entities: Vec<String> = nouns.iter().map(|n| n.to_uppercase()).collect();
+    let mut out = "erDiagram\n".to_string();
+
+    for (index, entity) in entities.iter().enumerate() {
+        out.push_str(&format!("    {} {{\n        string id PK\n        string name\n", entity));
+        if index > 0 {
+            out.push_str(&format!("        string {}Id FK\n", nouns[0]));
+        }
+        out.push_str("        datetime createdAt\n    }\n");
+    }
+    for entity in &entities[1..] {
+        out.push_str(&format!("    {} ||--o{{ {} : has\n", entities[0], entity));
+    }
+    out
+}
+
+fn class_diagram(nouns: &[String]) -> String {
+    let classes: Vec<String> = nouns.iter().map(|n| pascal_case(n)).collect();
+    let mut out = "classDiagram\n".to_string();
+
+    for class in &classes {
+        out.push_str(&format!(
+            "    class {0} {{\n        +String id\n        +String name\n    }}\n    class {0}Repository {{\n        <<interface>>\n        +findById(id: String) {0}\n        +save(entity: {0}) {0}\n    }}\n",
+            class
+        ));
+    }
+    for class in &classes {
+        out.push_str(&format!("    {0}Repository ..> {0}\n", class));
+    }
+    for class in &classes[1..] {
+        out.push_str(&format!("    {} \"1\" --> \"*\" {}\n", classes[0], class));
+    }
+    out
+}
+
+/// Node label safe inside double quotes
+fn label(sentence: &str) -> String {
+    let text: String = sentence
+        .trim_end_matches(['.', '!', '?'])
+        .replace('"', "'")
+        .chars()
+        .take(40)
+        .collect();
+    text.trim().to_string()
+}
+
+fn flowchart(content: &str) -> String {
+    let steps: Vec<String> = sentences(content).iter().take(MAX_STEPS).map(|s| label(s)).collect();
+    let mut out = "flowchart TD\n    Start([\"Start\"])\n".to_string();
+
+    let mut previous = "Start".to_string();
+    for (index, step) in steps.iter().enumerate() {
+        let id = format!("S{}", index + 1);
+        out.push_str(&format!("    {}[\"{}\"]\n    {} --> {}\n", id, step, previous, id));
+        previous = id;
+    }
+    out.push_str(&format!("    End([\"End\"])\n    {} --> End\n", previous));
+    out
+}
+
+fn journey(content: &str) -> String {
+    let sentences = sentences(content);
+    let title = sentences.first().map(|s| label(s)).unwrap_or_else(|| "User Journey".to_string());
+    let mut out = format!("journey\n    title {}\n", title.replace(':', " "));
+
+    for noun in nouns(content) {
+        let name = pascal_case(&noun);
+        out.push_str(&format!(
+            "    section {0}\n      Discover {0}: 3: User\n      Use {0}: 4: User, System\n",
+            name
+        ));
+    }
+    out
+}
+
+fn sequence_diagram(nouns: &[String]) -> String {
+    let mut out = "sequenceDiagram\n    actor User\n    participant App\n    participant Store\n".to_string();
+    for noun in nouns {
+        out.push_str(&format!(
+            "    User->>App: Open {0}\n    App->>Store: Load {0}\n    Store-->>App: {1}\n    App-->>User: Show {0}\n",
+            noun,
+            pascal_case(noun)
+        ));
+    }
+    out
+}
+
+fn state_diagram(nouns: &[String]) -> String {
+    let name = pascal_case(&nouns[0]);
+    format!(
+        "stateDiagram-v2\n    [*] --> Draft\n    Draft --> Active : publish {0}\n    Active --> Archived : archive {0}\n    \
+         Archived --> Active : restore {0}\n    Archived --> [*]\n",
+        name
+    )
+}
+
+fn c4_diagram(nouns: &[String]) -> String {
+    let mut out = "C4Container\n    title Containers\n    Person(user, \"User\")\n    System_Boundary(app, \"Application\") {\n        \
+                   Container(web, \"Web App\", \"TypeScript\")\n        Container(api, \"API\", \"Rust\")\n        \
+                   ContainerDb(db, \"Database\", \"SQL\")\n    }\n    Rel(user, web, \"Uses\")\n    Rel(web, api, \"Calls\")\n"
+        .to_string();
+    for noun in nouns {
+        out.push_str(&format!("    Rel(api, db, \"Stores {}\")\n", noun));
+    }
+    out
+}
+
+fn user_stories(nouns: &[String]) -> String {
+    let mut out = String::new();
+    for noun in nouns {
+        let name = pascal_case(noun);
+        out.push_str(&format!(
+            "## {0} Management\n- **As a** user, **I want to** add a new {1} **so that** I can keep track of it.\n- **As a** user, **I want to** update an existing {1} **so that** it stays accurate.\n- **As an** admin, **I want to** remove an outdated {1} **so that** stale data does not linger.\n\n",
+            name, noun
+        ));
+    }
+    out
+}
+
+fn edit(current_diagram: &str, instruction: &str) -> String {
+    format!(
+        "{}\n    %% echo: {}\n",
+        current_diagram.trim_end(),
+        normalize_whitespace(instruction)
+    )
+}
+
+fn answer(diagram_type: &str, current_diagram: &str, question: &str) -> String {
+    let lines = current_diagram.lines().filter(|l| !l.trim().is_empty()).count();
+    let mentioned: Vec<String> = nouns(current_diagram).iter().map(|n| pascal_case(n)).collect();
+    format!(
+        "This is the offline echo provider, so no model looked at your question:\n\n> {}\n\nThe {} diagram has {} non-empty lines and mostly mentions {}.\n",
+        normalize_whitespace(question),
+        diagram_type,
+        lines,
+        mentioned.join(", ")
+    )
+}
@@ -1,5 +1,20 @@
-mod models;
+pub mod cache;
+pub mod codegen;
+pub mod diagram_diff;
+pub mod diagnostics;
+pub mod diff;
+pub mod echo;
+pub mod llm;
+pub mod mermaid;
+pub mod models;
+pub mod prompts;
+pub mod restructure;
+pub mod schema;
+pub mod settings;
 mod state;
+pub mod stories;
+pub mod usage;
+pub mod vault;
 mod commands;
 
 use state::AppState;
@@ -44,25 +59,65 @@ pub fn run() {
             commands::save_section_refinement,
             commands::save_project_refinement,
             commands::delete_project_refinement,
-            commands::save_project_er_diagram,
-            commands::refine_er_diagram_with_llm_stream,
-            commands::save_project_uml_diagram,
-            commands::refine_uml_diagram_with_llm_stream,
-            commands::save_project_flowchart,
-            commands::refine_flowchart_with_llm_stream,
-            commands::save_project_user_journey,
+            commands::create_diagram,
+            commands::rename_diagram,
+            commands::delete_diagram,
+            commands::save_project_diagram,
+            commands::list_diagram_versions,
+            commands::diff_diagram_versions,
+            commands::label_diagram_version,
+            commands::revert_diagram,
+            commands::generate_diagram_with_llm_stream,
+            commands::validate_mermaid,
+            commands::diff_diagram_edit,
+            commands::apply_diagram_edit,
+            commands::generate_sql_from_er_diagram,
+            commands::import_er_diagram_from_sql,
+            commands::import_er_diagram_from_sqlite,
+            commands::refine_topic_with_llm_stream,
+            commands::refine_section_with_llm_stream,
+            commands::accept_refinement,
+            commands::apply_refinement,
+            commands::refine_structured_with_llm_stream,
+            commands::split_merged_output,
+            commands::apply_merged_split,
+            commands::generate_code_from_uml_diagram,
             commands::save_project_user_stories,
-            commands::refine_user_journey_with_llm_stream,
+            commands::parse_user_stories,
+            commands::export_user_stories_to_gherkin,
             commands::refine_user_stories_with_llm_stream,
-            commands::edit_er_diagram_with_llm_stream,
-            commands::edit_uml_diagram_with_llm_stream,
-            commands::edit_flowchart_with_llm_stream,
-            commands::edit_user_journey_with_llm_stream,
+            commands::edit_diagram_with_llm_stream,
             commands::ask_llm_about_diagram_stream,
+            commands::create_thread,
+            commands::list_threads,
+            commands::continue_thread,
+            commands::fork_thread,
+            commands::delete_thread,
             commands::get_llm_settings,
             commands::save_llm_settings,
+            commands::list_ollama_models,
+            commands::test_llm_settings,
+            commands::get_response_cache_stats,
+            commands::clear_response_cache,
+            commands::get_vault_status,
+            commands::create_vault,
+            commands::unlock_vault,
+            commands::lock_vault,
+            commands::get_usage_records,
+            commands::get_usage_summary,
+            commands::get_prompt_templates,
+            commands::save_prompt_template,
+            commands::reset_prompt_template,
             commands::get_platform,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::Exit = event {
+                // Cache reads only update the LRU order in memory
+                if let Err(e) = app.state::<AppState>().cache.lock().unwrap().flush() {
+                    eprintln!("Failed to save response cache index: {}", e);
+                }
+            }
+        });
 }
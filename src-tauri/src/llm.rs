@@ -0,0 +1,513 @@
+use crate::settings::LlmProfile;
+use crate::usage::TokenUsage;
+use futures_util::StreamExt;
+use std::convert::Infallible;
+use std::time::Duration;
+
+/// Model discovery backs the settings modal, so an unreachable server must fail fast
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Wire format spoken by a profile
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    /// `/chat/completions` with SSE `data:` lines (OpenAI, GLM, most compatible servers)
+    OpenAi,
+    /// `/messages` with SSE events
+    Anthropic,
+    /// Native Ollama `/api/chat` with newline-delimited JSON
+    Ollama,
+    /// Google `models/<model>:streamGenerateContent` with `alt=sse` framing
+    Gemini,
+}
+
+impl Protocol {
+    pub fn for_profile(profile: &LlmProfile) -> Self {
+        match profile.protocol.as_deref() {
+            Some("anthropic") => Protocol::Anthropic,
+            Some("ollama") => Protocol::Ollama,
+            Some("gemini") => Protocol::Gemini,
+            Some(_) => Protocol::OpenAi,
+            None => match profile.provider.as_str() {
+                "anthropic" => Protocol::Anthropic,
+                "ollama" => Protocol::Ollama,
+                "gemini" => Protocol::Gemini,
+                _ => Protocol::OpenAi,
+            },
+        }
+    }
+
+    /// Name used for the `protocol` setting
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Protocol::OpenAi => "openai",
+            Protocol::Anthropic => "anthropic",
+            Protocol::Ollama => "ollama",
+            Protocol::Gemini => "gemini",
+        }
+    }
+
+    pub fn default_model(&self) -> &'static str {
+        match self {
+            Protocol::OpenAi => "gpt-4",
+            Protocol::Anthropic => "claude-3-sonnet-20240229",
+            Protocol::Ollama => "llama3",
+            Protocol::Gemini => "gemini-1.5-pro",
+        }
+    }
+}
+
+/// Anthropic has no "system" role in `messages`, so the system prompt is folded into the user turn
+pub fn build_messages(system_prompt: &str, user_content: String, protocol: Protocol) -> serde_json::Value {
+    build_conversation(system_prompt, &[("user", user_content)], protocol)
+}
+
+/// Messages for a multi-turn conversation; `turns` are `(role, content)` pairs in order, ending
+/// with the new user message. Anthropic has no system role here, so the system prompt is folded
+/// into the first user turn.
+pub fn build_conversation(system_prompt: &str, turns: &[(&str, String)], protocol: Protocol) -> serde_json::Value {
+    let mut messages = Vec::new();
+    if protocol != Protocol::Anthropic {
+        messages.push(serde_json::json!({ "role": "system", "content": system_prompt }));
+    }
+
+    for (index, (role, content)) in turns.iter().enumerate() {
+        let content = if protocol == Protocol::Anthropic && index == 0 {
+            format!("{}\n\n{}", system_prompt, content)
+        } else {
+            content.clone()
+        };
+        messages.push(serde_json::json!({ "role": role, "content": content }));
+    }
+    serde_json::Value::Array(messages)
+}
+
+/// The model a request with `profile` targets, falling back to the protocol's default
+pub fn model_name(profile: &LlmProfile, protocol: Protocol) -> String {
+    profile.model
+        .clone()
+        .filter(|m| !m.is_empty())
+        .unwrap_or_else(|| protocol.default_model().to_string())
+}
+
+/// Build the streaming request for `messages`, returning it together with the model it targets
+pub fn build_stream_request(
+    client: &reqwest::Client,
+    profile: &LlmProfile,
+    protocol: Protocol,
+    messages: serde_json::Value,
+) -> (reqwest::RequestBuilder, String) {
+    let model = model_name(profile, protocol);
+    let base_url = profile.base_url.trim_end_matches('/');
+
+    let request_builder = match protocol {
+        Protocol::OpenAi => {
+            let mut body = serde_json::json!({
+                "model": model,
+                "stream": true,
+                "messages": messages
+            });
+            if profile.stream_usage != Some(false) {
+                body["stream_options"] = serde_json::json!({ "include_usage": true });
+            }
+            client
+                .post(format!("{}/chat/completions", base_url))
+                .header("Authorization", format!("Bearer {}", profile.api_key))
+                .json(&body)
+        }
+        Protocol::Anthropic => client
+            .post(format!("{}/messages", base_url))
+            .header("x-api-key", &profile.api_key)
+            .header("anthropic-version", "2023-06-01")
+            .json(&serde_json::json!({
+                "model": model,
+                "max_tokens": 4096,
+                "stream": true,
+                "messages": messages
+            })),
+        Protocol::Gemini => client
+            .post(format!("{}/models/{}:streamGenerateContent?alt=sse", gemini_root(base_url), model))
+            .header("x-goog-api-key", &profile.api_key)
+            .json(&gemini_body(&messages)),
+        Protocol::Ollama => client
+            .post(format!("{}/api/chat", ollama_root(base_url)))
+            .json(&serde_json::json!({
+                "model": model,
+                "stream": true,
+                "messages": messages
+            })),
+    };
+
+    (request_builder.header("Content-Type", "application/json"), model)
+}
+
+/// Gemini takes the system prompt as `systemInstruction` and the conversation as `contents`
+/// made of `parts`, with `model` instead of `assistant` as the reply role
+fn gemini_body(messages: &serde_json::Value) -> serde_json::Value {
+    let mut system_parts = Vec::new();
+    let mut contents = Vec::new();
+
+    for message in messages.as_array().into_iter().flatten() {
+        let text = message["content"].as_str().unwrap_or_default();
+        match message["role"].as_str() {
+            Some("system") => system_parts.push(serde_json::json!({ "text": text })),
+            Some("assistant") => contents.push(serde_json::json!({ "role": "model", "parts": [{ "text": text }] })),
+            _ => contents.push(serde_json::json!({ "role": "user", "parts": [{ "text": text }] })),
+        }
+    }
+
+    let mut body = serde_json::json!({ "contents": contents });
+    if !system_parts.is_empty() {
+        body["systemInstruction"] = serde_json::json!({ "parts": system_parts });
+    }
+    body
+}
+
+/// The native API sits next to the OpenAI-compatible shim, so accept base URLs ending in `/openai`
+pub fn gemini_root(base_url: &str) -> &str {
+    let base_url = base_url.trim_end_matches('/');
+    base_url.strip_suffix("/openai").unwrap_or(base_url)
+}
+
+/// What a single line of a streamed response means for the caller
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamLine {
+    Text(String),
+    Done,
+    Error(String),
+    /// Keep-alives, event names, usage-only chunks and anything unparseable
+    Ignored,
+}
+
+/// Interpret one line of a streamed response, collecting any usage it reports into `usage`
+pub fn parse_stream_line(protocol: Protocol, line: &str, usage: &mut TokenUsage) -> StreamLine {
+    let line = line.trim();
+    if line.is_empty() {
+        return StreamLine::Ignored;
+    }
+
+    if protocol == Protocol::Ollama {
+        let Ok(json) = serde_json::from_str::<serde_json::Value>(line) else {
+            return StreamLine::Ignored;
+        };
+        if let Some(error) = json["error"].as_str() {
+            return StreamLine::Error(error.to_string());
+        }
+        usage.merge_ollama(&json);
+        if json["done"].as_bool() == Some(true) {
+            return StreamLine::Done;
+        }
+        return match json["message"]["content"].as_str() {
+            Some(content) if !content.is_empty() => StreamLine::Text(content.to_string()),
+            _ => StreamLine::Ignored,
+        };
+    }
+
+    // Anthropic also sends `event: ...` lines, we mostly care about data:
+    let Some(data) = line.strip_prefix("data:").map(str::trim_start) else {
+        return StreamLine::Ignored;
+    };
+    if data == "[DONE]" {
+        return StreamLine::Done;
+    }
+    let Ok(json) = serde_json::from_str::<serde_json::Value>(data) else {
+        return StreamLine::Ignored;
+    };
+
+    if protocol == Protocol::OpenAi {
+        if let Some(message) = json["error"]["message"].as_str() {
+            return StreamLine::Error(message.to_string());
+        }
+        usage.merge_openai(&json);
+        // The first chunk usually only carries the role, with empty content
+        match json["choices"][0]["delta"]["content"].as_str() {
+            Some(content) if !content.is_empty() => StreamLine::Text(content.to_string()),
+            _ => StreamLine::Ignored,
+        }
+    } else if protocol == Protocol::Gemini {
+        parse_gemini_chunk(&json, usage)
+    } else {
+        usage.merge_anthropic(&json);
+        if json["type"] == "error" {
+            let message = json["error"]["message"].as_str().unwrap_or("Unknown error");
+            return StreamLine::Error(message.to_string());
+        }
+        if json["type"] == "content_block_delta" {
+            if let Some(text) = json["delta"]["text"].as_str() {
+                return StreamLine::Text(text.to_string());
+            }
+        }
+        if json["type"] == "message_stop" {
+            return StreamLine::Done;
+        }
+        StreamLine::Ignored
+    }
+}
+
+/// Events relayed to the frontend as `<prefix>:chunk`, `<prefix>:done` and `<prefix>:error`
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamEvent {
+    Chunk(String),
+    Done,
+    Error(String),
+}
+
+/// Send the streaming request, turning a non-2xx answer into an error before any event is emitted.
+/// Returns the response together with the model it targets.
+pub async fn start_stream(
+    client: &reqwest::Client,
+    profile: &LlmProfile,
+    protocol: Protocol,
+    messages: serde_json::Value,
+) -> Result<(reqwest::Response, String), String> {
+    let (request_builder, model) = build_stream_request(client, profile, protocol, messages);
+
+    let response = request_builder
+        .send()
+        .await
+        .map_err(|e| format!("Failed to send request: {}", e))?;
+
+    if !response.status().is_success() {
+        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+        return Err(format!("LLM API error: {}", error_text));
+    }
+
+    Ok((response, model))
+}
+
+/// Feed a streamed response body through `parse_stream_line`, calling `on_event` for every piece
+/// of text and then exactly once with `Done` or `Error`. Returns the usage reported along the way.
+pub async fn relay_stream<S, B, E>(stream: S, protocol: Protocol, mut on_event: impl FnMut(StreamEvent)) -> TokenUsage
+where
+    S: futures_util::Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: std::fmt::Display,
+{
+    let mut stream = std::pin::pin!(stream);
+    let mut lines = LineBuffer::default();
+    let mut usage = TokenUsage::default();
+
+    let outcome = 'stream: loop {
+        let (batch, finished) = match stream.next().await {
+            Some(Ok(bytes)) => (lines.push(bytes.as_ref()), false),
+            Some(Err(e)) => break Err(format!("Stream error: {}", e)),
+            None => (lines.finish().into_iter().collect(), true),
+        };
+
+        for line in batch {
+            match parse_stream_line(protocol, &line, &mut usage) {
+                StreamLine::Text(text) => on_event(StreamEvent::Chunk(text)),
+                StreamLine::Done => break 'stream Ok(()),
+                StreamLine::Error(error) => break 'stream Err(error),
+                StreamLine::Ignored => {}
+            }
+        }
+
+        // Gemini's `alt=sse` stream has no end marker; the others must send theirs
+        if finished {
+            break match protocol {
+                Protocol::Gemini => Ok(()),
+                _ => Err("Incomplete response: the stream ended before the provider finished".to_string()),
+            };
+        }
+    };
+
+    on_event(match outcome {
+        Ok(()) => StreamEvent::Done,
+        Err(error) => StreamEvent::Error(error),
+    });
+    usage
+}
+
+/// `relay_stream` that also collects the text, returning the chunks only when the answer completed.
+/// Only complete answers may be cached or added to a thread.
+pub async fn relay_answer<S, B, E>(
+    stream: S,
+    protocol: Protocol,
+    mut on_event: impl FnMut(StreamEvent),
+) -> (Option<Vec<String>>, TokenUsage)
+where
+    S: futures_util::Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: std::fmt::Display,
+{
+    let mut chunks = Vec::new();
+    let mut completed = false;
+    let usage = relay_stream(stream, protocol, |event| {
+        match &event {
+            StreamEvent::Chunk(text) => chunks.push(text.clone()),
+            StreamEvent::Done => completed = true,
+            StreamEvent::Error(_) => {}
+        }
+        on_event(event)
+    })
+    .await;
+
+    (completed.then_some(chunks), usage)
+}
+
+/// Serve text that is already known (cached or generated locally) as an OpenAI-style SSE body,
+/// one event per chunk, pausing `delay` before each so `relay_stream` treats it like a provider
+pub fn replay_stream(
+    chunks: Vec<String>,
+    delay: Duration,
+) -> impl futures_util::Stream<Item = Result<Vec<u8>, Infallible>> {
+    let mut events: Vec<Vec<u8>> = chunks
+        .into_iter()
+        .map(|chunk| {
+            let event = serde_json::json!({ "choices": [{ "index": 0, "delta": { "content": chunk } }] });
+            format!("data: {}\n\n", event).into_bytes()
+        })
+        .collect();
+    events.push(b"data: [DONE]\n\n".to_vec());
+
+    futures_util::stream::iter(events).then(move |event| async move {
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        Ok(event)
+    })
+}
+
+/// Reassembles lines from a byte stream whose chunks may split a line, or a UTF-8 character, anywhere
+#[derive(Debug, Default)]
+pub struct LineBuffer {
+    pending: Vec<u8>,
+}
+
+impl LineBuffer {
+    /// Append `bytes` and return every line completed by them
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.pending.extend_from_slice(bytes);
+
+        let mut lines = Vec::new();
+        while let Some(newline) = self.pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=newline).collect();
+            lines.push(String::from_utf8_lossy(&line).into_owned());
+        }
+        lines
+    }
+
+    /// Whatever is left once the stream has ended without a trailing newline
+    pub fn finish(&mut self) -> Option<String> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        Some(String::from_utf8_lossy(&std::mem::take(&mut self.pending)).into_owned())
+    }
+}
+
+/// Gemini has no end-of-stream marker (the connection just closes) and reports blocked
+/// prompts or answers through `promptFeedback` and `finishReason` instead of an error
+fn parse_gemini_chunk(json: &serde_json::Value, usage: &mut TokenUsage) -> StreamLine {
+    if let Some(message) = json["error"]["message"].as_str() {
+        return StreamLine::Error(message.to_string());
+    }
+    usage.merge_gemini(json);
+
+    if let Some(reason) = json["promptFeedback"]["blockReason"].as_str() {
+        return StreamLine::Error(format!("Gemini blocked the prompt: {}", reason));
+    }
+
+    let candidate = &json["candidates"][0];
+    let text: String = candidate["content"]["parts"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|part| part["text"].as_str())
+        .collect();
+    if !text.is_empty() {
+        return StreamLine::Text(text);
+    }
+
+    match candidate["finishReason"].as_str() {
+        Some(reason @ ("SAFETY" | "RECITATION" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII")) => {
+            StreamLine::Error(format!("Gemini stopped the response: {}", reason))
+        }
+        _ => StreamLine::Ignored,
+    }
+}
+
+/// Ollama's native API lives at the server root; tolerate base URLs copied from the
+/// OpenAI-compatible setup that end in `/v1`
+pub fn ollama_root(base_url: &str) -> &str {
+    let base_url = base_url.trim_end_matches('/');
+    base_url.strip_suffix("/v1").unwrap_or(base_url)
+}
+
+/// A model installed in a local Ollama server
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LocalModel {
+    pub name: String,
+    pub size: Option<u64>,
+    pub parameter_size: Option<String>,
+    pub quantization_level: Option<String>,
+    /// Maximum context window in tokens, from the model metadata
+    pub context_length: Option<u64>,
+}
+
+/// List the models installed in the Ollama server at `base_url` via `/api/tags` and `/api/show`
+pub async fn list_ollama_models(client: &reqwest::Client, base_url: &str) -> Result<Vec<LocalModel>, String> {
+    let root = ollama_root(base_url);
+
+    let tags: serde_json::Value = client
+        .get(format!("{}/api/tags", root))
+        .timeout(DISCOVERY_TIMEOUT)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to reach Ollama at {}: {}", root, e))?
+        .error_for_status()
+        .map_err(|e| format!("Ollama returned an error: {}", e))?
+        .json()
+        .await
+        .map_err(|e| format!("Failed to parse Ollama model list: {}", e))?;
+
+    let mut models: Vec<LocalModel> = tags["models"]
+        .as_array()
+        .map(|models| {
+            models
+                .iter()
+                .filter_map(|m| {
+                    Some(LocalModel {
+                        name: m["name"].as_str()?.to_string(),
+                        size: m["size"].as_u64(),
+                        parameter_size: m["details"]["parameter_size"].as_str().map(str::to_string),
+                        quantization_level: m["details"]["quantization_level"].as_str().map(str::to_string),
+                        context_length: None,
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let context_lengths = futures_util::future::join_all(
+        models.iter().map(|m| ollama_context_length(client, root, &m.name)),
+    )
+    .await;
+    for (model, context_length) in models.iter_mut().zip(context_lengths) {
+        model.context_length = context_length;
+    }
+
+    models.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(models)
+}
+
+/// `/api/show` reports the context window as `<architecture>.context_length` in `model_info`
+async fn ollama_context_length(client: &reqwest::Client, root: &str, model: &str) -> Option<u64> {
+    let info: serde_json::Value = client
+        .post(format!("{}/api/show", root))
+        .json(&serde_json::json!({ "model": model }))
+        .timeout(DISCOVERY_TIMEOUT)
+        .send()
+        .await
+        .ok()?
+        .json()
+        .await
+        .ok()?;
+
+    info["model_info"]
+        .as_object()?
+        .iter()
+        .find(|(key, _)| key.ends_with(".context_length"))
+        .and_then(|(_, value)| value.as_u64())
+}
@@ -0,0 +1,1293 @@
+//! Syntax checks for the Mermaid diagrams the app stores, and cleanup of LLM answers
+//!
+//! This is not a full Mermaid grammar: it covers the statements the supported diagram kinds
+//! use and reports the mistakes that make Mermaid refuse to render.
+
+use serde::Serialize;
+
+/// Diagram kinds as named by the frontend
+pub const DIAGRAM_KINDS: [&str; 7] = ["er", "uml", "flowchart", "journey", "sequence", "state", "c4"];
+
+const DIRECTIONS: [&str; 5] = ["TB", "TD", "BT", "RL", "LR"];
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Diagnostic {
+    /// 1-based
+    pub line: usize,
+    /// 1-based, in characters
+    pub column: usize,
+    pub message: String,
+    /// The line does not look like a statement at all (probably prose, not a typo)
+    #[serde(skip)]
+    pub unrecognized: bool,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MermaidReport {
+    pub cleaned: String,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+fn headers(kind: &str) -> Result<&'static [&'static str], String> {
+    match kind {
+        "er" => Ok(&["erDiagram"]),
+        "uml" => Ok(&["classDiagram", "classDiagram-v2"]),
+        "flowchart" => Ok(&["flowchart", "graph"]),
+        "journey" => Ok(&["journey"]),
+        "sequence" => Ok(&["sequenceDiagram"]),
+        "state" => Ok(&["stateDiagram-v2", "stateDiagram"]),
+        "c4" => Ok(&["C4Context", "C4Container", "C4Component", "C4Dynamic", "C4Deployment"]),
+        _ => Err(format!("Unknown diagram kind: {}", kind)),
+    }
+}
+
+/// Clean `text` and validate the result
+pub fn check(kind: &str, text: &str) -> Result<MermaidReport, String> {
+    let cleaned = clean(kind, text)?;
+    let diagnostics = validate(kind, &cleaned)?;
+    Ok(MermaidReport { cleaned, diagnostics })
+}
+
+/// Diagnostics for `code` as a diagram of `kind`; empty when it should render
+pub fn validate(kind: &str, code: &str) -> Result<Vec<Diagnostic>, String> {
+    let headers = headers(kind)?;
+    let mut diagnostics = Vec::new();
+    let lines: Vec<&str> = code.lines().collect();
+    let mut statements = statement_lines(&lines).into_iter();
+
+    let Some((header_index, header)) = statements.next() else {
+        diagnostics.push(error(1, 1, format!("Empty diagram; expected `{}`", headers[0])));
+        return Ok(diagnostics);
+    };
+    let header_trimmed = header.trim().trim_end_matches(';');
+    let mut words = header_trimmed.split_whitespace();
+    let keyword = words.next().unwrap_or_default();
+    if !headers.contains(&keyword) {
+        diagnostics.push(error(
+            header_index + 1,
+            column_of(header, 0),
+            format!("Expected the diagram to start with `{}`, found `{}`", headers[0], keyword),
+        ));
+        return Ok(diagnostics);
+    }
+    let rest: Vec<&str> = words.collect();
+    let header_ok = match kind {
+        "flowchart" => rest.is_empty() || (rest.len() == 1 && DIRECTIONS.contains(&rest[0])),
+        _ => rest.is_empty(),
+    };
+    if !header_ok {
+        diagnostics.push(error(
+            header_index + 1,
+            column_of(header, keyword.len()),
+            match kind {
+                "flowchart" => format!("Unexpected `{}` after `{}`; use a direction like TD or LR", rest.join(" "), keyword),
+                _ => format!("Unexpected `{}` after `{}`", rest.join(" "), keyword),
+            },
+        ));
+    }
+
+    let mut checker: Box<dyn Checker> = match kind {
+        "er" => Box::new(ErChecker::default()),
+        "uml" => Box::new(ClassChecker::default()),
+        "flowchart" => Box::new(FlowchartChecker::default()),
+        "sequence" => Box::new(SequenceChecker::default()),
+        "state" => Box::new(StateChecker::default()),
+        "c4" => Box::new(C4Checker::default()),
+        _ => Box::new(JourneyChecker),
+    };
+    for (index, line) in statements {
+        checker.line(index + 1, line, &mut diagnostics);
+    }
+    checker.finish(&mut diagnostics);
+    Ok(diagnostics)
+}
+
+/// Pull the diagram out of an LLM answer: the first matching code fence if there is one, minus
+/// any prose before the header line and any prose after the last statement
+pub fn clean(kind: &str, text: &str) -> Result<String, String> {
+    let wanted = headers(kind)?;
+    let body = fenced_block(text, wanted).unwrap_or_else(|| text.to_string());
+    let lines: Vec<&str> = body.lines().collect();
+
+    // Start at the header, keeping directives and comments right above it; another kind's
+    // header still counts so validation can report the mismatch
+    let all_headers: Vec<&str> = DIAGRAM_KINDS.iter().flat_map(|k| headers(k).unwrap_or_default()).copied().collect();
+    let is_header = |line: &str, candidates: &[&str]| {
+        let first = line.trim().split(|c: char| c.is_whitespace() || c == ';').next().unwrap_or_default();
+        candidates.contains(&first)
+    };
+    let start = lines
+        .iter()
+        .position(|line| is_header(line, wanted))
+        .or_else(|| lines.iter().position(|line| is_header(line, &all_headers)));
+    let Some(mut start) = start else {
+        return Ok(body.trim().to_string());
+    };
+    while start > 0 && lines[start - 1].trim_start().starts_with("%%") {
+        start -= 1;
+    }
+    let mut lines = lines[start..].to_vec();
+
+    // Drop trailing lines that read like prose rather than statements
+    let candidate = lines.join("\n");
+    let prose: Vec<usize> = validate(kind, &candidate)?
+        .into_iter()
+        .filter(|d| d.unrecognized && lines[d.line - 1].split_whitespace().count() >= 3)
+        .map(|d| d.line - 1)
+        .collect();
+    let mut end = lines.len();
+    while end > 1 && (lines[end - 1].trim().is_empty() || prose.contains(&(end - 1))) {
+        end -= 1;
+    }
+    if lines[end..].iter().any(|line| !line.trim().is_empty()) {
+        lines.truncate(end);
+    }
+
+    Ok(lines.join("\n").trim_end().to_string())
+}
+
+/// Content of the best code fence: the first one holding a diagram of the wanted kind, else the
+/// first `mermaid` fence, else the first fence
+fn fenced_block(text: &str, headers: &[&str]) -> Option<String> {
+    let mut blocks: Vec<(String, Vec<&str>)> = Vec::new();
+    let mut open: Option<(String, Vec<&str>)> = None;
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if let Some(info) = trimmed.strip_prefix("```") {
+            match open.take() {
+                Some(block) => blocks.push(block),
+                None => open = Some((info.trim().to_lowercase(), Vec::new())),
+            }
+        } else if let Some((_, body)) = open.as_mut() {
+            body.push(line);
+        }
+    }
+    // An answer cut off before the closing fence still holds the diagram
+    blocks.extend(open);
+
+    let starts_with_header = |body: &[&str]| {
+        body.iter()
+            .map(|line| line.trim())
+            .find(|line| !line.is_empty() && !line.starts_with("%%"))
+            .is_some_and(|line| headers.iter().any(|h| line.split_whitespace().next() == Some(h)))
+    };
+    let index = blocks
+        .iter()
+        .position(|(_, body)| starts_with_header(body))
+        .or_else(|| blocks.iter().position(|(info, _)| info == "mermaid"))
+        .or((!blocks.is_empty()).then_some(0))?;
+    Some(blocks[index].1.join("\n"))
+}
+
+/// Lines that carry statements, with their index: comments, directives, blank lines,
+/// front matter and accessibility descriptions are skipped
+pub(crate) fn statement_lines<'a>(lines: &[&'a str]) -> Vec<(usize, &'a str)> {
+    let mut out = Vec::new();
+    let mut index = 0;
+
+    let first = lines.iter().position(|line| !line.trim().is_empty());
+    if let Some(first) = first.filter(|&i| lines[i].trim() == "---") {
+        index = lines[first + 1..]
+            .iter()
+            .position(|line| line.trim() == "---")
+            .map_or(lines.len(), |end| first + end + 2);
+    }
+
+    let mut in_description = false;
+    while index < lines.len() {
+        let line = lines[index];
+        let trimmed = line.trim();
+        if in_description {
+            in_description = !trimmed.contains('}');
+        } else if trimmed.starts_with("accDescr") && trimmed.contains('{') && !trimmed.contains('}') {
+            in_description = true;
+        } else if !(trimmed.is_empty()
+            || trimmed.starts_with("%%")
+            || trimmed.starts_with("accTitle")
+            || trimmed.starts_with("accDescr"))
+        {
+            out.push((index, line));
+        }
+        index += 1;
+    }
+    out
+}
+
+fn error(line: usize, column: usize, message: String) -> Diagnostic {
+    Diagnostic { line, column, message, unrecognized: false }
+}
+
+fn unrecognized(line: usize, column: usize, message: String) -> Diagnostic {
+    Diagnostic { line, column, message, unrecognized: true }
+}
+
+/// 1-based column of the character `offset` bytes into the trimmed `line`
+fn column_of(line: &str, offset: usize) -> usize {
+    let indent = line.len() - line.trim_start().len();
+    line[..(indent + offset).min(line.len())].chars().count() + 1
+}
+
+/// Byte offset of `part` inside `whole`, when `part` is a subslice of it
+fn offset_in(whole: &str, part: &str) -> usize {
+    (part.as_ptr() as usize).saturating_sub(whole.as_ptr() as usize)
+}
+
+fn first_word(line: &str) -> &str {
+    line.split_whitespace().next().unwrap_or_default()
+}
+
+fn is_name(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Split on whitespace, keeping double-quoted strings whole; items are subslices of `text`
+pub(crate) fn tokens(text: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    let mut start = None;
+    let mut quoted = false;
+    for (i, c) in text.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        if c.is_whitespace() && !quoted {
+            if let Some(s) = start.take() {
+                out.push(&text[s..i]);
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(s) = start {
+        out.push(&text[s..]);
+    }
+    out
+}
+
+trait Checker {
+    fn line(&mut self, number: usize, line: &str, diagnostics: &mut Vec<Diagnostic>);
+    fn finish(&mut self, diagnostics: &mut Vec<Diagnostic>);
+}
+
+// erDiagram
+
+#[derive(Default)]
+struct ErChecker {
+    /// Name and line of the entity block being read
+    entity: Option<(String, usize)>,
+}
+
+const ER_LEFT: [&str; 4] = ["|o", "||", "}o", "}|"];
+const ER_RIGHT: [&str; 4] = ["o|", "||", "o{", "|{"];
+const ER_WORDS: [&str; 12] = [
+    "one or zero", "zero or one", "one or more", "one or many", "many(1)", "1+",
+    "zero or more", "zero or many", "many(0)", "0+", "only one", "1",
+];
+
+impl Checker for ErChecker {
+    fn line(&mut self, number: usize, line: &str, diagnostics: &mut Vec<Diagnostic>) {
+        let text = line.trim();
+
+        if self.entity.is_some() {
+            if text == "}" {
+                self.entity = None;
+            } else if let Some(d) = er_attribute(text) {
+                diagnostics.push(error(number, column_of(line, d.0), d.1));
+            }
+            return;
+        }
+
+        if text == "}" {
+            diagnostics.push(error(number, column_of(line, 0), "`}` without an open entity block".to_string()));
+            return;
+        }
+        if matches!(first_word(text), "direction" | "style" | "classDef" | "class") {
+            return;
+        }
+
+        if let Some(head) = text.strip_suffix('{') {
+            let name = head.trim();
+            if er_entity_name(name) {
+                self.entity = Some((name.to_string(), number));
+            } else {
+                diagnostics.push(error(number, column_of(line, 0), format!("Invalid entity name `{}`", name)));
+            }
+            return;
+        }
+        if let (Some(open), true) = (text.find('{'), text.ends_with('}')) {
+            // Single-line block: `NAME { type name }`
+            if !er_entity_name(text[..open].trim()) {
+                diagnostics.push(error(number, column_of(line, 0), format!("Invalid entity name `{}`", text[..open].trim())));
+            }
+            return;
+        }
+        if er_entity_name(text) {
+            return;
+        }
+
+        if let Some(d) = er_relationship(number, line, text) {
+            diagnostics.push(d);
+        }
+    }
+
+    fn finish(&mut self, diagnostics: &mut Vec<Diagnostic>) {
+        if let Some((name, number)) = self.entity.take() {
+            diagnostics.push(error(number, 1, format!("Entity block `{}` is not closed with `}}`", name)));
+        }
+    }
+}
+
+/// An entity name, optionally quoted or followed by an alias in brackets
+fn er_entity_name(text: &str) -> bool {
+    let text = match text.find('[') {
+        Some(open) if text.ends_with(']') => &text[..open],
+        _ => text,
+    };
+    is_name(text) || (text.len() > 2 && text.starts_with('"') && text.ends_with('"') && !text[1..text.len() - 1].contains('"'))
+}
+
+/// Offset and message of the first problem in an attribute line
+fn er_attribute(text: &str) -> Option<(usize, String)> {
+    let (body, comment_at) = match text.find('"') {
+        Some(quote) => (&text[..quote], Some(quote)),
+        None => (text, None),
+    };
+    if let Some(quote) = comment_at {
+        if !text.ends_with('"') || text.len() == quote + 1 || text[quote + 1..text.len() - 1].contains('"') {
+            return Some((quote, "Attribute comments must be a single double-quoted string at the end".to_string()));
+        }
+    }
+
+    let words = tokens(body);
+    if words.len() < 2 {
+        return Some((0, "Attributes need a type and a name, e.g. `string email`".to_string()));
+    }
+    let attribute_word = |word: &str| {
+        let word = word.strip_prefix('*').unwrap_or(word);
+        let mut chars = word.chars();
+        chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+            && chars.all(|c| c.is_alphanumeric() || "_-[]()".contains(c))
+    };
+    for word in &words[..2] {
+        if !attribute_word(word) {
+            return Some((
+                offset_in(text, word),
+                format!("`{}` is not a valid attribute type or name; use letters, digits, `_`, `-`, `()` or `[]` only", word),
+            ));
+        }
+    }
+    let keys = words[2..].join(" ");
+    if !keys.is_empty() && !keys.split(',').all(|key| matches!(key.trim(), "PK" | "FK" | "UK")) {
+        return Some((offset_in(text, words[2]), format!("Unknown attribute key `{}`; use PK, FK or UK", keys)));
+    }
+    None
+}
+
+fn er_relationship(number: usize, line: &str, text: &str) -> Option<Diagnostic> {
+    let Some(colon) = text.find(':') else {
+        return Some(if text.contains("--") || text.contains("..") {
+            error(number, column_of(line, text.len()), "Relationship needs a label after `:`".to_string())
+        } else {
+            unrecognized(
+                number,
+                column_of(line, 0),
+                "Expected an entity, an entity block or a relationship like `A ||--o{ B : places`".to_string(),
+            )
+        });
+    };
+    let (head, label) = (&text[..colon], text[colon + 1..].trim());
+    if label.is_empty() {
+        return Some(error(number, column_of(line, colon), "Relationship needs a label after `:`".to_string()));
+    }
+
+    let words = tokens(head);
+    if words.len() == 3 {
+        let (left, spec, right) = (words[0], words[1], words[2]);
+        if !er_entity_name(left) || !er_entity_name(right) {
+            let bad = if er_entity_name(left) { right } else { left };
+            return Some(error(number, column_of(line, offset_in(text, bad)), format!("Invalid entity name `{}`", bad)));
+        }
+        let valid = spec.len() == 6
+            && spec.is_char_boundary(2)
+            && spec.is_char_boundary(4)
+            && ER_LEFT.contains(&&spec[..2])
+            && matches!(&spec[2..4], "--" | "..")
+            && ER_RIGHT.contains(&&spec[4..]);
+        return (!valid).then(|| {
+            error(
+                number,
+                column_of(line, offset_in(text, spec)),
+                format!("Unknown relationship `{}`; use cardinalities like `||--o{{` or `}}|..|{{`", spec),
+            )
+        });
+    }
+
+    // Word form: `A one or more to zero or one B : label`
+    if words.len() > 3 {
+        let (left, right) = (words[0], words[words.len() - 1]);
+        let middle = head[offset_in(head, words[1])..offset_in(head, right)].trim();
+        let valid = ER_WORDS.iter().any(|l| {
+            middle.strip_prefix(l).is_some_and(|rest| {
+                let rest = rest.trim_start();
+                let rest = rest.strip_prefix("optionally to").or_else(|| rest.strip_prefix("to"));
+                rest.is_some_and(|r| ER_WORDS.contains(&r.trim()))
+            })
+        });
+        if valid && er_entity_name(left) && er_entity_name(right) {
+            return None;
+        }
+    }
+    Some(unrecognized(
+        number,
+        column_of(line, 0),
+        "Expected a relationship like `CUSTOMER ||--o{ ORDER : places`".to_string(),
+    ))
+}
+
+// classDiagram
+
+#[derive(Default)]
+struct ClassChecker {
+    /// Open `class` and `namespace` blocks: whether it is a class, and its line
+    blocks: Vec<(bool, usize)>,
+}
+
+fn class_name(text: &str) -> bool {
+    let text = text.split(":::").next().unwrap_or_default();
+    let text = match text.find('[') {
+        Some(open) if text.ends_with("\"]") => &text[..open],
+        _ => text,
+    };
+    if text.len() > 2 && text.starts_with('`') && text.ends_with('`') {
+        return true;
+    }
+    // Generic parameters: `Box~T~`
+    let base = match text.find('~') {
+        Some(open) if text.ends_with('~') && text.len() > open + 1 => &text[..open],
+        Some(_) => return false,
+        None => text,
+    };
+    let mut chars = base.chars();
+    chars.next().is_some_and(|c| c.is_alphanumeric() || c == '_') && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Whether `token` is a relation arrow like `<|--`, `*--`, `..>` or `--`
+pub(crate) fn class_relation(token: &str) -> bool {
+    let (token, _) = strip_any(token, &["<|", "*", "o", "<", "()"]);
+    let Some(rest) = token.strip_prefix("--").or_else(|| token.strip_prefix("..")) else {
+        return false;
+    };
+    let (rest, _) = strip_any(rest, &["|>", "*", "o", ">", "()"]);
+    rest.is_empty()
+}
+
+fn strip_any<'a>(text: &'a str, prefixes: &[&str]) -> (&'a str, bool) {
+    prefixes
+        .iter()
+        .find_map(|p| text.strip_prefix(p).map(|rest| (rest, true)))
+        .unwrap_or((text, false))
+}
+
+/// Split `A<|--B` into `A`, `<|--`, `B` so unspaced relations read like spaced ones
+pub(crate) fn split_relation(token: &str) -> Option<[&str; 3]> {
+    let at = token.find("--").or_else(|| token.find(".."))?;
+    let symbol = |c: char| "<|*>().".contains(c) || c == '-';
+    let start = token[..at].trim_end_matches(symbol).len();
+    let end = at + token[at..].len() - token[at..].trim_start_matches(symbol).len();
+    let parts = [&token[..start], &token[start..end], &token[end..]];
+    (!parts[0].is_empty() && !parts[2].is_empty()).then_some(parts)
+}
+
+impl Checker for ClassChecker {
+    fn line(&mut self, number: usize, line: &str, diagnostics: &mut Vec<Diagnostic>) {
+        let text = line.trim();
+
+        if text == "}" {
+            if self.blocks.pop().is_none() {
+                diagnostics.push(error(number, column_of(line, 0), "`}` without an open class or namespace block".to_string()));
+            }
+            return;
+        }
+        // Members and annotations inside a class body
+        if self.blocks.last().is_some_and(|(is_class, _)| *is_class) {
+            return;
+        }
+
+        match first_word(text) {
+            "direction" | "note" | "link" | "click" | "callback" | "style" | "classDef" | "cssClass" => return,
+            "namespace" => {
+                let rest = text["namespace".len()..].trim();
+                match rest.strip_suffix('{').map(str::trim) {
+                    Some(name) if !name.is_empty() => self.blocks.push((false, number)),
+                    _ => diagnostics.push(error(number, column_of(line, 0), "Expected `namespace Name {`".to_string())),
+                }
+                return;
+            }
+            "class" => {
+                let rest = text["class".len()..].trim();
+                let (declaration, opens) = match rest.find('{') {
+                    Some(open) => (rest[..open].trim(), !rest.ends_with('}')),
+                    None => (rest, false),
+                };
+                // An annotation may follow the name: `class Shape <<interface>>`
+                let name = declaration.split("<<").next().unwrap_or_default().trim();
+                if class_name(name) {
+                    if opens {
+                        self.blocks.push((true, number));
+                    }
+                } else {
+                    diagnostics.push(error(
+                        number,
+                        column_of(line, offset_in(text, rest)),
+                        format!("Invalid class name `{}`", name),
+                    ));
+                }
+                return;
+            }
+            _ => {}
+        }
+
+        if let Some(annotated) = text.strip_prefix("<<") {
+            match annotated.split_once(">>") {
+                Some((_, name)) if class_name(name.trim()) => {}
+                _ => diagnostics.push(error(number, column_of(line, 0), "Expected `<<annotation>> ClassName`".to_string())),
+            }
+            return;
+        }
+
+        let (head, label) = match text.find(':') {
+            Some(colon) if !text[colon..].starts_with(":::") => (&text[..colon], Some(&text[colon + 1..])),
+            _ => (text, None),
+        };
+        let mut words = tokens(head);
+        if words.len() == 1 {
+            if let Some(parts) = split_relation(words[0]) {
+                words = parts.to_vec();
+            }
+        }
+
+        match words.as_slice() {
+            // `Name : member` or a bare class name
+            [name] if class_name(name) => {
+                if label.is_some_and(|l| l.trim().is_empty()) {
+                    diagnostics.push(error(number, column_of(line, text.len()), "Expected a member after `:`".to_string()));
+                }
+            }
+            _ => {
+                let Some(arrow) = words.iter().position(|w| class_relation(w)) else {
+                    diagnostics.push(unrecognized(
+                        number,
+                        column_of(line, 0),
+                        "Expected a class, a member like `Name : +field` or a relation like `A <|-- B`".to_string(),
+                    ));
+                    return;
+                };
+                let quoted = |w: &&str| w.len() >= 2 && w.starts_with('"') && w.ends_with('"');
+                let before = &words[..arrow];
+                let after = &words[arrow + 1..];
+                let side_ok = |side: &[&str], name_first: bool| match side {
+                    [name] => class_name(name),
+                    [a, b] if name_first => class_name(a) && quoted(b),
+                    [a, b] => quoted(a) && class_name(b),
+                    _ => false,
+                };
+                if !side_ok(before, true) || !side_ok(after, false) {
+                    diagnostics.push(error(
+                        number,
+                        column_of(line, offset_in(text, words[arrow])),
+                        "A relation needs a class on each side, with optional quoted cardinalities like `A \"1\" --> \"*\" B`".to_string(),
+                    ));
+                }
+            }
+        }
+    }
+
+    fn finish(&mut self, diagnostics: &mut Vec<Diagnostic>) {
+        for (is_class, number) in self.blocks.drain(..).rev() {
+            let what = if is_class { "Class" } else { "Namespace" };
+            diagnostics.push(error(number, 1, format!("{} block is not closed with `}}`", what)));
+        }
+    }
+}
+
+// flowchart / graph
+
+#[derive(Default)]
+struct FlowchartChecker {
+    /// Lines of the open subgraphs
+    subgraphs: Vec<usize>,
+}
+
+#[derive(Debug, PartialEq)]
+enum FlowToken {
+    Node(String),
+    Arrow,
+    And,
+}
+
+/// Shape openers and their closers, longest first
+pub(crate) const SHAPES: [(&str, &[&str]); 11] = [
+    ("(((", &[")))"]),
+    ("[[", &["]]"]),
+    ("[(", &[")]"]),
+    ("([", &["])"]),
+    ("((", &["))"]),
+    ("{{", &["}}"]),
+    ("[/", &["/]", "\\]"]),
+    ("[\\", &["\\]", "/]"]),
+    ("[", &["]"]),
+    ("(", &[")"]),
+    ("{", &["}"]),
+];
+
+impl Checker for FlowchartChecker {
+    fn line(&mut self, number: usize, line: &str, diagnostics: &mut Vec<Diagnostic>) {
+        let text = line.trim().trim_end_matches(';').trim_end();
+
+        match first_word(text) {
+            "subgraph" => {
+                self.subgraphs.push(number);
+                return;
+            }
+            "end" if text == "end" => {
+                if self.subgraphs.pop().is_none() {
+                    diagnostics.push(error(number, column_of(line, 0), "`end` without an open subgraph".to_string()));
+                }
+                return;
+            }
+            "direction" | "classDef" | "class" | "style" | "linkStyle" | "click" => return,
+            _ => {}
+        }
+
+        for statement in split_statements(text) {
+            if let Err((offset, message, is_prose)) = flow_statement(statement) {
+                let column = column_of(line, offset_in(text, statement) + offset);
+                diagnostics.push(if is_prose { unrecognized(number, column, message) } else { error(number, column, message) });
+                return;
+            }
+        }
+    }
+
+    fn finish(&mut self, diagnostics: &mut Vec<Diagnostic>) {
+        for number in self.subgraphs.drain(..).rev() {
+            diagnostics.push(error(number, 1, "Subgraph is not closed with `end`".to_string()));
+        }
+    }
+}
+
+/// Split a line on `;` outside quotes
+pub(crate) fn split_statements(text: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                out.push(&text[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    out.push(&text[start..]);
+    out.into_iter().filter(|s| !s.trim().is_empty()).collect()
+}
+
+type FlowError = (usize, String, bool);
+
+fn flow_statement(text: &str) -> Result<(), FlowError> {
+    let mut tokens: Vec<(usize, FlowToken)> = Vec::new();
+    // Sequence problems come first: prose like `This is the flow.` should read as prose, not
+    // as a bad link at the full stop
+    let lexed = flow_tokens(text, &mut tokens);
+
+    let mut previous: Option<&FlowToken> = None;
+    for (offset, token) in &tokens {
+        match (previous, token) {
+            (None, FlowToken::Arrow) => return Err((*offset, "Link has no source node".to_string(), false)),
+            (None | Some(FlowToken::Arrow | FlowToken::And), FlowToken::And) => {
+                return Err((*offset, "`&` must join two nodes".to_string(), false))
+            }
+            (Some(FlowToken::Arrow), FlowToken::Arrow) => {
+                return Err((*offset, "Two links in a row; put a node between them".to_string(), false))
+            }
+            (Some(FlowToken::Node(_)), FlowToken::Node(_)) => {
+                return Err((*offset, "Expected a link between two nodes, e.g. `A --> B`".to_string(), true))
+            }
+            _ => {}
+        }
+        if let FlowToken::Node(id) = token {
+            if id == "end" {
+                return Err((*offset, "`end` cannot be a node id; capitalize it or pick another id".to_string(), false));
+            }
+        }
+        previous = Some(token);
+    }
+    lexed?;
+    match tokens.last() {
+        Some((offset, FlowToken::Arrow)) => Err((*offset, "Link has no target node".to_string(), false)),
+        Some((offset, FlowToken::And)) => Err((*offset, "`&` must join two nodes".to_string(), false)),
+        _ => Ok(()),
+    }
+}
+
+fn flow_tokens(text: &str, tokens: &mut Vec<(usize, FlowToken)>) -> Result<(), FlowError> {
+    let bytes = text.as_bytes();
+    let mut i = 0;
+
+    while i < text.len() {
+        let c = text[i..].chars().next().unwrap_or_default();
+        if c.is_whitespace() {
+            i += c.len_utf8();
+        } else if c == '&' {
+            tokens.push((i, FlowToken::And));
+            i += 1;
+        } else if c.is_alphanumeric() || c == '_' {
+            let start = i;
+            while let Some(c) = text[i..].chars().next().filter(|c| c.is_alphanumeric() || *c == '_') {
+                i += c.len_utf8();
+            }
+            let id = text[start..i].to_string();
+            i = skip_class_suffix(text, i);
+            if text[i..].starts_with("@{") {
+                // Shape metadata: `id@{ shape: rect, label: "text" }`
+                match text[i..].find('}') {
+                    Some(close) => i += close + 1,
+                    None => return Err((i, "Node metadata opened with `@{` is not closed".to_string(), false)),
+                }
+            } else if let Some((opener, closers)) = SHAPES.iter().find(|(o, _)| text[i..].starts_with(o)) {
+                i = flow_shape(text, i, opener, closers)?;
+                i = skip_class_suffix(text, i);
+            } else if text[i..].starts_with('>') && !text[..i].ends_with(' ') {
+                // Asymmetric shape: `id>label]`
+                i = flow_shape(text, i, ">", &["]"])?;
+            }
+            tokens.push((start, FlowToken::Node(id)));
+        } else if "-=.<~".contains(c) {
+            let start = i;
+            while i < text.len() && b"-=.<>~".contains(&bytes[i]) {
+                i += 1;
+            }
+            let arrow = &text[start..i];
+            // Arrow heads: `--o` and `--x`
+            if matches!(bytes.get(i), Some(b'o' | b'x'))
+                && matches!(bytes.get(i + 1), None | Some(b' ' | b'\t'))
+                && arrow.ends_with(['-', '='])
+            {
+                i += 1;
+            }
+            if arrow.len() < 2 || !arrow.contains(['-', '=', '~']) {
+                return Err((start, format!("`{}` is not a valid link", arrow), false));
+            }
+            // Text on the link itself: `A -- text --> B`
+            if matches!(arrow, "--" | "==" | "-.") {
+                let rest = &text[i..];
+                let close = rest.find("-->").or_else(|| rest.find("---")).or_else(|| rest.find("==>")).or_else(|| rest.find(".->"));
+                if let Some(close) = close {
+                    i += close;
+                    while i < text.len() && b"-=.>".contains(&bytes[i]) {
+                        i += 1;
+                    }
+                }
+            }
+            tokens.push((start, FlowToken::Arrow));
+            // Text on the link in pipes: `-->|yes|`
+            let after = i + text[i..].len() - text[i..].trim_start().len();
+            if text[after..].starts_with('|') {
+                match text[after + 1..].find('|') {
+                    Some(close) => i = after + close + 2,
+                    None => return Err((after, "Link text opened with `|` is not closed".to_string(), false)),
+                }
+            }
+        } else if c == '"' {
+            return Err((i, "Quoted text needs a node id and shape around it, e.g. `A[\"text\"]`".to_string(), false));
+        } else if "[({".contains(c) {
+            return Err((i, "A node shape needs an id before it, e.g. `A[text]`".to_string(), false));
+        } else {
+            return Err((i, format!("Unexpected `{}`", c), false));
+        }
+    }
+    Ok(())
+}
+
+fn skip_class_suffix(text: &str, mut i: usize) -> usize {
+    if text[i..].starts_with(":::") {
+        i += 3;
+        while let Some(c) = text[i..].chars().next().filter(|c| c.is_alphanumeric() || *c == '_' || *c == '-') {
+            i += c.len_utf8();
+        }
+    }
+    i
+}
+
+/// Check the shape opened at `start`; returns the offset after its closer
+fn flow_shape(text: &str, start: usize, opener: &str, closers: &[&str]) -> Result<usize, FlowError> {
+    let mut i = start + opener.len();
+    let inner = &text[i..];
+    let content_start = i + inner.len() - inner.trim_start().len();
+    let close_at = |i: usize| closers.iter().find(|c| text[i..].starts_with(**c)).map(|c| i + c.len());
+
+    if text[content_start..].starts_with('"') {
+        let Some(quote) = text[content_start + 1..].find('"') else {
+            return Err((content_start, "Quoted label is not closed".to_string(), false));
+        };
+        i = content_start + quote + 2;
+        i += text[i..].len() - text[i..].trim_start().len();
+        return close_at(i).ok_or_else(|| {
+            (i, format!("Expected `{}` after the quoted label", closers[0]), false)
+        });
+    }
+
+    while i < text.len() {
+        if let Some(end) = close_at(i) {
+            return Ok(end);
+        }
+        let c = text[i..].chars().next().unwrap_or_default();
+        if "[](){}\"".contains(c) {
+            return Err((
+                i,
+                format!("`{}` inside a node label; wrap the label in double quotes, e.g. `A[\"text (note)\"]`", c),
+                false,
+            ));
+        }
+        i += c.len_utf8();
+    }
+    Err((start, format!("Node shape `{}` is not closed with `{}`", opener, closers[0]), false))
+}
+
+// journey
+
+struct JourneyChecker;
+
+impl Checker for JourneyChecker {
+    fn line(&mut self, number: usize, line: &str, diagnostics: &mut Vec<Diagnostic>) {
+        let text = line.trim();
+        match first_word(text) {
+            "title" => return,
+            "section" => {
+                if text["section".len()..].trim().is_empty() {
+                    diagnostics.push(error(number, column_of(line, 0), "Section needs a name".to_string()));
+                }
+                return;
+            }
+            _ => {}
+        }
+
+        if let Some(bad) = text.find(['#', ';']) {
+            diagnostics.push(error(number, column_of(line, bad), format!("`{}` is not allowed in a task", &text[bad..bad + 1])));
+            return;
+        }
+        let Some((name, data)) = text.split_once(':') else {
+            diagnostics.push(unrecognized(
+                number,
+                column_of(line, 0),
+                "Expected a task like `Sign up: 5: User`, a `section` or a `title`".to_string(),
+            ));
+            return;
+        };
+        if name.trim().is_empty() {
+            diagnostics.push(error(number, column_of(line, 0), "Task needs a name before `:`".to_string()));
+            return;
+        }
+        let score = data.split(':').next().unwrap_or_default();
+        if score.trim().parse::<i32>().is_err() {
+            diagnostics.push(error(
+                number,
+                column_of(line, name.len() + 1),
+                format!("Task score must be a whole number, found `{}`", score.trim()),
+            ));
+        }
+    }
+
+    fn finish(&mut self, _diagnostics: &mut Vec<Diagnostic>) {}
+}
+
+// sequenceDiagram
+
+#[derive(Default)]
+struct SequenceChecker {
+    /// Keyword and line of the open `loop`, `alt`, `par` ... blocks
+    blocks: Vec<(String, usize)>,
+}
+
+const SEQUENCE_BLOCKS: [&str; 8] = ["loop", "alt", "opt", "par", "critical", "break", "rect", "box"];
+/// Message arrows, longest first
+const SEQUENCE_ARROWS: [&str; 10] = ["<<-->>", "<<->>", "-->>", "->>", "--x", "--)", "-->", "-x", "-)", "->"];
+
+impl Checker for SequenceChecker {
+    fn line(&mut self, number: usize, line: &str, diagnostics: &mut Vec<Diagnostic>) {
+        let text = line.trim().trim_end_matches(';').trim_end();
+        let keyword = first_word(text).to_lowercase();
+        let rest = text[keyword.len()..].trim();
+
+        match keyword.as_str() {
+            "participant" | "actor" | "destroy" | "activate" | "deactivate" => {
+                if rest.is_empty() {
+                    diagnostics.push(error(number, column_of(line, 0), format!("`{}` needs a participant name", keyword)));
+                }
+                return;
+            }
+            "create" | "autonumber" | "title" | "link" | "links" | "properties" | "details" => return,
+            "end" if rest.is_empty() => {
+                if self.blocks.pop().is_none() {
+                    diagnostics.push(error(number, column_of(line, 0), "`end` without an open block".to_string()));
+                }
+                return;
+            }
+            "else" | "and" | "option" => {
+                let parent = match keyword.as_str() {
+                    "else" => "alt",
+                    "and" => "par",
+                    _ => "critical",
+                };
+                if !self.blocks.iter().any(|(block, _)| block == parent) {
+                    diagnostics.push(error(number, column_of(line, 0), format!("`{}` outside of an `{}` block", keyword, parent)));
+                }
+                return;
+            }
+            "note" => {
+                let position = ["left of ", "right of ", "over "].iter().any(|p| rest.to_lowercase().starts_with(p));
+                if !position {
+                    diagnostics.push(error(
+                        number,
+                        column_of(line, keyword.len()),
+                        "Notes need a position: `left of`, `right of` or `over`".to_string(),
+                    ));
+                } else if !rest.contains(':') {
+                    diagnostics.push(error(number, column_of(line, text.len()), "Note needs its text after `:`".to_string()));
+                }
+                return;
+            }
+            _ => {}
+        }
+        if SEQUENCE_BLOCKS.contains(&keyword.as_str()) {
+            self.blocks.push((keyword, number));
+            return;
+        }
+
+        let arrow = SEQUENCE_ARROWS
+            .iter()
+            .filter_map(|arrow| text.find(arrow).map(|at| (at, *arrow)))
+            .min_by_key(|(at, arrow)| (*at, usize::MAX - arrow.len()));
+        let Some((at, arrow)) = arrow else {
+            diagnostics.push(unrecognized(
+                number,
+                column_of(line, 0),
+                "Expected a message like `Alice->>Bob: Hello`, a participant or a block like `loop`".to_string(),
+            ));
+            return;
+        };
+        let Some((target, _)) = text[at + arrow.len()..].split_once(':') else {
+            diagnostics.push(error(number, column_of(line, text.len()), "Message needs its text after `:`".to_string()));
+            return;
+        };
+        if text[..at].trim().is_empty() {
+            diagnostics.push(error(number, column_of(line, 0), "Message has no sender".to_string()));
+        } else if target.trim().trim_start_matches(['+', '-']).trim().is_empty() {
+            diagnostics.push(error(number, column_of(line, at + arrow.len()), "Message has no receiver".to_string()));
+        }
+    }
+
+    fn finish(&mut self, diagnostics: &mut Vec<Diagnostic>) {
+        for (keyword, number) in self.blocks.drain(..).rev() {
+            diagnostics.push(error(number, 1, format!("`{}` block is not closed with `end`", keyword)));
+        }
+    }
+}
+
+// stateDiagram
+
+#[derive(Default)]
+struct StateChecker {
+    /// Lines of the open composite states
+    states: Vec<usize>,
+    /// Line of the open multi-line note
+    note: Option<usize>,
+}
+
+/// A state name, `[*]` or a name with a `:::class`
+fn state_name(text: &str) -> bool {
+    let text = text.split(":::").next().unwrap_or_default().trim();
+    text == "[*]" || is_name(text)
+}
+
+impl Checker for StateChecker {
+    fn line(&mut self, number: usize, line: &str, diagnostics: &mut Vec<Diagnostic>) {
+        let text = line.trim().trim_end_matches(';').trim_end();
+
+        if self.note.is_some() {
+            if text.eq_ignore_ascii_case("end note") {
+                self.note = None;
+            }
+            return;
+        }
+        if text == "}" {
+            if self.states.pop().is_none() {
+                diagnostics.push(error(number, column_of(line, 0), "`}` without an open composite state".to_string()));
+            }
+            return;
+        }
+        if text == "--" || text == "hide empty description" {
+            return;
+        }
+
+        match first_word(text) {
+            "direction" | "classDef" | "class" | "style" | "click" => return,
+            "note" => {
+                let rest = text["note".len()..].trim_start();
+                if !(rest.starts_with("left of ") || rest.starts_with("right of ")) {
+                    diagnostics.push(error(
+                        number,
+                        column_of(line, "note".len()),
+                        "Notes need a position: `left of` or `right of`".to_string(),
+                    ));
+                } else if !rest.contains(':') {
+                    self.note = Some(number);
+                }
+                return;
+            }
+            "state" => {
+                let rest = text["state".len()..].trim();
+                let (rest, composite) = match rest.strip_suffix('{') {
+                    Some(head) => (head.trim(), true),
+                    None => (rest, false),
+                };
+                let name = match rest.rsplit_once(" as ") {
+                    Some((_, alias)) if rest.starts_with('"') => alias.trim(),
+                    _ => rest.split_whitespace().next().unwrap_or_default(),
+                };
+                if !state_name(name) || name == "[*]" {
+                    diagnostics.push(error(number, column_of(line, 0), "`state` needs a state name".to_string()));
+                } else if composite {
+                    self.states.push(number);
+                }
+                return;
+            }
+            _ => {}
+        }
+
+        if let Some(at) = text.find("-->") {
+            let source = text[..at].trim();
+            let target = text[at + 3..].split(':').next().unwrap_or_default().trim();
+            for (side, offset) in [(source, 0), (target, at + 3)] {
+                if !state_name(side) {
+                    let message = if side.is_empty() {
+                        "Transition needs a state on both sides of `-->`".to_string()
+                    } else {
+                        format!("Invalid state name `{}`", side)
+                    };
+                    diagnostics.push(error(number, column_of(line, offset), message));
+                    return;
+                }
+            }
+            return;
+        }
+
+        // `Id` or `Id : description`
+        let name = text.split(':').next().unwrap_or_default().trim();
+        if !state_name(name) {
+            diagnostics.push(unrecognized(
+                number,
+                column_of(line, 0),
+                "Expected a transition like `Idle --> Running : start`, a `state` or a note".to_string(),
+            ));
+        }
+    }
+
+    fn finish(&mut self, diagnostics: &mut Vec<Diagnostic>) {
+        if let Some(number) = self.note.take() {
+            diagnostics.push(error(number, 1, "Note is not closed with `end note`".to_string()));
+        }
+        for number in self.states.drain(..).rev() {
+            diagnostics.push(error(number, 1, "Composite state is not closed with `}`".to_string()));
+        }
+    }
+}
+
+// C4Context, C4Container ...
+
+#[derive(Default)]
+struct C4Checker {
+    /// Lines of the open boundaries
+    boundaries: Vec<usize>,
+}
+
+impl Checker for C4Checker {
+    fn line(&mut self, number: usize, line: &str, diagnostics: &mut Vec<Diagnostic>) {
+        let text = line.trim();
+
+        if text == "}" {
+            if self.boundaries.pop().is_none() {
+                diagnostics.push(error(number, column_of(line, 0), "`}` without an open boundary".to_string()));
+            }
+            return;
+        }
+        if first_word(text) == "title" {
+            return;
+        }
+
+        let name_end = text.find('(').unwrap_or(text.len());
+        let name = text[..name_end].trim();
+        if name_end == text.len() || !is_name(name) {
+            diagnostics.push(unrecognized(
+                number,
+                column_of(line, 0),
+                "Expected an element like `Person(customer, \"Customer\")`, a relationship like `Rel(a, b, \"Uses\")` or a boundary".to_string(),
+            ));
+            return;
+        }
+
+        let mut quoted = false;
+        let mut depth = 0;
+        let mut close = None;
+        for (i, c) in text.char_indices().skip_while(|(i, _)| *i < name_end) {
+            match c {
+                '"' => quoted = !quoted,
+                '(' if !quoted => depth += 1,
+                ')' if !quoted => {
+                    depth -= 1;
+                    if depth == 0 {
+                        close = Some(i);
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        let Some(close) = close else {
+            let message = if quoted { "String is not closed with `\"`" } else { "`(` is not closed with `)`" };
+            diagnostics.push(error(number, column_of(line, name_end), message.to_string()));
+            return;
+        };
+
+        match text[close + 1..].trim() {
+            "" => {}
+            "{" => self.boundaries.push(number),
+            extra => {
+                diagnostics.push(error(number, column_of(line, close + 1), format!("Unexpected `{}` after `)`", extra)));
+                return;
+            }
+        }
+
+        let arguments = split_arguments(&text[name_end + 1..close]);
+        if name.starts_with("Update") {
+            return;
+        }
+        let (needed, message) = if name.starts_with("Rel") || name.starts_with("BiRel") {
+            (3, "Relationships need a source, a target and a label".to_string())
+        } else {
+            (2, format!("`{}` needs an alias and a label", name))
+        };
+        if arguments.len() < needed || arguments[..needed].iter().any(|a| a.trim().is_empty()) {
+            diagnostics.push(error(number, column_of(line, name_end), message));
+        }
+    }
+
+    fn finish(&mut self, diagnostics: &mut Vec<Diagnostic>) {
+        for number in self.boundaries.drain(..).rev() {
+            diagnostics.push(error(number, 1, "Boundary is not closed with `}`".to_string()));
+        }
+    }
+}
+
+/// Split C4 macro arguments on `,` outside quotes
+fn split_arguments(text: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                out.push(&text[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    out.push(&text[start..]);
+    if out.len() == 1 && out[0].trim().is_empty() {
+        out.clear();
+    }
+    out
+}
+
+// Repair loop
+
+/// Repair requests sent for one invalid diagram when the settings don't say otherwise
+pub const DEFAULT_REPAIR_ATTEMPTS: u32 = 2;
+
+/// Progress of a repair loop, reported before each repair request
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RepairAttempt {
+    /// 1-based
+    pub attempt: u32,
+    pub max_attempts: u32,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Repaired {
+    /// Cleaned code of the last answer
+    pub code: String,
+    /// Problems left in `code`; empty unless every attempt failed
+    pub diagnostics: Vec<Diagnostic>,
+    /// Repair requests sent
+    pub attempts: u32,
+}
+
+/// Follow-up message asking the model to fix `diagnostics` in its last answer
+pub fn repair_message(kind: &str, diagnostics: &[Diagnostic]) -> String {
+    let header = headers(kind).map(|h| h[0]).unwrap_or("Mermaid");
+    let problems: Vec<String> = diagnostics.iter().map(|d| format!("- {}", d)).collect();
+    format!(
+        "The diagram you returned does not parse as a Mermaid `{}`:\n{}\n\n\
+         Return the complete corrected diagram. Keep everything else unchanged. \
+         Output only the Mermaid code, without code fences or explanations.",
+        header,
+        problems.join("\n")
+    )
+}
+
+/// Validate `answer`, the reply to `turns`, and while it has problems and attempts remain,
+/// send the conversation plus a repair request to `ask` for a corrected answer.
+/// `on_attempt` is called before each request.
+pub async fn repair<F, Fut>(
+    kind: &str,
+    mut turns: Vec<(&'static str, String)>,
+    answer: String,
+    max_attempts: u32,
+    mut ask: F,
+    mut on_attempt: impl FnMut(&RepairAttempt),
+) -> Result<Repaired, String>
+where
+    F: FnMut(Vec<(&'static str, String)>) -> Fut,
+    Fut: std::future::Future<Output = Result<String, String>>,
+{
+    let mut report = check(kind, &answer)?;
+    let mut answer = answer;
+    let mut attempts = 0;
+
+    while !report.diagnostics.is_empty() && attempts < max_attempts {
+        attempts += 1;
+        on_attempt(&RepairAttempt { attempt: attempts, max_attempts, diagnostics: report.diagnostics.clone() });
+
+        turns.push(("assistant", answer));
+        turns.push(("user", repair_message(kind, &report.diagnostics)));
+        answer = ask(turns.clone()).await?;
+        report = check(kind, &answer)?;
+    }
+
+    Ok(Repaired { code: report.cleaned, diagnostics: report.diagnostics, attempts })
+}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -7,6 +8,15 @@ pub struct Workspace {
     pub active_project_id: String,
     pub created_at: String,
     pub updated_at: String,
+    /// Prompt template overrides for every project, keyed by prompt task
+    #[serde(default)]
+    pub prompt_templates: HashMap<String, PromptTemplate>,
+}
+
+impl Default for Workspace {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Workspace {
@@ -21,6 +31,7 @@ impl Workspace {
             active_project_id: active_id,
             created_at: now.clone(),
             updated_at: now,
+            prompt_templates: HashMap::new(),
         }
     }
 
@@ -89,11 +100,24 @@ pub struct Project {
     pub updated_at: String,
     #[serde(default)]
     pub history: Vec<Refinement>,
-    pub er_diagram: Option<String>,
-    pub uml_diagram: Option<String>,
-    pub flowchart: Option<String>,
-    pub user_journey: Option<String>,
+    /// Named diagrams in display order; a project can hold several of each kind
+    #[serde(default)]
+    pub diagrams: Vec<Diagram>,
+    // Diagram fields of older workspaces, moved into `diagrams` by `migrate_diagrams`
+    #[serde(default, skip_serializing)]
+    er_diagram: Option<String>,
+    #[serde(default, skip_serializing)]
+    uml_diagram: Option<String>,
+    #[serde(default, skip_serializing)]
+    flowchart: Option<String>,
+    #[serde(default, skip_serializing)]
+    user_journey: Option<String>,
     pub user_stories: Option<String>,
+    /// Prompt template overrides for this project only, keyed by prompt task
+    #[serde(default)]
+    pub prompt_templates: HashMap<String, PromptTemplate>,
+    #[serde(default)]
+    pub threads: Vec<ConversationThread>,
 }
 
 impl Project {
@@ -106,11 +130,65 @@ impl Project {
             created_at: now.clone(),
             updated_at: now,
             history: Vec::new(),
+            diagrams: Vec::new(),
             er_diagram: None,
             uml_diagram: None,
             flowchart: None,
             user_journey: None,
             user_stories: None,
+            prompt_templates: HashMap::new(),
+            threads: Vec::new(),
+        }
+    }
+
+    /// The first diagram of `kind`, unless it is missing or blank
+    pub fn diagram(&self, kind: &str) -> Option<&str> {
+        self.diagrams
+            .iter()
+            .find(|d| d.kind == kind)
+            .map(|d| d.code.as_str())
+            .filter(|code| !code.trim().is_empty())
+    }
+
+    pub fn get_diagram(&self, diagram_id: &str) -> Option<&Diagram> {
+        self.diagrams.iter().find(|d| d.id == diagram_id)
+    }
+
+    pub fn get_diagram_mut(&mut self, diagram_id: &str) -> Option<&mut Diagram> {
+        self.diagrams.iter_mut().find(|d| d.id == diagram_id)
+    }
+
+    pub fn add_diagram(&mut self, diagram: Diagram) {
+        self.diagrams.push(diagram);
+        self.updated_at = chrono::Utc::now().to_rfc3339();
+    }
+
+    pub fn remove_diagram(&mut self, diagram_id: &str) -> Result<(), String> {
+        let original_len = self.diagrams.len();
+        self.diagrams.retain(|d| d.id != diagram_id);
+
+        if self.diagrams.len() < original_len {
+            self.updated_at = chrono::Utc::now().to_rfc3339();
+            Ok(())
+        } else {
+            Err(format!("Diagram with id {} not found", diagram_id))
+        }
+    }
+
+    /// Move diagrams from the fixed fields of older workspaces into `diagrams`
+    pub fn migrate_diagrams(&mut self) {
+        let legacy = [
+            ("er", self.er_diagram.take()),
+            ("uml", self.uml_diagram.take()),
+            ("flowchart", self.flowchart.take()),
+            ("journey", self.user_journey.take()),
+        ];
+        for (kind, code) in legacy {
+            if let Some(code) = code {
+                if !self.diagrams.iter().any(|d| d.kind == kind) {
+                    self.diagrams.push(Diagram::new(kind.to_string(), default_diagram_name(kind), code));
+                }
+            }
         }
     }
 
@@ -180,6 +258,24 @@ impl Project {
                     section.order_index = index;
                 }
             }
+            "diagram" => {
+                let current_index = self.diagrams
+                    .iter()
+                    .position(|d| d.id == id)
+                    .ok_or("Diagram not found")?;
+
+                if current_index == new_index {
+                    return Ok(());
+                }
+
+                let diagram = self.diagrams.remove(current_index);
+                let adjusted_index = if new_index > current_index {
+                    new_index - 1
+                } else {
+                    new_index
+                };
+                self.diagrams.insert(adjusted_index.min(self.diagrams.len()), diagram);
+            }
             "topic" => {
                 // Find which section contains the topic
                 let section = self.sections
@@ -221,6 +317,182 @@ impl Project {
     pub fn add_refinement(&mut self, refinement: Refinement) {
         self.history.push(refinement);
     }
+
+    /// Sections in display order
+    fn ordered_sections(&self) -> Vec<&Section> {
+        let mut sections: Vec<&Section> = self.sections.iter().collect();
+        sections.sort_by_key(|s| s.order_index);
+        sections
+    }
+
+    /// Section and topic names in display order, marking `current` (a section or topic id)
+    fn outline(&self, current: &str) -> String {
+        self.ordered_sections()
+            .iter()
+            .map(|section| {
+                let topics = section.ordered_topics()
+                    .iter()
+                    .map(|t| if t.id == current { format!("{} (this topic)", t.name) } else { t.name.clone() })
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                let marker = if section.id == current { " (this section)" } else { "" };
+                format!("- {}{}: {}", section.name, marker, topics)
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// Project outline plus the full topics just before and after `topic_id` in merged order,
+    /// for prompts that refine a single topic
+    pub fn topic_context(&self, topic_id: &str) -> Option<String> {
+        let topics: Vec<(&Section, &Topic)> = self.ordered_sections()
+            .into_iter()
+            .flat_map(|section| section.ordered_topics().into_iter().map(move |topic| (section, topic)))
+            .collect();
+        let index = topics.iter().position(|(_, t)| t.id == topic_id)?;
+
+        let mut context = format!("Outline:\n{}", self.outline(topic_id));
+        let neighbors = [("Previous", index.checked_sub(1)), ("Next", Some(index + 1))];
+        for (label, neighbor) in neighbors {
+            if let Some((section, topic)) = neighbor.and_then(|i| topics.get(i)) {
+                context.push_str(&format!(
+                    "\n\n{} topic ({} / {}):\n{}",
+                    label,
+                    section.name,
+                    topic.name,
+                    topic.content.trim()
+                ));
+            }
+        }
+        Some(context)
+    }
+
+    /// Project outline plus the full sections just before and after `section_id`, for prompts
+    /// that refine a single section
+    pub fn section_context(&self, section_id: &str) -> Option<String> {
+        let sections = self.ordered_sections();
+        let index = sections.iter().position(|s| s.id == section_id)?;
+
+        let mut context = format!("Outline:\n{}", self.outline(section_id));
+        let neighbors = [("Previous", index.checked_sub(1)), ("Next", Some(index + 1))];
+        for (label, neighbor) in neighbors {
+            if let Some(section) = neighbor.and_then(|i| sections.get(i)) {
+                context.push_str(&format!("\n\n{} section ({}):\n{}", label, section.name, section.get_content()));
+            }
+        }
+        Some(context)
+    }
+
+    pub fn get_thread(&self, thread_id: &str) -> Option<&ConversationThread> {
+        self.threads.iter().find(|t| t.id == thread_id)
+    }
+
+    pub fn get_thread_mut(&mut self, thread_id: &str) -> Option<&mut ConversationThread> {
+        self.threads.iter_mut().find(|t| t.id == thread_id)
+    }
+
+    pub fn remove_thread(&mut self, thread_id: &str) -> Result<(), String> {
+        let original_len = self.threads.len();
+        self.threads.retain(|t| t.id != thread_id);
+
+        if self.threads.len() < original_len {
+            self.updated_at = chrono::Utc::now().to_rfc3339();
+            Ok(())
+        } else {
+            Err(format!("Thread with id {} not found", thread_id))
+        }
+    }
+}
+
+/// A Mermaid diagram of one kind (see `mermaid::DIAGRAM_KINDS`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Diagram {
+    pub id: String,
+    pub kind: String,
+    pub name: String,
+    pub code: String,
+    pub created_at: String,
+    pub updated_at: String,
+    /// Earlier and current code, oldest first
+    #[serde(default)]
+    pub versions: Vec<DiagramVersion>,
+}
+
+/// Unlabeled versions beyond this many are dropped, oldest first
+const MAX_VERSIONS: usize = 200;
+
+impl Diagram {
+    pub fn new(kind: String, name: String, code: String) -> Self {
+        let now = chrono::Utc::now().to_rfc3339();
+        let mut diagram = Diagram {
+            id: Uuid::new_v4().to_string(),
+            kind,
+            name,
+            code: String::new(),
+            created_at: now.clone(),
+            updated_at: now,
+            versions: Vec::new(),
+        };
+        if !code.trim().is_empty() {
+            diagram.save(code, "save");
+        }
+        diagram
+    }
+
+    /// Store `code` and record it as a new version. `source` says where it came from (`save`,
+    /// `import` or `revert`). Versions never change once recorded.
+    pub fn save(&mut self, code: String, source: &str) {
+        let now = chrono::Utc::now().to_rfc3339();
+        self.code = code.clone();
+        self.updated_at = now.clone();
+        if self.versions.last().is_some_and(|last| last.code == code) {
+            return;
+        }
+
+        self.versions.push(DiagramVersion {
+            id: Uuid::new_v4().to_string(),
+            code,
+            created_at: now,
+            source: source.to_string(),
+            label: None,
+        });
+        // Labeled versions and the current one are always kept
+        while self.versions.len() > MAX_VERSIONS {
+            match self.versions[..self.versions.len() - 1].iter().position(|v| v.label.is_none()) {
+                Some(index) => self.versions.remove(index),
+                None => break,
+            };
+        }
+    }
+
+    pub fn get_version(&self, version_id: &str) -> Option<&DiagramVersion> {
+        self.versions.iter().find(|v| v.id == version_id)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiagramVersion {
+    pub id: String,
+    pub code: String,
+    pub created_at: String,
+    pub source: String, // 'save', 'import', 'revert'
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+/// Name given to a diagram created without one, like `Flowchart`
+pub fn default_diagram_name(kind: &str) -> String {
+    match kind {
+        "er" => "ER Diagram".to_string(),
+        "uml" => "Class Diagram".to_string(),
+        "flowchart" => "Flowchart".to_string(),
+        "journey" => "User Journey".to_string(),
+        _ => {
+            let mut chars = kind.chars();
+            let first: String = chars.next().map(|c| c.to_uppercase().collect()).unwrap_or_default();
+            format!("{}{} Diagram", first, chars.as_str())
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -244,6 +516,23 @@ impl Section {
         }
     }
 
+    /// Topics in display order
+    fn ordered_topics(&self) -> Vec<&Topic> {
+        let mut topics: Vec<&Topic> = self.topics.iter().collect();
+        topics.sort_by_key(|t| t.order_index);
+        topics
+    }
+
+    /// Non-empty topic contents in order, as refined when the whole section is refined
+    pub fn get_content(&self) -> String {
+        self.ordered_topics()
+            .iter()
+            .map(|topic| topic.content.trim())
+            .filter(|content| !content.is_empty())
+            .collect::<Vec<&str>>()
+            .join("\n\n")
+    }
+
     pub fn add_topic(&mut self, mut topic: Topic) {
         topic.order_index = self.topics.len();
         topic.section_id = self.id.clone();
@@ -313,6 +602,16 @@ pub struct Refinement {
     pub kind: Option<String>, // 'text', 'er', 'uml', 'flowchart', 'journey'
     #[serde(default)]
     pub mode: Option<String>, // 'edit', 'ask'
+    /// Diagram the refinement belongs to, for diagram kinds
+    #[serde(default)]
+    pub diagram_id: Option<String>,
+}
+
+/// System prompt and user message sent for an LLM task; `{variable}` placeholders are filled in per call
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptTemplate {
+    pub system: String,
+    pub user: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -342,3 +641,86 @@ impl Topic {
         self.history.push(refinement);
     }
 }
+
+/// A multi-turn conversation with the LLM about one project, topic or diagram
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationThread {
+    pub id: String,
+    pub title: String,
+    /// Prompt task of the first turn; follow-ups stream on the same event prefix
+    pub task: String,
+    pub target_type: String, // 'project', 'topic', 'diagram'
+    pub target_id: Option<String>, // topic or diagram id
+    /// System prompt of the first turn, reused for every follow-up
+    #[serde(default)]
+    pub system_prompt: String,
+    pub messages: Vec<ThreadMessage>,
+    #[serde(default)]
+    pub forked_from: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+impl ConversationThread {
+    pub fn new(title: String, task: String, target_type: String, target_id: Option<String>) -> Self {
+        let now = chrono::Utc::now().to_rfc3339();
+        ConversationThread {
+            id: Uuid::new_v4().to_string(),
+            title,
+            task,
+            target_type,
+            target_id,
+            system_prompt: String::new(),
+            messages: Vec::new(),
+            forked_from: None,
+            created_at: now.clone(),
+            updated_at: now,
+        }
+    }
+
+    /// A copy holding the first `message_count` messages (all of them when `None`).
+    /// The fork must end on an answer so the next turn follows a complete exchange.
+    pub fn fork(&self, message_count: Option<usize>) -> Result<Self, String> {
+        let count = message_count.unwrap_or(self.messages.len());
+        if count > self.messages.len() {
+            return Err(format!("Thread has only {} messages", self.messages.len()));
+        }
+        if count > 0 && self.messages[count - 1].role != "assistant" {
+            return Err("A fork must end on an assistant message".to_string());
+        }
+
+        let mut fork = ConversationThread::new(
+            format!("{} (fork)", self.title),
+            self.task.clone(),
+            self.target_type.clone(),
+            self.target_id.clone(),
+        );
+        fork.system_prompt = self.system_prompt.clone();
+        fork.messages = self.messages[..count].to_vec();
+        fork.forked_from = Some(self.id.clone());
+        Ok(fork)
+    }
+
+    /// Record a completed exchange
+    pub fn add_turn(&mut self, user_message: String, answer: String) {
+        let now = chrono::Utc::now().to_rfc3339();
+        self.messages.push(ThreadMessage {
+            role: "user".to_string(),
+            content: user_message,
+            timestamp: now.clone(),
+        });
+        self.messages.push(ThreadMessage {
+            role: "assistant".to_string(),
+            content: answer,
+            timestamp: now.clone(),
+        });
+        self.updated_at = now;
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadMessage {
+    pub role: String, // 'user', 'assistant'
+    pub content: String,
+    pub timestamp: String,
+}
@@ -0,0 +1,364 @@
+use crate::models::{PromptTemplate, Workspace};
+
+/// Prompt tasks and the variables their templates may use
+pub const PROMPT_TASKS: [(&str, &[&str]); 20] = [
+    ("refine", &["content"]),
+    ("refine_topic", &["project_name", "section_name", "topic_name", "context", "content"]),
+    ("refine_section", &["project_name", "section_name", "context", "content"]),
+    ("refine_structured", &["content"]),
+    ("er", &["content"]),
+    ("uml", &["content"]),
+    ("flowchart", &["content"]),
+    ("journey", &["content"]),
+    ("sequence", &["content"]),
+    ("state", &["content"]),
+    ("c4", &["content"]),
+    ("stories", &["content"]),
+    ("edit_er", &["current_diagram", "instruction"]),
+    ("edit_uml", &["current_diagram", "instruction"]),
+    ("edit_flowchart", &["current_diagram", "instruction"]),
+    ("edit_journey", &["current_diagram", "instruction"]),
+    ("edit_sequence", &["current_diagram", "instruction"]),
+    ("edit_state", &["current_diagram", "instruction"]),
+    ("edit_c4", &["current_diagram", "instruction"]),
+    ("ask", &["diagram_type", "current_diagram", "question"]),
+];
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PromptTemplateEntry {
+    pub task: String,
+    pub variables: Vec<String>,
+    pub default: PromptTemplate,
+    pub workspace_override: Option<PromptTemplate>,
+    pub project_override: Option<PromptTemplate>,
+    /// The template that will actually be sent: project override, then workspace override, then default
+    pub effective: PromptTemplate,
+}
+
+pub fn default_template(task: &str) -> Option<PromptTemplate> {
+    match task {
+        "refine" => Some(PromptTemplate {
+            system: "You are an expert at refining and improving prompts for software development projects. Your task is to take the user's prompt and make it clearer, more specific, and more effective while maintaining the original intent. Keep any `// Section:` marker lines exactly as they are, so the result can be mapped back onto its sections.".to_string(),
+            user: "Refine and improve the following prompt for a software development project:\n\n{content}".to_string(),
+        }),
+        "refine_topic" => Some(PromptTemplate {
+            system: "You are an expert at refining and improving prompts for software development projects. You are given one topic of a larger project prompt together with the surrounding project for context.
+Refine only the topic: make it clearer, more specific, and more effective while maintaining the original intent. Keep it consistent with the rest of the project and do not repeat what neighboring topics already cover.
+Output only the refined topic text, without headings for the project or section and without any explanation.".to_string(),
+            user: "Project: {project_name}\nSection: {section_name}\nTopic: {topic_name}\n\nContext:\n{context}\n\nRefine this topic:\n\n{content}".to_string(),
+        }),
+        "refine_section" => Some(PromptTemplate {
+            system: "You are an expert at refining and improving prompts for software development projects. You are given one section of a larger project prompt together with the surrounding project for context.
+Refine only the section: make it clearer, more specific, and more effective while maintaining the original intent. Keep it consistent with the rest of the project and do not repeat what neighboring sections already cover.
+Output only the refined section text, without any explanation.".to_string(),
+            user: "Project: {project_name}\nSection: {section_name}\n\nContext:\n{context}\n\nRefine this section:\n\n{content}".to_string(),
+        }),
+        "refine_structured" => Some(PromptTemplate {
+            system: "You are an expert at refining and improving prompts for software development projects. The prompt is given as JSON: a list of sections, each with a list of named topics.
+Refine the content of every topic: make it clearer, more specific, and more effective while maintaining the original intent. Keep the section and topic names unless a name is clearly wrong. You may add a topic or section when something important is missing, and drop a topic only if its content moved into another one.
+Output ONLY JSON of the same shape: {\"sections\": [{\"name\": \"...\", \"topics\": [{\"name\": \"...\", \"content\": \"...\"}]}]}. Do not wrap it in a code fence and do not add any explanation.".to_string(),
+            user: "Refine this project prompt:\n\n{content}".to_string(),
+        }),
+        "er" => Some(PromptTemplate {
+            system: "You are an expert software architect. Your task is to analyze the provided software project description and generate a Mermaid ER Diagram representing the data model.
+    
+Output ONLY the mermaid code block. Do not include markdown code fences (```mermaid). Do not include any explanation. Just the code.
+Start with `erDiagram`.
+    
+Example output:
+erDiagram
+    USER ||--o{ ORDER : places
+    USER {
+        string username
+        string email
+    }
+    ORDER ||--|{ ORDER_ITEM : contains
+    ORDER {
+        int id
+        string deliveryAddress
+    }".to_string(),
+            user: "Generate a Mermaid ER Diagram for the following project description:\n\n{content}".to_string(),
+        }),
+        "uml" => Some(PromptTemplate {
+            system: "You are an expert software architect. Your task is to analyze the provided software project description and generate a Mermaid Class Diagram representing the architecture using the Repository Pattern.
+    
+Include the following layers where appropriate:
+- Interface
+- Repository
+- Service
+- Resource/Controller
+- DTO
+
+Output ONLY the mermaid code block. Do not include markdown code fences (```mermaid). Do not include any explanation. Just the code.
+Start with `classDiagram`.
+
+Example output:
+classDiagram
+    class UserRepository {
+        <<interface>>
+        +findById(id: Long) User
+        +save(user: User) User
+    }
+    class UserRepositoryImpl {
+        +findById(id: Long) User
+        +save(user: User) User
+    }
+    class UserService {
+        -userRepository: UserRepository
+        +createUser(userDto: UserDTO) UserDTO
+    }
+    UserRepository <|.. UserRepositoryImpl
+    UserService --> UserRepository".to_string(),
+            user: "Generate a Mermaid Class Diagram (Repository Pattern) for the following project description:\n\n{content}".to_string(),
+        }),
+        "flowchart" => Some(PromptTemplate {
+            system: "You are an expert software architect. Your task is to analyze the provided software project description and generate a detailed Mermaid Flowchart representing the system logic, data flow, and key processes.
+
+Guidelines:
+1. Syntax: Start with `graph TD` (Top-Down) or `graph LR` (Left-Right) as appropriate for the flow.
+2. Shapes: Use standard flowchart shapes to represent different elements clearly:
+   - `[\"Action/Process\"]` for standard steps (Square).
+   - `{\"Decision?\"}` for logic checks and branching (Diamond).
+   - `([\"Start/End\"])` for entry and exit points (Rounded).
+   - `[(\"Database\")]` for data storage interactions (Cylinder).
+   - `[[\"Subroutine/Module\"]]` for complex components (Double border).
+3. Text Labels: **CRITICAL** - ALWAYS enclose the text inside shapes in double quotes.
+   - CORRECT: `A[\"User Request (HTTP)\"]`, `B{\"Is Valid?\"}`
+   - INCORRECT: `A[User Request (HTTP)]`, `B{Is Valid?}`
+   - This prevents syntax errors from special characters like `(`, `)`, `,`, etc.
+4. Structure: 
+   - Use `subgraph` to group related components (e.g., `subgraph Frontend`, `subgraph Backend`).
+   - Ensure arrows `-->` are clearly labeled with logic or data passed (e.g., `-->|\"Valid\"|`, `-->|\"Data\"|`).
+5. Clarity: Keep the chart legible. Avoid excessive crossing lines.
+
+Output Requirement:
+- Output ONLY the raw Mermaid code.
+- Do NOT include markdown code fences (```mermaid).
+- Do NOT include any explanations.
+- The output must start directly with `graph` or `flowchart`.".to_string(),
+            user: "Generate a Mermaid Flowchart for the following project description:\n\n{content}".to_string(),
+        }),
+        "journey" => Some(PromptTemplate {
+            system: "You are an expert UX designer and Product Manager. Your task is to analyze the provided software project description and generate a Mermaid User Journey Map (`journey`) that visualizes the user's experience.
+
+Guidelines:
+1. Syntax: Start with `journey`.
+2. Structure:
+   - title: A clear title for the journey.
+   - section: Group actions into logical phases (e.g., `section Onboarding`, `section Usage`, `section Support`).
+   - Task format: `Task name: Score: Actor1, Actor2`
+     - Score: 1-5 (1=bad, 5=delight)
+     - Actors: Who is performing the action (e.g., `User`, `System`, `Admin`).
+3. Content:
+   - Focus on the key interactions derived from the project description.
+   - Ensure the flow is logical and covers the main value proposition.
+
+Output Requirement:
+- Output ONLY the raw Mermaid code.
+- Do NOT include markdown code fences (```mermaid).
+- Do NOT include any explanations.
+- The output must start directly with `journey`.".to_string(),
+            user: "Generate a Mermaid User Journey for the following project description:\n\n{content}".to_string(),
+        }),
+        "sequence" => Some(PromptTemplate {
+            system: "You are an expert software architect. Your task is to analyze the provided software project description and generate a Mermaid Sequence Diagram of its most important interaction.
+
+Guidelines:
+1. Syntax: Start with `sequenceDiagram`.
+2. Declare the participants first with `actor` for people and `participant` for systems, using short aliases (e.g., `participant API as Backend API`).
+3. Messages: `->>` for requests and `-->>` for responses, each with a label after `:`.
+4. Use `alt`/`else`, `opt` and `loop` blocks for branches and repetition, each closed with `end`.
+
+Output Requirement:
+- Output ONLY the raw Mermaid code.
+- Do NOT include markdown code fences (```mermaid).
+- Do NOT include any explanations.
+- The output must start directly with `sequenceDiagram`.".to_string(),
+            user: "Generate a Mermaid Sequence Diagram for the following project description:\n\n{content}".to_string(),
+        }),
+        "state" => Some(PromptTemplate {
+            system: "You are an expert software architect. Your task is to analyze the provided software project description and generate a Mermaid State Diagram for the lifecycle of its central entity (e.g., an order, a ticket, an account).
+
+Guidelines:
+1. Syntax: Start with `stateDiagram-v2`.
+2. Start with `[*] --> FirstState` and end final states with `--> [*]`.
+3. Label every transition with the event causing it: `Draft --> Submitted : submit`.
+4. Use composite states (`state Name { ... }`) only for clearly nested lifecycles.
+
+Output Requirement:
+- Output ONLY the raw Mermaid code.
+- Do NOT include markdown code fences (```mermaid).
+- Do NOT include any explanations.
+- The output must start directly with `stateDiagram-v2`.".to_string(),
+            user: "Generate a Mermaid State Diagram for the following project description:\n\n{content}".to_string(),
+        }),
+        "c4" => Some(PromptTemplate {
+            system: "You are an expert software architect. Your task is to analyze the provided software project description and generate a Mermaid C4 Container diagram of the system.
+
+Guidelines:
+1. Syntax: Start with `C4Container`, then a `title`.
+2. People: `Person(alias, \"Label\", \"Description\")`; external systems: `System_Ext(alias, \"Label\", \"Description\")`.
+3. Group the containers in `System_Boundary(alias, \"Label\") { ... }`: `Container(alias, \"Label\", \"Technology\", \"Description\")` and `ContainerDb(...)` for databases.
+4. Relationships: `Rel(from, to, \"Label\", \"Technology\")`.
+5. Quote every label.
+
+Output Requirement:
+- Output ONLY the raw Mermaid code.
+- Do NOT include markdown code fences (```mermaid).
+- Do NOT include any explanations.
+- The output must start directly with `C4Container`.".to_string(),
+            user: "Generate a Mermaid C4 Container diagram for the following project description:\n\n{content}".to_string(),
+        }),
+        "stories" => Some(PromptTemplate {
+            system: "You are an expert Product Manager. Your task is to analyze the provided software project description and generate a comprehensive list of User Stories grouped by Feature.
+
+Guidelines:
+1. Format:
+   - Use Markdown.
+   - Group stories by `## Feature Name`.
+   - Each story should follow the standard format: `- **As a** <role>, **I want to** <action> **so that** <benefit>.`
+   - Optionally add acceptance criteria if relevant as a sub-list.
+2. Content:
+   - Ensure coverage of all key features mentioned or implied in the description.
+   - Include both functional and non-functional requirements (e.g., Install, Report).
+3. Tone: Professional and clear.
+
+Output Requirement:
+- Output only the markdown text.
+- Do NOT wrap the entire output in a code block.".to_string(),
+            user: "Generate User Stories for the following project description:\n\n{content}".to_string(),
+        }),
+        "edit_er" => Some(PromptTemplate {
+            system: "You are an expert software architect. Modify the provided Mermaid ER Diagram based on the user's instruction.
+Output ONLY the mermaid code block. Do not include markdown code fences (```mermaid). Do not include any explanation. Just the code.
+Start with `erDiagram`.".to_string(),
+            user: "Current Diagram:\n{current_diagram}\n\nInstruction: {instruction}".to_string(),
+        }),
+        "edit_uml" => Some(PromptTemplate {
+            system: "You are an expert software architect. Modify the provided Mermaid Class Diagram based on the user's instruction.
+Output ONLY the mermaid code block. Do not include markdown code fences (```mermaid). Do not include any explanation. Just the code.
+Start with `classDiagram`.".to_string(),
+            user: "Current Diagram:\n{current_diagram}\n\nInstruction: {instruction}".to_string(),
+        }),
+        "edit_flowchart" => Some(PromptTemplate {
+            system: "You are an expert software architect. Modify the provided Mermaid Flowchart based on the user's instruction.
+Output ONLY the mermaid code block. Do not include markdown code fences (```mermaid). Do not include any explanation. Just the code.
+Start with `graph` or `flowchart`.
+Remember to keep text in quotes inside nodes.".to_string(),
+            user: "Current Diagram:\n{current_diagram}\n\nInstruction: {instruction}".to_string(),
+        }),
+        "edit_journey" => Some(PromptTemplate {
+            system: "You are an expert UX designer. Modify the provided Mermaid User Journey based on the user's instruction.
+Output ONLY the mermaid code block. Do not include markdown code fences (```mermaid). Do not include any explanation. Just the code.
+Start with `journey`.".to_string(),
+            user: "Current Diagram:\n{current_diagram}\n\nInstruction: {instruction}".to_string(),
+        }),
+        "edit_sequence" => Some(PromptTemplate {
+            system: "You are an expert software architect. Modify the provided Mermaid Sequence Diagram based on the user's instruction.
+Output ONLY the mermaid code block. Do not include markdown code fences (```mermaid). Do not include any explanation. Just the code.
+Start with `sequenceDiagram`.".to_string(),
+            user: "Current Diagram:\n{current_diagram}\n\nInstruction: {instruction}".to_string(),
+        }),
+        "edit_state" => Some(PromptTemplate {
+            system: "You are an expert software architect. Modify the provided Mermaid State Diagram based on the user's instruction.
+Output ONLY the mermaid code block. Do not include markdown code fences (```mermaid). Do not include any explanation. Just the code.
+Start with `stateDiagram-v2`.".to_string(),
+            user: "Current Diagram:\n{current_diagram}\n\nInstruction: {instruction}".to_string(),
+        }),
+        "edit_c4" => Some(PromptTemplate {
+            system: "You are an expert software architect. Modify the provided Mermaid C4 diagram based on the user's instruction.
+Output ONLY the mermaid code block. Do not include markdown code fences (```mermaid). Do not include any explanation. Just the code.
+Keep the `C4Context`, `C4Container` or `C4Component` header of the current diagram and quote every label.".to_string(),
+            user: "Current Diagram:\n{current_diagram}\n\nInstruction: {instruction}".to_string(),
+        }),
+        "ask" => Some(PromptTemplate {
+            system: "You are an expert software architect. You are provided with a Mermaid {diagram_type} diagram.
+Your task is to answer the user's question about this diagram.
+Provide clear, concise explanations. Do not generate code unless asked for an example.".to_string(),
+            user: "Current Diagram:\n{current_diagram}\n\nQuestion: {question}".to_string(),
+        }),
+        _ => None,
+    }
+}
+
+/// Resolve the template for `task`: a project override wins over a workspace override, which wins over the default
+pub fn resolve(workspace: &Workspace, project_id: Option<&str>, task: &str) -> Result<PromptTemplate, String> {
+    let default = default_template(task).ok_or(format!("Unknown prompt task: {}", task))?;
+
+    let project_override = project_id
+        .and_then(|id| workspace.get_project(id))
+        .and_then(|p| p.prompt_templates.get(task));
+
+    Ok(project_override
+        .or_else(|| workspace.prompt_templates.get(task))
+        .cloned()
+        .unwrap_or(default))
+}
+
+pub fn list(workspace: &Workspace, project_id: Option<&str>) -> Vec<PromptTemplateEntry> {
+    let project = project_id.and_then(|id| workspace.get_project(id));
+
+    PROMPT_TASKS
+        .iter()
+        .filter_map(|(task, variables)| {
+            let default = default_template(task)?;
+            let workspace_override = workspace.prompt_templates.get(*task).cloned();
+            let project_override = project.and_then(|p| p.prompt_templates.get(*task)).cloned();
+            let effective = project_override
+                .clone()
+                .or_else(|| workspace_override.clone())
+                .unwrap_or_else(|| default.clone());
+
+            Some(PromptTemplateEntry {
+                task: task.to_string(),
+                variables: variables.iter().map(|v| v.to_string()).collect(),
+                default,
+                workspace_override,
+                project_override,
+                effective,
+            })
+        })
+        .collect()
+}
+
+/// Replace `{name}` placeholders in a single pass, so braces inside substituted values
+/// (or unknown placeholders such as Mermaid's `{` blocks) are left untouched
+pub fn render(template: &str, variables: &[(&str, &str)]) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        let replacement = after.find('}').and_then(|end| {
+            let name = &after[..end];
+            variables
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| (*value, end))
+        });
+
+        match replacement {
+            Some((value, end)) => {
+                output.push_str(value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                output.push('{');
+                rest = after;
+            }
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+impl PromptTemplate {
+    /// Render both parts of the template, returning `(system_prompt, user_message)`
+    pub fn render(&self, variables: &[(&str, &str)]) -> (String, String) {
+        (render(&self.system, variables), render(&self.user, variables))
+    }
+}
@@ -0,0 +1,410 @@
+//! Maps a refined merged prompt back onto the sections and topics it was merged from
+
+use crate::models::{Project, Section, Topic};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+const SECTION_MARKER: &str = "// Section:";
+const TOPIC_MARKER: &str = "// Topic:";
+/// Longest name derived from content for topics the model added without one
+const MAX_DERIVED_NAME: usize = 40;
+const NON_EMPTY_BONUS: f64 = 1e-6;
+
+/// Sections and topics as exchanged with the model by the `refine_structured` task
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StructuredPrompt {
+    pub sections: Vec<StructuredSection>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StructuredSection {
+    pub name: String,
+    pub topics: Vec<StructuredTopic>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StructuredTopic {
+    /// Missing when the text only marked sections; the content is then matched to topics by similarity
+    #[serde(default)]
+    pub name: Option<String>,
+    pub content: String,
+}
+
+impl StructuredPrompt {
+    pub(crate) fn from_project(project: &Project) -> Self {
+        let mut sections: Vec<&Section> = project.sections.iter().collect();
+        sections.sort_by_key(|s| s.order_index);
+
+        StructuredPrompt {
+            sections: sections
+                .into_iter()
+                .map(|section| {
+                    let mut topics: Vec<_> = section.topics.iter().collect();
+                    topics.sort_by_key(|t| t.order_index);
+                    StructuredSection {
+                        name: section.name.clone(),
+                        topics: topics
+                            .into_iter()
+                            .map(|t| StructuredTopic { name: Some(t.name.clone()), content: t.content.clone() })
+                            .collect(),
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// One difference between the project and the refined text
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SplitChange {
+    pub kind: ChangeKind,
+    pub item_type: String, // 'section', 'topic'
+    /// Existing section; `None` for topics of a section that is added too
+    pub section_id: Option<String>,
+    pub section_name: String,
+    pub topic_id: Option<String>,
+    pub topic_name: Option<String>,
+    pub original_content: Option<String>,
+    pub new_content: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SplitPlan {
+    pub changes: Vec<SplitChange>,
+    /// Topics whose content is the same apart from surrounding whitespace
+    pub unchanged: usize,
+}
+
+/// The project with the reviewed `changes` of a split applied. Works on a copy and fails as a
+/// whole, so a change that no longer fits the project leaves nothing half-applied.
+pub fn apply_split(project: &Project, changes: &[SplitChange]) -> Result<Project, String> {
+    let mut updated = project.clone();
+
+    // Sections first, so topics of a new section can find it by name
+    let mut new_sections: HashMap<String, String> = HashMap::new();
+    for change in changes.iter().filter(|c| c.item_type == "section" && c.kind == ChangeKind::Added) {
+        let section = Section::new(change.section_name.clone());
+        new_sections.insert(change.section_name.clone(), section.id.clone());
+        updated.add_section(section);
+    }
+
+    for change in changes.iter().filter(|c| c.item_type == "topic") {
+        match change.kind {
+            ChangeKind::Added => {
+                let section_id = change.section_id.clone()
+                    .or_else(|| new_sections.get(&change.section_name).cloned())
+                    .ok_or(format!("Section {} is not part of the changes", change.section_name))?;
+                let section = updated.get_section_mut(&section_id)
+                    .ok_or(format!("Section with id {} not found", section_id))?;
+                let name = change.topic_name.clone().unwrap_or_else(|| "New Topic".to_string());
+                let content = change.new_content.clone().unwrap_or_default();
+                section.add_topic(Topic::new(name, content, section_id.clone()));
+            }
+            ChangeKind::Changed => {
+                let topic_id = change.topic_id.as_deref().ok_or("Changed topic without an id")?;
+                let topic = updated.get_topic_mut(topic_id)
+                    .ok_or(format!("Topic with id {} not found", topic_id))?;
+                topic.content = change.new_content.clone().unwrap_or_default();
+            }
+            ChangeKind::Removed => {
+                let topic_id = change.topic_id.as_deref().ok_or("Removed topic without an id")?;
+                for section in &mut updated.sections {
+                    if section.remove_topic(topic_id).is_ok() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    for change in changes.iter().filter(|c| c.item_type == "section" && c.kind == ChangeKind::Removed) {
+        if let Some(section_id) = &change.section_id {
+            updated.remove_section(section_id)?;
+        }
+    }
+    updated.updated_at = chrono::Utc::now().to_rfc3339();
+    Ok(updated)
+}
+
+/// Parse a refined merged prompt: either the JSON answer of the `refine_structured` task (possibly
+/// in a code fence) or text with the `// Section:` markers `get_merged_output` emits and optional
+/// `// Topic:` markers
+pub fn parse_refined(text: &str) -> Result<StructuredPrompt, String> {
+    let trimmed = strip_code_fence(text.trim());
+    if trimmed.starts_with('{') {
+        return serde_json::from_str(trimmed)
+            .map_err(|e| format!("The refined output looks like JSON but is not a section list: {}", e));
+    }
+
+    let mut sections: Vec<(String, Vec<String>)> = Vec::new();
+    for line in trimmed.lines() {
+        if let Some(name) = line.trim_start().strip_prefix(SECTION_MARKER) {
+            sections.push((name.trim().to_string(), Vec::new()));
+        } else if let Some((_, lines)) = sections.last_mut() {
+            lines.push(line.to_string());
+        } else if !line.trim().is_empty() {
+            return Err("Text before the first `// Section:` marker cannot be mapped to a section".to_string());
+        }
+    }
+    if sections.is_empty() {
+        return Err("The refined output has no `// Section:` markers; refine it with the structure kept".to_string());
+    }
+
+    Ok(StructuredPrompt {
+        sections: sections
+            .into_iter()
+            .map(|(name, lines)| StructuredSection { name, topics: parse_topics(&lines) })
+            .collect(),
+    })
+}
+
+fn strip_code_fence(text: &str) -> &str {
+    text.strip_prefix("```")
+        .and_then(|rest| rest.strip_suffix("```"))
+        .map(|body| body.split_once('\n').map(|(_, body)| body).unwrap_or(body).trim())
+        .unwrap_or(text)
+}
+
+/// Split a section body at `// Topic:` markers
+fn parse_topics(lines: &[String]) -> Vec<StructuredTopic> {
+    let mut topics: Vec<StructuredTopic> = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    let mut name = None;
+
+    for line in lines {
+        if let Some(topic_name) = line.trim_start().strip_prefix(TOPIC_MARKER) {
+            push_topic(&mut topics, name.take(), &current);
+            current.clear();
+            name = Some(topic_name.trim().to_string());
+        } else {
+            current.push(line);
+        }
+    }
+    push_topic(&mut topics, name, &current);
+    topics
+}
+
+/// Add a topic unless it is an unnamed stretch of blank lines, dropping the `---` that
+/// separates sections
+fn push_topic(topics: &mut Vec<StructuredTopic>, name: Option<String>, lines: &[&str]) {
+    let content = lines.join("\n").trim().trim_end_matches("---").trim().to_string();
+    if name.is_some() || !content.is_empty() {
+        topics.push(StructuredTopic { name, content });
+    }
+}
+
+/// Compare `refined` with the project. Sections and named topics match by name (ignoring case);
+/// an unnamed section body is cut into paragraphs that are spread over the section's existing
+/// topics in order, each topic taking the run of paragraphs most similar to its old content.
+pub(crate) fn plan_split(project: &Project, refined: &StructuredPrompt) -> SplitPlan {
+    let mut changes = Vec::new();
+    let mut unchanged = 0;
+    let mut matched_sections = HashSet::new();
+
+    for parsed in &refined.sections {
+        let existing = project
+            .sections
+            .iter()
+            .find(|s| !matched_sections.contains(&s.id) && same_name(&s.name, &parsed.name));
+
+        let Some(section) = existing else {
+            changes.push(SplitChange {
+                kind: ChangeKind::Added,
+                item_type: "section".to_string(),
+                section_id: None,
+                section_name: parsed.name.clone(),
+                topic_id: None,
+                topic_name: None,
+                original_content: None,
+                new_content: None,
+            });
+            for topic in &parsed.topics {
+                changes.push(added_topic(None, &parsed.name, topic));
+            }
+            continue;
+        };
+        matched_sections.insert(section.id.clone());
+
+        let mut topics: Vec<_> = section.topics.iter().collect();
+        topics.sort_by_key(|t| t.order_index);
+
+        // Pair each existing topic with its new content, if any
+        let mut assigned: Vec<Option<String>> = vec![None; topics.len()];
+        let mut extra: Vec<&StructuredTopic> = Vec::new();
+        let unnamed: Vec<&StructuredTopic> = parsed.topics.iter().filter(|t| t.name.is_none()).collect();
+
+        if !unnamed.is_empty() && topics.is_empty() {
+            extra.extend(unnamed);
+        } else if !unnamed.is_empty() {
+            let body = unnamed.iter().map(|t| t.content.as_str()).collect::<Vec<_>>().join("\n\n");
+            let paragraphs = paragraphs(&body);
+            let old: Vec<&str> = topics.iter().map(|t| t.content.as_str()).collect();
+            // A topic left without paragraphs was folded into its neighbors
+            for (slot, content) in assigned.iter_mut().zip(align_paragraphs(&paragraphs, &old)) {
+                *slot = Some(content).filter(|c| !c.is_empty());
+            }
+        }
+        for topic in parsed.topics.iter().filter(|t| t.name.is_some()) {
+            let name = topic.name.as_deref().unwrap_or_default();
+            match topics.iter().position(|t| same_name(&t.name, name)) {
+                Some(index) if assigned[index].is_none() => assigned[index] = Some(topic.content.clone()),
+                _ => extra.push(topic),
+            }
+        }
+
+        for (topic, content) in topics.iter().zip(assigned) {
+            match content {
+                Some(content) if content.trim() == topic.content.trim() => unchanged += 1,
+                Some(content) => changes.push(SplitChange {
+                    kind: ChangeKind::Changed,
+                    item_type: "topic".to_string(),
+                    section_id: Some(section.id.clone()),
+                    section_name: section.name.clone(),
+                    topic_id: Some(topic.id.clone()),
+                    topic_name: Some(topic.name.clone()),
+                    original_content: Some(topic.content.clone()),
+                    new_content: Some(content),
+                }),
+                None => changes.push(SplitChange {
+                    kind: ChangeKind::Removed,
+                    item_type: "topic".to_string(),
+                    section_id: Some(section.id.clone()),
+                    section_name: section.name.clone(),
+                    topic_id: Some(topic.id.clone()),
+                    topic_name: Some(topic.name.clone()),
+                    original_content: Some(topic.content.clone()),
+                    new_content: None,
+                }),
+            }
+        }
+        for topic in extra {
+            changes.push(added_topic(Some(section), &section.name, topic));
+        }
+    }
+
+    for section in project.sections.iter().filter(|s| !matched_sections.contains(&s.id)) {
+        changes.push(SplitChange {
+            kind: ChangeKind::Removed,
+            item_type: "section".to_string(),
+            section_id: Some(section.id.clone()),
+            section_name: section.name.clone(),
+            topic_id: None,
+            topic_name: None,
+            original_content: Some(section.get_content()),
+            new_content: None,
+        });
+    }
+
+    SplitPlan { changes, unchanged }
+}
+
+fn added_topic(section: Option<&Section>, section_name: &str, topic: &StructuredTopic) -> SplitChange {
+    SplitChange {
+        kind: ChangeKind::Added,
+        item_type: "topic".to_string(),
+        section_id: section.map(|s| s.id.clone()),
+        section_name: section_name.to_string(),
+        topic_id: None,
+        topic_name: Some(topic.name.clone().unwrap_or_else(|| derived_name(&topic.content))),
+        original_content: None,
+        new_content: Some(topic.content.clone()),
+    }
+}
+
+fn same_name(a: &str, b: &str) -> bool {
+    a.trim().eq_ignore_ascii_case(b.trim())
+}
+
+/// First line of `content` without Markdown heading marks, shortened to a usable name
+fn derived_name(content: &str) -> String {
+    let line = content
+        .lines()
+        .map(|l| l.trim().trim_start_matches('#').trim())
+        .find(|l| !l.is_empty())
+        .unwrap_or("New Topic");
+    line.chars().take(MAX_DERIVED_NAME).collect()
+}
+
+/// Blocks of `text` separated by blank lines
+pub fn paragraphs(text: &str) -> Vec<String> {
+    text.split("\n\n")
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Cut `paragraphs` into one consecutive run per old topic, in order, maximizing how much every
+/// paragraph shares with the topic it lands in. A run may be empty; paragraphs that resemble no
+/// topic stay with the one before them.
+pub fn align_paragraphs(paragraphs: &[String], old_topics: &[&str]) -> Vec<String> {
+    let (n, k) = (paragraphs.len(), old_topics.len());
+    if k == 0 {
+        return Vec::new();
+    }
+    let old_words: Vec<HashSet<String>> = old_topics.iter().map(|t| words(t)).collect();
+    let scores: Vec<Vec<f64>> = paragraphs
+        .iter()
+        .map(|p| {
+            let words = words(p);
+            old_words.iter().map(|old| similarity(&words, old)).collect()
+        })
+        .collect();
+
+    // best[j][i]: best score placing the first i paragraphs into the first j topics
+    let mut best = vec![vec![f64::NEG_INFINITY; n + 1]; k + 1];
+    let mut cut = vec![vec![0; n + 1]; k + 1];
+    best[0][0] = 0.0;
+    for j in 1..=k {
+        for i in 0..=n {
+            for start in 0..=i {
+                if best[j - 1][start] == f64::NEG_INFINITY {
+                    continue;
+                }
+                // A small bonus for non-empty runs keeps wholly rewritten text spread over the topics
+                let filled = if start < i { NON_EMPTY_BONUS } else { 0.0 };
+                let score = best[j - 1][start] + scores[start..i].iter().map(|s| s[j - 1]).sum::<f64>() + filled;
+                if score >= best[j][i] {
+                    best[j][i] = score;
+                    cut[j][i] = start;
+                }
+            }
+        }
+    }
+
+    let mut runs = vec![String::new(); k];
+    let mut end = n;
+    for j in (1..=k).rev() {
+        let start = cut[j][end];
+        runs[j - 1] = paragraphs[start..end].join("\n\n");
+        end = start;
+    }
+    runs
+}
+
+fn words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| w.len() > 2)
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Jaccard index of two word sets
+fn similarity(a: &HashSet<String>, b: &HashSet<String>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {
+        0.0
+    } else {
+        a.intersection(b).count() as f64 / union as f64
+    }
+}
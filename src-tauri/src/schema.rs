@@ -0,0 +1,1257 @@
+//! Relational view of the project ER diagram: entities, attributes and relationships read from
+//! a Mermaid `erDiagram` or an existing database schema, and SQL DDL generated from them
+
+use crate::mermaid;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// How many entities on one side of a relationship relate to each entity on the other side
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cardinality {
+    ZeroOrOne,
+    ExactlyOne,
+    ZeroOrMore,
+    OneOrMore,
+}
+
+impl Cardinality {
+    pub fn is_many(self) -> bool {
+        matches!(self, Cardinality::ZeroOrMore | Cardinality::OneOrMore)
+    }
+
+    /// One side of a relationship spec like `||--o{`, read from either end
+    fn from_symbol(symbol: &str) -> Option<Self> {
+        match symbol {
+            "|o" | "o|" => Some(Cardinality::ZeroOrOne),
+            "||" => Some(Cardinality::ExactlyOne),
+            "}o" | "o{" => Some(Cardinality::ZeroOrMore),
+            "}|" | "|{" => Some(Cardinality::OneOrMore),
+            _ => None,
+        }
+    }
+
+    /// Symbol for the left (`|o`) or right (`o|`) end of a relationship spec
+    fn symbol(self, left: bool) -> &'static str {
+        match (self, left) {
+            (Cardinality::ZeroOrOne, true) => "|o",
+            (Cardinality::ZeroOrOne, false) => "o|",
+            (Cardinality::ExactlyOne, _) => "||",
+            (Cardinality::ZeroOrMore, true) => "}o",
+            (Cardinality::ZeroOrMore, false) => "o{",
+            (Cardinality::OneOrMore, true) => "}|",
+            (Cardinality::OneOrMore, false) => "|{",
+        }
+    }
+}
+
+/// Word forms of the cardinalities, as in `A one or more to zero or one B`
+const CARDINALITY_WORDS: [(&str, Cardinality); 12] = [
+    ("one or zero", Cardinality::ZeroOrOne),
+    ("zero or one", Cardinality::ZeroOrOne),
+    ("one or more", Cardinality::OneOrMore),
+    ("one or many", Cardinality::OneOrMore),
+    ("many(1)", Cardinality::OneOrMore),
+    ("1+", Cardinality::OneOrMore),
+    ("zero or more", Cardinality::ZeroOrMore),
+    ("zero or many", Cardinality::ZeroOrMore),
+    ("many(0)", Cardinality::ZeroOrMore),
+    ("0+", Cardinality::ZeroOrMore),
+    ("only one", Cardinality::ExactlyOne),
+    ("1", Cardinality::ExactlyOne),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribute {
+    pub data_type: String,
+    pub name: String,
+    /// `PK`, `FK` and `UK` markers
+    pub keys: Vec<String>,
+    pub comment: Option<String>,
+}
+
+impl Attribute {
+    pub fn has_key(&self, key: &str) -> bool {
+        self.keys.iter().any(|k| k == key)
+    }
+
+    /// `type name PK, FK "comment"`, as written in an entity block
+    pub fn to_mermaid(&self) -> String {
+        let mut out = format!("{} {}", self.data_type, self.name);
+        if !self.keys.is_empty() {
+            out.push_str(&format!(" {}", self.keys.join(", ")));
+        }
+        if let Some(comment) = &self.comment {
+            out.push_str(&format!(" \"{}\"", comment.replace('"', "'")));
+        }
+        out
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entity {
+    pub name: String,
+    pub attributes: Vec<Attribute>,
+}
+
+/// `left` relates to `right`: `left_cardinality` of `left` per `right`, and
+/// `right_cardinality` of `right` per `left`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relationship {
+    pub left: String,
+    pub right: String,
+    pub left_cardinality: Cardinality,
+    pub right_cardinality: Cardinality,
+    pub label: String,
+}
+
+impl Relationship {
+    /// Relationship spec like `||--o{`
+    pub fn spec(&self) -> String {
+        format!("{}--{}", self.left_cardinality.symbol(true), self.right_cardinality.symbol(false))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ErModel {
+    pub entities: Vec<Entity>,
+    pub relationships: Vec<Relationship>,
+}
+
+impl ErModel {
+    /// Read a Mermaid `erDiagram`; invalid diagrams are rejected with their first diagnostic
+    pub fn from_mermaid(code: &str) -> Result<Self, String> {
+        if let Some(diagnostic) = mermaid::validate("er", code)?.first() {
+            return Err(format!("The ER diagram is not valid: {}", diagnostic));
+        }
+
+        let mut model = ErModel::default();
+        let lines: Vec<&str> = code.lines().collect();
+        // Entity name and attribute text of the open block
+        let mut block: Option<(String, String)> = None;
+
+        for (_, line) in mermaid::statement_lines(&lines).into_iter().skip(1) {
+            let text = line.trim();
+            if let Some((_, body)) = block.as_mut() {
+                if text == "}" {
+                    if let Some((name, body)) = block.take() {
+                        model.add_attributes(&name, &body);
+                    }
+                } else {
+                    body.push('\n');
+                    body.push_str(text);
+                }
+                continue;
+            }
+            if matches!(text.split_whitespace().next(), Some("direction" | "style" | "classDef" | "class")) {
+                continue;
+            }
+
+            if let Some(head) = text.strip_suffix('{').and_then(|head| entity_name(head.trim())) {
+                model.entity_mut(&head);
+                block = Some((head, String::new()));
+            } else if let Some((name, body)) = single_line_block(text) {
+                model.entity_mut(&name);
+                model.add_attributes(&name, body);
+            } else if let Some(name) = entity_name(text) {
+                model.entity_mut(&name);
+            } else if let Some(relationship) = parse_relationship(text) {
+                model.entity_mut(&relationship.left);
+                model.entity_mut(&relationship.right);
+                model.relationships.push(relationship);
+            }
+        }
+
+        Ok(model)
+    }
+
+    /// Mermaid `erDiagram` with one block per entity followed by the relationships
+    pub fn to_mermaid(&self) -> String {
+        let mut out = String::from("erDiagram\n");
+        for entity in &self.entities {
+            let name = mermaid_name(&entity.name);
+            if entity.attributes.is_empty() {
+                out.push_str(&format!("    {}\n", name));
+                continue;
+            }
+            out.push_str(&format!("    {} {{\n", name));
+            for attribute in &entity.attributes {
+                out.push_str(&format!("        {}\n", attribute.to_mermaid()));
+            }
+            out.push_str("    }\n");
+        }
+        for relationship in &self.relationships {
+            out.push_str(&format!(
+                "    {} {} {} : {}\n",
+                mermaid_name(&relationship.left),
+                relationship.spec(),
+                mermaid_name(&relationship.right),
+                relationship_label(&relationship.label)
+            ));
+        }
+        out
+    }
+
+    pub fn entity(&self, name: &str) -> Option<&Entity> {
+        self.entities.iter().find(|e| e.name == name)
+    }
+
+    /// The entity called `name`, added without attributes if it is new
+    fn entity_mut(&mut self, name: &str) -> &mut Entity {
+        let index = match self.entities.iter().position(|e| e.name == name) {
+            Some(index) => index,
+            None => {
+                self.entities.push(Entity { name: name.to_string(), attributes: Vec::new() });
+                self.entities.len() - 1
+            }
+        };
+        &mut self.entities[index]
+    }
+
+    /// Parse `type name [keys] ["comment"]` attributes, separated by whitespace or new lines
+    fn add_attributes(&mut self, entity: &str, body: &str) {
+        let words = mermaid::tokens(body);
+        let mut attributes = Vec::new();
+        let mut i = 0;
+        while i + 1 < words.len() {
+            let mut attribute = Attribute {
+                data_type: words[i].to_string(),
+                name: words[i + 1].trim_start_matches('*').to_string(),
+                keys: Vec::new(),
+                comment: None,
+            };
+            i += 2;
+            while let Some(keys) = words.get(i).and_then(|w| key_list(w)) {
+                attribute.keys.extend(keys);
+                i += 1;
+            }
+            if let Some(comment) = words.get(i).filter(|w| w.starts_with('"')) {
+                attribute.comment = Some(comment.trim_matches('"').to_string());
+                i += 1;
+            }
+            attributes.push(attribute);
+        }
+        self.entity_mut(entity).attributes.extend(attributes);
+    }
+}
+
+/// `PK`, `PK,FK` or `PK,` as one word of an attribute
+fn key_list(word: &str) -> Option<Vec<String>> {
+    let keys: Vec<&str> = word.split(',').map(str::trim).filter(|k| !k.is_empty()).collect();
+    (!keys.is_empty() && keys.iter().all(|k| matches!(*k, "PK" | "FK" | "UK")))
+        .then(|| keys.into_iter().map(str::to_string).collect())
+}
+
+/// Entity name without quotes or alias: `CUSTOMER`, `"Line Item"` or `P["Person"]`
+fn entity_name(text: &str) -> Option<String> {
+    let text = match text.find('[') {
+        Some(open) if text.ends_with(']') => &text[..open],
+        _ => text,
+    };
+    if text.len() > 2 && text.starts_with('"') && text.ends_with('"') && !text[1..text.len() - 1].contains('"') {
+        return Some(text[1..text.len() - 1].to_string());
+    }
+    let mut chars = text.chars();
+    let valid = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+    valid.then(|| text.to_string())
+}
+
+/// Entity name as written in a diagram, quoted unless it is a plain identifier
+pub(crate) fn mermaid_name(name: &str) -> String {
+    match entity_name(name) {
+        Some(plain) if plain == name => plain,
+        _ => format!("\"{}\"", name.replace('"', "'")),
+    }
+}
+
+/// Relationship label as written in a diagram, quoted unless it is a single word
+pub(crate) fn relationship_label(label: &str) -> String {
+    let simple = !label.is_empty() && label.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+    if simple {
+        label.to_string()
+    } else {
+        format!("\"{}\"", label.replace('"', "'"))
+    }
+}
+
+/// `NAME { type name type name }`
+fn single_line_block(text: &str) -> Option<(String, &str)> {
+    let open = text.find('{')?;
+    let body = text.strip_suffix('}')?.get(open + 1..)?;
+    Some((entity_name(text[..open].trim())?, body))
+}
+
+fn parse_relationship(text: &str) -> Option<Relationship> {
+    let (head, label) = text.split_once(':')?;
+    let label = label.trim().trim_matches('"').to_string();
+    let words = mermaid::tokens(head);
+
+    if let [left, spec, right] = words.as_slice() {
+        return Some(Relationship {
+            left: entity_name(left)?,
+            right: entity_name(right)?,
+            left_cardinality: Cardinality::from_symbol(spec.get(..2)?)?,
+            right_cardinality: Cardinality::from_symbol(spec.get(4..)?)?,
+            label,
+        });
+    }
+
+    let (left, right) = (*words.first()?, *words.last()?);
+    let middle = head.trim().strip_prefix(left)?.trim().strip_suffix(right)?.trim();
+    CARDINALITY_WORDS.iter().find_map(|(word, left_cardinality)| {
+        let rest = middle.strip_prefix(word)?.trim_start();
+        let rest = rest.strip_prefix("optionally to").or_else(|| rest.strip_prefix("to"))?.trim();
+        let right_cardinality = CARDINALITY_WORDS.iter().find(|(w, _)| *w == rest)?.1;
+        Some(Relationship {
+            left: entity_name(left)?,
+            right: entity_name(right)?,
+            left_cardinality: *left_cardinality,
+            right_cardinality,
+            label: label.clone(),
+        })
+    })
+}
+
+// SQL DDL
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dialect {
+    Postgres,
+    Mysql,
+    Sqlite,
+}
+
+impl Dialect {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "postgres" | "postgresql" | "pg" => Ok(Dialect::Postgres),
+            "mysql" | "mariadb" => Ok(Dialect::Mysql),
+            "sqlite" | "sqlite3" => Ok(Dialect::Sqlite),
+            _ => Err(format!("Unknown SQL dialect: {}", name)),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Dialect::Postgres => "PostgreSQL",
+            Dialect::Mysql => "MySQL",
+            Dialect::Sqlite => "SQLite",
+        }
+    }
+
+    fn quote(self, identifier: &str) -> String {
+        const RESERVED: [&str; 16] = [
+            "check", "column", "default", "from", "group", "index", "key", "order", "primary",
+            "references", "select", "table", "to", "user", "where", "values",
+        ];
+        let mut chars = identifier.chars();
+        let plain = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && !RESERVED.contains(&identifier.to_lowercase().as_str());
+        match (plain, self) {
+            (true, _) => identifier.to_string(),
+            (false, Dialect::Mysql) => format!("`{}`", identifier.replace('`', "``")),
+            (false, _) => format!("\"{}\"", identifier.replace('"', "\"\"")),
+        }
+    }
+
+    /// SQL type for a Mermaid attribute type; unknown types are passed through
+    fn column_type(self, data_type: &str) -> String {
+        let lower = data_type.to_lowercase();
+        let (base, args) = match lower.find('(') {
+            Some(open) => (&lower[..open], &data_type[open..]),
+            None => (lower.as_str(), ""),
+        };
+        let pick = |postgres: &str, mysql: &str, sqlite: &str| {
+            match self {
+                Dialect::Postgres => postgres,
+                Dialect::Mysql => mysql,
+                Dialect::Sqlite => sqlite,
+            }
+            .to_string()
+        };
+        match base {
+            "string" | "str" => pick("TEXT", "VARCHAR(255)", "TEXT"),
+            "text" | "longtext" => "TEXT".to_string(),
+            "varchar" | "char" | "character" if !args.is_empty() => format!("{}{}", base.to_uppercase(), args),
+            "varchar" | "char" | "character" => pick("TEXT", "VARCHAR(255)", "TEXT"),
+            "int" | "integer" => pick("INTEGER", "INT", "INTEGER"),
+            "smallint" | "short" => pick("SMALLINT", "SMALLINT", "INTEGER"),
+            "bigint" | "long" => pick("BIGINT", "BIGINT", "INTEGER"),
+            "float" | "double" | "real" => pick("DOUBLE PRECISION", "DOUBLE", "REAL"),
+            "decimal" | "numeric" | "money" | "currency" if !args.is_empty() => format!("NUMERIC{}", args),
+            "decimal" | "numeric" | "money" | "currency" => pick("NUMERIC", "DECIMAL(12,2)", "NUMERIC"),
+            "bool" | "boolean" => pick("BOOLEAN", "BOOLEAN", "INTEGER"),
+            "date" => pick("DATE", "DATE", "TEXT"),
+            "datetime" | "timestamp" | "timestamptz" => pick("TIMESTAMP", "DATETIME", "TEXT"),
+            "time" => pick("TIME", "TIME", "TEXT"),
+            "uuid" | "guid" => pick("UUID", "CHAR(36)", "TEXT"),
+            "json" | "jsonb" => pick("JSONB", "JSON", "TEXT"),
+            "blob" | "binary" | "bytes" | "bytea" => pick("BYTEA", "BLOB", "BLOB"),
+            _ => data_type.to_uppercase(),
+        }
+    }
+
+    /// Type of a generated key column, and of the columns referencing it
+    fn key_type(self) -> &'static str {
+        match self {
+            Dialect::Sqlite => "INTEGER",
+            _ => "BIGINT",
+        }
+    }
+}
+
+struct Column {
+    name: String,
+    sql_type: String,
+    not_null: bool,
+    /// Generated surrogate key
+    identity: bool,
+    /// Marked `FK` in the diagram
+    foreign: bool,
+    comment: Option<String>,
+}
+
+struct ForeignKey {
+    columns: Vec<String>,
+    table: String,
+    references: Vec<String>,
+}
+
+struct Table {
+    name: String,
+    columns: Vec<Column>,
+    primary_key: Vec<String>,
+    unique: Vec<Vec<String>>,
+    foreign_keys: Vec<ForeignKey>,
+}
+
+impl Table {
+    fn column(&self, name: &str) -> Option<&Column> {
+        self.columns.iter().find(|c| c.name.eq_ignore_ascii_case(name))
+    }
+
+    fn column_mut(&mut self, name: &str) -> Option<&mut Column> {
+        self.columns.iter_mut().find(|c| c.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// `LineItem`, `LINE-ITEM` and `line item` all become `line_item`
+pub(crate) fn snake_case(name: &str) -> String {
+    let mut out = String::new();
+    let mut previous_lower = false;
+    for c in name.chars() {
+        if c.is_alphanumeric() {
+            if c.is_uppercase() && previous_lower {
+                out.push('_');
+            }
+            out.extend(c.to_lowercase());
+            previous_lower = c.is_lowercase() || c.is_ascii_digit();
+        } else {
+            if !out.ends_with('_') && !out.is_empty() {
+                out.push('_');
+            }
+            previous_lower = false;
+        }
+    }
+    out.trim_end_matches('_').to_string()
+}
+
+/// SQL script creating one table per entity, foreign keys for one-to-one and one-to-many
+/// relationships and a join table per many-to-many relationship
+pub fn to_ddl(model: &ErModel, dialect: Dialect) -> String {
+    let mut tables: Vec<Table> = model.entities.iter().map(|entity| entity_table(entity, dialect)).collect();
+    // FK-marked attributes already used for a relationship
+    let mut used: HashSet<(usize, String)> = HashSet::new();
+
+    for relationship in &model.relationships {
+        let (Some(left), Some(right)) = (
+            tables.iter().position(|t| t.name == relationship.left),
+            tables.iter().position(|t| t.name == relationship.right),
+        ) else {
+            continue;
+        };
+        let (left_many, right_many) = (relationship.left_cardinality.is_many(), relationship.right_cardinality.is_many());
+        if left_many && right_many {
+            let table = join_table(&tables, left, right, &relationship.label);
+            tables.push(table);
+            continue;
+        }
+
+        // The foreign key goes on the side that has many, or on the right for one-to-one
+        let (child, parent, parent_cardinality) = if left_many {
+            (left, right, relationship.right_cardinality)
+        } else {
+            (right, left, relationship.left_cardinality)
+        };
+        let mandatory = parent_cardinality == Cardinality::ExactlyOne;
+        add_foreign_key(&mut tables, &mut used, child, parent, &relationship.label, mandatory, !left_many && !right_many);
+    }
+
+    render(&tables, dialect)
+}
+
+fn entity_table(entity: &Entity, dialect: Dialect) -> Table {
+    let mut table = Table {
+        name: entity.name.clone(),
+        columns: Vec::new(),
+        primary_key: Vec::new(),
+        unique: Vec::new(),
+        foreign_keys: Vec::new(),
+    };
+
+    for attribute in &entity.attributes {
+        let primary = attribute.has_key("PK");
+        table.columns.push(Column {
+            name: attribute.name.clone(),
+            sql_type: dialect.column_type(&attribute.data_type),
+            not_null: primary,
+            identity: false,
+            foreign: attribute.has_key("FK"),
+            comment: attribute.comment.clone(),
+        });
+        if primary {
+            table.primary_key.push(attribute.name.clone());
+        }
+        if attribute.has_key("UK") {
+            table.unique.push(vec![attribute.name.clone()]);
+        }
+    }
+
+    // Every table gets a key: an `id` attribute, or a generated one
+    if table.primary_key.is_empty() {
+        if let Some(id) = table.column_mut("id") {
+            id.not_null = true;
+            let name = id.name.clone();
+            table.primary_key.push(name);
+        } else {
+            table.columns.insert(0, Column {
+                name: "id".to_string(),
+                sql_type: dialect.key_type().to_string(),
+                not_null: true,
+                identity: true,
+                foreign: false,
+                comment: None,
+            });
+            table.primary_key.push("id".to_string());
+        }
+    }
+    table
+}
+
+/// Name and type of the columns referencing `parent`'s primary key, prefixed with `prefix`
+fn key_columns(parent: &Table, prefix: &str) -> Vec<(String, String, String)> {
+    let parent_name = snake_case(&parent.name);
+    parent
+        .primary_key
+        .iter()
+        .map(|key| {
+            let key_name = snake_case(key);
+            let name = if key_name.starts_with(&parent_name) { key_name } else { format!("{}_{}", parent_name, key_name) };
+            let column = parent.column(key);
+            let sql_type = column.map(|c| c.sql_type.clone()).unwrap_or_default();
+            (format!("{}{}", prefix, name), sql_type, key.clone())
+        })
+        .collect()
+}
+
+fn add_foreign_key(
+    tables: &mut [Table],
+    used: &mut HashSet<(usize, String)>,
+    child: usize,
+    parent: usize,
+    label: &str,
+    mandatory: bool,
+    unique: bool,
+) {
+    let prefix = if child == parent { "parent_" } else { "" };
+    let parent_name = snake_case(&tables[parent].name);
+    let mut columns = Vec::new();
+    let mut references = Vec::new();
+
+    for (name, sql_type, key) in key_columns(&tables[parent], prefix) {
+        let table = &tables[child];
+        let already_used = |column: &str| used.contains(&(child, column.to_lowercase()));
+        // Reuse an attribute with the expected name, or an unused FK attribute naming the parent
+        let existing = table
+            .column(&name)
+            .filter(|c| !already_used(&c.name))
+            .or_else(|| {
+                table.columns.iter().find(|c| {
+                    c.foreign && !already_used(&c.name) && snake_case(&c.name).contains(&parent_name)
+                })
+            })
+            .map(|c| c.name.clone());
+
+        let column = match existing {
+            Some(existing) => existing,
+            None => {
+                let name = if table.column(&name).is_some() { format!("{}_{}", snake_case(label), name) } else { name };
+                tables[child].columns.push(Column {
+                    name: name.clone(),
+                    sql_type,
+                    not_null: false,
+                    identity: false,
+                    foreign: true,
+                    comment: None,
+                });
+                name
+            }
+        };
+        if mandatory {
+            if let Some(c) = tables[child].column_mut(&column) {
+                c.not_null = true;
+            }
+        }
+        used.insert((child, column.to_lowercase()));
+        columns.push(column);
+        references.push(key);
+    }
+
+    let table = &mut tables[child];
+    if unique && columns != table.primary_key {
+        table.unique.push(columns.clone());
+    }
+    let referenced = tables[parent].name.clone();
+    tables[child].foreign_keys.push(ForeignKey { columns, table: referenced, references });
+}
+
+fn join_table(tables: &[Table], left: usize, right: usize, label: &str) -> Table {
+    let mut name = format!("{}_{}", snake_case(&tables[left].name), snake_case(&tables[right].name));
+    if tables.iter().any(|t| t.name.eq_ignore_ascii_case(&name)) {
+        name = format!("{}_{}", name, snake_case(label));
+    }
+    let mut table = Table { name, columns: Vec::new(), primary_key: Vec::new(), unique: Vec::new(), foreign_keys: Vec::new() };
+
+    let sides = [(left, ""), (right, if left == right { "related_" } else { "" })];
+    for (side, prefix) in sides {
+        let mut fk = ForeignKey { columns: Vec::new(), table: tables[side].name.clone(), references: Vec::new() };
+        for (name, sql_type, key) in key_columns(&tables[side], prefix) {
+            table.columns.push(Column { name: name.clone(), sql_type, not_null: true, identity: false, foreign: true, comment: None });
+            table.primary_key.push(name.clone());
+            fk.columns.push(name);
+            fk.references.push(key);
+        }
+        table.foreign_keys.push(fk);
+    }
+    table
+}
+
+/// Tables in dependency order: referenced tables first, then those left in cycles
+fn creation_order(tables: &[Table]) -> Vec<usize> {
+    let mut order: Vec<usize> = Vec::new();
+    let mut created: HashSet<&str> = HashSet::new();
+    loop {
+        let ready = (0..tables.len()).find(|&i| {
+            !order.contains(&i)
+                && tables[i].foreign_keys.iter().all(|fk| fk.table == tables[i].name || created.contains(fk.table.as_str()))
+        });
+        match ready {
+            Some(i) => {
+                created.insert(&tables[i].name);
+                order.push(i);
+            }
+            None => break,
+        }
+    }
+    let in_cycles: Vec<usize> = (0..tables.len()).filter(|i| !order.contains(i)).collect();
+    order.extend(in_cycles);
+    order
+}
+
+fn render(tables: &[Table], dialect: Dialect) -> String {
+    let q = |identifier: &str| dialect.quote(identifier);
+    let list = |names: &[String]| names.iter().map(|n| q(n)).collect::<Vec<_>>().join(", ");
+
+    let mut out = format!("-- Generated from the project ER diagram for {}\n", dialect.name());
+    if dialect == Dialect::Sqlite {
+        out.push_str("PRAGMA foreign_keys = ON;\n");
+    }
+    let mut created: HashSet<&str> = HashSet::new();
+    let mut deferred: Vec<(&str, &ForeignKey)> = Vec::new();
+    let mut comments: Vec<String> = Vec::new();
+
+    for index in creation_order(tables) {
+        let table = &tables[index];
+        created.insert(&table.name);
+        // Definition and trailing comment of each line
+        let mut items: Vec<(String, Option<&str>)> = Vec::new();
+
+        for column in &table.columns {
+            let mut definition = format!("{} {}", q(&column.name), column.sql_type);
+            if column.not_null {
+                definition.push_str(" NOT NULL");
+            }
+            if column.identity {
+                match dialect {
+                    Dialect::Postgres => definition.push_str(" GENERATED ALWAYS AS IDENTITY"),
+                    Dialect::Mysql => definition.push_str(" AUTO_INCREMENT"),
+                    Dialect::Sqlite => {}
+                }
+            }
+            let mut comment = None;
+            if let Some(text) = &column.comment {
+                match dialect {
+                    Dialect::Mysql => definition.push_str(&format!(" COMMENT '{}'", text.replace('\'', "''"))),
+                    Dialect::Postgres => comments.push(format!(
+                        "COMMENT ON COLUMN {}.{} IS '{}';",
+                        q(&table.name),
+                        q(&column.name),
+                        text.replace('\'', "''")
+                    )),
+                    Dialect::Sqlite => comment = Some(text.as_str()),
+                }
+            }
+            items.push((definition, comment));
+        }
+        items.push((format!("PRIMARY KEY ({})", list(&table.primary_key)), None));
+        for unique in &table.unique {
+            items.push((format!("UNIQUE ({})", list(unique)), None));
+        }
+        for fk in &table.foreign_keys {
+            // SQLite checks references only when rows are written, so cycles need no special care
+            if dialect != Dialect::Sqlite && fk.table != table.name && !created.contains(fk.table.as_str()) {
+                deferred.push((&table.name, fk));
+                continue;
+            }
+            items.push((
+                format!("FOREIGN KEY ({}) REFERENCES {} ({})", list(&fk.columns), q(&fk.table), list(&fk.references)),
+                None,
+            ));
+        }
+
+        out.push_str(&format!("\nCREATE TABLE {} (\n", q(&table.name)));
+        let last = items.len() - 1;
+        for (i, (definition, comment)) in items.iter().enumerate() {
+            out.push_str("    ");
+            out.push_str(definition);
+            if i < last {
+                out.push(',');
+            }
+            if let Some(comment) = comment {
+                out.push_str(&format!(" -- {}", comment));
+            }
+            out.push('\n');
+        }
+        out.push_str(");\n");
+    }
+
+    if !deferred.is_empty() {
+        out.push('\n');
+    }
+    for (table, fk) in deferred {
+        out.push_str(&format!(
+            "ALTER TABLE {} ADD FOREIGN KEY ({}) REFERENCES {} ({});\n",
+            q(table),
+            list(&fk.columns),
+            q(&fk.table),
+            list(&fk.references)
+        ));
+    }
+    if !comments.is_empty() {
+        out.push('\n');
+        out.push_str(&comments.join("\n"));
+        out.push('\n');
+    }
+    out
+}
+
+// Importing existing schemas
+
+#[derive(Debug, Clone, PartialEq)]
+enum SqlToken {
+    Word(String),
+    /// `"name"`, `` `name` `` or `[name]`
+    Identifier(String),
+    Text(String),
+    Symbol(char),
+}
+
+/// Words, quoted names, string literals and symbols of a SQL script, without comments
+fn sql_tokens(sql: &str) -> Result<Vec<SqlToken>, String> {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '-' && next == Some('-') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && next == Some('*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i += 2;
+        } else if matches!(c, '\'' | '"' | '`') || (c == '[' && next.is_some_and(|n| n != ']' && !n.is_ascii_digit())) {
+            let close = if c == '[' { ']' } else { c };
+            let mut text = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(format!("Unterminated {} in the SQL", if c == '\'' { "string" } else { "quoted name" })),
+                    // Quotes are escaped by doubling them
+                    Some(&ch) if ch == close && close != ']' && chars.get(i + 1) == Some(&close) => {
+                        text.push(close);
+                        i += 2;
+                    }
+                    Some(&ch) if ch == close => {
+                        i += 1;
+                        break;
+                    }
+                    Some(&ch) => {
+                        text.push(ch);
+                        i += 1;
+                    }
+                }
+            }
+            tokens.push(if c == '\'' { SqlToken::Text(text) } else { SqlToken::Identifier(text) });
+        } else if c.is_alphanumeric() || c == '_' || c == '$' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || matches!(chars[i], '_' | '$')) {
+                i += 1;
+            }
+            tokens.push(SqlToken::Word(chars[start..i].iter().collect()));
+        } else {
+            tokens.push(SqlToken::Symbol(c));
+            i += 1;
+        }
+    }
+    Ok(tokens)
+}
+
+struct SqlCursor<'a> {
+    tokens: &'a [SqlToken],
+    at: usize,
+}
+
+impl SqlCursor<'_> {
+    fn peek(&self) -> Option<&SqlToken> {
+        self.tokens.get(self.at)
+    }
+
+    fn peek_word(&self, offset: usize) -> Option<String> {
+        match self.tokens.get(self.at + offset) {
+            Some(SqlToken::Word(word)) => Some(word.to_uppercase()),
+            _ => None,
+        }
+    }
+
+    /// Consume `words` if they come next
+    fn keywords(&mut self, words: &[&str]) -> bool {
+        let found = words.iter().enumerate().all(|(i, word)| self.peek_word(i).as_deref() == Some(*word));
+        if found {
+            self.at += words.len();
+        }
+        found
+    }
+
+    fn keyword(&mut self, word: &str) -> bool {
+        self.keywords(&[word])
+    }
+
+    fn symbol(&mut self, symbol: char) -> bool {
+        let found = self.peek() == Some(&SqlToken::Symbol(symbol));
+        if found {
+            self.at += 1;
+        }
+        found
+    }
+
+    fn name_part(&mut self) -> Option<String> {
+        let name = match self.peek()? {
+            SqlToken::Word(name) | SqlToken::Identifier(name) => name.clone(),
+            _ => return None,
+        };
+        self.at += 1;
+        Some(name)
+    }
+
+    /// `schema.table.column` as its parts
+    fn qualified_name(&mut self) -> Vec<String> {
+        let mut parts = Vec::new();
+        while let Some(part) = self.name_part() {
+            parts.push(part);
+            if !self.symbol('.') {
+                break;
+            }
+        }
+        parts
+    }
+
+    /// Table name without its schema
+    fn name(&mut self) -> Option<String> {
+        self.qualified_name().pop()
+    }
+
+    /// `(a, b DESC, c(10))` as its names
+    fn name_list(&mut self) -> Vec<String> {
+        let mut names = Vec::new();
+        if !self.symbol('(') {
+            return names;
+        }
+        while let Some(name) = self.name_part() {
+            names.push(name);
+            self.skip_to_separator();
+            if !self.symbol(',') {
+                break;
+            }
+        }
+        self.skip_to_separator();
+        self.symbol(')');
+        names
+    }
+
+    /// Skip to the next `,` or `)` outside parentheses
+    fn skip_to_separator(&mut self) {
+        let mut depth = 0;
+        while let Some(token) = self.peek() {
+            match token {
+                SqlToken::Symbol(',' | ')') if depth == 0 => break,
+                SqlToken::Symbol('(') => depth += 1,
+                SqlToken::Symbol(')') => depth -= 1,
+                _ => {}
+            }
+            self.at += 1;
+        }
+    }
+}
+
+/// Entities and relationships of the tables created by a SQL DDL script. Foreign keys become
+/// relationships, and tables that only link two other tables become many-to-many relationships.
+pub fn parse_ddl(sql: &str) -> Result<ErModel, String> {
+    let tokens = sql_tokens(sql)?;
+    let mut tables: Vec<Table> = Vec::new();
+
+    for statement in tokens.split(|t| *t == SqlToken::Symbol(';')) {
+        let mut cursor = SqlCursor { tokens: statement, at: 0 };
+        if cursor.keyword("CREATE") {
+            while ["TEMP", "TEMPORARY", "UNLOGGED", "GLOBAL", "LOCAL"].iter().any(|word| cursor.keyword(word)) {}
+            if !cursor.keyword("TABLE") {
+                continue;
+            }
+            cursor.keywords(&["IF", "NOT", "EXISTS"]);
+            if let Some(table) = create_table(&mut cursor) {
+                tables.retain(|t| !t.name.eq_ignore_ascii_case(&table.name));
+                tables.push(table);
+            }
+        } else if cursor.keywords(&["ALTER", "TABLE"]) {
+            cursor.keywords(&["IF", "EXISTS"]);
+            cursor.keyword("ONLY");
+            let name = cursor.name().unwrap_or_default();
+            let Some(table) = tables.iter_mut().find(|t| t.name.eq_ignore_ascii_case(&name)) else {
+                continue;
+            };
+            loop {
+                if cursor.keyword("ADD") && !table_constraint(&mut cursor, table) {
+                    cursor.keyword("COLUMN");
+                    cursor.keywords(&["IF", "NOT", "EXISTS"]);
+                    column_definition(&mut cursor, table);
+                }
+                cursor.skip_to_separator();
+                if !cursor.symbol(',') {
+                    break;
+                }
+            }
+        } else if cursor.keywords(&["COMMENT", "ON", "COLUMN"]) {
+            let mut parts = cursor.qualified_name();
+            let (Some(column), Some(table)) = (parts.pop(), parts.pop()) else {
+                continue;
+            };
+            if let (true, Some(SqlToken::Text(text))) = (cursor.keyword("IS"), cursor.peek()) {
+                if let Some(column) = tables
+                    .iter_mut()
+                    .find(|t| t.name.eq_ignore_ascii_case(&table))
+                    .and_then(|t| t.column_mut(&column))
+                {
+                    column.comment = Some(text.clone());
+                }
+            }
+        }
+    }
+
+    if tables.is_empty() {
+        return Err("No CREATE TABLE statements found in the SQL".to_string());
+    }
+    Ok(model_from_tables(&tables))
+}
+
+/// Schema of a SQLite database file, opened read-only
+pub fn read_sqlite(path: &Path) -> Result<ErModel, String> {
+    let connection = rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let statements: Vec<String> = connection
+        .prepare("SELECT sql FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' AND sql IS NOT NULL ORDER BY rowid")
+        .and_then(|mut statement| statement.query_map([], |row| row.get(0))?.collect())
+        .map_err(|e| format!("Failed to read the schema of {}: {}", path.display(), e))?;
+    parse_ddl(&statements.join(";\n"))
+}
+
+/// Table name, column and constraint definitions of `CREATE TABLE`, after the `TABLE` keyword
+fn create_table(cursor: &mut SqlCursor) -> Option<Table> {
+    let name = cursor.name()?;
+    if !cursor.symbol('(') {
+        // `CREATE TABLE ... AS SELECT` has no definitions to read
+        return None;
+    }
+    let mut table = Table { name, columns: Vec::new(), primary_key: Vec::new(), unique: Vec::new(), foreign_keys: Vec::new() };
+    loop {
+        if !table_constraint(cursor, &mut table) {
+            column_definition(cursor, &mut table);
+        }
+        cursor.skip_to_separator();
+        if !cursor.symbol(',') {
+            break;
+        }
+    }
+    Some(table)
+}
+
+/// Read a `PRIMARY KEY`, `UNIQUE` or `FOREIGN KEY` table constraint. Other constraints and
+/// indexes are recognised but ignored; `false` means the definition is a column.
+fn table_constraint(cursor: &mut SqlCursor, table: &mut Table) -> bool {
+    let named = cursor.keyword("CONSTRAINT");
+    if named {
+        cursor.name_part();
+    }
+
+    if cursor.keywords(&["PRIMARY", "KEY"]) {
+        table.primary_key = cursor.name_list();
+        for key in table.primary_key.clone() {
+            if let Some(column) = table.column_mut(&key) {
+                column.not_null = true;
+            }
+        }
+    } else if cursor.keyword("UNIQUE") {
+        let _ = cursor.keyword("KEY") || cursor.keyword("INDEX");
+        if !cursor.symbol('(') {
+            cursor.name_part();
+        } else {
+            cursor.at -= 1;
+        }
+        table.unique.push(cursor.name_list());
+    } else if cursor.keywords(&["FOREIGN", "KEY"]) {
+        if cursor.peek() != Some(&SqlToken::Symbol('(')) {
+            cursor.name_part();
+        }
+        let columns = cursor.name_list();
+        if let Some(foreign_key) = references(cursor, columns) {
+            table.foreign_keys.push(foreign_key);
+        }
+    } else if named || is_index(cursor) || matches!(cursor.peek_word(0).as_deref(), Some("CHECK" | "EXCLUDE")) {
+        // Skipped by the caller
+    } else {
+        return false;
+    }
+    true
+}
+
+/// MySQL `KEY name (columns)` or `INDEX (columns)`, as opposed to a column called `key`
+fn is_index(cursor: &SqlCursor) -> bool {
+    let word = cursor.peek_word(0);
+    if matches!(word.as_deref(), Some("FULLTEXT" | "SPATIAL")) {
+        return true;
+    }
+    if !matches!(word.as_deref(), Some("KEY" | "INDEX")) {
+        return false;
+    }
+    let mut at = cursor.at + 1;
+    if matches!(cursor.tokens.get(at), Some(SqlToken::Word(_) | SqlToken::Identifier(_))) {
+        at += 1;
+    }
+    // Column types take numbers, like `key VARCHAR(10)`
+    cursor.tokens.get(at) == Some(&SqlToken::Symbol('('))
+        && matches!(cursor.tokens.get(at + 1), Some(SqlToken::Word(w) | SqlToken::Identifier(w)) if !w.starts_with(|c: char| c.is_ascii_digit()))
+}
+
+/// `REFERENCES table (columns)` for `columns`
+fn references(cursor: &mut SqlCursor, columns: Vec<String>) -> Option<ForeignKey> {
+    if !cursor.keyword("REFERENCES") {
+        return None;
+    }
+    let table = cursor.name()?;
+    let references = cursor.name_list();
+    Some(ForeignKey { columns, table, references })
+}
+
+/// Words that end a column type and start its constraints
+const COLUMN_CONSTRAINTS: [&str; 16] = [
+    "CONSTRAINT", "PRIMARY", "NOT", "NULL", "UNIQUE", "REFERENCES", "DEFAULT", "CHECK", "COLLATE", "GENERATED",
+    "AUTO_INCREMENT", "AUTOINCREMENT", "IDENTITY", "COMMENT", "ON", "CHARSET",
+];
+
+fn column_definition(cursor: &mut SqlCursor, table: &mut Table) {
+    let Some(name) = cursor.name_part() else {
+        return;
+    };
+
+    let mut sql_type = String::new();
+    let mut depth = 0;
+    while let Some(token) = cursor.peek() {
+        if depth == 0 {
+            let ends = match token {
+                SqlToken::Symbol(',' | ')') => true,
+                SqlToken::Word(word) => {
+                    let word = word.to_uppercase();
+                    COLUMN_CONSTRAINTS.contains(&word.as_str())
+                        || (word == "CHARACTER" && cursor.peek_word(1).as_deref() == Some("SET"))
+                }
+                _ => false,
+            };
+            if ends {
+                break;
+            }
+        }
+        match token {
+            SqlToken::Word(word) | SqlToken::Identifier(word) => {
+                if sql_type.ends_with(|c: char| c.is_alphanumeric() || c == ')') {
+                    sql_type.push(' ');
+                }
+                sql_type.push_str(word);
+            }
+            SqlToken::Text(text) => sql_type.push_str(&format!("'{}'", text)),
+            SqlToken::Symbol(symbol) => {
+                match symbol {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                sql_type.push(*symbol);
+            }
+        }
+        cursor.at += 1;
+    }
+
+    let mut column = Column { name: name.clone(), sql_type, not_null: false, identity: false, foreign: false, comment: None };
+    while !matches!(cursor.peek(), None | Some(SqlToken::Symbol(',' | ')'))) {
+        if cursor.keywords(&["PRIMARY", "KEY"]) {
+            column.not_null = true;
+            table.primary_key = vec![name.clone()];
+        } else if cursor.keywords(&["NOT", "NULL"]) {
+            column.not_null = true;
+        } else if cursor.keyword("UNIQUE") {
+            cursor.keyword("KEY");
+            table.unique.push(vec![name.clone()]);
+        } else if cursor.peek_word(0).as_deref() == Some("REFERENCES") {
+            if let Some(foreign_key) = references(cursor, vec![name.clone()]) {
+                table.foreign_keys.push(foreign_key);
+            }
+        } else if cursor.keyword("COMMENT") {
+            if let Some(SqlToken::Text(text)) = cursor.peek() {
+                column.comment = Some(text.clone());
+                cursor.at += 1;
+            }
+        } else if cursor.symbol('(') {
+            // Arguments of DEFAULT, CHECK or GENERATED
+            cursor.skip_to_separator();
+            cursor.symbol(')');
+        } else {
+            cursor.at += 1;
+        }
+    }
+    table.columns.push(column);
+}
+
+/// Same column names, in any order
+fn same_columns(a: &[String], b: &[String]) -> bool {
+    a.len() == b.len() && a.iter().all(|x| b.iter().any(|y| x.eq_ignore_ascii_case(y)))
+}
+
+/// A table made only of references to two other tables, which nothing references
+fn is_join_table(table: &Table, tables: &[Table]) -> bool {
+    let in_foreign_key = |column: &Column| {
+        table.foreign_keys.iter().any(|fk| fk.columns.iter().any(|c| c.eq_ignore_ascii_case(&column.name)))
+    };
+    let all_columns: Vec<String> = table.columns.iter().map(|c| c.name.clone()).collect();
+    table.foreign_keys.len() == 2
+        && table.columns.iter().all(|c| in_foreign_key(c) && c.comment.is_none())
+        && (same_columns(&table.primary_key, &all_columns) || table.unique.iter().any(|u| same_columns(u, &all_columns)))
+        && table.foreign_keys.iter().all(|fk| tables.iter().any(|t| t.name.eq_ignore_ascii_case(&fk.table)))
+        && !tables.iter().any(|t| t.foreign_keys.iter().any(|fk| fk.table.eq_ignore_ascii_case(&table.name)))
+}
+
+fn model_from_tables(tables: &[Table]) -> ErModel {
+    // Referenced tables as spelled in their CREATE TABLE
+    let table_name = |name: &str| {
+        tables.iter().find(|t| t.name.eq_ignore_ascii_case(name)).map_or(name.to_string(), |t| t.name.clone())
+    };
+    let mut model = ErModel::default();
+
+    for table in tables {
+        if is_join_table(table, tables) {
+            model.relationships.push(Relationship {
+                left: table_name(&table.foreign_keys[0].table),
+                right: table_name(&table.foreign_keys[1].table),
+                left_cardinality: Cardinality::ZeroOrMore,
+                right_cardinality: Cardinality::ZeroOrMore,
+                label: table.name.clone(),
+            });
+            continue;
+        }
+
+        let attributes = table.columns.iter().map(|column| table_attribute(table, column)).collect();
+        model.entities.push(Entity { name: table.name.clone(), attributes });
+
+        for fk in &table.foreign_keys {
+            let mandatory = fk.columns.iter().all(|name| table.column(name).is_some_and(|c| c.not_null));
+            let one_to_one = same_columns(&fk.columns, &table.primary_key) || table.unique.iter().any(|u| same_columns(&fk.columns, u));
+            model.relationships.push(Relationship {
+                left: table_name(&fk.table),
+                right: table.name.clone(),
+                left_cardinality: if mandatory { Cardinality::ExactlyOne } else { Cardinality::ZeroOrOne },
+                right_cardinality: if one_to_one { Cardinality::ZeroOrOne } else { Cardinality::ZeroOrMore },
+                label: fk.columns.join(", "),
+            });
+        }
+    }
+    model
+}
+
+fn table_attribute(table: &Table, column: &Column) -> Attribute {
+    let name = &column.name;
+    let primary = table.primary_key.iter().any(|k| k.eq_ignore_ascii_case(name));
+    let mut keys = Vec::new();
+    if primary {
+        keys.push("PK".to_string());
+    }
+    if table.foreign_keys.iter().any(|fk| fk.columns.iter().any(|c| c.eq_ignore_ascii_case(name))) {
+        keys.push("FK".to_string());
+    }
+    if !primary && table.unique.iter().any(|u| same_columns(u, std::slice::from_ref(name))) {
+        keys.push("UK".to_string());
+    }
+
+    let mut attribute_name: String =
+        name.chars().map(|c| if c.is_alphanumeric() || c == '_' || c == '-' { c } else { '_' }).collect();
+    if !attribute_name.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+        attribute_name.insert(0, '_');
+    }
+    Attribute { data_type: mermaid_type(&column.sql_type), name: attribute_name, keys, comment: column.comment.clone() }
+}
+
+/// SQL column type as a Mermaid attribute type: `VARCHAR(255)` becomes `varchar(255)` and
+/// `DOUBLE PRECISION` becomes `double_precision`. Arguments with commas are dropped.
+fn mermaid_type(sql_type: &str) -> String {
+    let lower = sql_type.trim().to_lowercase();
+    let lower = match lower.find('(') {
+        Some(open) if lower[open..].contains(',') => lower[..open].to_string(),
+        _ => lower,
+    };
+    let data_type: String = lower
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("_")
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '(' | ')' | '[' | ']'))
+        .collect();
+    if data_type.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+        data_type
+    } else {
+        // SQLite columns may have no type at all
+        "any".to_string()
+    }
+}
@@ -0,0 +1,283 @@
+use crate::cache::DEFAULT_CACHE_MAX_MB;
+use crate::llm::LocalModel;
+use crate::mermaid::DEFAULT_REPAIR_ATTEMPTS;
+use crate::usage::ModelPrice;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+pub const DEFAULT_READ_TIMEOUT_SECS: u64 = 120;
+
+/// LLM tasks that can be routed to a specific profile; they match the event prefixes of the streaming commands
+pub const LLM_TASKS: [&str; 10] = ["refine", "er", "uml", "flowchart", "journey", "sequence", "state", "c4", "stories", "ask"];
+
+pub const DEFAULT_PROFILE_NAME: &str = "Default";
+
+/// A named provider configuration
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LlmProfile {
+    #[serde(default = "default_profile_name")]
+    pub name: String,
+    pub provider: String,
+    /// Only set transiently: keys are moved into the vault on save and resolved again per request
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub api_key: String,
+    /// Reference to the API key stored in the vault
+    #[serde(default)]
+    pub api_key_ref: Option<String>,
+    pub base_url: String,
+    pub model: Option<String>,
+    /// "openai", "anthropic", "ollama" or "gemini"; inferred from `provider` when missing
+    pub protocol: Option<String>,
+    /// OpenAI protocol only: ask for token usage with `stream_options` (on unless `false`). Some
+    /// compatible servers reject the field with a 400.
+    #[serde(default)]
+    pub stream_usage: Option<bool>,
+    /// Models discovered on the server (Ollama only); filled in by `get_llm_settings`, never stored
+    #[serde(default, skip_deserializing, skip_serializing_if = "Vec::is_empty")]
+    pub available_models: Vec<LocalModel>,
+}
+
+impl Default for LlmProfile {
+    fn default() -> Self {
+        LlmProfile {
+            name: default_profile_name(),
+            provider: "openai".to_string(),
+            api_key: "".to_string(),
+            api_key_ref: None,
+            base_url: "https://api.openai.com/v1".to_string(),
+            model: Some("gpt-4".to_string()),
+            protocol: Some("openai".to_string()),
+            stream_usage: None,
+            available_models: Vec::new(),
+        }
+    }
+}
+
+fn default_profile_name() -> String {
+    DEFAULT_PROFILE_NAME.to_string()
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LlmSettings {
+    #[serde(default)]
+    pub profiles: Vec<LlmProfile>,
+    /// Name of the profile used by tasks without an override
+    #[serde(default = "default_profile_name")]
+    pub active_profile: String,
+    /// Task name (see `LLM_TASKS`) -> profile name
+    #[serde(default)]
+    pub task_profiles: HashMap<String, String>,
+    /// Seconds allowed for establishing the TCP/TLS connection
+    #[serde(default)]
+    pub connect_timeout_secs: Option<u64>,
+    /// Seconds allowed between two reads of the response body (applies per chunk when streaming)
+    #[serde(default)]
+    pub read_timeout_secs: Option<u64>,
+    /// HTTP(S) proxy used for every LLM request, e.g. `http://proxy.corp:3128`
+    #[serde(default)]
+    pub proxy_url: Option<String>,
+    /// Path to a PEM file with additional root certificates (corporate TLS inspection)
+    #[serde(default)]
+    pub ca_bundle_path: Option<String>,
+    /// Extra headers sent with every LLM request
+    #[serde(default)]
+    pub extra_headers: HashMap<String, String>,
+    /// Model name (or name prefix) -> price, used to compute the cost of recorded calls
+    #[serde(default)]
+    pub model_prices: HashMap<String, ModelPrice>,
+    /// Size limit of the on-disk response cache in megabytes; 0 disables caching
+    #[serde(default)]
+    pub response_cache_mb: Option<u64>,
+    /// Repair requests sent when a generated diagram does not parse (with `autoRepair`)
+    #[serde(default)]
+    pub diagram_repair_attempts: Option<u32>,
+}
+
+impl Default for LlmSettings {
+    fn default() -> Self {
+        LlmSettings {
+            profiles: vec![LlmProfile::default()],
+            active_profile: default_profile_name(),
+            task_profiles: HashMap::new(),
+            connect_timeout_secs: Some(DEFAULT_CONNECT_TIMEOUT_SECS),
+            read_timeout_secs: Some(DEFAULT_READ_TIMEOUT_SECS),
+            proxy_url: None,
+            ca_bundle_path: None,
+            extra_headers: HashMap::new(),
+            model_prices: HashMap::new(),
+            response_cache_mb: Some(DEFAULT_CACHE_MAX_MB),
+            diagram_repair_attempts: Some(DEFAULT_REPAIR_ATTEMPTS),
+        }
+    }
+}
+
+impl LlmSettings {
+    pub fn get_profile(&self, name: &str) -> Option<&LlmProfile> {
+        self.profiles.iter().find(|p| p.name == name)
+    }
+
+    /// Resolve the profile for a task: its override if set, otherwise the active profile
+    pub fn profile_for_task(&self, task: &str) -> Result<LlmProfile, String> {
+        let name = self.task_profiles
+            .get(task)
+            .filter(|name| !name.is_empty())
+            .unwrap_or(&self.active_profile);
+
+        self.get_profile(name)
+            .or_else(|| self.profiles.first())
+            .cloned()
+            .ok_or("No LLM profile configured".to_string())
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.profiles.is_empty() {
+            return Err("At least one LLM profile is required".to_string());
+        }
+
+        for (index, profile) in self.profiles.iter().enumerate() {
+            if profile.name.trim().is_empty() {
+                return Err("Profile names cannot be empty".to_string());
+            }
+            if self.profiles[..index].iter().any(|p| p.name == profile.name) {
+                return Err(format!("Duplicate profile name: {}", profile.name));
+            }
+        }
+
+        if self.get_profile(&self.active_profile).is_none() {
+            return Err(format!("Active profile '{}' does not exist", self.active_profile));
+        }
+
+        for (task, name) in &self.task_profiles {
+            if !LLM_TASKS.contains(&task.as_str()) {
+                return Err(format!("Unknown LLM task: {}", task));
+            }
+            if !name.is_empty() && self.get_profile(name).is_none() {
+                return Err(format!("Profile '{}' assigned to task '{}' does not exist", name, task));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn settings_dir() -> Result<PathBuf, String> {
+    Ok(dirs::home_dir()
+        .ok_or("Failed to get home directory")?
+        .join(".promptmux"))
+}
+
+pub fn load_settings() -> Result<LlmSettings, String> {
+    let settings_path = settings_dir()?.join("settings.json");
+
+    if !settings_path.exists() {
+        // Return default settings if not found
+        return Ok(LlmSettings::default());
+    }
+
+    let settings_content = fs::read_to_string(settings_path)
+        .map_err(|e| format!("Failed to read settings file: {}", e))?;
+    parse_settings(&settings_content)
+}
+
+/// Parse the content of `settings.json`, migrating older formats
+pub fn parse_settings(settings_content: &str) -> Result<LlmSettings, String> {
+    let value: serde_json::Value = serde_json::from_str(settings_content)
+        .map_err(|e| format!("Failed to parse settings file: {}", e))?;
+
+    let mut settings: LlmSettings = serde_json::from_value(value.clone())
+        .map_err(|e| format!("Failed to parse settings file: {}", e))?;
+
+    // Migrate the single-provider format, where the profile fields live at the top level
+    if settings.profiles.is_empty() && value.get("provider").is_some() {
+        let mut profile: LlmProfile = serde_json::from_value(value)
+            .map_err(|e| format!("Failed to parse settings file: {}", e))?;
+        profile.name = default_profile_name();
+        settings.active_profile = profile.name.clone();
+        settings.profiles.push(profile);
+    }
+
+    if settings.profiles.is_empty() {
+        settings.profiles.push(LlmProfile::default());
+        settings.active_profile = default_profile_name();
+    }
+
+    // Backfill protocol if missing, inferred from the provider
+    for profile in &mut settings.profiles {
+        if profile.protocol.is_none() {
+            profile.protocol = Some(crate::llm::Protocol::for_profile(profile).as_str().to_string());
+        }
+    }
+
+    Ok(settings)
+}
+
+pub fn write_settings(settings: &LlmSettings) -> Result<(), String> {
+    settings.validate()?;
+
+    let settings_dir = settings_dir()?;
+    let settings_path = settings_dir.join("settings.json");
+
+    if !settings_dir.exists() {
+        fs::create_dir_all(&settings_dir)
+            .map_err(|e| format!("Failed to create settings directory: {}", e))?;
+    }
+
+    let settings_json = serde_json::to_string_pretty(settings)
+        .map_err(|e| format!("Failed to serialize settings: {}", e))?;
+
+    fs::write(settings_path, settings_json)
+        .map_err(|e| format!("Failed to write settings file: {}", e))?;
+
+    Ok(())
+}
+
+/// Build the HTTP client shared by all LLM commands from the network options in `settings`.
+///
+/// No overall request timeout is set on purpose: a long streamed answer is fine as long as
+/// chunks keep arriving, which is what the read timeout guards.
+pub fn build_http_client(settings: &LlmSettings) -> Result<reqwest::Client, String> {
+    let mut builder = reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(
+            settings.connect_timeout_secs.unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECS),
+        ))
+        .read_timeout(Duration::from_secs(
+            settings.read_timeout_secs.unwrap_or(DEFAULT_READ_TIMEOUT_SECS),
+        ));
+
+    if let Some(proxy_url) = settings.proxy_url.as_deref().map(str::trim).filter(|u| !u.is_empty()) {
+        let proxy = reqwest::Proxy::all(proxy_url)
+            .map_err(|e| format!("Invalid proxy URL '{}': {}", proxy_url, e))?;
+        builder = builder.proxy(proxy);
+    }
+
+    if let Some(ca_path) = settings.ca_bundle_path.as_deref().map(str::trim).filter(|p| !p.is_empty()) {
+        let pem = fs::read(ca_path)
+            .map_err(|e| format!("Failed to read CA bundle '{}': {}", ca_path, e))?;
+        let certificates = reqwest::Certificate::from_pem_bundle(&pem)
+            .map_err(|e| format!("Failed to parse CA bundle '{}': {}", ca_path, e))?;
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    if !settings.extra_headers.is_empty() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        for (name, value) in &settings.extra_headers {
+            let header_name = reqwest::header::HeaderName::from_bytes(name.trim().as_bytes())
+                .map_err(|e| format!("Invalid header name '{}': {}", name, e))?;
+            let header_value = reqwest::header::HeaderValue::from_str(value.trim())
+                .map_err(|e| format!("Invalid value for header '{}': {}", name, e))?;
+            headers.insert(header_name, header_value);
+        }
+        builder = builder.default_headers(headers);
+    }
+
+    builder
+        .build()
+        .map_err(|e| format!("Failed to build HTTP client: {}", e))
+}
@@ -1,4 +1,9 @@
+use crate::cache::{ResponseCache, DEFAULT_CACHE_MAX_MB};
 use crate::models::{Workspace, Project};
+use crate::settings;
+use crate::usage::UsageLedger;
+use crate::vault::{self, Vault};
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 use std::sync::Mutex;
@@ -6,6 +11,13 @@ use std::sync::Mutex;
 pub struct AppState {
     pub workspace: Mutex<Workspace>,
     pub data_dir: PathBuf,
+    /// Shared client for LLM requests, rebuilt whenever the LLM settings are saved
+    pub http_client: Mutex<reqwest::Client>,
+    pub vault: Mutex<Vault>,
+    pub usage: Mutex<UsageLedger>,
+    pub cache: Mutex<ResponseCache>,
+    /// Content sent by topic and section refinements awaiting accept, keyed by topic or section id
+    pub refinement_drafts: Mutex<HashMap<String, String>>,
 }
 
 impl AppState {
@@ -16,7 +28,7 @@ impl AppState {
         
         // Try to load existing workspace, or create new one
         let workspace_path = data_dir.join("workspace.json");
-        let workspace = if workspace_path.exists() {
+        let mut workspace: Workspace = if workspace_path.exists() {
             let workspace_json = fs::read_to_string(&workspace_path)
                 .map_err(|e| format!("Failed to read workspace file: {}", e))?;
             
@@ -30,8 +42,9 @@ impl AppState {
                 let project_json = fs::read_to_string(&old_project_path)
                     .map_err(|e| format!("Failed to read old project file: {}", e))?;
                 
-                let project: Project = serde_json::from_str(&project_json)
+                let mut project: Project = serde_json::from_str(&project_json)
                     .map_err(|e| format!("Failed to parse old project file: {}", e))?;
+                project.migrate_diagrams();
                 
                 let active_id = project.id.clone();
                 Workspace {
@@ -39,6 +52,7 @@ impl AppState {
                     active_project_id: active_id,
                     created_at: chrono::Utc::now().to_rfc3339(),
                     updated_at: chrono::Utc::now().to_rfc3339(),
+                    prompt_templates: HashMap::new(),
                 }
             } else {
                 // Create new workspace with default project
@@ -55,9 +69,57 @@ impl AppState {
             workspace
         };
         
+        // Diagrams of older workspaces live in fixed fields
+        for project in &mut workspace.projects {
+            project.migrate_diagrams();
+        }
+        
+        // A broken proxy or CA path must not keep the app from starting; fall back to a plain client
+        let http_client = settings::load_settings()
+            .and_then(|s| settings::build_http_client(&s))
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to build HTTP client from settings: {}", e);
+                reqwest::Client::new()
+            });
+        
+        let vault_path = settings::settings_dir()
+            .unwrap_or_else(|_| data_dir.clone())
+            .join("vault.json");
+        let mut vault = Vault::open(vault_path);
+        
+        // Move plaintext API keys from older settings files into the vault when that needs no passphrase
+        if let Ok(mut llm_settings) = settings::load_settings() {
+            match vault::secure_api_keys(&mut vault, &mut llm_settings) {
+                Ok(true) => {
+                    if let Err(e) = settings::write_settings(&llm_settings) {
+                        eprintln!("Failed to rewrite settings after moving API keys: {}", e);
+                    }
+                }
+                Ok(false) => {}
+                Err(e) => eprintln!("API keys remain in plaintext settings: {}", e),
+            }
+        }
+        
+        // Usage history is informational; an unreadable ledger must not keep the app from starting
+        let usage = UsageLedger::load(&data_dir).unwrap_or_else(|e| {
+            eprintln!("Failed to load usage ledger: {}", e);
+            UsageLedger::empty(&data_dir)
+        });
+        
+        let cache_mb = settings::load_settings()
+            .ok()
+            .and_then(|s| s.response_cache_mb)
+            .unwrap_or(DEFAULT_CACHE_MAX_MB);
+        let cache = ResponseCache::open(&data_dir, cache_mb);
+        
         Ok(AppState {
             workspace: Mutex::new(workspace),
             data_dir,
+            http_client: Mutex::new(http_client),
+            vault: Mutex::new(vault),
+            usage: Mutex::new(usage),
+            cache: Mutex::new(cache),
+            refinement_drafts: Mutex::new(HashMap::new()),
         })
     }
 }
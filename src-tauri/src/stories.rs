@@ -0,0 +1,295 @@
+//! Structured view of the project user stories: features, stories and acceptance criteria read
+//! from the generated Markdown, and Gherkin feature files derived from them
+
+use crate::codegen::GeneratedFile;
+use crate::schema::snake_case;
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserStory {
+    /// The story as written, without Markdown
+    pub text: String,
+    pub role: String,
+    /// What the role wants, like `add a new item`
+    pub goal: String,
+    pub benefit: Option<String>,
+    pub acceptance_criteria: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Feature {
+    pub name: String,
+    pub stories: Vec<UserStory>,
+}
+
+/// Phrases starting the goal, and what is kept of them
+const GOAL_PHRASES: [(&str, &str); 8] = [
+    ("i would like to ", ""),
+    ("i'd like to ", ""),
+    ("i want to ", ""),
+    ("i need to ", ""),
+    ("i can ", ""),
+    ("i want ", "want "),
+    ("i need ", "need "),
+    ("i should be able to ", ""),
+];
+
+const BENEFIT_PHRASES: [&str; 3] = [" so that ", " in order to ", " so "];
+
+const STEP_KEYWORDS: [&str; 5] = ["given", "when", "then", "and", "but"];
+
+/// Features and their stories from Markdown like
+/// `## Feature` / `- **As a** user, **I want to** act **so that** benefit`, with acceptance
+/// criteria as nested list items or listed after an `Acceptance criteria` label
+pub fn parse(text: &str) -> Vec<Feature> {
+    let mut features: Vec<Feature> = Vec::new();
+    let mut heading: Option<String> = None;
+    // Indentation of the current story, while its criteria may follow
+    let mut story_indent: Option<usize> = None;
+    let mut in_criteria = false;
+
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        let indent: usize = line.chars().take_while(|c| c.is_whitespace()).map(|c| if c == '\t' { 4 } else { 1 }).sum();
+
+        if trimmed.starts_with('#') {
+            let title = plain(trimmed.trim_start_matches('#'));
+            let lower = title.to_lowercase();
+            // `### Story 1` headings belong to the feature above them
+            if !(lower.starts_with("story") || lower.starts_with("user story") || lower.starts_with("us-")) {
+                heading = Some(feature_name(&title));
+            }
+            story_indent = None;
+            continue;
+        }
+
+        let item = list_item(trimmed);
+        let content = plain(item.unwrap_or(trimmed));
+        if let Some(story) = parse_story(&content) {
+            let name = heading.clone().unwrap_or_else(|| "User Stories".to_string());
+            match features.last_mut() {
+                Some(feature) if feature.name == name => feature.stories.push(story),
+                _ => features.push(Feature { name, stories: vec![story] }),
+            }
+            story_indent = Some(indent);
+            in_criteria = false;
+            continue;
+        }
+
+        let (Some(story_indent_value), Some(story)) =
+            (story_indent, features.last_mut().and_then(|f| f.stories.last_mut()))
+        else {
+            continue;
+        };
+        let lower = content.to_lowercase();
+        if lower.starts_with("acceptance criteria") {
+            in_criteria = true;
+            let inline = content.split_once(':').map(|(_, rest)| rest.trim()).unwrap_or_default();
+            if !inline.is_empty() {
+                story.acceptance_criteria.push(inline.to_string());
+            }
+            continue;
+        }
+
+        let first_word = lower.split_whitespace().next().unwrap_or_default();
+        let continues_steps = matches!(first_word, "when" | "then" | "and" | "but")
+            && story.acceptance_criteria.last().is_some_and(|c| steps(c).is_some());
+        if continues_steps {
+            if let Some(last) = story.acceptance_criteria.last_mut() {
+                last.push('\n');
+                last.push_str(&content);
+            }
+        } else if (item.is_some() && (indent > story_indent_value || in_criteria)) || first_word == "given" {
+            story.acceptance_criteria.push(content);
+        } else {
+            // Anything else ends the story
+            story_indent = None;
+        }
+    }
+    features
+}
+
+/// Text of a `-`, `*`, `+`, `1.` or `1)` list item, without a `[ ]` checkbox
+fn list_item(line: &str) -> Option<&str> {
+    let rest = match line.strip_prefix(['-', '*', '+']) {
+        Some(rest) => rest,
+        None => {
+            let digits = line.chars().take_while(|c| c.is_ascii_digit()).count();
+            if digits == 0 {
+                return None;
+            }
+            line[digits..].strip_prefix(['.', ')'])?
+        }
+    };
+    if !rest.starts_with(' ') {
+        return None;
+    }
+    let rest = rest.trim_start();
+    Some(["[ ] ", "[x] ", "[X] "].iter().find_map(|box_| rest.strip_prefix(box_)).unwrap_or(rest).trim_start())
+}
+
+/// Text without bold, italic and code markers
+fn plain(text: &str) -> String {
+    text.replace("**", "").replace("__", "").replace(['*', '`'], "").trim().to_string()
+}
+
+/// `1. Feature: Billing` as `Billing`
+fn feature_name(title: &str) -> String {
+    let title = title.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.' || c == ')').trim();
+    let title = title.strip_prefix("Feature:").or_else(|| title.strip_prefix("Feature ")).unwrap_or(title);
+    title.trim().to_string()
+}
+
+/// `As a <role>, I want to <goal> so that <benefit>`, anywhere in `text`
+fn parse_story(text: &str) -> Option<UserStory> {
+    // ASCII lowercase keeps byte offsets valid for `text`
+    let lower = text.to_ascii_lowercase();
+    let role_start = ["as an ", "as a "].iter().find_map(|phrase| {
+        lower
+            .match_indices(phrase)
+            .find(|(at, _)| *at == 0 || !lower[..*at].ends_with(|c: char| c.is_alphanumeric()))
+            .map(|(at, _)| at + phrase.len())
+    })?;
+
+    let (goal_at, phrase, kept) = GOAL_PHRASES
+        .iter()
+        .filter_map(|(phrase, kept)| lower[role_start..].find(phrase).map(|at| (role_start + at, *phrase, *kept)))
+        .min_by_key(|(at, phrase, _)| (*at, usize::MAX - phrase.len()))?;
+    let role = text[role_start..goal_at].trim().trim_end_matches(',').trim();
+
+    let goal_start = goal_at + phrase.len();
+    let benefit_at = BENEFIT_PHRASES
+        .iter()
+        .filter_map(|phrase| lower[goal_start..].find(phrase).map(|at| (goal_start + at, phrase.len())))
+        .min_by_key(|(at, _)| *at);
+    let goal_end = benefit_at.map_or(text.len(), |(at, _)| at);
+    let goal = format!("{}{}", kept, text[goal_start..goal_end].trim().trim_end_matches([',', '.']).trim());
+    let benefit = benefit_at
+        .map(|(at, len)| text[at + len..].trim().trim_end_matches('.').trim().to_string())
+        .filter(|b| !b.is_empty());
+
+    if role.is_empty() || goal.trim().is_empty() {
+        return None;
+    }
+    Some(UserStory {
+        text: text.trim().to_string(),
+        role: role.to_string(),
+        goal,
+        benefit,
+        acceptance_criteria: Vec::new(),
+    })
+}
+
+/// Steps of a criterion written as `Given ..., when ..., then ...`, on one line or several.
+/// A keyword starts a step at the beginning, after a line break or after `,` or `;`.
+fn steps(criterion: &str) -> Option<Vec<String>> {
+    let mut steps: Vec<(String, Vec<&str>)> = Vec::new();
+    for line in criterion.lines() {
+        let mut clause_end = true;
+        for word in line.split_whitespace() {
+            let keyword = word.to_lowercase();
+            if clause_end && STEP_KEYWORDS.contains(&keyword.as_str()) {
+                steps.push((capitalize(&keyword), Vec::new()));
+            } else if let Some((_, words)) = steps.last_mut() {
+                words.push(word);
+            } else {
+                return None;
+            }
+            clause_end = word.ends_with([',', ';']);
+        }
+    }
+    if !matches!(steps.first().map(|(k, _)| k.as_str()), Some("Given" | "When" | "Then")) {
+        return None;
+    }
+    Some(
+        steps
+            .into_iter()
+            .map(|(keyword, words)| format!("{} {}", keyword, words.join(" ").trim_end_matches([',', ';', '.'])))
+            .collect(),
+    )
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Lowercase the first letter unless the first word is an acronym
+fn lower_first(text: &str) -> String {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(first), Some(second)) if !second.is_uppercase() => {
+            first.to_lowercase().chain(text[first.len_utf8()..].chars()).collect()
+        }
+        _ => text.to_string(),
+    }
+}
+
+/// `a user`, `an admin`
+fn with_article(role: &str) -> String {
+    let article = if role.starts_with(['a', 'e', 'i', 'o', 'A', 'E', 'I', 'O']) { "an" } else { "a" };
+    format!("{} {}", article, role)
+}
+
+/// One `.feature` file per feature, with a rule per story and a scenario per acceptance
+/// criterion. Stories without criteria get one scenario for the story itself.
+pub fn to_gherkin(features: &[Feature]) -> Vec<GeneratedFile> {
+    let mut files: Vec<GeneratedFile> = Vec::new();
+    for feature in features {
+        let mut content = format!("Feature: {}\n", feature.name);
+        for story in &feature.stories {
+            content.push_str(&format!("\n  Rule: {}\n    {}\n", capitalize(&story.goal), story.text));
+
+            let given = format!("Given I am {}", with_article(&story.role));
+            let when = format!("When I {}", story.goal);
+            let outcome = story.benefit.as_deref().map(lower_first).unwrap_or_else(|| "the action succeeds".to_string());
+            let mut scenarios: Vec<(String, Vec<String>)> = Vec::new();
+            for criterion in &story.acceptance_criteria {
+                match steps(criterion) {
+                    Some(steps) => {
+                        let name = steps
+                            .iter()
+                            .rev()
+                            .find_map(|s| s.strip_prefix("Then "))
+                            .map(capitalize)
+                            .unwrap_or_else(|| capitalize(&story.goal));
+                        scenarios.push((name, steps));
+                    }
+                    None => {
+                        let criterion = criterion.trim_end_matches('.');
+                        let then = format!("Then {}", lower_first(criterion));
+                        scenarios.push((capitalize(criterion), vec![given.clone(), when.clone(), then]));
+                    }
+                }
+            }
+            if scenarios.is_empty() {
+                scenarios.push((capitalize(&story.goal), vec![given.clone(), when.clone(), format!("Then {}", outcome)]));
+            }
+
+            for (name, steps) in scenarios {
+                content.push_str(&format!("\n    Scenario: {}\n", name));
+                for step in steps {
+                    content.push_str(&format!("      {}\n", step));
+                }
+            }
+        }
+
+        let stem = Some(snake_case(&feature.name)).filter(|s| !s.is_empty()).unwrap_or_else(|| "user_stories".to_string());
+        let mut path = format!("{}.feature", stem);
+        let mut n = 2;
+        while files.iter().any(|f| f.path == path) {
+            path = format!("{}_{}.feature", stem, n);
+            n += 1;
+        }
+        files.push(GeneratedFile { path, content });
+    }
+    files
+}
@@ -0,0 +1,247 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Token counts reported by the provider at the end of a stream
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TokenUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
+impl TokenUsage {
+    /// OpenAI sends `usage` on the last chunk when `stream_options.include_usage` is set
+    pub fn merge_openai(&mut self, chunk: &serde_json::Value) {
+        let usage = &chunk["usage"];
+        if let Some(prompt_tokens) = usage["prompt_tokens"].as_u64() {
+            self.input_tokens = prompt_tokens;
+        }
+        if let Some(completion_tokens) = usage["completion_tokens"].as_u64() {
+            self.output_tokens = completion_tokens;
+        }
+    }
+
+    /// Anthropic reports input tokens in `message_start` and the running output count in `message_delta`
+    pub fn merge_anthropic(&mut self, event: &serde_json::Value) {
+        match event["type"].as_str() {
+            Some("message_start") => {
+                let usage = &event["message"]["usage"];
+                if let Some(input_tokens) = usage["input_tokens"].as_u64() {
+                    self.input_tokens = input_tokens;
+                }
+                if let Some(output_tokens) = usage["output_tokens"].as_u64() {
+                    self.output_tokens = output_tokens;
+                }
+            }
+            Some("message_delta") => {
+                if let Some(output_tokens) = event["usage"]["output_tokens"].as_u64() {
+                    self.output_tokens = output_tokens;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Ollama puts `prompt_eval_count` and `eval_count` on the final `done` message
+    pub fn merge_ollama(&mut self, message: &serde_json::Value) {
+        if let Some(prompt_eval_count) = message["prompt_eval_count"].as_u64() {
+            self.input_tokens = prompt_eval_count;
+        }
+        if let Some(eval_count) = message["eval_count"].as_u64() {
+            self.output_tokens = eval_count;
+        }
+    }
+
+    /// Gemini repeats `usageMetadata` on every chunk with running totals
+    pub fn merge_gemini(&mut self, chunk: &serde_json::Value) {
+        let usage = &chunk["usageMetadata"];
+        if let Some(prompt_token_count) = usage["promptTokenCount"].as_u64() {
+            self.input_tokens = prompt_token_count;
+        }
+        if let Some(candidates_token_count) = usage["candidatesTokenCount"].as_u64() {
+            self.output_tokens = candidates_token_count;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.input_tokens == 0 && self.output_tokens == 0
+    }
+}
+
+/// Price of a model in USD per million tokens
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelPrice {
+    pub input_per_million: f64,
+    pub output_per_million: f64,
+}
+
+impl ModelPrice {
+    pub fn cost(&self, usage: TokenUsage) -> f64 {
+        (usage.input_tokens as f64 * self.input_per_million
+            + usage.output_tokens as f64 * self.output_per_million)
+            / 1_000_000.0
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageRecord {
+    pub id: String,
+    /// Project that was active when the call was made
+    pub project_id: Option<String>,
+    pub task: String,
+    pub profile: String,
+    pub model: String,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// `None` when the model has no entry in the price table
+    pub cost_usd: Option<f64>,
+    pub timestamp: String,
+}
+
+impl UsageRecord {
+    pub fn new(
+        project_id: Option<String>,
+        task: &str,
+        profile: &str,
+        model: &str,
+        usage: TokenUsage,
+        price: Option<&ModelPrice>,
+    ) -> Self {
+        UsageRecord {
+            id: Uuid::new_v4().to_string(),
+            project_id,
+            task: task.to_string(),
+            profile: profile.to_string(),
+            model: model.to_string(),
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            cost_usd: price.map(|p| p.cost(usage)),
+            timestamp: chrono::Utc::now().to_rfc3339(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageSummary {
+    /// Day (`YYYY-MM-DD`, UTC), project id or task name depending on the grouping
+    pub key: String,
+    pub calls: usize,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cost_usd: f64,
+    /// Calls whose model had no price and are therefore missing from `cost_usd`
+    pub unpriced_calls: usize,
+}
+
+/// Append-only list of LLM calls, persisted as `usage.jsonl` (one record per line) in the app
+/// data directory, so recording a call appends a line instead of rewriting the file
+pub struct UsageLedger {
+    path: PathBuf,
+    records: Vec<UsageRecord>,
+}
+
+impl UsageLedger {
+    pub fn load(data_dir: &Path) -> Result<Self, String> {
+        let path = data_dir.join("usage.jsonl");
+        let mut records = Vec::new();
+        if path.exists() {
+            let content = fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read usage file: {}", e))?;
+            for (index, line) in content.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+                // A line cut short by a crash loses that call only
+                match serde_json::from_str(line) {
+                    Ok(record) => records.push(record),
+                    Err(e) => eprintln!("Skipping usage record on line {}: {}", index + 1, e),
+                }
+            }
+            // Start the next record on a line of its own after a cut-off one
+            if !content.is_empty() && !content.ends_with('\n') {
+                OpenOptions::new()
+                    .append(true)
+                    .open(&path)
+                    .and_then(|mut file| file.write_all(b"\n"))
+                    .map_err(|e| format!("Failed to write usage file: {}", e))?;
+            }
+        }
+
+        Ok(UsageLedger { path, records })
+    }
+
+    /// A ledger without earlier records that still appends to `usage.jsonl` in `data_dir`
+    pub fn empty(data_dir: &Path) -> Self {
+        UsageLedger {
+            path: data_dir.join("usage.jsonl"),
+            records: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, record: UsageRecord) -> Result<(), String> {
+        let mut line = serde_json::to_string(&record)
+            .map_err(|e| format!("Failed to serialize usage: {}", e))?;
+        line.push('\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format!("Failed to open usage file: {}", e))?;
+        file.write_all(line.as_bytes())
+            .map_err(|e| format!("Failed to write usage file: {}", e))?;
+
+        self.records.push(record);
+        Ok(())
+    }
+
+    pub fn records(&self, project_id: Option<&str>) -> Vec<UsageRecord> {
+        self.records
+            .iter()
+            .filter(|r| project_id.is_none() || r.project_id.as_deref() == project_id)
+            .cloned()
+            .collect()
+    }
+
+    /// Totals grouped by `"day"`, `"project"` or `"task"`, sorted by key
+    pub fn summarize(&self, group_by: &str, project_id: Option<&str>) -> Result<Vec<UsageSummary>, String> {
+        let mut groups: BTreeMap<String, UsageSummary> = BTreeMap::new();
+
+        for record in self.records(project_id) {
+            let key = match group_by {
+                "day" => record.timestamp.get(..10).unwrap_or(&record.timestamp).to_string(),
+                "project" => record.project_id.clone().unwrap_or_default(),
+                "task" => record.task.clone(),
+                _ => return Err(format!("Invalid usage grouping: {}", group_by)),
+            };
+
+            let summary = groups.entry(key.clone()).or_insert_with(|| UsageSummary {
+                key,
+                ..Default::default()
+            });
+            summary.calls += 1;
+            summary.input_tokens += record.input_tokens;
+            summary.output_tokens += record.output_tokens;
+            match record.cost_usd {
+                Some(cost) => summary.cost_usd += cost,
+                None => summary.unpriced_calls += 1,
+            }
+        }
+
+        Ok(groups.into_values().collect())
+    }
+}
+
+/// Look up the price for `model`: an exact entry wins, otherwise the longest key that prefixes
+/// the model name (so `gpt-4o` also prices `gpt-4o-2024-08-06`)
+pub fn find_price<'a>(prices: &'a HashMap<String, ModelPrice>, model: &str) -> Option<&'a ModelPrice> {
+    prices.get(model).or_else(|| {
+        prices
+            .iter()
+            .filter(|(key, _)| model.starts_with(key.as_str()))
+            .max_by_key(|(key, _)| key.len())
+            .map(|(_, price)| price)
+    })
+}
@@ -0,0 +1,374 @@
+use crate::settings::{LlmProfile, LlmSettings};
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+const VAULT_VERSION: u32 = 1;
+const KEYRING_SERVICE: &str = "promptmux";
+const KEYRING_USER: &str = "vault-key";
+const NONCE_LEN: usize = 12;
+/// `decrypt_with` error when the key does not authenticate the ciphertext
+const DECRYPT_FAILED: &str = "Failed to decrypt vault";
+
+/// Where the vault encryption key comes from
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum KeySource {
+    /// Random key stored in the OS secret service (Keychain, Credential Manager, Secret Service)
+    Keyring,
+    /// Key derived from a user passphrase with Argon2id
+    Passphrase,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VaultFile {
+    version: u32,
+    key_source: KeySource,
+    /// Argon2 salt, only present for passphrase vaults
+    #[serde(default)]
+    salt: Option<String>,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VaultStatus {
+    pub exists: bool,
+    pub unlocked: bool,
+    pub key_source: Option<KeySource>,
+    pub keyring_available: bool,
+    /// Why an existing vault file could not be read; it is left untouched until fixed
+    pub error: Option<String>,
+}
+
+/// Encrypted store for API keys, persisted as `~/.promptmux/vault.json`
+pub struct Vault {
+    path: PathBuf,
+    key_source: Option<KeySource>,
+    salt: Option<Vec<u8>>,
+    key: Option<[u8; 32]>,
+    secrets: HashMap<String, String>,
+    /// Read or parse error of an existing vault file
+    error: Option<String>,
+}
+
+impl Vault {
+    /// Open the vault file if there is one. Keyring vaults are unlocked right away;
+    /// passphrase vaults stay locked until `unlock` is called.
+    pub fn open(path: PathBuf) -> Self {
+        let mut vault = Vault {
+            path,
+            key_source: None,
+            salt: None,
+            key: None,
+            secrets: HashMap::new(),
+            error: None,
+        };
+        if !vault.path.exists() {
+            return vault;
+        }
+
+        match vault.read_file() {
+            Ok(file) => {
+                vault.key_source = Some(file.key_source);
+                vault.salt = file.salt.as_deref().and_then(|s| BASE64.decode(s).ok());
+
+                if file.key_source == KeySource::Keyring {
+                    if let Err(e) = read_keyring_key().and_then(|key| vault.decrypt_with(key, &file)) {
+                        eprintln!("Failed to unlock API key vault from keyring: {}", e);
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("API key vault is damaged: {}", e);
+                vault.error = Some(e);
+            }
+        }
+
+        vault
+    }
+
+    pub fn status(&self) -> VaultStatus {
+        VaultStatus {
+            exists: self.key_source.is_some() || self.error.is_some(),
+            unlocked: self.key.is_some(),
+            key_source: self.key_source,
+            keyring_available: keyring_available(),
+            error: self.error.clone(),
+        }
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.key.is_some()
+    }
+
+    /// Create a new, empty vault. Without a passphrase the key is generated and kept in the OS keyring.
+    /// Never replaces a vault file, even one that cannot be read.
+    pub fn create(&mut self, passphrase: Option<&str>) -> Result<(), String> {
+        if let Some(e) = &self.error {
+            return Err(format!("The API key vault at {} cannot be read ({}). Repair or remove it first.", self.path.display(), e));
+        }
+        if self.key_source.is_some() || self.path.exists() {
+            return Err("An API key vault already exists".to_string());
+        }
+
+        let mut key = [0u8; 32];
+        let key_source = match passphrase {
+            Some(passphrase) => {
+                if passphrase.is_empty() {
+                    return Err("Passphrase cannot be empty".to_string());
+                }
+                let mut salt = vec![0u8; 16];
+                OsRng.fill_bytes(&mut salt);
+                key = derive_key(passphrase, &salt)?;
+                self.salt = Some(salt);
+                KeySource::Passphrase
+            }
+            None => {
+                OsRng.fill_bytes(&mut key);
+                write_keyring_key(&key)?;
+                KeySource::Keyring
+            }
+        };
+
+        self.key_source = Some(key_source);
+        self.key = Some(key);
+        self.secrets.clear();
+        self.persist()
+    }
+
+    pub fn unlock(&mut self, passphrase: &str) -> Result<(), String> {
+        let file = self.read_file()?;
+        match file.key_source {
+            KeySource::Passphrase => {
+                let salt = self.salt.as_deref().ok_or("Vault file is missing its salt")?;
+                let key = derive_key(passphrase, salt)?;
+                // Only a failed authentication means a wrong key; damaged files report themselves
+                self.decrypt_with(key, &file).map_err(|e| {
+                    if e == DECRYPT_FAILED { "Incorrect passphrase".to_string() } else { e }
+                })
+            }
+            KeySource::Keyring => {
+                let key = read_keyring_key()?;
+                self.decrypt_with(key, &file)
+            }
+        }
+    }
+
+    pub fn lock(&mut self) {
+        self.key = None;
+        self.secrets.clear();
+    }
+
+    pub fn get(&self, key_ref: &str) -> Result<Option<String>, String> {
+        self.ensure_unlocked()?;
+        Ok(self.secrets.get(key_ref).cloned())
+    }
+
+    pub fn set(&mut self, key_ref: &str, secret: String) -> Result<(), String> {
+        self.ensure_unlocked()?;
+        self.secrets.insert(key_ref.to_string(), secret);
+        self.persist()
+    }
+
+    /// Drop every secret whose reference is not in `keep`
+    pub fn retain(&mut self, keep: &[String]) -> Result<(), String> {
+        self.ensure_unlocked()?;
+        let original_len = self.secrets.len();
+        self.secrets.retain(|key_ref, _| keep.contains(key_ref));
+        if self.secrets.len() < original_len {
+            self.persist()?;
+        }
+        Ok(())
+    }
+
+    fn ensure_unlocked(&self) -> Result<(), String> {
+        if self.key.is_none() {
+            return Err("The API key vault is locked. Unlock it in LLM Settings.".to_string());
+        }
+        Ok(())
+    }
+
+    fn read_file(&self) -> Result<VaultFile, String> {
+        let content = fs::read_to_string(&self.path)
+            .map_err(|e| format!("Failed to read vault file: {}", e))?;
+        serde_json::from_str(&content)
+            .map_err(|e| format!("Failed to parse vault file: {}", e))
+    }
+
+    fn decrypt_with(&mut self, key: [u8; 32], file: &VaultFile) -> Result<(), String> {
+        let nonce = BASE64.decode(&file.nonce)
+            .map_err(|e| format!("Invalid vault nonce: {}", e))?;
+        if nonce.len() != NONCE_LEN {
+            return Err("Invalid vault nonce length".to_string());
+        }
+        let ciphertext = BASE64.decode(&file.ciphertext)
+            .map_err(|e| format!("Invalid vault ciphertext: {}", e))?;
+
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_ref())
+            .map_err(|_| DECRYPT_FAILED.to_string())?;
+
+        self.secrets = serde_json::from_slice(&plaintext)
+            .map_err(|e| format!("Failed to parse vault contents: {}", e))?;
+        self.key = Some(key);
+        Ok(())
+    }
+
+    fn persist(&self) -> Result<(), String> {
+        let key = self.key.ok_or("The API key vault is locked")?;
+        let key_source = self.key_source.ok_or("No API key vault has been created")?;
+
+        let plaintext = serde_json::to_vec(&self.secrets)
+            .map_err(|e| format!("Failed to serialize vault contents: {}", e))?;
+
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_ref())
+            .map_err(|_| "Failed to encrypt vault".to_string())?;
+
+        let file = VaultFile {
+            version: VAULT_VERSION,
+            key_source,
+            salt: self.salt.as_deref().map(|s| BASE64.encode(s)),
+            nonce: BASE64.encode(nonce),
+            ciphertext: BASE64.encode(ciphertext),
+        };
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create vault directory: {}", e))?;
+        }
+
+        let json = serde_json::to_string_pretty(&file)
+            .map_err(|e| format!("Failed to serialize vault file: {}", e))?;
+        fs::write(&self.path, json)
+            .map_err(|e| format!("Failed to write vault file: {}", e))
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<[u8; 32], String> {
+    let mut key = [0u8; 32];
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| format!("Failed to derive vault key: {}", e))?;
+    Ok(key)
+}
+
+fn keyring_entry() -> Result<keyring::Entry, String> {
+    keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER)
+        .map_err(|e| format!("OS keyring unavailable: {}", e))
+}
+
+fn keyring_available() -> bool {
+    matches!(
+        keyring_entry().map(|entry| entry.get_password()),
+        Ok(Ok(_)) | Ok(Err(keyring::Error::NoEntry))
+    )
+}
+
+fn read_keyring_key() -> Result<[u8; 32], String> {
+    let encoded = keyring_entry()?
+        .get_password()
+        .map_err(|e| format!("Failed to read vault key from OS keyring: {}", e))?;
+    BASE64.decode(encoded)
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .ok_or("Vault key in OS keyring is malformed".to_string())
+}
+
+fn write_keyring_key(key: &[u8; 32]) -> Result<(), String> {
+    keyring_entry()?
+        .set_password(&BASE64.encode(key))
+        .map_err(|e| format!("Failed to store vault key in OS keyring: {}", e))
+}
+
+/// `PROMPTMUX_API_KEY_<PROFILE>` with the profile name upper-cased and non-alphanumerics replaced by `_`
+pub fn profile_env_var(profile_name: &str) -> String {
+    let suffix: String = profile_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    format!("PROMPTMUX_API_KEY_{}", suffix)
+}
+
+/// Fill in `profile.api_key` for a request. The profile's environment variable wins (for CI), then
+/// the vault, then a plaintext key that has not been migrated yet. There is no unscoped variable,
+/// so one provider's key is never sent to another profile's endpoint.
+pub fn resolve_api_key(vault: &Vault, profile: &mut LlmProfile) -> Result<(), String> {
+    resolve_api_key_with(vault, profile, |name| std::env::var(name).ok())
+}
+
+/// `resolve_api_key` reading environment variables through `env`
+pub fn resolve_api_key_with(
+    vault: &Vault,
+    profile: &mut LlmProfile,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<(), String> {
+    if let Some(value) = env(&profile_env_var(&profile.name)) {
+        if !value.is_empty() {
+            profile.api_key = value;
+            return Ok(());
+        }
+    }
+
+    if let Some(key_ref) = &profile.api_key_ref {
+        if let Some(secret) = vault.get(key_ref)? {
+            profile.api_key = secret;
+        }
+    }
+
+    Ok(())
+}
+
+/// Move every plaintext `apiKey` in `settings` into the vault, leaving only `apiKeyRef` behind,
+/// and drop vault entries no profile refers to anymore. Returns whether `settings` changed.
+pub fn secure_api_keys(vault: &mut Vault, settings: &mut LlmSettings) -> Result<bool, String> {
+    let has_plaintext = settings.profiles.iter().any(|p| !p.api_key.is_empty());
+    if !has_plaintext {
+        if vault.is_unlocked() {
+            let refs: Vec<String> = settings.profiles.iter().filter_map(|p| p.api_key_ref.clone()).collect();
+            vault.retain(&refs)?;
+        }
+        return Ok(false);
+    }
+
+    if !vault.is_unlocked() {
+        if let Some(e) = &vault.error {
+            return Err(format!("The API key vault cannot be read ({}). Repair or remove it before saving API keys.", e));
+        }
+        if vault.status().exists {
+            return Err("The API key vault is locked. Unlock it before saving API keys.".to_string());
+        }
+        if !keyring_available() {
+            return Err("Create an API key vault with a passphrase before saving API keys.".to_string());
+        }
+        vault.create(None)?;
+    }
+
+    for profile in &mut settings.profiles {
+        if profile.api_key.is_empty() {
+            continue;
+        }
+        let key_ref = profile
+            .api_key_ref
+            .get_or_insert_with(|| format!("llm-key-{}", uuid::Uuid::new_v4()))
+            .clone();
+        vault.set(&key_ref, std::mem::take(&mut profile.api_key))?;
+    }
+
+    let refs: Vec<String> = settings.profiles.iter().filter_map(|p| p.api_key_ref.clone()).collect();
+    vault.retain(&refs)?;
+
+    Ok(true)
+}
@@ -0,0 +1,203 @@
+//! Class diagram model and code skeleton generation
+
+use promptmux::codegen::{generate, ClassModel, Language, RelationKind, Visibility};
+
+const SHOP: &str = "classDiagram
+    class Account {
+        <<abstract>>
+        +String email
+        -int failedLogins
+        +login(password: String) bool
+        +describe()* String
+    }
+    class Auditable {
+        <<interface>>
+        +auditTrail() List~String~
+    }
+    class Customer {
+        +Map~String, int~ loyalty
+        +register(String email)$ Customer
+    }
+    class Page~T~
+    Account <|-- Customer
+    Customer ..|> Auditable
+    Customer \"1\" --> \"*\" Order : places
+    Order *-- \"0..1\" Invoice
+    Order : +decimal total
+";
+
+fn file<'a>(files: &'a [promptmux::codegen::GeneratedFile], path: &str) -> &'a str {
+    &files.iter().find(|f| f.path == path).unwrap_or_else(|| panic!("no {}", path)).content
+}
+
+#[test]
+fn diagram_is_read_into_classes_and_relations() {
+    let model = ClassModel::from_mermaid(SHOP).unwrap();
+
+    let names: Vec<&str> = model.classes.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, ["Account", "Auditable", "Customer", "Page", "Order", "Invoice"]);
+    assert_eq!(model.class("Page").unwrap().generics, ["T"]);
+
+    let account = model.class("Account").unwrap();
+    assert_eq!(account.annotation.as_deref(), Some("abstract"));
+    assert_eq!(account.fields[1].visibility, Some(Visibility::Private));
+    assert_eq!(account.fields[1].data_type.as_deref(), Some("int"));
+    let login = &account.methods[0];
+    assert_eq!((login.parameters[0].name.as_str(), login.parameters[0].data_type.as_deref()), ("password", Some("String")));
+    assert_eq!(login.return_type.as_deref(), Some("bool"));
+    assert!(account.methods[1].is_abstract);
+    assert!(model.class("Customer").unwrap().methods[0].is_static);
+    assert_eq!(model.class("Order").unwrap().fields[0].name, "total");
+
+    let relations: Vec<(&str, &str, RelationKind, Option<&str>)> = model
+        .relations
+        .iter()
+        .map(|r| (r.source.as_str(), r.target.as_str(), r.kind, r.target_multiplicity.as_deref()))
+        .collect();
+    assert_eq!(
+        relations,
+        [
+            ("Customer", "Account", RelationKind::Inheritance, None),
+            ("Customer", "Auditable", RelationKind::Realization, None),
+            ("Customer", "Order", RelationKind::Association, Some("*")),
+            ("Order", "Invoice", RelationKind::Composition, Some("0..1")),
+        ]
+    );
+    assert_eq!(model.relations[2].label.as_deref(), Some("places"));
+}
+
+#[test]
+fn rust_skeletons_use_traits_for_abstract_types() {
+    let files = generate(&ClassModel::from_mermaid(SHOP).unwrap(), Language::Rust);
+
+    assert_eq!(
+        file(&files, "customer.rs"),
+        "//! Generated from the project class diagram
+
+use super::*;
+use std::collections::HashMap;
+
+pub struct Customer {
+    pub loyalty: HashMap<String, i32>,
+    pub orders: Vec<Order>,
+}
+
+impl Customer {
+    pub fn register(email: String) -> Customer {
+        todo!()
+    }
+}
+
+impl Account for Customer {
+    fn email(&self) -> String {
+        todo!()
+    }
+
+    fn failed_logins(&self) -> i32 {
+        todo!()
+    }
+
+    fn login(&self, password: String) -> bool {
+        todo!()
+    }
+
+    fn describe(&self) -> String {
+        todo!()
+    }
+}
+
+impl Auditable for Customer {
+    fn audit_trail(&self) -> Vec<String> {
+        todo!()
+    }
+}
+"
+    );
+    assert!(file(&files, "order.rs").contains("    pub total: f64,\n    pub invoice: Option<Invoice>,\n"));
+    assert!(file(&files, "page.rs").contains("pub struct Page<T> {\n    _t: std::marker::PhantomData<T>,\n}"));
+    assert!(file(&files, "mod.rs").contains("mod account;\nmod auditable;\n"));
+    assert!(file(&files, "mod.rs").contains("pub use customer::Customer;\n"));
+}
+
+#[test]
+fn typescript_classes_extend_and_implement_their_parents() {
+    let files = generate(&ClassModel::from_mermaid(SHOP).unwrap(), Language::TypeScript);
+
+    let customer = file(&files, "Customer.ts");
+    assert!(customer.starts_with(
+        "// Generated from the project class diagram
+
+import { Account } from './Account';
+import type { Auditable } from './Auditable';
+import type { Order } from './Order';
+
+export class Customer extends Account implements Auditable {
+  public loyalty!: Map<string, number>;
+  orders!: Order[];
+"
+    ), "{}", customer);
+    // Abstract and interface methods are stubbed, inherited concrete ones are not
+    assert!(customer.contains("  public describe(): string {\n    throw new Error('Not implemented');\n  }"));
+    assert!(customer.contains("  public auditTrail(): string[] {"));
+    assert!(!customer.contains("login("));
+
+    let account = file(&files, "Account.ts");
+    assert!(account.contains("export abstract class Account {\n  public email!: string;\n  private failedLogins!: number;\n"));
+    assert!(account.contains("  public abstract describe(): string;\n"));
+    assert_eq!(
+        file(&files, "Auditable.ts"),
+        "// Generated from the project class diagram\n\nexport interface Auditable {\n  auditTrail(): string[];\n}\n"
+    );
+    assert!(file(&files, "index.ts").contains("export * from './Invoice';"));
+}
+
+#[test]
+fn python_skeletons_are_dataclasses() {
+    let files = generate(&ClassModel::from_mermaid(SHOP).unwrap(), Language::Python);
+
+    assert_eq!(
+        file(&files, "order.py"),
+        "\"\"\"Generated from the project class diagram\"\"\"
+
+from __future__ import annotations
+
+from dataclasses import dataclass
+from typing import TYPE_CHECKING
+
+if TYPE_CHECKING:
+    from .invoice import Invoice
+
+
+@dataclass
+class Order:
+    total: float
+    invoice: Invoice | None
+"
+    );
+
+    let account = file(&files, "account.py");
+    assert!(account.contains("class Account(ABC):\n    email: str\n    _failed_logins: int\n"), "{}", account);
+    assert!(account.contains("    @abstractmethod\n    def describe(self) -> str:\n"));
+    let customer = file(&files, "customer.py");
+    assert!(customer.contains("from .account import Account\nfrom .auditable import Auditable\n"), "{}", customer);
+    assert!(customer.contains("class Customer(Account, Auditable):\n"));
+    assert!(customer.contains("    @staticmethod\n    def register(email: str) -> Customer:\n"));
+    assert!(file(&files, "page.py").contains("T = TypeVar(\"T\")\n\n\n@dataclass\nclass Page(Generic[T]):\n    pass\n"));
+}
+
+#[test]
+fn enumerations_list_their_values() {
+    let model = ClassModel::from_mermaid("classDiagram\n    class Status {\n        <<enumeration>>\n        ACTIVE\n        CLOSED\n    }").unwrap();
+
+    assert!(file(&generate(&model, Language::Rust), "status.rs").contains("pub enum Status {\n    ACTIVE,\n    CLOSED,\n}"));
+    assert!(file(&generate(&model, Language::TypeScript), "Status.ts").contains("  ACTIVE = 'ACTIVE',\n"));
+    assert!(file(&generate(&model, Language::Python), "status.py").contains("class Status(Enum):\n    ACTIVE = \"ACTIVE\"\n"));
+}
+
+#[test]
+fn invalid_diagrams_and_languages_are_rejected() {
+    let error = ClassModel::from_mermaid("classDiagram\n    class A {\n        +id").unwrap_err();
+    assert!(error.contains("Line 2"), "{}", error);
+    assert_eq!(Language::parse("TS").unwrap(), Language::TypeScript);
+    assert!(Language::parse("cobol").is_err());
+}
@@ -0,0 +1,246 @@
+//! Helpers shared by the integration tests: temporary directories and a minimal HTTP server that
+//! replays recorded LLM responses
+
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// A fresh directory per test, removed when dropped
+pub struct TempDir(pub PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!("promptmux-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Read a transcript from `tests/fixtures`
+pub fn fixture(name: &str) -> Vec<u8> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name);
+    std::fs::read(&path).unwrap_or_else(|e| panic!("Failed to read fixture {}: {}", path.display(), e))
+}
+
+/// What the mock server answers to every request
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub status: u16,
+    pub content_type: String,
+    pub body: Vec<u8>,
+    /// Write the body in pieces of this many bytes, flushing and pausing between them
+    pub chunk_size: Option<usize>,
+    /// Drop the connection after this many body bytes, without ending the chunked encoding
+    pub disconnect_after: Option<usize>,
+}
+
+impl MockResponse {
+    pub fn sse(body: Vec<u8>) -> Self {
+        MockResponse {
+            status: 200,
+            content_type: "text/event-stream".to_string(),
+            body,
+            chunk_size: None,
+            disconnect_after: None,
+        }
+    }
+
+    /// Newline-delimited JSON, as Ollama streams it
+    pub fn ndjson(body: Vec<u8>) -> Self {
+        MockResponse {
+            status: 200,
+            content_type: "application/x-ndjson".to_string(),
+            body,
+            chunk_size: None,
+            disconnect_after: None,
+        }
+    }
+
+    pub fn json(status: u16, body: &str) -> Self {
+        MockResponse {
+            status,
+            content_type: "application/json".to_string(),
+            body: body.as_bytes().to_vec(),
+            chunk_size: None,
+            disconnect_after: None,
+        }
+    }
+
+    pub fn fragmented(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = Some(chunk_size);
+        self
+    }
+
+    pub fn disconnect_after(mut self, bytes: usize) -> Self {
+        self.disconnect_after = Some(bytes);
+        self
+    }
+}
+
+/// A request as received by the mock server
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    /// Header names are lower-cased
+    pub headers: Vec<(String, String)>,
+    pub body: serde_json::Value,
+}
+
+impl RecordedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub struct MockServer {
+    pub base_url: String,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl MockServer {
+    /// Start a server on a random local port that answers every request with `response`
+    pub async fn start(response: MockResponse) -> Self {
+        Self::start_routes(vec![("/", response)]).await
+    }
+
+    /// Start a server that answers each request with the response of the first route whose path
+    /// prefix matches, or a 404
+    pub async fn start_routes(routes: Vec<(&str, MockResponse)>) -> Self {
+        let routes: Vec<(String, MockResponse)> = routes.into_iter().map(|(path, r)| (path.to_string(), r)).collect();
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("Failed to bind mock server");
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let routes = routes.clone();
+                let recorded = recorded.clone();
+                tokio::spawn(async move {
+                    handle_connection(&mut socket, &routes, &recorded).await;
+                });
+            }
+        });
+
+        MockServer { base_url, requests }
+    }
+
+    /// Start a server that accepts connections but never answers, to provoke timeouts
+    pub async fn start_silent() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("Failed to bind mock server");
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut open = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                open.push(socket);
+            }
+        });
+        MockServer { base_url, requests: Arc::new(Mutex::new(Vec::new())) }
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Record one request and answer it; connections that close early are simply dropped
+async fn handle_connection(
+    socket: &mut TcpStream,
+    routes: &[(String, MockResponse)],
+    recorded: &Mutex<Vec<RecordedRequest>>,
+) -> Option<()> {
+    let mut buffer = Vec::new();
+    let header_end = loop {
+        let mut chunk = [0u8; 4096];
+        let read = socket.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+        if let Some(position) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break position + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..header_end]).into_owned();
+    let mut head_lines = head.split("\r\n");
+    let mut request_line = head_lines.next()?.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let path = request_line.next()?.to_string();
+    let headers: Vec<(String, String)> = head_lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect();
+
+    let content_length = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+        .unwrap_or(0);
+    while buffer.len() < header_end + content_length {
+        let mut chunk = [0u8; 4096];
+        let read = socket.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            break;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+    let body = serde_json::from_slice(&buffer[header_end..]).unwrap_or(serde_json::Value::Null);
+
+    let not_found = MockResponse::json(404, r#"{"error":"not found"}"#);
+    let response = routes
+        .iter()
+        .find(|(prefix, _)| path.starts_with(prefix.as_str()))
+        .map_or(&not_found, |(_, response)| response);
+
+    recorded.lock().unwrap().push(RecordedRequest { method, path, headers, body });
+    write_response(socket, response).await.ok()?;
+    Some(())
+}
+
+async fn write_response(socket: &mut TcpStream, response: &MockResponse) -> std::io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Type: {}\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n",
+        response.status, response.content_type
+    );
+    socket.write_all(head.as_bytes()).await?;
+
+    let body = match response.disconnect_after {
+        Some(limit) => &response.body[..limit.min(response.body.len())],
+        None => &response.body[..],
+    };
+    let chunk_size = response.chunk_size.unwrap_or(body.len()).max(1);
+
+    for piece in body.chunks(chunk_size) {
+        socket.write_all(format!("{:x}\r\n", piece.len()).as_bytes()).await?;
+        socket.write_all(piece).await?;
+        socket.write_all(b"\r\n").await?;
+        socket.flush().await?;
+        if response.chunk_size.is_some() {
+            // Give the client a chance to read each piece on its own
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    if response.disconnect_after.is_some() {
+        // Closing without the terminating chunk is what a dropped upstream connection looks like
+        return socket.shutdown().await;
+    }
+
+    socket.write_all(b"0\r\n\r\n").await?;
+    socket.flush().await
+}
@@ -0,0 +1,151 @@
+//! Connection test diagnoses against a mock server: rejected keys, wrong base URLs, unknown models,
+//! protocol mismatches and transport failures
+
+mod common;
+
+use common::{fixture, MockResponse, MockServer};
+use promptmux::diagnostics::{guess_protocol, test_connection, ConnectionDiagnosis, DiagnosisKind};
+use promptmux::llm::Protocol;
+use promptmux::settings::LlmProfile;
+use std::time::Duration;
+
+fn profile(base_url: &str, protocol: Protocol) -> LlmProfile {
+    LlmProfile {
+        provider: protocol.as_str().to_string(),
+        protocol: Some(protocol.as_str().to_string()),
+        api_key: "test-key".to_string(),
+        base_url: base_url.to_string(),
+        model: Some("test-model".to_string()),
+        ..Default::default()
+    }
+}
+
+async fn diagnose(response: MockResponse, protocol: Protocol) -> ConnectionDiagnosis {
+    let server = MockServer::start(response).await;
+    test_connection(&reqwest::Client::new(), &profile(&server.base_url, protocol)).await
+}
+
+#[tokio::test]
+async fn working_stream_reports_the_first_answer() {
+    let diagnosis = diagnose(MockResponse::sse(fixture("openai_stream.sse")), Protocol::OpenAi).await;
+
+    assert_eq!(diagnosis.kind, DiagnosisKind::Ok);
+    assert_eq!(diagnosis.status, Some(200));
+    assert_eq!(diagnosis.sample.as_deref(), Some("erDiagram"));
+    assert!(diagnosis.latency_ms.is_some());
+    assert!(diagnosis.first_token_ms.is_some());
+}
+
+#[tokio::test]
+async fn rejected_keys_are_unauthorized() {
+    let body = r#"{"error":{"message":"Incorrect API key provided","type":"invalid_request_error"}}"#;
+    for status in [401, 403] {
+        let diagnosis = diagnose(MockResponse::json(status, body), Protocol::OpenAi).await;
+
+        assert_eq!(diagnosis.kind, DiagnosisKind::Unauthorized);
+        assert_eq!(diagnosis.status, Some(status));
+        assert!(diagnosis.message.contains("Incorrect API key provided"), "{}", diagnosis.message);
+    }
+}
+
+#[tokio::test]
+async fn missing_endpoint_points_at_the_base_url() {
+    let mut response = MockResponse::json(404, "404 page not found");
+    response.content_type = "text/plain".to_string();
+    let diagnosis = diagnose(response, Protocol::OpenAi).await;
+
+    assert_eq!(diagnosis.kind, DiagnosisKind::WrongProtocol);
+    assert!(diagnosis.message.contains("no openai endpoint at this base URL"), "{}", diagnosis.message);
+    assert!(diagnosis.message.contains("404 page not found"));
+}
+
+#[tokio::test]
+async fn unknown_models_are_recognized_in_each_providers_wording() {
+    let cases = [
+        (
+            Protocol::OpenAi,
+            r#"{"error":{"message":"The model `test-model` does not exist","code":"model_not_found"}}"#,
+        ),
+        (Protocol::Anthropic, r#"{"type":"error","error":{"type":"not_found_error","message":"model: test-model"}}"#),
+        (Protocol::Ollama, r#"{"error":"model \"test-model\" not found, try pulling it first"}"#),
+    ];
+
+    for (protocol, body) in cases {
+        let diagnosis = diagnose(MockResponse::json(404, body), protocol).await;
+        assert_eq!(diagnosis.kind, DiagnosisKind::UnknownModel, "{:?}: {}", protocol, diagnosis.message);
+        assert!(diagnosis.message.contains("'test-model'"));
+    }
+}
+
+#[tokio::test]
+async fn other_http_errors_keep_the_providers_message() {
+    // Gemini wraps errors of streaming calls in an array
+    let body = r#"[{"error":{"code":400,"message":"API key not valid","status":"INVALID_ARGUMENT"}}]"#;
+    let diagnosis = diagnose(MockResponse::json(400, body), Protocol::Gemini).await;
+
+    assert_eq!(diagnosis.kind, DiagnosisKind::Http);
+    assert_eq!(diagnosis.message, "HTTP 400: API key not valid");
+    assert_eq!(diagnosis.detected_protocol.as_deref(), Some("gemini"));
+}
+
+#[tokio::test]
+async fn rejected_stream_options_point_at_the_usage_setting() {
+    let body = r#"{"error":{"message":"Unrecognized request argument supplied: stream_options","type":"invalid_request_error"}}"#;
+    let diagnosis = diagnose(MockResponse::json(400, body), Protocol::OpenAi).await;
+
+    assert_eq!(diagnosis.kind, DiagnosisKind::InvalidSettings);
+    assert!(diagnosis.message.contains("Report token usage"), "{}", diagnosis.message);
+}
+
+#[tokio::test]
+async fn a_stream_in_another_protocol_is_a_protocol_mismatch() {
+    let diagnosis = diagnose(MockResponse::sse(fixture("openai_stream.sse")), Protocol::Anthropic).await;
+
+    assert_eq!(diagnosis.kind, DiagnosisKind::WrongProtocol);
+    assert_eq!(diagnosis.detected_protocol.as_deref(), Some("openai"));
+    assert!(diagnosis.message.contains("speaks the openai protocol"), "{}", diagnosis.message);
+}
+
+#[tokio::test]
+async fn unresolvable_hosts_are_dns_failures() {
+    let diagnosis = test_connection(
+        &reqwest::Client::new(),
+        &profile("http://promptmux-test.invalid", Protocol::OpenAi),
+    )
+    .await;
+
+    assert_eq!(diagnosis.kind, DiagnosisKind::Dns, "{}", diagnosis.message);
+}
+
+#[tokio::test]
+async fn refused_connections_are_connect_failures() {
+    // A port that was just free is very unlikely to be taken again right away
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+
+    let diagnosis = test_connection(&reqwest::Client::new(), &profile(&base_url, Protocol::OpenAi)).await;
+
+    assert_eq!(diagnosis.kind, DiagnosisKind::Connect, "{}", diagnosis.message);
+    assert!(diagnosis.message.starts_with("Could not connect"));
+}
+
+#[tokio::test]
+async fn silent_servers_time_out() {
+    let server = MockServer::start_silent().await;
+    let client = reqwest::Client::builder().read_timeout(Duration::from_millis(200)).build().unwrap();
+
+    let diagnosis = test_connection(&client, &profile(&server.base_url, Protocol::OpenAi)).await;
+
+    assert_eq!(diagnosis.kind, DiagnosisKind::Timeout, "{}", diagnosis.message);
+}
+
+#[test]
+fn protocols_are_guessed_from_the_response_shape() {
+    assert_eq!(guess_protocol(&String::from_utf8(fixture("openai_stream.sse")).unwrap()), Some(Protocol::OpenAi));
+    assert_eq!(guess_protocol(&String::from_utf8(fixture("anthropic_stream.sse")).unwrap()), Some(Protocol::Anthropic));
+    assert_eq!(guess_protocol(&String::from_utf8(fixture("gemini_stream.sse")).unwrap()), Some(Protocol::Gemini));
+    assert_eq!(guess_protocol(&String::from_utf8(fixture("ollama_chat.ndjson")).unwrap()), Some(Protocol::Ollama));
+    assert_eq!(guess_protocol(r#"{"error":"model not found"}"#), Some(Protocol::Ollama));
+    assert_eq!(guess_protocol("<html>Not Found</html>"), None);
+}
@@ -0,0 +1,228 @@
+//! Structural diffs of diagram edits and partial acceptance
+
+use promptmux::diagram_diff::{apply, diff, ChangeKind, DiagramChange};
+
+const SHOP: &str = "erDiagram
+    CUSTOMER {
+        int id PK
+        string email
+        string name
+    }
+    ORDER {
+        int id PK
+        int customer_id FK
+    }
+    CUSTOMER ||--o{ ORDER : places
+";
+
+const SHOP_EDITED: &str = "erDiagram
+    CLIENT {
+        int id PK
+        string email_address
+        string name
+        date joined
+    }
+    ORDER {
+        int id PK
+        int client_id FK
+    }
+    PRODUCT {
+        int id PK
+    }
+    CLIENT ||--o{ ORDER : submits
+    ORDER }o--o{ PRODUCT : contains
+";
+
+fn summary(changes: &[DiagramChange]) -> Vec<String> {
+    changes
+        .iter()
+        .map(|c| {
+            format!(
+                "{:?} {} {}: {} => {}",
+                c.kind,
+                c.element,
+                c.subject,
+                c.before.as_deref().unwrap_or("-"),
+                c.after.as_deref().unwrap_or("-")
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn er_edit_reports_renamed_entities_attributes_edges_and_labels() {
+    let changes = diff("er", SHOP, SHOP_EDITED).unwrap();
+
+    assert_eq!(
+        summary(&changes),
+        [
+            "Renamed entity CUSTOMER: CUSTOMER => CLIENT",
+            "Added entity PRODUCT: - => -",
+            "Renamed attribute CUSTOMER: string email => string email_address",
+            "Added attribute CUSTOMER: - => date joined",
+            "Renamed attribute ORDER: int customer_id FK => int client_id FK",
+            "Changed label CUSTOMER -> ORDER: places => submits",
+            "Added edge ORDER -> PRODUCT: - => ORDER }o--o{ PRODUCT : contains",
+        ]
+    );
+}
+
+#[test]
+fn accepting_every_change_gives_the_proposal() {
+    let changes = diff("er", SHOP, SHOP_EDITED).unwrap();
+    let merged = apply("er", SHOP, SHOP_EDITED, &changes).unwrap();
+
+    assert!(diff("er", &merged, SHOP_EDITED).unwrap().is_empty());
+}
+
+#[test]
+fn declined_rename_keeps_the_current_name_in_accepted_edges() {
+    let changes = diff("er", SHOP, SHOP_EDITED).unwrap();
+    let accepted: Vec<DiagramChange> = changes
+        .into_iter()
+        .filter(|c| c.element == "edge" || (c.element == "entity" && c.kind == ChangeKind::Added))
+        .collect();
+    let merged = apply("er", SHOP, SHOP_EDITED, &accepted).unwrap();
+
+    assert!(merged.contains("    CUSTOMER {\n        int id PK\n        string email\n"));
+    assert!(merged.contains("    PRODUCT {"));
+    assert!(merged.contains("CUSTOMER ||--o{ ORDER : places"));
+    assert!(merged.contains("ORDER }o--o{ PRODUCT : contains"));
+    assert!(!merged.contains("CLIENT"));
+}
+
+#[test]
+fn removing_an_entity_drops_its_relationships() {
+    let proposed = "erDiagram
+    CUSTOMER {
+        int id PK
+        string email
+        string name
+    }
+";
+    let changes = diff("er", SHOP, proposed).unwrap();
+    let removal: Vec<DiagramChange> = changes.into_iter().filter(|c| c.element == "entity").collect();
+    assert_eq!(summary(&removal), ["Removed entity ORDER: - => -"]);
+
+    let merged = apply("er", SHOP, proposed, &removal).unwrap();
+    assert!(!merged.contains("ORDER"));
+}
+
+#[test]
+fn class_edit_reports_members_annotations_and_relations() {
+    let current = "classDiagram
+    class Account {
+        +String email
+        +login(String password) bool
+    }
+    class Customer
+    Customer --|> Account
+";
+    let proposed = "classDiagram
+    class Account {
+        <<abstract>>
+        +String email
+        +login(String password, bool remember) bool
+        +logout()
+    }
+    class Customer
+    Customer --|> Account
+    Customer --> \"*\" Order : places
+";
+    let changes = diff("uml", current, proposed).unwrap();
+
+    assert_eq!(
+        summary(&changes),
+        [
+            "Added entity Order: - => -",
+            "Changed attribute Account: +login(String password) bool => +login(String password, bool remember) bool",
+            "Added attribute Account: - => <<abstract>>",
+            "Added attribute Account: - => +logout()",
+            "Added edge Customer -> Order: - => Customer --> \"*\" Order : places",
+        ]
+    );
+
+    let accepted: Vec<DiagramChange> = changes.into_iter().filter(|c| c.element == "attribute").collect();
+    let merged = apply("uml", current, proposed, &accepted).unwrap();
+    assert!(merged.contains("    class Account {\n        <<abstract>>\n        +String email\n"));
+    assert!(merged.contains("+logout()"));
+    assert!(!merged.contains("Order"));
+}
+
+#[test]
+fn flowchart_edit_reports_node_and_edge_labels() {
+    let current = "flowchart LR
+    A[Start] --> B{Valid?}
+    B -->|yes| C[Save]
+    B -- no --> D[Reject]
+    style A fill:#fff
+";
+    let proposed = "flowchart LR
+    A[Start] --> B{Input valid?}
+    B -->|yes| S[Save]
+    B -->|no| D[Show errors]
+    S --> E((Done))
+    style A fill:#fff
+";
+    let changes = diff("flowchart", current, proposed).unwrap();
+
+    assert_eq!(
+        summary(&changes),
+        [
+            "Renamed entity C: C => S",
+            "Added entity E: - => ((Done))",
+            "Changed label B: {Valid?} => {Input valid?}",
+            "Changed label D: [Reject] => [Show errors]",
+            "Added edge S -> E: - => S --> E",
+        ]
+    );
+
+    let accepted: Vec<DiagramChange> = changes.into_iter().filter(|c| c.element == "label").collect();
+    let merged = apply("flowchart", current, proposed, &accepted).unwrap();
+    assert_eq!(
+        merged,
+        "flowchart LR
+    A[Start]
+    B{Input valid?}
+    C[Save]
+    D[Show errors]
+    A --> B
+    B -->|yes| C
+    B -->|no| D
+    style A fill:#fff
+"
+    );
+}
+
+#[test]
+fn flowchart_subgraphs_survive_a_partial_apply() {
+    let current = "flowchart TD
+    subgraph checkout [Checkout]
+        A[Cart] --> B[Pay]
+    end
+";
+    let proposed = "flowchart TD
+    subgraph checkout [Checkout]
+        A[Cart] --> B[Pay]
+        B --> C[Receipt]
+    end
+";
+    let changes = diff("flowchart", current, proposed).unwrap();
+    let merged = apply("flowchart", current, proposed, &changes).unwrap();
+
+    assert!(merged.contains("    subgraph checkout [Checkout]\n        A[Cart]\n        B[Pay]\n        C[Receipt]\n    end\n"));
+    assert!(merged.contains("    B --> C\n"));
+}
+
+#[test]
+fn stale_changes_and_other_kinds_are_rejected() {
+    let stale = DiagramChange {
+        kind: ChangeKind::Added,
+        element: "entity".to_string(),
+        subject: "INVOICE".to_string(),
+        before: None,
+        after: None,
+    };
+    assert!(apply("er", SHOP, SHOP_EDITED, &[stale]).is_err());
+    assert!(diff("journey", "journey", "journey").is_err());
+}
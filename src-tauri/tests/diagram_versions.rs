@@ -0,0 +1,57 @@
+//! Diagram version history: one immutable version per save, trimming and reverts
+
+use promptmux::models::Diagram;
+
+fn flowchart(step: usize) -> String {
+    format!("flowchart LR\n    A --> B{}", step)
+}
+
+#[test]
+fn every_distinct_save_adds_a_version_and_keeps_earlier_ones() {
+    let mut diagram = Diagram::new("flowchart".to_string(), "Checkout".to_string(), flowchart(0));
+    let first = diagram.versions[0].clone();
+
+    diagram.save(flowchart(1), "save");
+    diagram.save(flowchart(1), "save");
+    diagram.save(flowchart(2), "save");
+
+    let codes: Vec<&str> = diagram.versions.iter().map(|v| v.code.as_str()).collect();
+    assert_eq!(codes, [flowchart(0), flowchart(1), flowchart(2)]);
+    assert_eq!(diagram.code, flowchart(2));
+    // A version id keeps pointing at the code it was recorded with
+    assert_eq!(diagram.get_version(&first.id).unwrap().code, first.code);
+}
+
+#[test]
+fn oldest_unlabeled_versions_are_trimmed_first() {
+    let mut diagram = Diagram::new("flowchart".to_string(), "Checkout".to_string(), flowchart(0));
+    diagram.versions[0].label = Some("baseline".to_string());
+    diagram.save(flowchart(1), "save");
+    let second = diagram.versions[1].id.clone();
+
+    for step in 2..=250 {
+        diagram.save(flowchart(step), "save");
+    }
+
+    assert_eq!(diagram.versions.len(), 200);
+    assert_eq!(diagram.versions[0].label.as_deref(), Some("baseline"));
+    assert!(diagram.get_version(&second).is_none());
+    assert_eq!(diagram.versions.last().unwrap().code, flowchart(250));
+}
+
+#[test]
+fn reverting_adds_the_old_code_as_a_new_version() {
+    let mut diagram = Diagram::new("flowchart".to_string(), "Checkout".to_string(), flowchart(0));
+    diagram.save(flowchart(1), "save");
+    let original = diagram.versions[0].clone();
+
+    let code = diagram.get_version(&original.id).unwrap().code.clone();
+    diagram.save(code, "revert");
+
+    assert_eq!(diagram.code, flowchart(0));
+    assert_eq!(diagram.versions.len(), 3);
+    assert_eq!(diagram.versions[2].source, "revert");
+    assert_ne!(diagram.versions[2].id, original.id);
+    // The reverted-away version is still there to undo the revert
+    assert_eq!(diagram.versions[1].code, flowchart(1));
+}
@@ -0,0 +1,95 @@
+//! Three-way merge used when a stored refinement is applied to a topic that changed since
+
+use promptmux::diff::{line_diff, matching_lines, merge_refinement, LineChange, MergeStatus};
+
+const BASE: &str = "# Goal\nBuild a todo app.\n\n# Stack\nRust backend.\nSvelte frontend.\n";
+
+#[test]
+fn untouched_topic_takes_the_refinement() {
+    let refined = "# Goal\nBuild a collaborative todo app.\n";
+    let result = merge_refinement(BASE, BASE, refined);
+
+    assert_eq!(result.status, MergeStatus::Applied);
+    assert_eq!(result.content, refined);
+}
+
+#[test]
+fn topic_that_already_holds_the_refinement_is_reported() {
+    let refined = "# Goal\nBuild a collaborative todo app.\n";
+    let result = merge_refinement(BASE, refined, refined);
+
+    assert_eq!(result.status, MergeStatus::AlreadyApplied);
+    assert_eq!(result.content, refined);
+}
+
+#[test]
+fn edits_to_other_lines_are_kept() {
+    let current = "# Goal\nBuild a todo app.\n\n# Stack\nRust backend with SQLite.\nSvelte frontend.\n";
+    let refined = "# Goal\nBuild a collaborative todo app for small teams.\n\n# Stack\nRust backend.\nSvelte frontend.\n";
+
+    let result = merge_refinement(BASE, current, refined);
+
+    assert_eq!(result.status, MergeStatus::Merged);
+    assert_eq!(result.conflicts, 0);
+    assert_eq!(
+        result.content,
+        "# Goal\nBuild a collaborative todo app for small teams.\n\n# Stack\nRust backend with SQLite.\nSvelte frontend.\n"
+    );
+}
+
+#[test]
+fn identical_changes_on_both_sides_merge_cleanly() {
+    let current = "# Goal\nBuild a todo app.\n\n# Stack\nRust backend.\nSvelte 5 frontend.\n";
+    let refined = "# Goal\nBuild a shared todo app.\n\n# Stack\nRust backend.\nSvelte 5 frontend.\n";
+
+    let result = merge_refinement(BASE, current, refined);
+
+    assert_eq!(result.status, MergeStatus::Merged);
+    assert_eq!(result.content, refined);
+}
+
+#[test]
+fn overlapping_changes_become_conflict_blocks() {
+    let current = "# Goal\nBuild a todo app for families.\n\n# Stack\nRust backend.\nSvelte frontend.\n";
+    let refined = "# Goal\nBuild a collaborative todo app.\n\n# Stack\nRust backend.\nSvelte frontend.\n";
+
+    let result = merge_refinement(BASE, current, refined);
+
+    assert_eq!(result.status, MergeStatus::Conflict);
+    assert_eq!(result.conflicts, 1);
+    assert_eq!(
+        result.content,
+        "# Goal\n<<<<<<< current\nBuild a todo app for families.\n=======\nBuild a collaborative todo app.\n>>>>>>> refined\n\n# Stack\nRust backend.\nSvelte frontend.\n"
+    );
+}
+
+#[test]
+fn matching_lines_finds_a_longest_common_subsequence() {
+    let a = ["a", "b", "c", "d", "e"];
+    let b = ["a", "x", "c", "d", "y", "e"];
+
+    assert_eq!(matching_lines(&a, &b), vec![(0, 0), (2, 2), (3, 3), (4, 5)]);
+    assert_eq!(matching_lines(&a, &[]), vec![]);
+}
+
+#[test]
+fn line_diff_lists_removed_before_added_lines() {
+    let old = "flowchart TD\n    A[Start] --> B[Sign up]\n    B --> C[Done]\n";
+    let new = "flowchart TD\n    A[Start] --> B[Register]\n    B --> C[Done]\n    C --> D[Welcome]\n";
+    let lines = line_diff(old, new);
+
+    let summary: Vec<(LineChange, Option<usize>, Option<usize>)> =
+        lines.iter().map(|l| (l.change, l.old_line, l.new_line)).collect();
+    assert_eq!(
+        summary,
+        [
+            (LineChange::Unchanged, Some(1), Some(1)),
+            (LineChange::Removed, Some(2), None),
+            (LineChange::Added, None, Some(2)),
+            (LineChange::Unchanged, Some(3), Some(3)),
+            (LineChange::Added, None, Some(4)),
+        ]
+    );
+    assert_eq!(lines[2].text, "    A[Start] --> B[Register]");
+    assert!(line_diff(old, old).iter().all(|l| l.change == LineChange::Unchanged));
+}
@@ -0,0 +1,225 @@
+//! Mermaid validation and cleanup of LLM answers
+
+use promptmux::echo;
+use promptmux::mermaid::{check, clean, repair, validate, DIAGRAM_KINDS};
+use std::cell::RefCell;
+
+fn messages(kind: &str, code: &str) -> Vec<(usize, usize, String)> {
+    validate(kind, code).unwrap().into_iter().map(|d| (d.line, d.column, d.message)).collect()
+}
+
+#[test]
+fn valid_diagrams_have_no_diagnostics() {
+    let er = "erDiagram\n    CUSTOMER ||--o{ ORDER : places\n    CUSTOMER {\n        string name PK \"full name\"\n        varchar(255) email UK\n    }\n    ORDER }|..|{ PRODUCT : contains";
+    let uml = "classDiagram\n    class Animal {\n        +String name\n        +eat() void\n    }\n    class Box~T~\n    <<interface>> Animal\n    Animal <|-- Duck\n    Customer \"1\" --> \"*\" Order : places\n    Duck : +swim()";
+    let flowchart = "%% generated\nflowchart TD\n    A[Start] --> B{Signed in?}\n    B -->|Yes| C([\"Dashboard (home)\"])\n    B -- No --> D[[Login]] & E((Help))\n    subgraph Admin\n        F --> G\n    end\n    classDef warn fill:#f96\n    class D warn";
+    let journey = "journey\n    title Onboarding\n    section Sign up\n      Fill in form: 3: User\n      Confirm email: 5: User, System";
+
+    assert_eq!(messages("er", er), vec![]);
+    assert_eq!(messages("uml", uml), vec![]);
+    assert_eq!(messages("flowchart", flowchart), vec![]);
+    assert_eq!(messages("journey", journey), vec![]);
+}
+
+#[test]
+fn wrong_header_is_reported_on_the_first_statement() {
+    assert_eq!(
+        messages("er", "%% comment\nclassDiagram\n  A <|-- B"),
+        vec![(2, 1, "Expected the diagram to start with `erDiagram`, found `classDiagram`".to_string())]
+    );
+    assert_eq!(messages("flowchart", "flowchart sideways\n  A --> B")[0].0, 1);
+    assert!(validate("pie", "pie").is_err());
+}
+
+#[test]
+fn er_mistakes_point_at_the_offending_column() {
+    let code = "erDiagram\n    ORDER {\n        decimal(10,2) total\n    }\n    CUSTOMER ||--*{ ORDER : has\n    CUSTOMER ||--o{ INVOICE";
+    let found = messages("er", code);
+
+    assert_eq!(found.len(), 3, "{:?}", found);
+    assert_eq!((found[0].0, found[0].1), (3, 9));
+    assert_eq!((found[1].0, found[1].1), (5, 14));
+    assert!(found[1].2.contains("||--*{"));
+    assert_eq!(found[2].0, 6);
+    assert!(found[2].2.contains("label"));
+}
+
+#[test]
+fn unclosed_blocks_are_reported_where_they_open() {
+    assert_eq!(
+        messages("er", "erDiagram\n  USER {\n    string id PK"),
+        vec![(2, 1, "Entity block `USER` is not closed with `}`".to_string())]
+    );
+    assert_eq!(
+        messages("flowchart", "graph LR\n  subgraph One\n    A --> B"),
+        vec![(2, 1, "Subgraph is not closed with `end`".to_string())]
+    );
+    assert_eq!(messages("uml", "classDiagram\n  class A {\n    +id")[0].0, 2);
+}
+
+#[test]
+fn flowchart_labels_with_brackets_must_be_quoted() {
+    let found = messages("flowchart", "flowchart TD\n    A[Save (draft)] --> B");
+    assert_eq!(found.len(), 1);
+    assert_eq!((found[0].0, found[0].1), (2, 12));
+    assert!(found[0].2.contains("double quotes"));
+
+    assert_eq!(messages("flowchart", "flowchart TD\n    A --> "), vec![(2, 7, "Link has no target node".to_string())]);
+    assert!(messages("flowchart", "flowchart TD\n    A --> end")[0].2.contains("`end`"));
+}
+
+#[test]
+fn journey_scores_must_be_numbers() {
+    let found = messages("journey", "journey\n  section Use\n    Browse: great: User");
+    assert_eq!(found, vec![(3, 12, "Task score must be a whole number, found `great`".to_string())]);
+}
+
+#[test]
+fn sequence_state_and_c4_diagrams_are_checked() {
+    let sequence = "sequenceDiagram\n    actor User\n    participant API as Backend API\n    User->>+API: POST /orders\n    \
+                    alt valid\n        API-->>-User: 201 Created\n    else invalid\n        API--xUser: 400\n    end\n    \
+                    Note over User,API: Retries are safe";
+    let state = "stateDiagram-v2\n    [*] --> Draft\n    Draft --> Submitted : submit\n    state Submitted {\n        \
+                 [*] --> Review\n        Review --> [*]\n    }\n    state \"Paid in full\" as Paid\n    Submitted --> Paid\n    \
+                 Paid : settled\n    note right of Paid\n        Final\n    end note\n    Paid --> [*]";
+    let c4 = "C4Container\n    title Shop\n    Person(customer, \"Customer\", \"Buys things, (often)\")\n    \
+              System_Boundary(shop, \"Shop\") {\n        Container(api, \"API\", \"Rust\")\n        ContainerDb(db, \"DB\", \"SQL\")\n    }\n    \
+              Rel(customer, api, \"Orders\", \"HTTPS\")\n    UpdateLayoutConfig($c4ShapeInRow=\"3\")";
+    assert_eq!(messages("sequence", sequence), vec![]);
+    assert_eq!(messages("state", state), vec![]);
+    assert_eq!(messages("c4", c4), vec![]);
+
+    let found = messages("sequence", "sequenceDiagram\n    else\n    loop Poll\n        A->>B");
+    assert_eq!(
+        found,
+        vec![
+            (2, 5, "`else` outside of an `alt` block".to_string()),
+            (4, 14, "Message needs its text after `:`".to_string()),
+            (3, 1, "`loop` block is not closed with `end`".to_string()),
+        ]
+    );
+    assert_eq!(
+        messages("state", "stateDiagram-v2\n    state Busy {\n        A --> \n"),
+        vec![
+            (3, 14, "Transition needs a state on both sides of `-->`".to_string()),
+            (2, 1, "Composite state is not closed with `}`".to_string()),
+        ]
+    );
+    assert_eq!(
+        messages("c4", "C4Context\n    Rel(a, b)\n    Enterprise_Boundary(b0, \"Corp\") {\n    System(s1, \"Label)"),
+        vec![
+            (2, 8, "Relationships need a source, a target and a label".to_string()),
+            (4, 11, "String is not closed with `\"`".to_string()),
+            (3, 1, "Boundary is not closed with `}`".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn offline_answers_are_valid_for_every_kind() {
+    let content = "Customers place orders. Each order contains products and ships to an address.";
+    for kind in DIAGRAM_KINDS {
+        let answer = echo::respond(kind, &[("content", content)]);
+        assert_eq!(validate(kind, &answer).unwrap(), vec![], "{}:\n{}", kind, answer);
+    }
+}
+
+#[test]
+fn clean_extracts_the_diagram_from_an_answer() {
+    let answer = "Here is the updated diagram:\n\n```mermaid\nerDiagram\n    A ||--o{ B : has\n```\n\nLet me know if you need changes.";
+    assert_eq!(clean("er", answer).unwrap(), "erDiagram\n    A ||--o{ B : has");
+
+    let unfenced = "Sure! Here you go.\n%%{init: {'theme': 'dark'}}%%\nflowchart LR\n    A --> B\n\nThis shows the main flow of the app.";
+    assert_eq!(clean("flowchart", unfenced).unwrap(), "%%{init: {'theme': 'dark'}}%%\nflowchart LR\n    A --> B");
+}
+
+#[test]
+fn clean_keeps_real_mistakes_for_validation() {
+    let report = check("er", "```\nerDiagram\n    A ||--o{ B\n```").unwrap();
+    assert_eq!(report.cleaned, "erDiagram\n    A ||--o{ B");
+    assert_eq!(report.diagnostics.len(), 1);
+}
+
+#[tokio::test]
+async fn valid_answers_are_not_sent_back() {
+    let asked = RefCell::new(0);
+    let repaired = repair(
+        "er",
+        vec![("user", "Draw it".to_string())],
+        "```mermaid\nerDiagram\n    A ||--o{ B : has\n```".to_string(),
+        2,
+        |_| {
+            *asked.borrow_mut() += 1;
+            async { Ok(String::new()) }
+        },
+        |_| {},
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(*asked.borrow(), 0);
+    assert_eq!(repaired.attempts, 0);
+    assert_eq!(repaired.code, "erDiagram\n    A ||--o{ B : has");
+}
+
+#[tokio::test]
+async fn parse_errors_are_sent_back_until_the_diagram_is_valid() {
+    let requests = RefCell::new(Vec::new());
+    let repaired = repair(
+        "flowchart",
+        vec![("user", "Draw it".to_string())],
+        "flowchart TD\n    A[Save (draft)] --> B".to_string(),
+        3,
+        |turns| {
+            requests.borrow_mut().push(turns);
+            async { Ok("flowchart TD\n    A[\"Save (draft)\"] --> B".to_string()) }
+        },
+        |_| {},
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(repaired.attempts, 1);
+    assert!(repaired.diagnostics.is_empty());
+    let requests = requests.borrow();
+    let turns = &requests[0];
+    assert_eq!(turns.len(), 3);
+    assert_eq!(turns[1], ("assistant", "flowchart TD\n    A[Save (draft)] --> B".to_string()));
+    assert_eq!(turns[2].0, "user");
+    assert!(turns[2].1.contains("Line 2, column 12"), "{}", turns[2].1);
+}
+
+#[tokio::test]
+async fn repairs_stop_after_the_attempt_limit() {
+    let mut reported = Vec::new();
+    let repaired = repair(
+        "journey",
+        vec![("user", "Draw it".to_string())],
+        "journey\n  Browse: great".to_string(),
+        2,
+        |_| async { Ok("journey\n  Browse: still great".to_string()) },
+        |attempt| reported.push((attempt.attempt, attempt.max_attempts)),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(repaired.attempts, 2);
+    assert_eq!(reported, vec![(1, 2), (2, 2)]);
+    assert_eq!(repaired.code, "journey\n  Browse: still great");
+    assert_eq!(repaired.diagnostics.len(), 1);
+}
+
+#[tokio::test]
+async fn a_failed_repair_request_is_an_error() {
+    let result = repair(
+        "journey",
+        vec![("user", "Draw it".to_string())],
+        "journey\n  Browse: great".to_string(),
+        2,
+        |_| async { Err("HTTP 500".to_string()) },
+        |_| {},
+    )
+    .await;
+
+    assert_eq!(result.unwrap_err(), "HTTP 500");
+}
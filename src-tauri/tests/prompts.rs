@@ -0,0 +1,61 @@
+//! Prompt template registry: override precedence and placeholder substitution
+
+use promptmux::models::{Project, PromptTemplate, Workspace};
+use promptmux::prompts::{default_template, list, render, resolve};
+
+fn template(system: &str) -> PromptTemplate {
+    PromptTemplate { system: system.to_string(), user: "{content}".to_string() }
+}
+
+/// A workspace with a second project next to the default one
+fn workspace() -> (Workspace, String, String) {
+    let mut workspace = Workspace::new();
+    let first = workspace.active_project_id.clone();
+    let second = workspace.add_project(Project::new("Other".to_string()));
+    (workspace, first, second)
+}
+
+#[test]
+fn defaults_apply_without_overrides() {
+    let (workspace, first, _) = workspace();
+
+    assert_eq!(resolve(&workspace, Some(&first), "er").unwrap().system, default_template("er").unwrap().system);
+    assert!(resolve(&workspace, Some(&first), "poem").unwrap_err().contains("Unknown prompt task"));
+}
+
+#[test]
+fn project_override_beats_workspace_override_beats_default() {
+    let (mut workspace, first, second) = workspace();
+    workspace.prompt_templates.insert("er".to_string(), template("workspace"));
+    workspace.get_project_mut(&first).unwrap().prompt_templates.insert("er".to_string(), template("project"));
+
+    assert_eq!(resolve(&workspace, Some(&first), "er").unwrap().system, "project");
+    assert_eq!(resolve(&workspace, Some(&second), "er").unwrap().system, "workspace");
+    assert_eq!(resolve(&workspace, None, "er").unwrap().system, "workspace");
+
+    let entry = list(&workspace, Some(&first)).into_iter().find(|e| e.task == "er").unwrap();
+    assert_eq!(entry.effective.system, "project");
+    assert_eq!(entry.workspace_override.unwrap().system, "workspace");
+    assert_eq!(entry.variables, ["content"]);
+}
+
+#[test]
+fn known_placeholders_are_replaced() {
+    let rendered = render("Project: {project_name}\n\n{content}", &[("project_name", "Shop"), ("content", "Sell shoes")]);
+
+    assert_eq!(rendered, "Project: Shop\n\nSell shoes");
+}
+
+#[test]
+fn unknown_placeholders_and_mermaid_braces_are_left_as_is() {
+    let template = "erDiagram\n    USER {\n        string email\n    }\n{unknown} {content";
+
+    assert_eq!(render(template, &[("content", "x")]), template);
+}
+
+#[test]
+fn substituted_values_are_not_rendered_again() {
+    let rendered = render("{content} / {instruction}", &[("content", "use {instruction} literally"), ("instruction", "add a field")]);
+
+    assert_eq!(rendered, "use {instruction} literally / add a field");
+}
@@ -0,0 +1,113 @@
+//! On-disk response cache: keys, replay of stored chunks and LRU eviction
+
+mod common;
+
+use common::TempDir;
+use promptmux::cache::{cache_key, CachedResponse, ResponseCache};
+
+fn response(text: &str) -> CachedResponse {
+    CachedResponse {
+        chunks: vec![text.to_string()],
+        model: "test-model".to_string(),
+        created_at: "2024-01-01T00:00:00Z".to_string(),
+    }
+}
+
+/// About 400 KB, so three of them overflow a 1 MB cache
+fn large_response(tag: &str) -> CachedResponse {
+    response(&format!("{}{}", tag, "x".repeat(400 * 1024)))
+}
+
+#[test]
+fn key_depends_on_every_input() {
+    let messages = serde_json::json!([{ "role": "user", "content": "Hello" }]);
+    let key = cache_key("openai", "https://api.openai.com/v1", "gpt-4o", "system", &messages);
+
+    assert_eq!(key, cache_key("openai", "https://api.openai.com/v1", "gpt-4o", "system", &messages));
+    assert_eq!(key.len(), 64);
+    assert_ne!(key, cache_key("anthropic", "https://api.openai.com/v1", "gpt-4o", "system", &messages));
+    assert_ne!(key, cache_key("openai", "http://localhost:8080/v1", "gpt-4o", "system", &messages));
+    assert_ne!(key, cache_key("openai", "https://api.openai.com/v1", "gpt-4o-mini", "system", &messages));
+    assert_ne!(key, cache_key("openai", "https://api.openai.com/v1", "gpt-4o", "other system", &messages));
+    let other_messages = serde_json::json!([{ "role": "user", "content": "Hello!" }]);
+    assert_ne!(key, cache_key("openai", "https://api.openai.com/v1", "gpt-4o", "system", &other_messages));
+}
+
+#[test]
+fn stored_chunks_survive_reopening() {
+    let dir = TempDir::new();
+    let mut cache = ResponseCache::open(&dir.0, 10);
+    let stored = CachedResponse {
+        chunks: vec!["erDiagram".to_string(), "\n    A ||--o{ B : has".to_string()],
+        ..response("")
+    };
+    cache.put("abc", &stored).unwrap();
+
+    let mut reopened = ResponseCache::open(&dir.0, 10);
+    assert_eq!(reopened.get("abc").unwrap().chunks, stored.chunks);
+    assert!(reopened.get("missing").is_none());
+    assert_eq!(reopened.stats().entries, 1);
+}
+
+#[test]
+fn least_recently_used_entry_is_evicted_first() {
+    let dir = TempDir::new();
+    let mut cache = ResponseCache::open(&dir.0, 1);
+
+    cache.put("a", &large_response("a")).unwrap();
+    cache.put("b", &large_response("b")).unwrap();
+    // Reading "a" makes "b" the least recently used entry
+    assert!(cache.get("a").is_some());
+    cache.put("c", &large_response("c")).unwrap();
+
+    assert!(cache.get("b").is_none());
+    assert!(cache.get("a").is_some());
+    assert!(cache.get("c").is_some());
+    assert!(cache.stats().total_bytes <= cache.stats().max_bytes);
+}
+
+#[test]
+fn reads_do_not_rewrite_the_index_until_flushed() {
+    let dir = TempDir::new();
+    let index = dir.0.join("llm-cache").join("index.json");
+    let mut cache = ResponseCache::open(&dir.0, 1);
+    cache.put("a", &large_response("a")).unwrap();
+    cache.put("b", &large_response("b")).unwrap();
+
+    std::fs::remove_file(&index).unwrap();
+    assert!(cache.get("a").is_some());
+    assert!(!index.exists());
+
+    // The read is kept once flushed: "b" is now the least recently used entry
+    cache.flush().unwrap();
+    let mut reopened = ResponseCache::open(&dir.0, 1);
+    reopened.put("c", &large_response("c")).unwrap();
+    assert!(reopened.get("b").is_none());
+    assert!(reopened.get("a").is_some());
+}
+
+#[test]
+fn zero_limit_disables_the_cache() {
+    let dir = TempDir::new();
+    let mut cache = ResponseCache::open(&dir.0, 10);
+    cache.put("a", &response("answer")).unwrap();
+
+    cache.set_max_mb(0).unwrap();
+    assert!(cache.get("a").is_none());
+    assert_eq!(cache.stats().entries, 0);
+
+    cache.put("b", &response("answer")).unwrap();
+    assert_eq!(cache.stats().entries, 0);
+}
+
+#[test]
+fn clear_removes_everything() {
+    let dir = TempDir::new();
+    let mut cache = ResponseCache::open(&dir.0, 10);
+    cache.put("a", &response("answer")).unwrap();
+
+    cache.clear().unwrap();
+
+    assert!(cache.get("a").is_none());
+    assert!(!dir.0.join("llm-cache").exists());
+}
@@ -0,0 +1,142 @@
+//! Parsing refined merged prompts back into sections and topics
+
+use promptmux::models::{Project, Section, Topic};
+use promptmux::restructure::{
+    align_paragraphs, apply_split, paragraphs, parse_refined, ChangeKind, SplitChange, StructuredTopic,
+};
+
+fn topic(name: Option<&str>, content: &str) -> StructuredTopic {
+    StructuredTopic { name: name.map(str::to_string), content: content.to_string() }
+}
+
+#[test]
+fn section_markers_split_the_merged_output() {
+    let merged = "// Section: Goal\nBuild a todo app.\n\nFor small teams.\n\n---\n\n// Section: Stack\nRust and Svelte.";
+    let prompt = parse_refined(merged).unwrap();
+
+    assert_eq!(prompt.sections.len(), 2);
+    assert_eq!(prompt.sections[0].name, "Goal");
+    assert_eq!(prompt.sections[0].topics, vec![topic(None, "Build a todo app.\n\nFor small teams.")]);
+    assert_eq!(prompt.sections[1].name, "Stack");
+    assert_eq!(prompt.sections[1].topics, vec![topic(None, "Rust and Svelte.")]);
+}
+
+#[test]
+fn topic_markers_name_the_topics() {
+    let merged = "// Section: Stack\n// Topic: Backend\nRust.\n// Topic: Frontend\nSvelte.\n";
+    let prompt = parse_refined(merged).unwrap();
+
+    assert_eq!(
+        prompt.sections[0].topics,
+        vec![topic(Some("Backend"), "Rust."), topic(Some("Frontend"), "Svelte.")]
+    );
+}
+
+#[test]
+fn structured_json_is_accepted_inside_a_code_fence() {
+    let answer = "```json\n{\"sections\":[{\"name\":\"Goal\",\"topics\":[{\"name\":\"Summary\",\"content\":\"A todo app.\"}]}]}\n```";
+    let prompt = parse_refined(answer).unwrap();
+
+    assert_eq!(prompt.sections[0].name, "Goal");
+    assert_eq!(prompt.sections[0].topics, vec![topic(Some("Summary"), "A todo app.")]);
+}
+
+#[test]
+fn text_without_markers_is_rejected() {
+    assert!(parse_refined("Build a todo app.").is_err());
+    assert!(parse_refined("Intro\n// Section: Goal\nBuild it.").is_err());
+    assert!(parse_refined("{\"title\": \"not a prompt\"}").is_err());
+}
+
+#[test]
+fn paragraphs_go_to_the_most_similar_topics_in_order() {
+    let old = [
+        "Users sign in with email and password.",
+        "Tasks have a title, a due date and an assignee.",
+        "Reminders are sent by email before the due date.",
+    ];
+    let refined = paragraphs(
+        "Users sign in with their email address and a password.\n\nSessions expire after a week.\n\n\
+         Each task has a title, a due date and one assignee.\n\n\
+         Reminder emails are sent a day before the due date.",
+    );
+
+    let runs = align_paragraphs(&refined, &old);
+
+    assert_eq!(
+        runs,
+        vec![
+            "Users sign in with their email address and a password.\n\nSessions expire after a week.".to_string(),
+            "Each task has a title, a due date and one assignee.".to_string(),
+            "Reminder emails are sent a day before the due date.".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn rewritten_text_is_still_spread_over_every_topic() {
+    let old = ["alpha", "beta"];
+    let refined = paragraphs("One.\n\nTwo.\n\nThree.");
+
+    let runs = align_paragraphs(&refined, &old);
+
+    assert_eq!(runs.len(), 2);
+    assert!(runs.iter().all(|run| !run.is_empty()), "{:?}", runs);
+}
+
+fn project_with_goal() -> Project {
+    let mut project = Project::new("Todo".to_string());
+    let mut section = Section::new("Goal".to_string());
+    section.add_topic(Topic::new("Summary".to_string(), "A todo app.".to_string(), section.id.clone()));
+    project.add_section(section);
+    project
+}
+
+fn topic_change(kind: ChangeKind, project: &Project, topic_id: Option<String>, content: &str) -> SplitChange {
+    SplitChange {
+        kind,
+        item_type: "topic".to_string(),
+        section_id: Some(project.sections[0].id.clone()),
+        section_name: "Goal".to_string(),
+        topic_id,
+        topic_name: Some("Audience".to_string()),
+        original_content: None,
+        new_content: Some(content.to_string()),
+    }
+}
+
+#[test]
+fn split_changes_are_applied_together() {
+    let project = project_with_goal();
+    let summary_id = project.sections[0].topics[0].id.clone();
+    let changes = vec![
+        topic_change(ChangeKind::Changed, &project, Some(summary_id.clone()), "A shared todo app."),
+        topic_change(ChangeKind::Added, &project, None, "Small teams."),
+    ];
+
+    let updated = apply_split(&project, &changes).unwrap();
+
+    assert_eq!(updated.get_topic(&summary_id).unwrap().content, "A shared todo app.");
+    assert_eq!(updated.sections[0].topics.len(), 2);
+}
+
+#[test]
+fn a_failing_split_change_leaves_the_project_untouched() {
+    let project = project_with_goal();
+    let summary_id = project.sections[0].topics[0].id.clone();
+    let mut missing_section = topic_change(ChangeKind::Removed, &project, None, "");
+    missing_section.item_type = "section".to_string();
+    missing_section.section_id = Some("gone".to_string());
+    let changes = vec![
+        topic_change(ChangeKind::Changed, &project, Some(summary_id.clone()), "A shared todo app."),
+        topic_change(ChangeKind::Added, &project, None, "Small teams."),
+        missing_section,
+    ];
+    let before = serde_json::to_string(&project).unwrap();
+
+    assert!(apply_split(&project, &changes).is_err());
+    assert_eq!(serde_json::to_string(&project).unwrap(), before);
+
+    let stale_topic = vec![topic_change(ChangeKind::Changed, &project, Some("gone".to_string()), "x")];
+    assert_eq!(apply_split(&project, &stale_topic).unwrap_err(), "Topic with id gone not found");
+}
@@ -0,0 +1,245 @@
+//! ER diagram model, SQL DDL generation and schema import
+
+use promptmux::mermaid::validate;
+use promptmux::schema::{parse_ddl, read_sqlite, to_ddl, Cardinality, Dialect, ErModel};
+
+const SHOP: &str = "erDiagram
+    CUSTOMER ||--o{ ORDER : places
+    CUSTOMER {
+        int id PK
+        string email UK \"login address\"
+    }
+    ORDER {
+        int id PK
+        int customer_id FK
+        datetime placed_at
+    }
+    ORDER }o--o{ PRODUCT : contains
+    PRODUCT { string sku PK decimal price }
+";
+
+#[test]
+fn diagram_is_read_into_entities_and_relationships() {
+    let model = ErModel::from_mermaid(SHOP).unwrap();
+
+    let names: Vec<&str> = model.entities.iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names, ["CUSTOMER", "ORDER", "PRODUCT"]);
+
+    let email = &model.entity("CUSTOMER").unwrap().attributes[1];
+    assert_eq!(email.name, "email");
+    assert!(email.has_key("UK"));
+    assert_eq!(email.comment.as_deref(), Some("login address"));
+    assert_eq!(model.entity("PRODUCT").unwrap().attributes.len(), 2);
+
+    let places = &model.relationships[0];
+    assert_eq!((places.left.as_str(), places.right.as_str()), ("CUSTOMER", "ORDER"));
+    assert_eq!(places.left_cardinality, Cardinality::ExactlyOne);
+    assert_eq!(places.right_cardinality, Cardinality::ZeroOrMore);
+    assert_eq!(places.label, "places");
+}
+
+#[test]
+fn word_cardinalities_are_understood() {
+    let model = ErModel::from_mermaid("erDiagram\n    TEAM only one to zero or more PLAYER : has").unwrap();
+    assert_eq!(model.relationships[0].left_cardinality, Cardinality::ExactlyOne);
+    assert_eq!(model.relationships[0].right_cardinality, Cardinality::ZeroOrMore);
+}
+
+#[test]
+fn invalid_diagrams_are_rejected_with_their_position() {
+    let error = ErModel::from_mermaid("erDiagram\n    A ||--o{ B").unwrap_err();
+    assert!(error.contains("Line 2"), "{}", error);
+}
+
+#[test]
+fn one_to_many_reuses_the_marked_foreign_key_column() {
+    let ddl = to_ddl(&ErModel::from_mermaid(SHOP).unwrap(), Dialect::Postgres);
+
+    assert!(ddl.contains(
+        "CREATE TABLE \"ORDER\" (\n    id INTEGER NOT NULL,\n    customer_id INTEGER NOT NULL,\n    placed_at TIMESTAMP,\n    \
+         PRIMARY KEY (id),\n    FOREIGN KEY (customer_id) REFERENCES CUSTOMER (id)\n);"
+    ), "{}", ddl);
+    assert!(ddl.contains("UNIQUE (email)"));
+    assert!(ddl.contains("COMMENT ON COLUMN CUSTOMER.email IS 'login address';"));
+}
+
+#[test]
+fn many_to_many_becomes_a_join_table() {
+    let ddl = to_ddl(&ErModel::from_mermaid(SHOP).unwrap(), Dialect::Mysql);
+
+    assert!(ddl.contains(
+        "CREATE TABLE order_product (\n    order_id INT NOT NULL,\n    product_sku VARCHAR(255) NOT NULL,\n    \
+         PRIMARY KEY (order_id, product_sku),\n    FOREIGN KEY (order_id) REFERENCES `ORDER` (id),\n    \
+         FOREIGN KEY (product_sku) REFERENCES PRODUCT (sku)\n);"
+    ), "{}", ddl);
+    assert!(ddl.contains("email VARCHAR(255) COMMENT 'login address'"));
+}
+
+#[test]
+fn entities_without_a_key_get_a_generated_one() {
+    let model = ErModel::from_mermaid("erDiagram\n    USER ||--o| PROFILE : has\n    PROFILE { string bio }").unwrap();
+
+    let postgres = to_ddl(&model, Dialect::Postgres);
+    assert!(postgres.contains("CREATE TABLE \"USER\" (\n    id BIGINT NOT NULL GENERATED ALWAYS AS IDENTITY,"), "{}", postgres);
+    // One-to-one: the reference is unique
+    assert!(postgres.contains("user_id BIGINT NOT NULL,"));
+    assert!(postgres.contains("UNIQUE (user_id)"));
+
+    let sqlite = to_ddl(&model, Dialect::Sqlite);
+    assert!(sqlite.starts_with("-- Generated from the project ER diagram for SQLite\nPRAGMA foreign_keys = ON;\n"));
+    assert!(sqlite.contains("id INTEGER NOT NULL,\n    bio TEXT,"), "{}", sqlite);
+}
+
+#[test]
+fn reference_cycles_are_closed_with_alter_table() {
+    let model = ErModel::from_mermaid(
+        "erDiagram\n    DEPARTMENT ||--o{ EMPLOYEE : employs\n    EMPLOYEE |o--o| DEPARTMENT : heads\n    \
+         EMPLOYEE |o--o{ EMPLOYEE : manages",
+    )
+    .unwrap();
+
+    let ddl = to_ddl(&model, Dialect::Postgres);
+    assert!(ddl.contains("parent_employee_id BIGINT,"), "{}", ddl);
+    assert!(ddl.contains("FOREIGN KEY (parent_employee_id) REFERENCES EMPLOYEE (id)"));
+    assert!(ddl.contains("ALTER TABLE "), "{}", ddl);
+
+    // SQLite resolves references lazily, so everything stays inline
+    assert!(!to_ddl(&model, Dialect::Sqlite).contains("ALTER TABLE"));
+}
+
+#[test]
+fn dialect_names_are_checked() {
+    assert_eq!(Dialect::parse("PostgreSQL").unwrap(), Dialect::Postgres);
+    assert_eq!(Dialect::parse("sqlite").unwrap(), Dialect::Sqlite);
+    assert!(Dialect::parse("oracle").is_err());
+}
+
+#[test]
+fn ddl_is_imported_as_an_er_diagram() {
+    let sql = r#"
+        -- Customers and their orders
+        CREATE TABLE IF NOT EXISTS public.customer (
+            id BIGSERIAL PRIMARY KEY,
+            email VARCHAR(255) NOT NULL UNIQUE,
+            "display name" TEXT /* optional */
+        );
+        CREATE TABLE public."order" (
+            id BIGINT GENERATED ALWAYS AS IDENTITY,
+            customer_id BIGINT NOT NULL REFERENCES customer (id) ON DELETE CASCADE,
+            total NUMERIC(10, 2) DEFAULT (0),
+            placed_at TIMESTAMP WITH TIME ZONE,
+            CONSTRAINT order_pk PRIMARY KEY (id)
+        );
+        CREATE TABLE invoice (id INT PRIMARY KEY, order_id INT, UNIQUE (order_id));
+        ALTER TABLE ONLY invoice ADD CONSTRAINT invoice_order FOREIGN KEY (order_id) REFERENCES public."order";
+        COMMENT ON COLUMN customer.email IS 'Login address';
+        CREATE INDEX order_customer ON "order" (customer_id);
+    "#;
+
+    let diagram = parse_ddl(sql).unwrap().to_mermaid();
+    assert_eq!(
+        diagram,
+        "erDiagram
+    customer {
+        bigserial id PK
+        varchar(255) email UK \"Login address\"
+        text display_name
+    }
+    order {
+        bigint id PK
+        bigint customer_id FK
+        numeric total
+        timestamp_with_time_zone placed_at
+    }
+    invoice {
+        int id PK
+        int order_id FK, UK
+    }
+    customer ||--o{ order : customer_id
+    order |o--o| invoice : order_id
+"
+    );
+    assert_eq!(validate("er", &diagram).unwrap(), vec![]);
+}
+
+#[test]
+fn mysql_dumps_and_link_tables_are_understood() {
+    let sql = "
+        CREATE TABLE `product` (
+          `sku` varchar(32) NOT NULL COMMENT 'Stock keeping unit',
+          `key` varchar(10),
+          PRIMARY KEY (`sku`),
+          KEY `product_key` (`key`)
+        ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+        CREATE TABLE `tag` (`id` int unsigned NOT NULL AUTO_INCREMENT, PRIMARY KEY (`id`));
+        CREATE TABLE `product_tag` (
+          `product_sku` varchar(32) NOT NULL,
+          `tag_id` int unsigned NOT NULL,
+          PRIMARY KEY (`product_sku`, `tag_id`),
+          CONSTRAINT `fk_product` FOREIGN KEY (`product_sku`) REFERENCES `product` (`sku`),
+          CONSTRAINT `fk_tag` FOREIGN KEY (`tag_id`) REFERENCES `tag` (`id`)
+        );
+    ";
+
+    let model = parse_ddl(sql).unwrap();
+    let names: Vec<&str> = model.entities.iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names, ["product", "tag"]);
+    let product = model.entity("product").unwrap();
+    assert_eq!(product.attributes.len(), 2);
+    assert_eq!(product.attributes[0].comment.as_deref(), Some("Stock keeping unit"));
+    assert_eq!(model.entity("tag").unwrap().attributes[0].data_type, "int_unsigned");
+
+    assert_eq!(model.relationships.len(), 1);
+    let tagged = &model.relationships[0];
+    assert_eq!((tagged.left.as_str(), tagged.right.as_str(), tagged.label.as_str()), ("product", "tag", "product_tag"));
+    assert!(tagged.left_cardinality.is_many() && tagged.right_cardinality.is_many());
+}
+
+#[test]
+fn generated_ddl_imports_back_to_the_same_relationships() {
+    let original = ErModel::from_mermaid(SHOP).unwrap();
+    let imported = parse_ddl(&to_ddl(&original, Dialect::Postgres)).unwrap();
+
+    let names: Vec<&str> = imported.entities.iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names, ["CUSTOMER", "ORDER", "PRODUCT"]);
+    assert_eq!(imported.entity("CUSTOMER").unwrap().attributes[1].comment.as_deref(), Some("login address"));
+
+    let shape: Vec<(&str, &str, bool)> = imported
+        .relationships
+        .iter()
+        .map(|r| (r.left.as_str(), r.right.as_str(), r.left_cardinality.is_many()))
+        .collect();
+    assert_eq!(shape, [("CUSTOMER", "ORDER", false), ("ORDER", "PRODUCT", true)]);
+    assert_eq!(ErModel::from_mermaid(&imported.to_mermaid()).unwrap(), imported);
+}
+
+#[test]
+fn sqlite_files_are_read_without_changes() {
+    let path = std::env::temp_dir().join(format!("promptmux-schema-{}.db", uuid::Uuid::new_v4()));
+    let connection = rusqlite::Connection::open(&path).unwrap();
+    connection
+        .execute_batch(
+            "CREATE TABLE author (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL);
+             CREATE TABLE book (id INTEGER PRIMARY KEY, author_id INTEGER REFERENCES author, title);",
+        )
+        .unwrap();
+    drop(connection);
+
+    let model = read_sqlite(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(model.entities.len(), 2);
+    assert_eq!(model.entity("book").unwrap().attributes[2].data_type, "any");
+    let written = &model.relationships[0];
+    assert_eq!((written.left.as_str(), written.right.as_str()), ("author", "book"));
+    assert_eq!(written.left_cardinality, Cardinality::ZeroOrOne);
+    assert_eq!(written.right_cardinality, Cardinality::ZeroOrMore);
+
+    assert!(read_sqlite(&std::env::temp_dir().join("promptmux-missing.db")).is_err());
+}
+
+#[test]
+fn sql_without_tables_is_rejected() {
+    assert_eq!(parse_ddl("CREATE INDEX i ON t (c);").unwrap_err(), "No CREATE TABLE statements found in the SQL");
+    assert!(parse_ddl("CREATE TABLE t (name TEXT DEFAULT 'oops").is_err());
+}
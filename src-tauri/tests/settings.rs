@@ -0,0 +1,47 @@
+//! Migrating saved settings: the single-provider format and profiles saved without a protocol
+
+use promptmux::settings::{parse_settings, DEFAULT_PROFILE_NAME};
+
+fn protocols(json: &str) -> Vec<(String, String)> {
+    parse_settings(json)
+        .unwrap()
+        .profiles
+        .into_iter()
+        .map(|p| (p.name, p.protocol.unwrap()))
+        .collect()
+}
+
+#[test]
+fn missing_protocols_are_inferred_from_the_provider() {
+    let json = r#"{
+        "activeProfile": "Gemini",
+        "profiles": [
+            { "name": "Gemini", "provider": "gemini", "baseUrl": "https://generativelanguage.googleapis.com/v1beta", "model": "gemini-1.5-flash" },
+            { "name": "Claude", "provider": "anthropic", "baseUrl": "https://api.anthropic.com/v1", "model": "claude-3-5-sonnet" },
+            { "name": "Local", "provider": "ollama", "baseUrl": "http://localhost:11434", "model": "llama3" },
+            { "name": "GLM", "provider": "glm", "baseUrl": "https://open.bigmodel.cn/api/paas/v4", "model": "glm-4" },
+            { "name": "Proxy", "provider": "gemini", "protocol": "openai", "baseUrl": "https://proxy.example", "model": "gemini-1.5-flash" }
+        ]
+    }"#;
+
+    assert_eq!(
+        protocols(json),
+        [
+            ("Gemini".to_string(), "gemini".to_string()),
+            ("Claude".to_string(), "anthropic".to_string()),
+            ("Local".to_string(), "ollama".to_string()),
+            ("GLM".to_string(), "openai".to_string()),
+            ("Proxy".to_string(), "openai".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn single_provider_settings_become_the_default_profile() {
+    let json = r#"{ "provider": "gemini", "baseUrl": "https://generativelanguage.googleapis.com/v1beta", "model": "gemini-1.5-pro" }"#;
+
+    let settings = parse_settings(json).unwrap();
+
+    assert_eq!(settings.active_profile, DEFAULT_PROFILE_NAME);
+    assert_eq!(protocols(json), [(DEFAULT_PROFILE_NAME.to_string(), "gemini".to_string())]);
+}
@@ -0,0 +1,99 @@
+//! User story parsing and Gherkin export
+
+use promptmux::stories::{parse, to_gherkin};
+
+const STORIES: &str = "# User Stories
+
+## 1. Feature: Order Management
+- **As a** customer, **I want to** place an order **so that** I receive my products.
+  - The order total includes taxes
+  - Given my cart is empty, when I check out, then I see an error
+- **As an** admin, **I want** the order list exported **so that** finance can reconcile it.
+
+## Catalog
+### Story 1
+**As a** shopper, **I can** search products by name.
+
+**Acceptance Criteria:**
+- Results appear while typing
+- Given a product named \"Lamp\"
+  When I search for \"lam\"
+  Then \"Lamp\" is listed
+
+Notes that are not criteria.
+- Unrelated list item
+";
+
+#[test]
+fn stories_are_read_into_features() {
+    let features = parse(STORIES);
+
+    let names: Vec<&str> = features.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, ["Order Management", "Catalog"]);
+
+    let order = &features[0].stories[0];
+    assert_eq!(order.role, "customer");
+    assert_eq!(order.goal, "place an order");
+    assert_eq!(order.benefit.as_deref(), Some("I receive my products"));
+    assert_eq!(order.acceptance_criteria.len(), 2);
+    assert_eq!(order.text, "As a customer, I want to place an order so that I receive my products.");
+
+    let export = &features[0].stories[1];
+    assert_eq!((export.role.as_str(), export.goal.as_str()), ("admin", "want the order list exported"));
+    assert!(export.acceptance_criteria.is_empty());
+
+    let search = &features[1].stories[0];
+    assert_eq!(search.benefit, None);
+    assert_eq!(
+        search.acceptance_criteria,
+        ["Results appear while typing", "Given a product named \"Lamp\"\nWhen I search for \"lam\"\nThen \"Lamp\" is listed"]
+    );
+}
+
+#[test]
+fn criteria_become_scenarios() {
+    let files = to_gherkin(&parse(STORIES));
+
+    let paths: Vec<&str> = files.iter().map(|f| f.path.as_str()).collect();
+    assert_eq!(paths, ["order_management.feature", "catalog.feature"]);
+    assert_eq!(
+        files[0].content,
+        "Feature: Order Management
+
+  Rule: Place an order
+    As a customer, I want to place an order so that I receive my products.
+
+    Scenario: The order total includes taxes
+      Given I am a customer
+      When I place an order
+      Then the order total includes taxes
+
+    Scenario: I see an error
+      Given my cart is empty
+      When I check out
+      Then I see an error
+
+  Rule: Want the order list exported
+    As an admin, I want the order list exported so that finance can reconcile it.
+
+    Scenario: Want the order list exported
+      Given I am an admin
+      When I want the order list exported
+      Then finance can reconcile it
+"
+    );
+    assert!(files[1].content.contains(
+        "    Scenario: \"Lamp\" is listed\n      Given a product named \"Lamp\"\n      When I search for \"lam\"\n      Then \"Lamp\" is listed\n"
+    ));
+    assert!(files[1].content.contains(
+        "    Scenario: Results appear while typing\n      Given I am a shopper\n      When I search products by name\n      Then results appear while typing\n"
+    ));
+}
+
+#[test]
+fn text_without_stories_has_no_features() {
+    assert!(parse("## Notes\n- Remember to ask about billing\n").is_empty());
+    let features = parse("- As a visitor I want to sign up so that I can comment");
+    assert_eq!(features[0].name, "User Stories");
+    assert_eq!(features[0].stories[0].role, "visitor");
+}
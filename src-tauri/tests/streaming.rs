@@ -0,0 +1,451 @@
+//! Replays recorded provider transcripts through the same request and relay code the streaming
+//! commands use, and checks the exact chunk/done/error events they would emit.
+
+mod common;
+
+use common::{fixture, MockResponse, MockServer};
+use promptmux::echo;
+use promptmux::llm::{build_conversation, build_messages, list_ollama_models, relay_answer, relay_stream, start_stream, Protocol, StreamEvent};
+use promptmux::settings::LlmProfile;
+use promptmux::usage::TokenUsage;
+
+fn profile(server: &MockServer, protocol: Protocol) -> LlmProfile {
+    LlmProfile {
+        provider: protocol.as_str().to_string(),
+        protocol: Some(protocol.as_str().to_string()),
+        api_key: "test-key".to_string(),
+        base_url: server.base_url.clone(),
+        model: Some("test-model".to_string()),
+        ..Default::default()
+    }
+}
+
+/// Run one streaming call against `response` and collect everything that would reach the frontend
+async fn replay(response: MockResponse, protocol: Protocol) -> (Vec<StreamEvent>, TokenUsage) {
+    let server = MockServer::start(response).await;
+    let messages = build_messages("You are a test.", "Hello".to_string(), protocol);
+    let (response, _) = start_stream(&reqwest::Client::new(), &profile(&server, protocol), protocol, messages)
+        .await
+        .expect("stream should start");
+
+    let mut events = Vec::new();
+    let usage = relay_stream(response.bytes_stream(), protocol, |event| events.push(event)).await;
+    (events, usage)
+}
+
+fn chunk(text: &str) -> StreamEvent {
+    StreamEvent::Chunk(text.to_string())
+}
+
+fn openai_events() -> Vec<StreamEvent> {
+    vec![
+        chunk("erDiagram"),
+        chunk("\n    CUSTOMER ||--o{ ORDER : places"),
+        chunk("\n    %% café — naïve ✓"),
+        StreamEvent::Done,
+    ]
+}
+
+fn anthropic_events() -> Vec<StreamEvent> {
+    vec![chunk("classDiagram"), chunk("\n    class Order"), StreamEvent::Done]
+}
+
+fn ollama_events() -> Vec<StreamEvent> {
+    vec![chunk("sequenceDiagram"), chunk("\n    Alice->>Bob: Hi"), StreamEvent::Done]
+}
+
+fn gemini_events() -> Vec<StreamEvent> {
+    vec![chunk("flowchart TD"), chunk("\n    A[Cart] --> B[Pay]"), StreamEvent::Done]
+}
+
+fn fixture_text(name: &str) -> String {
+    String::from_utf8(fixture(name)).unwrap()
+}
+
+/// Offset just past the `n`-th blank line separating SSE events
+fn after_events(body: &[u8], n: usize) -> usize {
+    body.windows(2)
+        .enumerate()
+        .filter(|(_, w)| w == b"\n\n")
+        .nth(n - 1)
+        .map(|(i, _)| i + 2)
+        .expect("transcript has enough events")
+}
+
+#[tokio::test]
+async fn openai_transcript_emits_chunks_then_done() {
+    let (events, usage) = replay(MockResponse::sse(fixture("openai_stream.sse")), Protocol::OpenAi).await;
+
+    assert_eq!(events, openai_events());
+    assert_eq!(usage, TokenUsage { input_tokens: 42, output_tokens: 17 });
+}
+
+#[tokio::test]
+async fn anthropic_transcript_emits_chunks_then_done() {
+    let (events, usage) = replay(MockResponse::sse(fixture("anthropic_stream.sse")), Protocol::Anthropic).await;
+
+    assert_eq!(events, anthropic_events());
+    assert_eq!(usage, TokenUsage { input_tokens: 25, output_tokens: 12 });
+}
+
+#[tokio::test]
+async fn fragmented_transcripts_emit_the_same_events() {
+    for chunk_size in [1, 2, 3, 7, 64] {
+        let response = MockResponse::sse(fixture("openai_stream.sse")).fragmented(chunk_size);
+        let (events, _) = replay(response, Protocol::OpenAi).await;
+        assert_eq!(events, openai_events(), "OpenAI transcript split every {} bytes", chunk_size);
+
+        let response = MockResponse::sse(fixture("anthropic_stream.sse")).fragmented(chunk_size);
+        let (events, _) = replay(response, Protocol::Anthropic).await;
+        assert_eq!(events, anthropic_events(), "Anthropic transcript split every {} bytes", chunk_size);
+    }
+}
+
+#[tokio::test]
+async fn openai_error_event_ends_the_stream_with_error() {
+    let (events, _) = replay(MockResponse::sse(fixture("openai_midstream_error.sse")), Protocol::OpenAi).await;
+
+    assert_eq!(
+        events,
+        vec![
+            chunk("erDiagram"),
+            StreamEvent::Error("The server had an error while processing your request. Sorry about that!".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn anthropic_error_event_ends_the_stream_with_error() {
+    let (events, usage) = replay(MockResponse::sse(fixture("anthropic_overloaded.sse")), Protocol::Anthropic).await;
+
+    assert_eq!(
+        events,
+        vec![chunk("classDiagram"), chunk("\n    class Order"), StreamEvent::Error("Overloaded".to_string())]
+    );
+    // Input tokens from message_start are still reported for usage tracking
+    assert_eq!(usage.input_tokens, 25);
+}
+
+#[tokio::test]
+async fn early_disconnect_emits_error_after_received_chunks() {
+    let body = fixture("openai_stream.sse");
+    // Cut in the middle of the third event so a partial line is pending when the connection drops
+    let cut = after_events(&body, 2) + 20;
+    let response = MockResponse::sse(body).fragmented(16).disconnect_after(cut);
+
+    let (events, _) = replay(response, Protocol::OpenAi).await;
+
+    assert_eq!(events.len(), 2, "unexpected events: {:?}", events);
+    assert_eq!(events[0], chunk("erDiagram"));
+    assert!(
+        matches!(&events[1], StreamEvent::Error(error) if error.starts_with("Stream error")),
+        "expected a stream error, got {:?}",
+        events[1]
+    );
+}
+
+#[tokio::test]
+async fn anthropic_disconnect_before_message_stop_emits_error() {
+    let body = fixture("anthropic_stream.sse");
+    let cut = after_events(&body, 4);
+    let response = MockResponse::sse(body).disconnect_after(cut);
+
+    let (events, _) = replay(response, Protocol::Anthropic).await;
+
+    assert_eq!(events.len(), 2, "unexpected events: {:?}", events);
+    assert_eq!(events[0], chunk("classDiagram"));
+    assert!(matches!(&events[1], StreamEvent::Error(_)));
+}
+
+#[tokio::test]
+async fn clean_close_before_the_end_marker_is_an_incomplete_answer() {
+    let body = fixture_text("anthropic_stream.sse");
+    let anthropic_cut = body[..body.find("event: message_stop").unwrap()].to_string();
+    let cases = [
+        (fixture("openai_truncated.sse"), Protocol::OpenAi),
+        (anthropic_cut.into_bytes(), Protocol::Anthropic),
+    ];
+
+    for (body, protocol) in cases {
+        let server = MockServer::start(MockResponse::sse(body)).await;
+        let messages = build_messages("You are a test.", "Hello".to_string(), protocol);
+        let (response, _) = start_stream(&reqwest::Client::new(), &profile(&server, protocol), protocol, messages)
+            .await
+            .unwrap();
+
+        let mut events = Vec::new();
+        let (answer, _) = relay_answer(response.bytes_stream(), protocol, |event| events.push(event)).await;
+
+        // Without a complete answer there is nothing to cache or add to a thread
+        assert_eq!(answer, None, "{:?}", protocol);
+        assert!(
+            matches!(events.last(), Some(StreamEvent::Error(error)) if error.starts_with("Incomplete response")),
+            "{:?}: {:?}",
+            protocol,
+            events
+        );
+    }
+}
+
+#[tokio::test]
+async fn http_error_fails_before_any_event() {
+    let body = r#"{"error":{"message":"Incorrect API key provided: test-key.","type":"invalid_request_error","param":null,"code":"invalid_api_key"}}"#;
+    let server = MockServer::start(MockResponse::json(401, body)).await;
+    let messages = build_messages("You are a test.", "Hello".to_string(), Protocol::OpenAi);
+
+    let error = start_stream(&reqwest::Client::new(), &profile(&server, Protocol::OpenAi), Protocol::OpenAi, messages)
+        .await
+        .expect_err("401 must not start a stream");
+
+    assert_eq!(error, format!("LLM API error: {}", body));
+}
+
+#[tokio::test]
+async fn requests_match_each_protocol() {
+    let server = MockServer::start(MockResponse::sse(fixture("openai_stream.sse"))).await;
+    let messages = build_messages("System prompt", "User message".to_string(), Protocol::OpenAi);
+    start_stream(&reqwest::Client::new(), &profile(&server, Protocol::OpenAi), Protocol::OpenAi, messages)
+        .await
+        .unwrap();
+
+    let request = &server.requests()[0];
+    assert_eq!(request.method, "POST");
+    assert_eq!(request.path, "/chat/completions");
+    assert_eq!(request.header("authorization"), Some("Bearer test-key"));
+    assert_eq!(request.body["model"], "test-model");
+    assert_eq!(request.body["stream"], true);
+    assert_eq!(request.body["stream_options"]["include_usage"], true);
+    assert_eq!(request.body["messages"][0]["role"], "system");
+
+    // Compatible servers that reject unknown fields get a request without `stream_options`
+    let server = MockServer::start(MockResponse::sse(fixture("openai_stream.sse"))).await;
+    let messages = build_messages("System prompt", "User message".to_string(), Protocol::OpenAi);
+    let strict = LlmProfile { stream_usage: Some(false), ..profile(&server, Protocol::OpenAi) };
+    start_stream(&reqwest::Client::new(), &strict, Protocol::OpenAi, messages).await.unwrap();
+    assert!(server.requests()[0].body.get("stream_options").is_none());
+
+    let server = MockServer::start(MockResponse::sse(fixture("anthropic_stream.sse"))).await;
+    let messages = build_messages("System prompt", "User message".to_string(), Protocol::Anthropic);
+    start_stream(&reqwest::Client::new(), &profile(&server, Protocol::Anthropic), Protocol::Anthropic, messages)
+        .await
+        .unwrap();
+
+    let request = &server.requests()[0];
+    assert_eq!(request.path, "/messages");
+    assert_eq!(request.header("x-api-key"), Some("test-key"));
+    assert_eq!(request.header("anthropic-version"), Some("2023-06-01"));
+    assert_eq!(request.body["messages"][0]["content"], "System prompt\n\nUser message");
+}
+
+#[tokio::test]
+async fn echo_provider_streams_its_answer_through_the_same_relay() {
+    let answer = echo::respond("er", &[("content", "Customers place orders. Each order contains products.")]);
+    assert!(answer.starts_with("erDiagram\n"));
+    assert!(answer.contains("ORDER ||--o{ CUSTOMER : has") || answer.contains("CUSTOMER ||--o{ ORDER : has"));
+
+    let mut events = Vec::new();
+    relay_stream(echo::sse_stream(&answer), Protocol::OpenAi, |event| events.push(event)).await;
+
+    assert_eq!(events.last(), Some(&StreamEvent::Done));
+    assert!(events.len() > 2, "answer should arrive in several chunks");
+    let streamed: String = events
+        .iter()
+        .filter_map(|event| match event {
+            StreamEvent::Chunk(text) => Some(text.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(streamed, answer);
+    assert_eq!(echo::respond("er", &[("content", "Customers place orders. Each order contains products.")]), answer);
+}
+
+#[tokio::test]
+async fn thread_history_is_sent_before_the_new_message() {
+    let turns = [
+        ("user", "Refine this".to_string()),
+        ("assistant", "## Goal".to_string()),
+        ("user", "Shorter, and keep the bullet list".to_string()),
+    ];
+
+    let server = MockServer::start(MockResponse::sse(fixture("openai_stream.sse"))).await;
+    let messages = build_conversation("System prompt", &turns, Protocol::OpenAi);
+    start_stream(&reqwest::Client::new(), &profile(&server, Protocol::OpenAi), Protocol::OpenAi, messages)
+        .await
+        .unwrap();
+
+    let body = &server.requests()[0].body;
+    let roles: Vec<&str> = body["messages"].as_array().unwrap().iter().map(|m| m["role"].as_str().unwrap()).collect();
+    assert_eq!(roles, ["system", "user", "assistant", "user"]);
+    assert_eq!(body["messages"][3]["content"], "Shorter, and keep the bullet list");
+
+    let server = MockServer::start(MockResponse::sse(fixture("anthropic_stream.sse"))).await;
+    let messages = build_conversation("System prompt", &turns, Protocol::Anthropic);
+    start_stream(&reqwest::Client::new(), &profile(&server, Protocol::Anthropic), Protocol::Anthropic, messages)
+        .await
+        .unwrap();
+
+    let body = &server.requests()[0].body;
+    let roles: Vec<&str> = body["messages"].as_array().unwrap().iter().map(|m| m["role"].as_str().unwrap()).collect();
+    assert_eq!(roles, ["user", "assistant", "user"]);
+    // Only the first turn carries the system prompt
+    assert_eq!(body["messages"][0]["content"], "System prompt\n\nRefine this");
+    assert_eq!(body["messages"][2]["content"], "Shorter, and keep the bullet list");
+}
+
+#[tokio::test]
+async fn ollama_transcript_emits_chunks_then_done() {
+    let (events, usage) = replay(MockResponse::ndjson(fixture("ollama_chat.ndjson")), Protocol::Ollama).await;
+
+    assert_eq!(events, ollama_events());
+    // Counts come with the final `done` line
+    assert_eq!(usage, TokenUsage { input_tokens: 26, output_tokens: 14 });
+
+    for chunk_size in [1, 5, 64] {
+        let response = MockResponse::ndjson(fixture("ollama_chat.ndjson")).fragmented(chunk_size);
+        let (events, _) = replay(response, Protocol::Ollama).await;
+        assert_eq!(events, ollama_events(), "Ollama transcript split every {} bytes", chunk_size);
+    }
+}
+
+#[tokio::test]
+async fn ollama_error_line_ends_the_stream_with_error() {
+    let body = concat!(
+        r#"{"model":"llama3","message":{"role":"assistant","content":"sequenceDiagram"},"done":false}"#,
+        "\n",
+        r#"{"error":"model runner has unexpectedly stopped"}"#,
+        "\n",
+    );
+    let (events, _) = replay(MockResponse::ndjson(body.as_bytes().to_vec()), Protocol::Ollama).await;
+
+    assert_eq!(
+        events,
+        vec![chunk("sequenceDiagram"), StreamEvent::Error("model runner has unexpectedly stopped".to_string())]
+    );
+}
+
+#[tokio::test]
+async fn ollama_requests_go_to_the_native_chat_endpoint() {
+    let server = MockServer::start(MockResponse::ndjson(fixture("ollama_chat.ndjson"))).await;
+    // Base URLs copied from the OpenAI-compatible setup end in `/v1`
+    let profile = LlmProfile { base_url: format!("{}/v1", server.base_url), ..profile(&server, Protocol::Ollama) };
+    let messages = build_messages("System prompt", "User message".to_string(), Protocol::Ollama);
+    start_stream(&reqwest::Client::new(), &profile, Protocol::Ollama, messages).await.unwrap();
+
+    let request = &server.requests()[0];
+    assert_eq!(request.method, "POST");
+    assert_eq!(request.path, "/api/chat");
+    assert_eq!(request.body["model"], "test-model");
+    assert_eq!(request.body["stream"], true);
+    assert_eq!(request.body["messages"][0]["role"], "system");
+    assert_eq!(request.body["messages"][1]["content"], "User message");
+}
+
+#[tokio::test]
+async fn ollama_models_are_listed_with_their_context_length() {
+    let server = MockServer::start_routes(vec![
+        ("/api/tags", MockResponse::json(200, &fixture_text("ollama_tags.json"))),
+        ("/api/show", MockResponse::json(200, &fixture_text("ollama_show.json"))),
+    ])
+    .await;
+
+    let models = list_ollama_models(&reqwest::Client::new(), &format!("{}/v1/", server.base_url)).await.unwrap();
+
+    let names: Vec<&str> = models.iter().map(|m| m.name.as_str()).collect();
+    assert_eq!(names, ["llama3:8b", "mistral:7b"]);
+    assert_eq!(models[0].size, Some(4661224676));
+    assert_eq!(models[0].parameter_size.as_deref(), Some("8.0B"));
+    assert_eq!(models[0].quantization_level.as_deref(), Some("Q4_0"));
+    assert_eq!(models[0].context_length, Some(8192));
+
+    let mut shown: Vec<String> = server
+        .requests()
+        .iter()
+        .filter(|r| r.path == "/api/show")
+        .map(|r| r.body["model"].as_str().unwrap().to_string())
+        .collect();
+    shown.sort();
+    assert_eq!(shown, ["llama3:8b", "mistral:7b"]);
+}
+
+#[tokio::test]
+async fn ollama_model_listing_reports_server_errors() {
+    let server = MockServer::start(MockResponse::json(500, r#"{"error":"internal"}"#)).await;
+
+    let error = list_ollama_models(&reqwest::Client::new(), &server.base_url).await.unwrap_err();
+    assert!(error.starts_with("Ollama returned an error"), "unexpected error: {}", error);
+
+    // Without `/api/show` answers the models are still listed, without a context length
+    let server = MockServer::start_routes(vec![("/api/tags", MockResponse::json(200, &fixture_text("ollama_tags.json")))]).await;
+    let models = list_ollama_models(&reqwest::Client::new(), &server.base_url).await.unwrap();
+    assert_eq!(models.len(), 2);
+    assert!(models.iter().all(|m| m.context_length.is_none()));
+}
+
+#[tokio::test]
+async fn gemini_transcript_emits_chunks_then_done_when_the_stream_closes() {
+    let (events, usage) = replay(MockResponse::sse(fixture("gemini_stream.sse")), Protocol::Gemini).await;
+
+    // Parts of one chunk arrive as one piece of text; the empty final chunk adds nothing
+    assert_eq!(events, gemini_events());
+    // `usageMetadata` carries running totals, so the last one wins
+    assert_eq!(usage, TokenUsage { input_tokens: 31, output_tokens: 11 });
+
+    for chunk_size in [1, 7, 64] {
+        let response = MockResponse::sse(fixture("gemini_stream.sse")).fragmented(chunk_size);
+        let (events, _) = replay(response, Protocol::Gemini).await;
+        assert_eq!(events, gemini_events(), "Gemini transcript split every {} bytes", chunk_size);
+    }
+}
+
+#[tokio::test]
+async fn gemini_errors_and_blocks_end_the_stream_with_error() {
+    let cases = [
+        (
+            r#"data: {"error": {"code": 429, "message": "Resource has been exhausted (e.g. check quota).", "status": "RESOURCE_EXHAUSTED"}}"#,
+            "Resource has been exhausted (e.g. check quota).",
+        ),
+        (
+            r#"data: {"promptFeedback": {"blockReason": "SAFETY"}, "usageMetadata": {"promptTokenCount": 8}}"#,
+            "Gemini blocked the prompt: SAFETY",
+        ),
+        (
+            r#"data: {"candidates": [{"content": {"parts": []}, "finishReason": "RECITATION", "index": 0}]}"#,
+            "Gemini stopped the response: RECITATION",
+        ),
+    ];
+
+    for (line, error) in cases {
+        let first = r#"data: {"candidates": [{"content": {"parts": [{"text": "flowchart TD"}], "role": "model"}, "index": 0}]}"#;
+        let body = format!("{}\n\n{}\n\n", first, line);
+        let (events, _) = replay(MockResponse::sse(body.into_bytes()), Protocol::Gemini).await;
+
+        assert_eq!(events, vec![chunk("flowchart TD"), StreamEvent::Error(error.to_string())]);
+    }
+}
+
+#[tokio::test]
+async fn gemini_requests_use_system_instruction_and_contents() {
+    let turns = [
+        ("user", "Draw the checkout".to_string()),
+        ("assistant", "flowchart TD".to_string()),
+        ("user", "Add a payment step".to_string()),
+    ];
+
+    let server = MockServer::start(MockResponse::sse(fixture("gemini_stream.sse"))).await;
+    // Base URLs copied from the OpenAI-compatible endpoint end in `/openai`
+    let profile = LlmProfile { base_url: format!("{}/openai/", server.base_url), ..profile(&server, Protocol::Gemini) };
+    let messages = build_conversation("System prompt", &turns, Protocol::Gemini);
+    start_stream(&reqwest::Client::new(), &profile, Protocol::Gemini, messages).await.unwrap();
+
+    let request = &server.requests()[0];
+    assert_eq!(request.method, "POST");
+    assert_eq!(request.path, "/models/test-model:streamGenerateContent?alt=sse");
+    assert_eq!(request.header("x-goog-api-key"), Some("test-key"));
+    assert_eq!(request.header("authorization"), None);
+    assert_eq!(request.body["systemInstruction"]["parts"][0]["text"], "System prompt");
+
+    let contents = request.body["contents"].as_array().unwrap();
+    let roles: Vec<&str> = contents.iter().map(|c| c["role"].as_str().unwrap()).collect();
+    assert_eq!(roles, ["user", "model", "user"]);
+    assert_eq!(contents[2]["parts"][0]["text"], "Add a payment step");
+}
@@ -0,0 +1,32 @@
+//! Forking conversation threads at a complete exchange
+
+use promptmux::models::ConversationThread;
+
+fn thread_with_two_exchanges() -> ConversationThread {
+    let mut thread = ConversationThread::new("Checkout".to_string(), "ask".to_string(), "project".to_string(), None);
+    thread.add_turn("What is missing?".to_string(), "A refund story.".to_string());
+    thread.add_turn("Write it".to_string(), "As a customer...".to_string());
+    thread
+}
+
+#[test]
+fn fork_keeps_whole_exchanges() {
+    let thread = thread_with_two_exchanges();
+
+    let fork = thread.fork(Some(2)).unwrap();
+    assert_eq!(fork.messages.len(), 2);
+    assert_eq!(fork.messages[1].role, "assistant");
+    assert_eq!(fork.forked_from.as_deref(), Some(thread.id.as_str()));
+
+    assert_eq!(thread.fork(None).unwrap().messages.len(), 4);
+    assert!(thread.fork(Some(0)).unwrap().messages.is_empty());
+}
+
+#[test]
+fn fork_rejects_a_dangling_question_or_too_many_messages() {
+    let thread = thread_with_two_exchanges();
+
+    assert!(thread.fork(Some(1)).is_err());
+    assert!(thread.fork(Some(3)).is_err());
+    assert!(thread.fork(Some(5)).is_err());
+}
@@ -0,0 +1,44 @@
+//! Usage ledger persistence: appended records and damaged lines
+
+mod common;
+
+use common::TempDir;
+use promptmux::usage::{TokenUsage, UsageLedger, UsageRecord};
+
+fn record(task: &str) -> UsageRecord {
+    let usage = TokenUsage { input_tokens: 10, output_tokens: 5 };
+    UsageRecord::new(Some("project".to_string()), task, "default", "gpt-4o", usage, None)
+}
+
+#[test]
+fn records_are_appended_one_per_line() {
+    let dir = TempDir::new();
+    let mut ledger = UsageLedger::load(&dir.0).unwrap();
+    ledger.add(record("refine")).unwrap();
+    ledger.add(record("merge")).unwrap();
+
+    let content = std::fs::read_to_string(dir.0.join("usage.jsonl")).unwrap();
+    assert_eq!(content.lines().count(), 2);
+
+    let reloaded = UsageLedger::load(&dir.0).unwrap();
+    let tasks: Vec<String> = reloaded.records(None).into_iter().map(|r| r.task).collect();
+    assert_eq!(tasks, ["refine", "merge"]);
+}
+
+#[test]
+fn a_line_cut_short_only_loses_that_record() {
+    let dir = TempDir::new();
+    let mut ledger = UsageLedger::load(&dir.0).unwrap();
+    ledger.add(record("refine")).unwrap();
+
+    let path = dir.0.join("usage.jsonl");
+    let mut content = std::fs::read_to_string(&path).unwrap();
+    content.push_str("{\"id\":\"cut");
+    std::fs::write(&path, content).unwrap();
+
+    let mut reloaded = UsageLedger::load(&dir.0).unwrap();
+    assert_eq!(reloaded.records(None).len(), 1);
+
+    reloaded.add(record("merge")).unwrap();
+    assert_eq!(UsageLedger::load(&dir.0).unwrap().records(None).len(), 2);
+}
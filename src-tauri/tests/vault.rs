@@ -0,0 +1,187 @@
+//! Encrypted API key vault: round trip, damaged files, migration and key lookup order
+
+mod common;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use promptmux::settings::{LlmProfile, LlmSettings};
+use common::TempDir;
+use promptmux::vault::{profile_env_var, resolve_api_key_with, secure_api_keys, KeySource, Vault};
+use std::path::PathBuf;
+
+fn vault_path(dir: &TempDir) -> PathBuf {
+    dir.0.join("vault.json")
+}
+
+/// A passphrase vault holding `openai-key` -> `sk-test`
+fn passphrase_vault(dir: &TempDir) -> Vault {
+    let mut vault = Vault::open(vault_path(dir));
+    vault.create(Some("correct horse")).unwrap();
+    vault.set("openai-key", "sk-test".to_string()).unwrap();
+    vault
+}
+
+/// Rewrite one field of the vault file
+fn tamper(dir: &TempDir, field: &str, value: String) {
+    let path = vault_path(dir);
+    let mut file: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    file[field] = serde_json::Value::String(value);
+    std::fs::write(&path, file.to_string()).unwrap();
+}
+
+fn profile(name: &str, api_key_ref: Option<&str>) -> LlmProfile {
+    LlmProfile {
+        name: name.to_string(),
+        api_key_ref: api_key_ref.map(str::to_string),
+        ..LlmProfile::default()
+    }
+}
+
+#[test]
+fn secrets_survive_a_reopen_and_unlock() {
+    let dir = TempDir::new();
+    passphrase_vault(&dir);
+
+    let file = std::fs::read_to_string(vault_path(&dir)).unwrap();
+    assert!(!file.contains("sk-test"));
+
+    let mut reopened = Vault::open(vault_path(&dir));
+    let status = reopened.status();
+    assert!(status.exists);
+    assert!(!status.unlocked);
+    assert_eq!(status.key_source, Some(KeySource::Passphrase));
+    assert!(reopened.get("openai-key").is_err());
+
+    reopened.unlock("correct horse").unwrap();
+    assert_eq!(reopened.get("openai-key").unwrap().as_deref(), Some("sk-test"));
+    assert_eq!(reopened.get("missing").unwrap(), None);
+
+    reopened.lock();
+    assert!(reopened.get("openai-key").is_err());
+}
+
+#[test]
+fn wrong_passphrase_is_reported_as_such() {
+    let dir = TempDir::new();
+    passphrase_vault(&dir);
+
+    let mut reopened = Vault::open(vault_path(&dir));
+    assert_eq!(reopened.unlock("wrong").unwrap_err(), "Incorrect passphrase");
+    assert!(!reopened.is_unlocked());
+}
+
+#[test]
+fn tampered_ciphertext_does_not_decrypt() {
+    let dir = TempDir::new();
+    passphrase_vault(&dir);
+
+    let file: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(vault_path(&dir)).unwrap()).unwrap();
+    let mut ciphertext = BASE64.decode(file["ciphertext"].as_str().unwrap()).unwrap();
+    ciphertext[0] ^= 0x01;
+    tamper(&dir, "ciphertext", BASE64.encode(ciphertext));
+
+    let mut reopened = Vault::open(vault_path(&dir));
+    assert!(reopened.unlock("correct horse").is_err());
+    assert!(!reopened.is_unlocked());
+}
+
+#[test]
+fn damaged_files_report_the_damage_instead_of_a_wrong_passphrase() {
+    let dir = TempDir::new();
+    passphrase_vault(&dir);
+
+    tamper(&dir, "nonce", BASE64.encode([0u8; 8]));
+    let mut reopened = Vault::open(vault_path(&dir));
+    assert_eq!(reopened.unlock("correct horse").unwrap_err(), "Invalid vault nonce length");
+
+    tamper(&dir, "ciphertext", "not base64!".to_string());
+    let mut reopened = Vault::open(vault_path(&dir));
+    assert!(reopened.unlock("correct horse").unwrap_err().starts_with("Invalid vault"));
+}
+
+#[test]
+fn unreadable_vault_file_is_reported_and_left_alone() {
+    let dir = TempDir::new();
+    std::fs::write(vault_path(&dir), "{ not a vault").unwrap();
+
+    let mut vault = Vault::open(vault_path(&dir));
+    let status = vault.status();
+    assert!(status.exists);
+    assert!(!status.unlocked);
+    assert!(status.error.unwrap().starts_with("Failed to parse vault file"));
+
+    assert!(vault.create(None).is_err());
+    assert!(vault.create(Some("correct horse")).is_err());
+    let mut settings = LlmSettings::default();
+    settings.profiles[0].api_key = "sk-plain".to_string();
+    assert!(secure_api_keys(&mut vault, &mut settings).is_err());
+    assert_eq!(settings.profiles[0].api_key, "sk-plain");
+
+    assert_eq!(std::fs::read_to_string(vault_path(&dir)).unwrap(), "{ not a vault");
+}
+
+#[test]
+fn plaintext_keys_move_into_the_vault() {
+    let dir = TempDir::new();
+    let mut vault = Vault::open(vault_path(&dir));
+    vault.create(Some("correct horse")).unwrap();
+
+    let mut settings = LlmSettings::default();
+    settings.profiles[0].api_key = "sk-plain".to_string();
+
+    assert!(secure_api_keys(&mut vault, &mut settings).unwrap());
+    let profile = &settings.profiles[0];
+    assert!(profile.api_key.is_empty());
+    let key_ref = profile.api_key_ref.clone().unwrap();
+    assert_eq!(vault.get(&key_ref).unwrap().as_deref(), Some("sk-plain"));
+
+    // Nothing left to move; entries no profile refers to are dropped
+    vault.set("orphan", "sk-old".to_string()).unwrap();
+    assert!(!secure_api_keys(&mut vault, &mut settings).unwrap());
+    assert_eq!(vault.get("orphan").unwrap(), None);
+    assert!(vault.get(&key_ref).unwrap().is_some());
+}
+
+#[test]
+fn env_var_names_are_derived_from_the_profile() {
+    assert_eq!(profile_env_var("Work GPT-4"), "PROMPTMUX_API_KEY_WORK_GPT_4");
+    assert_eq!(profile_env_var("local"), "PROMPTMUX_API_KEY_LOCAL");
+}
+
+/// An environment holding only `vars`, so tests do not touch the shared process environment
+fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let vars: Vec<(String, String)> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    move |name| vars.iter().find(|(k, _)| k == name).map(|(_, v)| v.clone())
+}
+
+#[test]
+fn profile_env_var_wins_over_the_vault() {
+    let dir = TempDir::new();
+    let vault = passphrase_vault(&dir);
+    let name = "Work GPT";
+
+    let mut overridden = profile(name, Some("openai-key"));
+    resolve_api_key_with(&vault, &mut overridden, env(&[(&profile_env_var(name), "sk-env")])).unwrap();
+    assert_eq!(overridden.api_key, "sk-env");
+
+    // An empty variable does not hide the stored key
+    let mut empty = profile(name, Some("openai-key"));
+    resolve_api_key_with(&vault, &mut empty, env(&[(&profile_env_var(name), "")])).unwrap();
+    assert_eq!(empty.api_key, "sk-test");
+}
+
+#[test]
+fn vault_key_is_used_per_profile_and_never_shared() {
+    let dir = TempDir::new();
+    let vault = passphrase_vault(&dir);
+    let everywhere = env(&[("PROMPTMUX_API_KEY", "sk-everywhere")]);
+
+    let mut stored = profile("Work GPT", Some("openai-key"));
+    resolve_api_key_with(&vault, &mut stored, &everywhere).unwrap();
+    assert_eq!(stored.api_key, "sk-test");
+
+    // A profile without a key, like a local Ollama server, gets none
+    let mut keyless = profile("Local", None);
+    resolve_api_key_with(&vault, &mut keyless, &everywhere).unwrap();
+    assert_eq!(keyless.api_key, "");
+}
@@ -0,0 +1,40 @@
+//! Loading projects saved before diagrams became a list
+
+use promptmux::models::Project;
+
+#[test]
+fn legacy_diagram_fields_become_named_diagrams() {
+    let json = r#"{
+        "id": "p1",
+        "name": "Shop",
+        "sections": [],
+        "created_at": "2024-01-01T00:00:00Z",
+        "updated_at": "2024-01-01T00:00:00Z",
+        "er_diagram": "erDiagram\n    CUSTOMER",
+        "flowchart": "flowchart LR\n    A --> B"
+    }"#;
+    let mut project: Project = serde_json::from_str(json).unwrap();
+    project.migrate_diagrams();
+
+    let kinds: Vec<&str> = project.diagrams.iter().map(|d| d.kind.as_str()).collect();
+    assert_eq!(kinds, ["er", "flowchart"]);
+    assert_eq!(project.diagrams[0].code, "erDiagram\n    CUSTOMER");
+
+    // Legacy fields are not written back
+    let saved = serde_json::to_value(&project).unwrap();
+    assert!(saved.get("er_diagram").is_none());
+}
+
+#[test]
+fn a_malformed_diagram_list_names_the_problem() {
+    let json = r#"{
+        "id": "p1",
+        "name": "Shop",
+        "sections": [],
+        "created_at": "2024-01-01T00:00:00Z",
+        "updated_at": "2024-01-01T00:00:00Z",
+        "diagrams": [{ "id": "d1" }]
+    }"#;
+    let error = serde_json::from_str::<Project>(json).unwrap_err().to_string();
+    assert!(error.contains("missing field"), "{}", error);
+}
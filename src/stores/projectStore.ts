@@ -8,6 +8,7 @@ export interface Refinement {
   timestamp: string;
   kind?: string;
   mode?: 'edit' | 'ask';
+  diagram_id?: string;
 }
 
 export interface Topic {
@@ -50,6 +51,66 @@ export async function saveSectionRefinement(sectionId: string, refinement: Refin
   }
 }
 
+export async function acceptRefinement(targetType: 'topic' | 'section', targetId: string, refinedContent: string): Promise<Refinement> {
+  try {
+    const refinement = await invoke<Refinement>('accept_refinement', { targetType, targetId, refinedContent });
+    await loadProject();
+    return refinement;
+  } catch (error) {
+    console.error('Failed to accept refinement:', error);
+    throw error;
+  }
+}
+
+export interface MergeResult {
+  status: 'applied' | 'alreadyApplied' | 'merged' | 'conflict';
+  content: string;
+  conflicts: number;
+}
+
+export async function applyRefinement(topicId: string, refinementId: string): Promise<MergeResult> {
+  try {
+    const result = await invoke<MergeResult>('apply_refinement', { topicId, refinementId });
+    if (result.status === 'applied') {
+      await loadProject();
+    }
+    return result;
+  } catch (error) {
+    console.error('Failed to apply refinement:', error);
+    throw error;
+  }
+}
+
+export interface SplitChange {
+  kind: 'added' | 'removed' | 'changed';
+  itemType: 'section' | 'topic';
+  sectionId: string | null;
+  sectionName: string;
+  topicId: string | null;
+  topicName: string | null;
+  originalContent: string | null;
+  newContent: string | null;
+}
+
+export interface SplitPlan {
+  changes: SplitChange[];
+  unchanged: number;
+}
+
+export async function splitMergedOutput(content: string): Promise<SplitPlan> {
+  return await invoke<SplitPlan>('split_merged_output', { content });
+}
+
+export async function applyMergedSplit(changes: SplitChange[]): Promise<void> {
+  try {
+    await invoke('apply_merged_split', { changes });
+    await loadProject();
+  } catch (error) {
+    console.error('Failed to apply refined sections:', error);
+    throw error;
+  }
+}
+
 export async function saveProjectRefinement(refinement: Refinement): Promise<void> {
   try {
     await invoke('save_project_refinement', { refinement });
@@ -74,63 +135,196 @@ export async function deleteProjectRefinement(refinementId: string): Promise<voi
 }
 
 
-export async function saveProjectErDiagram(erDiagram: string): Promise<void> {
+export type DiagramKind = 'er' | 'uml' | 'flowchart' | 'journey' | 'sequence' | 'state' | 'c4';
+
+export interface Diagram {
+  id: string;
+  kind: DiagramKind;
+  name: string;
+  code: string;
+  created_at: string;
+  updated_at: string;
+  versions?: DiagramVersion[];
+}
+
+export async function createDiagram(kind: DiagramKind, name?: string, code?: string): Promise<Diagram> {
   try {
-    await invoke('save_project_er_diagram', { erDiagram });
+    const diagram = await invoke<Diagram>('create_diagram', { kind, name, code });
     await loadProject();
+    return diagram;
   } catch (error) {
-    console.error('Failed to save project ER diagram:', error);
+    console.error('Failed to create diagram:', error);
     throw error;
   }
 }
 
-export async function saveProjectUmlDiagram(umlDiagram: string): Promise<void> {
+export async function renameDiagram(diagramId: string, name: string): Promise<void> {
   try {
-    await invoke('save_project_uml_diagram', { umlDiagram });
+    await invoke('rename_diagram', { diagramId, name });
     await loadProject();
   } catch (error) {
-    console.error('Failed to save project UML diagram:', error);
+    console.error('Failed to rename diagram:', error);
     throw error;
   }
 }
 
-export async function saveProjectFlowchart(flowchart: string): Promise<void> {
+export async function deleteDiagram(diagramId: string): Promise<void> {
   try {
-    await invoke('save_project_flowchart', { flowchart });
+    await invoke('delete_diagram', { diagramId });
     await loadProject();
   } catch (error) {
-    console.error('Failed to save project Flowchart:', error);
+    console.error('Failed to delete diagram:', error);
     throw error;
   }
 }
 
-export interface Project {
+export async function saveProjectDiagram(diagramId: string, code: string, allowInvalid = false): Promise<string> {
+  try {
+    const saved = await invoke<string>('save_project_diagram', { diagramId, code, allowInvalid });
+    await loadProject();
+    return saved;
+  } catch (error) {
+    console.error('Failed to save project diagram:', error);
+    throw error;
+  }
+}
+
+export interface DiagramVersion {
   id: string;
-  name: string;
-  sections: Section[];
+  code: string;
   created_at: string;
-  updated_at: string;
-  history?: Refinement[];
-  er_diagram?: string;
-  uml_diagram?: string;
-  flowchart?: string;
-  user_journey?: string;
-  user_stories?: string;
+  source: 'save' | 'import' | 'revert';
+  label?: string;
 }
 
-export async function saveProjectUserJourney(content: string): Promise<void> {
-  try {
-    const activeProject = get(projectStore);
-    if (!activeProject) throw new Error('No active project');
+export interface DiffLine {
+  change: 'unchanged' | 'added' | 'removed';
+  text: string;
+  oldLine?: number;
+  newLine?: number;
+}
+
+// Versions are recorded by the backend on every save, newest first
+export async function listDiagramVersions(diagramId: string): Promise<DiagramVersion[]> {
+  return await invoke<DiagramVersion[]>('list_diagram_versions', { diagramId });
+}
 
-    await invoke('save_project_user_journey', { projectId: activeProject.id, content });
+export async function diffDiagramVersions(diagramId: string, fromVersionId: string, toVersionId: string): Promise<DiffLine[]> {
+  return await invoke<DiffLine[]>('diff_diagram_versions', { diagramId, fromVersionId, toVersionId });
+}
+
+export async function labelDiagramVersion(diagramId: string, versionId: string, label: string): Promise<void> {
+  await invoke('label_diagram_version', { diagramId, versionId, label });
+  await loadProject();
+}
+
+export async function revertDiagram(diagramId: string, versionId: string): Promise<string> {
+  try {
+    const code = await invoke<string>('revert_diagram', { diagramId, versionId });
     await loadProject();
+    return code;
   } catch (error) {
-    console.error('Failed to save project User Journey:', error);
+    console.error('Failed to revert diagram:', error);
     throw error;
   }
 }
 
+export interface MermaidDiagnostic {
+  line: number;
+  column: number;
+  message: string;
+}
+
+export interface MermaidReport {
+  cleaned: string;
+  diagnostics: MermaidDiagnostic[];
+}
+
+// Strips fences and surrounding prose from LLM output and reports syntax problems
+export async function validateMermaid(kind: DiagramKind, code: string): Promise<MermaidReport> {
+  return await invoke<MermaidReport>('validate_mermaid', { kind, code });
+}
+
+// One structural difference between a diagram and an edit proposed for it
+export interface DiagramChange {
+  kind: 'added' | 'removed' | 'renamed' | 'changed';
+  element: 'entity' | 'attribute' | 'edge' | 'label';
+  // Entity, class or node by its current name; `A -> B` for edges
+  subject: string;
+  before?: string;
+  after?: string;
+}
+
+// Structural diffs are available for ER, class and flowchart diagrams
+export const DIFFABLE_KINDS: DiagramKind[] = ['er', 'uml', 'flowchart'];
+
+export async function diffDiagramEdit(kind: DiagramKind, current: string, proposed: string): Promise<DiagramChange[]> {
+  return await invoke<DiagramChange[]>('diff_diagram_edit', { kind, current, proposed });
+}
+
+export async function applyDiagramEdit(kind: DiagramKind, current: string, proposed: string, changes: DiagramChange[]): Promise<string> {
+  return await invoke<string>('apply_diagram_edit', { kind, current, proposed, changes });
+}
+
+// Payload of `<prefix>:repair`, sent before each automatic repair request
+export interface RepairAttempt {
+  attempt: number;
+  maxAttempts: number;
+  diagnostics: MermaidDiagnostic[];
+}
+
+export function repairStatus(attempt: RepairAttempt): string {
+  return `Fixing syntax errors (attempt ${attempt.attempt}/${attempt.maxAttempts})...`;
+}
+
+export function formatDiagnostics(diagnostics: MermaidDiagnostic[]): string {
+  return diagnostics.map(d => `Line ${d.line}, column ${d.column}: ${d.message}`).join('\n');
+}
+
+export type SqlDialect = 'postgresql' | 'mysql' | 'sqlite';
+
+// CREATE TABLE script for an ER diagram of the active project
+export async function generateSqlFromErDiagram(dialect: SqlDialect, diagramId?: string): Promise<string> {
+  return await invoke<string>('generate_sql_from_er_diagram', { dialect, diagramId });
+}
+
+// Replace an ER diagram with the tables of a SQL DDL script
+export async function importErDiagramFromSql(sql: string, diagramId?: string): Promise<string> {
+  const imported = await invoke<string>('import_er_diagram_from_sql', { sql, diagramId });
+  await loadProject();
+  return imported;
+}
+
+// Replace an ER diagram with the schema of a local SQLite database file
+export async function importErDiagramFromSqlite(path: string, diagramId?: string): Promise<string> {
+  const imported = await invoke<string>('import_er_diagram_from_sqlite', { path, diagramId });
+  await loadProject();
+  return imported;
+}
+
+export type CodeLanguage = 'rust' | 'typescript' | 'python';
+
+export interface GeneratedFile {
+  path: string;
+  content: string;
+}
+
+// Code skeleton files for a class diagram of the active project
+export async function generateCodeFromUmlDiagram(language: CodeLanguage, diagramId?: string): Promise<GeneratedFile[]> {
+  return await invoke<GeneratedFile[]>('generate_code_from_uml_diagram', { language, diagramId });
+}
+
+export interface Project {
+  id: string;
+  name: string;
+  sections: Section[];
+  created_at: string;
+  updated_at: string;
+  history?: Refinement[];
+  diagrams?: Diagram[];
+  user_stories?: string;
+}
+
 export async function saveProjectUserStories(content: string): Promise<void> {
   try {
     const activeProject = get(projectStore);
@@ -144,6 +338,14 @@ export async function saveProjectUserStories(content: string): Promise<void> {
   }
 }
 
+// Gherkin feature files for the active project's user stories
+export async function exportUserStoriesToGherkin(): Promise<GeneratedFile[]> {
+  const activeProject = get(projectStore);
+  if (!activeProject) throw new Error('No active project');
+
+  return await invoke<GeneratedFile[]>('export_user_stories_to_gherkin', { projectId: activeProject.id });
+}
+
 export interface Workspace {
   projects: Project[];
   active_project_id: string;
@@ -248,8 +450,21 @@ export async function deleteProject(projectId: string): Promise<void> {
   }
 }
 
+// Debounced editor saves target the active project, so they must land before it changes
+const pendingSaveFlushes = new Set<() => Promise<void>>();
+
+export function registerPendingSaves(flush: () => Promise<void>): () => void {
+  pendingSaveFlushes.add(flush);
+  return () => pendingSaveFlushes.delete(flush);
+}
+
+export async function flushPendingSaves(): Promise<void> {
+  await Promise.all([...pendingSaveFlushes].map((flush) => flush()));
+}
+
 export async function switchProject(projectId: string): Promise<Project> {
   try {
+    await flushPendingSaves();
     const project = await invoke<Project>('switch_project', { projectId });
     await loadWorkspace();
 
@@ -378,15 +593,6 @@ export async function getMergedOutput(): Promise<string> {
   }
 }
 
-export async function refineWithLlm(content: string): Promise<string> {
-  try {
-    return await invoke<string>('refine_with_llm', { content });
-  } catch (error) {
-    console.error('Failed to refine with LLM:', error);
-    throw error;
-  }
-}
-
 export async function getPlatform(): Promise<string> {
   try {
     const os = await invoke<string>('get_platform');
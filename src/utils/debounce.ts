@@ -1,18 +1,31 @@
 export function debounce<T extends (...args: any[]) => any>(
   func: T,
   wait: number
-): (...args: Parameters<T>) => void {
+): ((...args: Parameters<T>) => void) & { flush: () => ReturnType<T> | undefined } {
   let timeout: number | null = null;
+  let pendingArgs: Parameters<T> | null = null;
 
-  return function executedFunction(...args: Parameters<T>) {
-    const later = () => {
-      timeout = null;
-      func(...args);
-    };
+  function executedFunction(...args: Parameters<T>) {
+    pendingArgs = args;
+    if (timeout !== null) {
+      clearTimeout(timeout);
+    }
+    timeout = window.setTimeout(flush, wait);
+  }
 
+  // Run a pending call right away instead of waiting for the delay
+  function flush(): ReturnType<T> | undefined {
     if (timeout !== null) {
       clearTimeout(timeout);
+      timeout = null;
+    }
+    if (pendingArgs === null) {
+      return undefined;
     }
-    timeout = window.setTimeout(later, wait);
-  };
+    const args = pendingArgs;
+    pendingArgs = null;
+    return func(...args);
+  }
+
+  return Object.assign(executedFunction, { flush });
 }